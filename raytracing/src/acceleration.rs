@@ -0,0 +1,82 @@
+use crate::bvh::Bvh;
+use crate::ray_intersect::SceneObject;
+use crate::voxel_grid::VoxelGrid;
+use raylib::prelude::Vector3;
+
+/// How rays find the objects they might hit without testing every one
+#[derive(Debug, Clone)]
+pub enum Acceleration {
+    Grid(VoxelGrid), // Blocks of about the same size packed together, like the diorama
+    Bvh(Bvh),        // Anything else
+}
+
+impl Acceleration {
+    /// The voxel grid when the objects fill one well, a BVH otherwise
+    pub fn build(objects: &[SceneObject]) -> Self {
+        match VoxelGrid::build(objects) {
+            Some(grid) => Acceleration::Grid(grid),
+            None => Acceleration::Bvh(Bvh::build(objects)),
+        }
+    }
+
+    /// Follows the objects at `moved` to where they are now: a BVH refits its boxes, a grid
+    /// re-bins just those objects. Either is built again once it can't keep up (see
+    /// `Bvh::refit` and `VoxelGrid::update`).
+    pub fn update(&mut self, objects: &[SceneObject], moved: &[usize]) {
+        if moved.is_empty() {
+            return;
+        }
+        let updated = match self {
+            Acceleration::Bvh(bvh) => bvh.refit(objects),
+            Acceleration::Grid(grid) => grid.update(objects, moved),
+        };
+        if !updated {
+            *self = Acceleration::build(objects);
+        }
+    }
+
+    /// Takes in the objects pushed onto the end of `objects` (placed blocks): a grid bins
+    /// them into the chunks they land in, a BVH is built again
+    pub fn insert(&mut self, objects: &[SceneObject]) {
+        let inserted = match self {
+            Acceleration::Bvh(_) => false,
+            Acceleration::Grid(grid) => grid.insert(objects),
+        };
+        if !inserted {
+            *self = Acceleration::build(objects);
+        }
+    }
+
+    /// Follows `objects.swap_remove(index)`, which the caller has already done: a grid
+    /// rebuilds the chunks the removed and the renumbered object were in, a BVH is built again
+    pub fn remove(&mut self, objects: &[SceneObject], index: usize) {
+        match self {
+            Acceleration::Grid(grid) => grid.remove(index),
+            Acceleration::Bvh(_) => *self = Acceleration::build(objects),
+        }
+    }
+
+    /// Calls `visit` with the objects along the ray, roughly nearest first; see
+    /// `VoxelGrid::traverse` and `Bvh::traverse`
+    pub fn traverse(&self, origin: Vector3, direction: Vector3, limit: f32, visit: impl FnMut(usize) -> Option<f32>) {
+        match self {
+            Acceleration::Grid(grid) => grid.traverse(origin, direction, limit, visit),
+            Acceleration::Bvh(bvh) => bvh.traverse(origin, direction, limit, visit),
+        }
+    }
+}
+
+/// `Acceleration::traverse` when there is a structure, otherwise every one of `count`
+/// objects in order
+pub fn candidates(accel: Option<&Acceleration>, count: usize, origin: Vector3, direction: Vector3, limit: f32, mut visit: impl FnMut(usize) -> Option<f32>) {
+    match accel {
+        Some(accel) => accel.traverse(origin, direction, limit, visit),
+        None => {
+            for index in 0..count {
+                if visit(index).is_none() {
+                    return;
+                }
+            }
+        }
+    }
+}
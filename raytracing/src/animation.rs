@@ -0,0 +1,341 @@
+use crate::light::Light;
+use crate::material::MaterialTable;
+use crate::ray_intersect::SceneObject;
+use raylib::prelude::*;
+use std::collections::HashMap;
+
+/// How a track moves from one keyframe to the next
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Easing {
+    #[default]
+    Linear,
+    Smooth, // Eases in and out, for doors and platforms that start and stop
+    Step,   // Holds the value until the next keyframe, for light shows
+}
+
+impl Easing {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Easing::Linear),
+            "smooth" => Some(Easing::Smooth),
+            "step" => Some(Easing::Step),
+            _ => None,
+        }
+    }
+
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::Smooth => t * t * (3.0 - 2.0 * t),
+            Easing::Step => 0.0,
+        }
+    }
+}
+
+/// Values a track can blend between
+pub trait Keyable: Copy {
+    fn lerp(from: Self, to: Self, t: f32) -> Self;
+}
+
+impl Keyable for f32 {
+    fn lerp(from: Self, to: Self, t: f32) -> Self {
+        from + (to - from) * t
+    }
+}
+
+impl Keyable for Vector3 {
+    fn lerp(from: Self, to: Self, t: f32) -> Self {
+        from + (to - from) * t
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe<T> {
+    pub time: f32,
+    pub value: T,
+    pub easing: Easing, // Used on the way to the next keyframe
+}
+
+/// Keyframes of one value, kept sorted by time
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T> Default for Track<T> {
+    fn default() -> Self {
+        Track { keys: Vec::new() }
+    }
+}
+
+impl<T: Keyable> Track<T> {
+    /// Adds a keyframe, replacing one already at the same time
+    pub fn insert(&mut self, time: f32, value: T, easing: Easing) {
+        let key = Keyframe { time, value, easing };
+        match self.keys.binary_search_by(|probe| probe.time.total_cmp(&time)) {
+            Ok(index) => self.keys[index] = key,
+            Err(index) => self.keys.insert(index, key),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Value at `time`, held at the first/last keyframe outside the keyed range
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let next = self.keys.partition_point(|key| key.time <= time);
+        match (next.checked_sub(1).map(|index| &self.keys[index]), self.keys.get(next)) {
+            (Some(from), Some(to)) => {
+                let t = (time - from.time) / (to.time - from.time);
+                Some(T::lerp(from.value, to.value, from.easing.apply(t)))
+            }
+            (Some(key), None) | (None, Some(key)) => Some(key.value),
+            (None, None) => None,
+        }
+    }
+}
+
+impl Track<Vector3> {
+    /// Back-and-forth route through `waypoints` at `speed` units per second, like an elevator:
+    /// eases into every waypoint and waits `dwell` seconds at both ends. Returns the track and
+    /// the time for a round trip.
+    pub fn ping_pong(waypoints: &[Vector3], speed: f32, dwell: f32) -> (Self, f32) {
+        let mut track = Track::default();
+        let mut time = 0.0;
+        let mut previous: Option<Vector3> = None;
+        let route = waypoints.iter().chain(waypoints.iter().rev().skip(1));
+        for (index, &point) in route.enumerate() {
+            if let Some(previous) = previous {
+                time += (point - previous).length() / speed.max(1e-3);
+            }
+            track.insert(time, point, Easing::Smooth);
+            // The wait at the start of the route wraps around to cover the return
+            let at_end = index == 0 || index + 1 == waypoints.len();
+            if at_end && dwell > 0.0 {
+                time += dwell;
+                track.insert(time, point, Easing::Smooth);
+            }
+            previous = Some(point);
+        }
+        (track, time)
+    }
+}
+
+/// Keyframed motion and look of a group of objects; offsets are from where they were built
+#[derive(Debug, Clone, Default)]
+pub struct ObjectTrack {
+    pub objects: Vec<usize>,
+    pub period: Option<f32>, // Loops on its own with this period, else follows the timeline
+    pub offset: Track<Vector3>,
+    pub color: Track<Vector3>,    // Diffuse color, 0-1
+    pub emission: Track<Vector3>, // Light given off
+}
+
+/// Keyframed parameters of one light, by index into the scene's lights
+#[derive(Debug, Clone, Default)]
+pub struct LightTrack {
+    pub light: usize,
+    pub position: Track<Vector3>,
+    pub intensity: Track<f32>,
+    pub color: Track<Vector3>, // 0-255 per channel
+}
+
+/// Every keyframed track of a scene, evaluated from simulation time each frame
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    pub objects: Vec<ObjectTrack>,
+    pub lights: Vec<LightTrack>,
+    pub length: Option<f32>, // Loops with this period when set, else holds after the last keyframe
+}
+
+impl Timeline {
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty() && self.lights.is_empty()
+    }
+
+    fn local_time(&self, time: f32) -> f32 {
+        looped(time, self.length)
+    }
+
+    /// Overrides keyframed light parameters; tracks for lights that don't exist are ignored
+    pub fn apply_lights(&self, lights: &mut [Light], time: f32) {
+        let time = self.local_time(time);
+        for track in &self.lights {
+            let Some(light) = lights.get_mut(track.light) else {
+                continue;
+            };
+            light.animated = true;
+            if let Some(position) = track.position.sample(time) {
+                light.position = position;
+            }
+            if let Some(intensity) = track.intensity.sample(time) {
+                light.intensity = intensity.max(0.0);
+            }
+            if let Some(color) = track.color.sample(time) {
+                let channel = |value: f32| value.clamp(0.0, 255.0) as u8;
+                light.color = Color::new(channel(color.x), channel(color.y), channel(color.z), light.color.a);
+            }
+        }
+    }
+}
+
+/// Runs a timeline's object tracks on the objects it was made for, always from their
+/// built state so evaluating a frame twice or jumping in time can't drift
+pub struct Animator {
+    timeline: Timeline,
+    rest: Vec<Vec<Vector3>>, // Built center of each animated object, per object track
+    entries: Vec<Vec<u32>>,  // Material table entries a track's color and glow keys write, per object track
+}
+
+impl Animator {
+    /// Blocks whose look is keyframed get their own copies of their materials, so the keys
+    /// don't repaint every other block sharing the preset
+    pub fn new(timeline: Timeline, objects: &mut [SceneObject], materials: &mut MaterialTable) -> Self {
+        let rest = timeline
+            .objects
+            .iter()
+            .map(|track| {
+                let center = |&index: &usize| objects.get(index).map_or(Vector3::zero(), |object| object.center());
+                track.objects.iter().map(center).collect()
+            })
+            .collect();
+        let entries = timeline
+            .objects
+            .iter()
+            .map(|track| {
+                let mut copies: HashMap<u32, u32> = HashMap::new();
+                if !track.color.is_empty() || !track.emission.is_empty() {
+                    for &index in &track.objects {
+                        let Some(object) = objects.get_mut(index).and_then(|object| object.as_cube_mut()) else {
+                            continue;
+                        };
+                        let material = materials[object.material];
+                        object.material = *copies.entry(object.material).or_insert_with(|| materials.add(material));
+                    }
+                }
+                copies.into_values().collect()
+            })
+            .collect();
+        Animator { timeline, rest, entries }
+    }
+
+    /// Whether a track moves or recolors object `index`
+    pub fn animates(&self, index: usize) -> bool {
+        self.timeline.objects.iter().any(|track| track.objects.contains(&index))
+    }
+
+    /// Follows `objects.swap_remove` of an object no track animates: tracks on the last
+    /// object, `from`, now point at the index it took, `to`
+    pub fn renumber(&mut self, from: usize, to: usize) {
+        for track in &mut self.timeline.objects {
+            for object in &mut track.objects {
+                if *object == from {
+                    *object = to;
+                }
+            }
+        }
+    }
+
+    /// Poses the objects for `time`, returns the indices of the ones a track places
+    pub fn apply(&self, objects: &mut [SceneObject], materials: &mut MaterialTable, time: f32) -> Vec<usize> {
+        let mut moved = Vec::new();
+        for ((track, rest), entries) in self.timeline.objects.iter().zip(&self.rest).zip(&self.entries) {
+            let time = match track.period {
+                Some(_) => looped(time, track.period),
+                None => self.timeline.local_time(time),
+            };
+            if let Some(offset) = track.offset.sample(time) {
+                // Only objects that actually moved, so a track holding still (a paused
+                // elevator) doesn't make the acceleration structure follow them every frame
+                for (&index, &center) in track.objects.iter().zip(rest) {
+                    if let Some(object) = objects.get_mut(index).and_then(|object| object.as_cube_mut())
+                        && object.center != center + offset
+                    {
+                        object.center = center + offset;
+                        moved.push(index);
+                    }
+                }
+            }
+            let color = track.color.sample(time);
+            let emission = track.emission.sample(time);
+            for &entry in entries {
+                if let Some(color) = color {
+                    materials[entry].diffuse = color;
+                }
+                if let Some(emission) = emission {
+                    materials[entry].emission = emission;
+                }
+            }
+        }
+        moved
+    }
+}
+
+fn looped(time: f32, period: Option<f32>) -> f32 {
+    match period {
+        Some(period) if period > 0.0 => time.rem_euclid(period),
+        _ => time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // From 0 at time 1 to 10 at time 3
+    fn ramp(easing: Easing) -> Track<f32> {
+        let mut track = Track::default();
+        track.insert(1.0, 0.0, easing);
+        track.insert(3.0, 10.0, easing);
+        track
+    }
+
+    #[test]
+    fn holds_the_first_and_last_key_outside_the_keyed_range() {
+        let track = ramp(Easing::Linear);
+        assert_eq!(track.sample(-5.0), Some(0.0));
+        assert_eq!(track.sample(1.0), Some(0.0));
+        assert_eq!(track.sample(2.0), Some(5.0));
+        assert_eq!(track.sample(3.0), Some(10.0));
+        assert_eq!(track.sample(100.0), Some(10.0));
+        assert_eq!(Track::<f32>::default().sample(1.0), None);
+    }
+
+    #[test]
+    fn step_holds_until_the_next_key_and_smooth_eases_in_and_out() {
+        let step = ramp(Easing::Step);
+        assert_eq!(step.sample(2.9), Some(0.0));
+        assert_eq!(step.sample(3.0), Some(10.0));
+
+        let smooth = ramp(Easing::Smooth);
+        assert_eq!(smooth.sample(2.0), Some(5.0));
+        // Slower than linear near the keys: a quarter of the way in, 0.25² × (3 - 0.5)
+        assert_eq!(smooth.sample(1.5), Some(1.5625));
+        assert_eq!(smooth.sample(2.5), Some(8.4375));
+    }
+
+    #[test]
+    fn a_key_at_the_same_time_replaces_the_old_one() {
+        let mut track = ramp(Easing::Linear);
+        track.insert(3.0, 20.0, Easing::Linear);
+        assert_eq!(track.keys.len(), 2);
+        assert_eq!(track.sample(3.0), Some(20.0));
+        assert_eq!(track.sample(2.0), Some(10.0));
+    }
+
+    #[test]
+    fn ping_pong_waits_once_at_each_end_per_round_trip() {
+        let (bottom, top) = (Vector3::zero(), Vector3::new(0.0, 2.0, 0.0));
+        let (track, period) = Track::ping_pong(&[bottom, top], 1.0, 0.5);
+        // Up and down 2 units each way, plus one wait at the top and one at the bottom
+        assert_eq!(period, 5.0);
+        assert_eq!(track.sample(0.25), Some(bottom));
+        assert_eq!(track.sample(1.5), Some(Vector3::new(0.0, 1.0, 0.0)));
+        assert_eq!(track.sample(2.75), Some(top));
+        // The return ends at the bottom, where the wait at the start of the next trip begins
+        assert_eq!(track.sample(period), Some(bottom));
+        assert_eq!(track.sample(looped(period + 0.25, Some(period))), Some(bottom));
+        assert!(track.sample(4.0).unwrap().y > 0.0);
+    }
+}
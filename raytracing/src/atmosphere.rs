@@ -0,0 +1,48 @@
+use raylib::prelude::Vector3;
+
+// Sea-level scattering coefficients per meter for red, green and blue light. Rayleigh
+// scattering by air molecules grows with 1/wavelength^4, so blue is lost first; Mie
+// scattering by haze is the same for every color.
+const RAYLEIGH: Vector3 = Vector3 { x: 5.8e-6, y: 13.5e-6, z: 33.1e-6 };
+const MIE: f32 = 21e-6;
+// Mie particles also absorb a little, extinction is about 1.1x their scattering
+const MIE_EXTINCTION: f32 = 1.1;
+// Heights over which air and haze thin out by a factor e, in meters
+const RAYLEIGH_SCALE_HEIGHT: f32 = 8000.0;
+const MIE_SCALE_HEIGHT: f32 = 1200.0;
+// Light that reaches the sky dome scatters on average halfway through the path the sunlight takes
+const SKY_PATH_FRACTION: f32 = 0.5;
+
+/// Air mass towards the sun relative to straight up (Kasten & Young 1989): 1 at the
+/// zenith, about 38 at the horizon. `elevation` in radians, clamped at the horizon.
+pub fn air_mass(elevation: f32) -> f32 {
+    let degrees = elevation.to_degrees().max(0.0);
+    1.0 / (degrees.to_radians().sin() + 0.50572 * (degrees + 6.07995).powf(-1.6364))
+}
+
+/// Fraction of each color that gets through `air_mass` atmospheres, Rayleigh and Mie extinction
+pub fn transmittance(air_mass: f32) -> Vector3 {
+    let mie = MIE * MIE_EXTINCTION * MIE_SCALE_HEIGHT;
+    let depth = |rayleigh: f32| (-(rayleigh * RAYLEIGH_SCALE_HEIGHT + mie) * air_mass).exp();
+    Vector3::new(depth(RAYLEIGH.x), depth(RAYLEIGH.y), depth(RAYLEIGH.z))
+}
+
+/// Color of direct sunlight at `elevation`, relative to the noon sun and scaled so its
+/// brightest channel is 1: white overhead, orange and then red as the sun sets
+pub fn sun_color(elevation: f32) -> Vector3 {
+    normalized_hue(transmittance(air_mass(elevation)))
+}
+
+/// Tint of the sky dome at `elevation`: the same shift as the sun, over the shorter
+/// path the scattered light travels, so the sky warms more gently than the sun
+pub fn sky_color(elevation: f32) -> Vector3 {
+    normalized_hue(transmittance(air_mass(elevation) * SKY_PATH_FRACTION))
+}
+
+// Relative to the noon sun, brightest channel at 1
+fn normalized_hue(light: Vector3) -> Vector3 {
+    let zenith = transmittance(1.0);
+    let relative = Vector3::new(light.x / zenith.x, light.y / zenith.y, light.z / zenith.z);
+    let brightest = relative.x.max(relative.y).max(relative.z).max(1e-6);
+    relative / brightest
+}
@@ -0,0 +1,256 @@
+use crate::acceleration::Acceleration;
+use crate::camera::Camera;
+use crate::material::TextureOptions;
+use crate::material_library::MaterialLibrary;
+use crate::metadata::ShotMetadata;
+use crate::offline::{bloom_emission, size_after, trace_sample, value_after, write_image};
+use crate::pathtrace::PathLights;
+use crate::post::PostProcess;
+use crate::render_thread::BuiltScene;
+use crate::scenes::{GALLERY, find_scene, gallery_builder};
+use crate::settings::{RenderOptions, SavedView};
+use crate::sky::Sky;
+use crate::{RENDER_SEED, block_lights};
+use raylib::prelude::Vector3;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where bookmarks are kept unless `--bookmarks <file>` says otherwise
+pub const BOOKMARKS_FILE: &str = "bookmarks.txt";
+
+/// A named camera position in a gallery scene
+#[derive(Debug, Clone)]
+pub struct Bookmark {
+    pub name: String,
+    pub view: SavedView,
+}
+
+/// Saved camera bookmarks, one `[name]` section per bookmark with its `scene`, `eye`, `yaw`
+/// and `pitch`, the same `key = value` layout as `materials.txt`
+#[derive(Debug, Clone, Default)]
+pub struct Bookmarks {
+    pub entries: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    /// Bookmarks in `path`, none if the file doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => parse(&text).map_err(|err| format!("{}: {}", path.display(), err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Bookmarks::default()),
+            Err(err) => Err(format!("Could not read {}: {}", path.display(), err)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut text = String::from("# Camera bookmarks: Shift+B adds one, --render-bookmarks <dir> renders them all\n");
+        for bookmark in &self.entries {
+            let view = &bookmark.view;
+            text += &format!("\n[{}]\nscene = {}\n", bookmark.name, view.scene);
+            text += &format!("eye = {} {} {}\nyaw = {}\npitch = {}\n", view.eye.x, view.eye.y, view.eye.z, view.yaw, view.pitch);
+        }
+        fs::write(path, text)
+    }
+
+    /// Adds the camera's view under the next free `shot_N` name and returns the name
+    pub fn add(&mut self, scene: &str, camera: &Camera) -> String {
+        let number = (1..).find(|n| !self.entries.iter().any(|bookmark| bookmark.name == format!("shot_{}", n))).unwrap_or(1);
+        let name = format!("shot_{}", number);
+        self.entries.push(Bookmark { name: name.clone(), view: SavedView::new(scene, camera) });
+        name
+    }
+}
+
+fn parse(text: &str) -> Result<Bookmarks, String> {
+    let mut bookmarks = Bookmarks::default();
+    for (index, raw_line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            let view = SavedView { scene: String::new(), eye: Vector3::zero(), yaw: 0.0, pitch: 0.0 };
+            bookmarks.entries.push(Bookmark { name: name.trim().to_string(), view });
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected `key = value`", line_number));
+        };
+        let Some(bookmark) = bookmarks.entries.last_mut() else {
+            return Err(format!("line {}: `{}` outside of a [bookmark] section", line_number, key.trim()));
+        };
+        let value = value.trim();
+        let numbers: Vec<f32> = value.split_whitespace().filter_map(|part| part.parse().ok()).collect();
+        let view = &mut bookmark.view;
+        match (key.trim(), numbers.as_slice()) {
+            ("scene", _) => view.scene = value.to_string(),
+            ("eye", &[x, y, z]) => view.eye = Vector3::new(x, y, z),
+            ("yaw", &[yaw]) => view.yaw = yaw,
+            ("pitch", &[pitch]) => view.pitch = pitch,
+            (key, _) => return Err(format!("line {}: bad or unknown value for `{}`", line_number, key)),
+        }
+    }
+    Ok(bookmarks)
+}
+
+/// Renders every bookmark at offline quality:
+/// `--render-bookmarks <dir> [--bookmarks file] [--size WxH] [--samples N]`
+/// `[--sharpen 0-1] [--aberration 0-1] [--vignette 0-1] [--grain 0-1] [--lut <file.cube>]`
+pub struct BookmarkJob {
+    pub bookmarks: PathBuf,
+    pub directory: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub options: RenderOptions,
+    pub post: PostProcess,
+}
+
+impl BookmarkJob {
+    /// None without `--render-bookmarks`, an error when a flag is missing its value or
+    /// `--samples` isn't a positive whole number
+    pub fn from_args() -> Option<Result<Self, String>> {
+        let args: Vec<String> = std::env::args().collect();
+        args.iter().any(|arg| arg == "--render-bookmarks").then(|| Self::parse(&args))
+    }
+
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let path = |flag: &str| match value_after(args, flag) {
+            Some(value) if !value.starts_with("--") => Ok(Some(PathBuf::from(value))),
+            _ if args.iter().any(|arg| arg == flag) => Err(format!("{} needs a path", flag)),
+            _ => Ok(None),
+        };
+        let directory = path("--render-bookmarks")?.ok_or("--render-bookmarks needs a path")?;
+        let samples = match value_after(args, "--samples") {
+            Some(value) => value.parse::<u32>().ok().filter(|&samples| samples > 0).ok_or_else(|| format!("--samples {} is not a positive whole number", value))?,
+            None => 16,
+        };
+
+        let (width, height) = size_after(args);
+
+        Ok(BookmarkJob {
+            bookmarks: path("--bookmarks")?.unwrap_or_else(|| PathBuf::from(BOOKMARKS_FILE)),
+            directory,
+            width,
+            height,
+            samples,
+            options: RenderOptions {
+                path_traced: args.iter().any(|arg| arg == "--path-trace"),
+                ..RenderOptions::default()
+            }
+            .with_quality_args(args),
+            post: PostProcess::from_args(args),
+        })
+    }
+}
+
+/// Writes `<name>.png` and its render metadata per bookmark. Every shot is rendered from a
+/// freshly built scene at simulation time 0, so the set stays comparable between runs.
+pub fn run_bookmarks(job: &BookmarkJob) -> Result<(), String> {
+    let bookmarks = Bookmarks::load(&job.bookmarks)?;
+    if bookmarks.entries.is_empty() {
+        return Err(format!("no bookmarks in {}", job.bookmarks.display()));
+    }
+    fs::create_dir_all(&job.directory).map_err(|err| format!("Could not create {}: {}", job.directory.display(), err))?;
+
+    let args: Vec<String> = std::env::args().collect();
+    let library = MaterialLibrary::load(TextureOptions::from_args(&args));
+    let sky = Sky::from_args(&args);
+    for (number, bookmark) in bookmarks.entries.iter().enumerate() {
+        let Some(index) = find_scene(&bookmark.view.scene) else {
+            println!("Skipping {}: `{}` is not a gallery scene", bookmark.name, bookmark.view.scene);
+            continue;
+        };
+        let entry = &GALLERY[index];
+        let BuiltScene { objects, materials, .. } = gallery_builder(index)(&library);
+        let accel = Acceleration::build(&objects);
+        let mut lights = vec![(entry.light)()];
+        lights.extend((entry.lamps)());
+        if !job.options.path_traced {
+            lights.extend(block_lights(&objects, &materials));
+        }
+        let mut camera = (entry.camera)();
+        bookmark.view.apply(&mut camera);
+
+        let path_lights = PathLights::new(&objects, &materials, &lights);
+        let mut colors = Vec::with_capacity(job.width as usize * job.height as usize);
+        for y in 0..job.height {
+            for x in 0..job.width {
+                let mut sum = Vector3::zero();
+                for sample in 0..job.samples {
+                    sum = sum + trace_sample(&objects, &materials, Some(&accel), &camera, &lights, &path_lights, &sky, &job.options, x, y, job.width, job.height, sample);
+                }
+                colors.push(sum / job.samples as f32);
+            }
+        }
+
+        println!("Bookmark {}/{}: {}", number + 1, bookmarks.entries.len(), bookmark.name);
+        let path = job.directory.join(format!("{}.png", bookmark.name));
+        let emission = bloom_emission(&job.post, &objects, &materials, &accel, &camera, job.width, job.height);
+        write_image(&path, job.width, job.height, &colors, emission, &job.post, 0, None);
+        let metadata = ShotMetadata {
+            scene: entry.name,
+            scene_files: library.source.iter().map(PathBuf::from).collect(),
+            camera: &camera,
+            lights: &lights,
+            options: &job.options,
+            width: job.width,
+            height: job.height,
+            seed: RENDER_SEED,
+            frame_index: 0,
+        };
+        if let Err(err) = metadata.write(&path) {
+            println!("Could not save metadata for {}: {}", bookmark.name, err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn reads_bookmark_sections() {
+        let text = "# Camera bookmarks\n\n[entrance]\nscene = Cave diorama\neye = 0 4 -12 # front\nyaw = 1.5\npitch = -0.2\n\n[top]\nscene = Terrain\n";
+        let bookmarks = parse(text).unwrap();
+        assert_eq!(bookmarks.entries.len(), 2);
+        let view = &bookmarks.entries[0].view;
+        assert_eq!((bookmarks.entries[0].name.as_str(), view.scene.as_str()), ("entrance", "Cave diorama"));
+        assert_eq!((view.eye, view.yaw, view.pitch), (Vector3::new(0.0, 4.0, -12.0), 1.5, -0.2));
+        assert_eq!(bookmarks.entries[1].view.scene, "Terrain");
+    }
+
+    #[test]
+    fn refuses_values_outside_a_section_or_of_the_wrong_shape() {
+        assert_eq!(parse("yaw = 1\n").unwrap_err(), "line 1: `yaw` outside of a [bookmark] section");
+        assert_eq!(parse("[a]\neye = 1 2\n").unwrap_err(), "line 2: bad or unknown value for `eye`");
+        assert_eq!(parse("[a]\nroll\n").unwrap_err(), "line 2: expected `key = value`");
+    }
+
+    #[test]
+    fn names_new_bookmarks_after_the_first_free_number() {
+        let camera = Camera::new(Vector3::zero(), Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 1.0, 0.0));
+        let mut bookmarks = Bookmarks::default();
+        assert_eq!(bookmarks.add("Terrain", &camera), "shot_1");
+        assert_eq!(bookmarks.add("Terrain", &camera), "shot_2");
+        bookmarks.entries.remove(0);
+        assert_eq!(bookmarks.add("Terrain", &camera), "shot_1");
+    }
+
+    #[test]
+    fn validates_the_render_arguments() {
+        let job = BookmarkJob::parse(&args("viewer --render-bookmarks shots --samples 4")).unwrap();
+        assert_eq!((job.directory, job.bookmarks, job.samples), (PathBuf::from("shots"), PathBuf::from(BOOKMARKS_FILE), 4));
+        assert_eq!(BookmarkJob::parse(&args("viewer --render-bookmarks")).err().unwrap(), "--render-bookmarks needs a path");
+        assert_eq!(BookmarkJob::parse(&args("viewer --render-bookmarks --samples 4")).err().unwrap(), "--render-bookmarks needs a path");
+        assert_eq!(BookmarkJob::parse(&args("viewer --render-bookmarks shots --samples 0")).err().unwrap(), "--samples 0 is not a positive whole number");
+        assert_eq!(BookmarkJob::parse(&args("viewer --render-bookmarks shots --bookmarks")).err().unwrap(), "--bookmarks needs a path");
+    }
+}
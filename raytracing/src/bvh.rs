@@ -0,0 +1,273 @@
+use crate::ray_intersect::SceneObject;
+use raylib::prelude::Vector3;
+
+// Objects per leaf; a few more box tests are cheaper than another level of nodes
+const LEAF_SIZE: usize = 4;
+// Node boxes are padded so a hit right on an object's face never falls outside its node
+const PADDING: f32 = 1e-4;
+// Deepest traversal stack; median splits keep the tree at about log2(objects / LEAF_SIZE)
+const MAX_DEPTH: usize = 64;
+// Refits may loosen the boxes to this many times their summed surface area at build time
+// before the tree is worth building again
+const REFIT_LIMIT: f32 = 2.0;
+
+// A box and what it holds: `count` objects from `first` in `order` for a leaf, or the two
+// children at `first` and `first + 1` for an inner node (`count` 0)
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    min: Vector3,
+    max: Vector3,
+    first: u32,
+    count: u32,
+}
+
+/// Bounding volume hierarchy over the objects' boxes, so a ray only tests the few objects
+/// along its path instead of the whole scene. Built from where the objects are; after they
+/// move, `refit` stretches the boxes over their new places without rebuilding the tree.
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    order: Vec<u32>,  // Object indices, grouped by leaf
+    built_area: f32, // Summed surface area of the node boxes as built
+}
+
+impl Bvh {
+    /// Splits the objects at the median of their centers along the widest axis until
+    /// each leaf holds at most LEAF_SIZE
+    pub fn build(objects: &[SceneObject]) -> Self {
+        let boxes: Vec<(Vector3, Vector3)> = objects.iter().map(|object| object.bounds()).collect();
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * boxes.len() / LEAF_SIZE + 1),
+            order: (0..boxes.len() as u32).collect(),
+            built_area: 0.0,
+        };
+        if !boxes.is_empty() {
+            bvh.nodes.push(Node { min: Vector3::zero(), max: Vector3::zero(), first: 0, count: 0 });
+            bvh.split(0, 0, boxes.len(), &boxes);
+        }
+        bvh.built_area = bvh.area();
+        bvh
+    }
+
+    /// Recomputes every node box around the objects where they are now, keeping the tree
+    /// as it was split. Far cheaper than `build` for objects that moved a little (the
+    /// elevator, falling blocks), but the boxes loosen as objects wander from where they
+    /// were grouped: false once they have grown past REFIT_LIMIT and the tree should be
+    /// built again. The objects must be the ones the tree was built over, in the same order.
+    pub fn refit(&mut self, objects: &[SceneObject]) -> bool {
+        let padding = Vector3::new(PADDING, PADDING, PADDING);
+        // Children always come after their parent, so walking backwards fits them first
+        for index in (0..self.nodes.len()).rev() {
+            let node = self.nodes[index];
+            let (min, max) = if node.count > 0 {
+                let members = &self.order[node.first as usize..(node.first + node.count) as usize];
+                let (min, max) = enclose(members.iter().map(|&object| objects[object as usize].bounds()));
+                (min - padding, max + padding)
+            } else {
+                let (left, right) = (self.nodes[node.first as usize], self.nodes[node.first as usize + 1]);
+                enclose([(left.min, left.max), (right.min, right.max)].into_iter())
+            };
+            self.nodes[index] = Node { min, max, ..node };
+        }
+        self.area() <= self.built_area * REFIT_LIMIT
+    }
+
+    // Summed surface area of the node boxes, how much a ray tends to visit
+    fn area(&self) -> f32 {
+        self.nodes
+            .iter()
+            .map(|node| {
+                let extent = node.max - node.min;
+                2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+            })
+            .sum()
+    }
+
+    fn split(&mut self, node: usize, first: usize, count: usize, boxes: &[(Vector3, Vector3)]) {
+        let members = &mut self.order[first..first + count];
+        let (min, max) = enclose(members.iter().map(|&index| boxes[index as usize]));
+        let padding = Vector3::new(PADDING, PADDING, PADDING);
+        let (min, max) = (min - padding, max + padding);
+        if count <= LEAF_SIZE {
+            self.nodes[node] = Node { min, max, first: first as u32, count: count as u32 };
+            return;
+        }
+
+        let center = |index: u32| {
+            let (min, max) = boxes[index as usize];
+            (min + max) * 0.5
+        };
+        let (low, high) = enclose(members.iter().map(|&index| (center(index), center(index))));
+        let extent = high - low;
+        let axis = |v: Vector3| if extent.x >= extent.y && extent.x >= extent.z { v.x } else if extent.y >= extent.z { v.y } else { v.z };
+        let half = count / 2;
+        members.select_nth_unstable_by(half, |&a, &b| axis(center(a)).total_cmp(&axis(center(b))));
+
+        let left = self.nodes.len();
+        let placeholder = Node { min, max, first: 0, count: 0 };
+        self.nodes.extend([placeholder, placeholder]);
+        self.nodes[node] = Node { min, max, first: left as u32, count: 0 };
+        self.split(left, first, half, boxes);
+        self.split(left + 1, first + half, count - half, boxes);
+    }
+
+    /// Walks the nodes the ray enters nearer than `limit`, nearest first, calling `visit`
+    /// with the index of every object in them. `visit` returns the new limit (the closest
+    /// hit so far, so farther nodes are skipped) or None to stop the walk.
+    pub fn traverse(&self, origin: Vector3, direction: Vector3, mut limit: f32, mut visit: impl FnMut(usize) -> Option<f32>) {
+        let Some(root) = self.nodes.first() else {
+            return;
+        };
+        let inverse = Vector3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+        let Some(entry) = root.entry(origin, inverse) else {
+            return;
+        };
+        let mut stack = [(0u32, 0.0f32); MAX_DEPTH];
+        stack[0] = (0, entry);
+        let mut top = 1;
+        while top > 0 {
+            top -= 1;
+            let (index, entry) = stack[top];
+            if entry > limit {
+                continue;
+            }
+            let node = self.nodes[index as usize];
+            if node.count > 0 {
+                for &object in &self.order[node.first as usize..(node.first + node.count) as usize] {
+                    match visit(object as usize) {
+                        Some(closest) => limit = limit.min(closest),
+                        None => return,
+                    }
+                }
+                continue;
+            }
+            // The far child goes on the stack first so the near one is walked first
+            let near = self.nodes[node.first as usize].entry(origin, inverse).map(|entry| (node.first, entry));
+            let far = self.nodes[node.first as usize + 1].entry(origin, inverse).map(|entry| (node.first + 1, entry));
+            let (near, far) = match (near, far) {
+                (Some(a), Some(b)) if b.1 < a.1 => (Some(b), Some(a)),
+                pair => pair,
+            };
+            for child in [far, near].into_iter().flatten() {
+                if top < MAX_DEPTH {
+                    stack[top] = child;
+                    top += 1;
+                }
+            }
+        }
+    }
+}
+
+impl Node {
+    // Distance along the ray to where it enters the box (0 from inside), None if it misses
+    fn entry(&self, origin: Vector3, inverse: Vector3) -> Option<f32> {
+        let slab = |min: f32, max: f32, origin: f32, inverse: f32| {
+            let (a, b) = ((min - origin) * inverse, (max - origin) * inverse);
+            (a.min(b), a.max(b))
+        };
+        let (near_x, far_x) = slab(self.min.x, self.max.x, origin.x, inverse.x);
+        let (near_y, far_y) = slab(self.min.y, self.max.y, origin.y, inverse.y);
+        let (near_z, far_z) = slab(self.min.z, self.max.z, origin.z, inverse.z);
+        let near = near_x.max(near_y).max(near_z).max(0.0);
+        let far = far_x.min(far_y).min(far_z);
+        (near <= far).then_some(near)
+    }
+}
+
+// Box around all the given boxes
+fn enclose(boxes: impl Iterator<Item = (Vector3, Vector3)>) -> (Vector3, Vector3) {
+    let infinity = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    boxes.fold((infinity, -infinity), |(min, max), (low, high)| {
+        (
+            Vector3::new(min.x.min(low.x), min.y.min(low.y), min.z.min(low.z)),
+            Vector3::new(max.x.max(high.x), max.y.max(high.y), max.z.max(high.z)),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cube::Cube;
+    use crate::ray_intersect::scene_objects;
+
+    // A 4×4×4 pile of unit blocks, x fastest, its corner at the origin
+    fn pile() -> Vec<SceneObject> {
+        let center = |index: usize| Vector3::new((index % 4) as f32, (index / 16) as f32, (index / 4 % 4) as f32);
+        scene_objects((0..64).map(|index| Cube::new(center(index), 1.0, 0)).collect())
+    }
+
+    fn move_to(objects: &mut [SceneObject], index: usize, center: Vector3) {
+        objects[index].as_cube_mut().unwrap().center = center;
+    }
+
+    // Index of the closest object along the ray, through the tree
+    fn closest(bvh: &Bvh, objects: &[SceneObject], origin: Vector3, direction: Vector3) -> Option<usize> {
+        let mut best = None;
+        bvh.traverse(origin, direction, f32::INFINITY, |index| {
+            if let Some(distance) = objects[index].hit_distance(&origin, &direction)
+                && best.is_none_or(|(_, closest)| distance < closest)
+            {
+                best = Some((index, distance));
+            }
+            Some(best.map_or(f32::INFINITY, |(_, closest)| closest))
+        });
+        best.map(|(index, _)| index)
+    }
+
+    // Random rays through and around the pile find the same closest hit through the tree
+    // as by testing every object
+    fn assert_matches_brute_force(bvh: &Bvh, objects: &[SceneObject]) {
+        let mut rng = crate::rng::Rng::new(7, 0);
+        for _ in 0..2000 {
+            let origin = Vector3::new(rng.range(-6.0, 9.0), rng.range(-3.0, 8.0), rng.range(-6.0, 9.0));
+            let direction = Vector3::new(rng.range(-1.0, 1.0), rng.range(-1.0, 1.0), rng.range(-1.0, 1.0)).normalized();
+            let through_tree = closest(bvh, objects, origin, direction).and_then(|index| objects[index].hit_distance(&origin, &direction));
+            let brute_force = objects.iter().filter_map(|object| object.hit_distance(&origin, &direction)).min_by(f32::total_cmp);
+            assert_eq!(through_tree, brute_force, "ray from {:?} along {:?}", origin, direction);
+        }
+    }
+
+    #[test]
+    fn traversal_finds_the_same_closest_hit_as_brute_force() {
+        let mut objects = pile();
+        // A few blocks scattered off the pile, so some rays only hit loose ones
+        move_to(&mut objects, 63, Vector3::new(6.0, 1.0, -2.0));
+        move_to(&mut objects, 21, Vector3::new(-3.0, 5.0, 2.0));
+        assert_matches_brute_force(&Bvh::build(&objects), &objects);
+
+        // And after a refit
+        let mut objects = pile();
+        let mut bvh = Bvh::build(&objects);
+        move_to(&mut objects, 63, Vector3::new(3.0, 4.5, 3.0));
+        move_to(&mut objects, 48, Vector3::new(-1.5, 3.0, 0.0));
+        assert!(bvh.refit(&objects));
+        assert_matches_brute_force(&bvh, &objects);
+    }
+
+    #[test]
+    fn refit_follows_moved_objects() {
+        let mut objects = pile();
+        let mut bvh = Bvh::build(&objects);
+        // One block of the top layer lifted, another slid off the pile
+        move_to(&mut objects, 63, Vector3::new(3.0, 4.5, 3.0));
+        move_to(&mut objects, 48, Vector3::new(-1.5, 3.0, 0.0));
+        assert!(bvh.refit(&objects));
+
+        let down = Vector3::new(0.0, -1.0, 0.0);
+        assert_eq!(closest(&bvh, &objects, Vector3::new(3.0, 10.0, 3.0), down), Some(63));
+        assert_eq!(closest(&bvh, &objects, Vector3::new(-1.5, 10.0, 0.0), down), Some(48));
+        // Where block 48 used to be, the ray now lands on the layer below it
+        assert_eq!(closest(&bvh, &objects, Vector3::new(0.0, 10.0, 0.0), down), Some(32));
+    }
+
+    #[test]
+    fn refit_asks_for_a_rebuild_once_the_boxes_loosen() {
+        let mut objects = pile();
+        let mut bvh = Bvh::build(&objects);
+        for index in (0..objects.len()).step_by(2) {
+            move_to(&mut objects, index, Vector3::new(index as f32, 100.0, 0.0));
+        }
+        assert!(!bvh.refit(&objects));
+    }
+}
@@ -1,6 +1,7 @@
 use raylib::prelude::*;
 
 /// A 3D camera for diorama navigation
+#[derive(Clone)]
 pub struct Camera {
     pub eye: Vector3,     // Camera position in world coordinates
     pub center: Vector3,  // Point the camera is looking at
@@ -86,4 +87,14 @@ impl Camera {
             v.x * self.right.z + v.y * self.up.z - v.z * self.forward.z,
         )
     }
-}
\ No newline at end of file
+
+    /// Blends between a previous and current camera state for rendering between simulation steps
+    pub fn interpolate(previous: &Camera, current: &Camera, alpha: f32) -> Camera {
+        let mut camera = current.clone();
+        camera.eye = previous.eye + (current.eye - previous.eye) * alpha;
+        camera.yaw = previous.yaw + (current.yaw - previous.yaw) * alpha;
+        camera.pitch = previous.pitch + (current.pitch - previous.pitch) * alpha;
+        camera.update_basis_vectors();
+        camera
+    }
+}
@@ -0,0 +1,41 @@
+use raylib::prelude::Vector3;
+use std::f32::consts::TAU;
+
+// Size of the pattern's cells in world units, and how many times a second it shifts
+const CELL_SIZE: f32 = 0.7;
+const DRIFT_SPEED: f32 = 0.35;
+
+// Width of the bright lines (higher is thinner) and the light left between them
+const SHARPNESS: i32 = 8;
+const BETWEEN_LINES: f32 = 0.5;
+// Average of the raw line pattern, so the lines get back what the gaps lose
+const LINE_COVERAGE: f32 = 0.19;
+
+// Ripple strength at which the pattern reaches full contrast
+const FULL_CONTRAST_RIPPLE: f32 = 0.15;
+
+/// Cheap stand-in for photon-mapped caustics: the web of bright lines light forms after
+/// passing through rippling water, projected along the light direction onto the surface
+/// receiving it. `ripple` is the strongest ripple the light went through; flat glass
+/// gives an even 1. Averages about 1, so it moves light around rather than adding it.
+pub fn caustic_intensity(point: Vector3, light_dir: Vector3, ripple: f32, time: f32) -> f32 {
+    let contrast = (ripple / FULL_CONTRAST_RIPPLE).clamp(0.0, 1.0);
+    if contrast <= 0.0 {
+        return 1.0;
+    }
+
+    // Coordinates on a plane facing the light, so the pattern slants across walls
+    let up = if light_dir.y.abs() < 0.9 { Vector3::new(0.0, 1.0, 0.0) } else { Vector3::new(1.0, 0.0, 0.0) };
+    let tangent = up.cross(light_dir).normalized();
+    let bitangent = light_dir.cross(tangent);
+    let (u, v) = (point.dot(tangent) / CELL_SIZE, point.dot(bitangent) / CELL_SIZE);
+    let t = time * DRIFT_SPEED * TAU;
+
+    // Three wobbling wave trains; where their sum crosses zero the light bunches up
+    let waves = ((u + 0.3 * (v * 1.7 + t).sin()) * TAU + t).sin()
+        + ((v + 0.3 * (u * 1.3 - t).sin()) * TAU - 0.8 * t).sin()
+        + ((u - v) * 0.7 * TAU + 1.3 * t).sin();
+    let lines = (1.0 - (waves / 3.0).abs()).powi(SHARPNESS);
+    let pattern = BETWEEN_LINES + lines * (1.0 - BETWEEN_LINES) / LINE_COVERAGE;
+    1.0 + (pattern - 1.0) * contrast
+}
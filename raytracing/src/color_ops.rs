@@ -1,7 +1,15 @@
-use raylib::prelude::Color;
+use raylib::prelude::{Color, Vector3};
+
+/// Relative luminance of a linear color, Rec. 709 weights
+pub fn luminance(color: Vector3) -> f32 {
+    color.x * 0.2126 + color.y * 0.7152 + color.z * 0.0722
+}
 
 pub trait ColorOps {
     fn mult_f32(&self, factor: f32) -> Color;
+
+    /// `luminance` of the color read as 0-1 per channel
+    fn luminance(&self) -> f32;
 }
 
 impl ColorOps for Color {
@@ -13,4 +21,8 @@ impl ColorOps for Color {
             self.a,
         )
     }
+
+    fn luminance(&self) -> f32 {
+        luminance(Vector3::new(self.r as f32, self.g as f32, self.b as f32)) / 255.0
+    }
 }
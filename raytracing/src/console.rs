@@ -0,0 +1,100 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// Objects a console command acts on; `Aimed` is resolved against the camera when sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsoleTarget {
+    Aimed,
+    All,
+    Block(String), // A material preset name
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsoleCommand {
+    Override { target: ConsoleTarget, material: String },
+    Reset(ConsoleTarget),
+    Help,
+}
+
+pub const CONSOLE_HELP: &str = "\
+Console commands (type in the terminal, then Enter):
+  override <aimed|all|preset> <preset|mirror|chrome|glass|matte>   draw objects with another material
+  reset [aimed|all|preset]                                         undo overrides (all by default)
+  help                                                             this list";
+
+/// Commands typed into the terminal while the viewer runs. Lines are read on their own
+/// thread so the frame loop never waits on stdin.
+pub struct Console {
+    lines: Receiver<String>,
+}
+
+impl Console {
+    pub fn spawn() -> Self {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Console { lines }
+    }
+
+    /// Commands typed since the last call; lines that don't parse are reported and dropped
+    pub fn poll(&self) -> Vec<ConsoleCommand> {
+        self.lines
+            .try_iter()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| parse(&line).map_err(|err| println!("{} (type `help`)", err)).ok())
+            .collect()
+    }
+}
+
+fn parse(line: &str) -> Result<ConsoleCommand, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["override", target, material] => Ok(ConsoleCommand::Override { target: target_of(target), material: material.to_string() }),
+        ["override", ..] => Err("Usage: override <aimed|all|preset> <material>".to_string()),
+        ["reset"] => Ok(ConsoleCommand::Reset(ConsoleTarget::All)),
+        ["reset", target] => Ok(ConsoleCommand::Reset(target_of(target))),
+        ["help"] => Ok(ConsoleCommand::Help),
+        _ => Err(format!("Unknown command `{}`", line.trim())),
+    }
+}
+
+fn target_of(word: &str) -> ConsoleTarget {
+    match word {
+        "aimed" => ConsoleTarget::Aimed,
+        "all" => ConsoleTarget::All,
+        name => ConsoleTarget::Block(name.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_overrides_and_resets() {
+        assert_eq!(
+            parse("override aimed mirror"),
+            Ok(ConsoleCommand::Override { target: ConsoleTarget::Aimed, material: "mirror".to_string() })
+        );
+        assert_eq!(
+            parse("  override  hierro glass "),
+            Ok(ConsoleCommand::Override { target: ConsoleTarget::Block("hierro".to_string()), material: "glass".to_string() })
+        );
+        assert_eq!(parse("reset"), Ok(ConsoleCommand::Reset(ConsoleTarget::All)));
+        assert_eq!(parse("reset aimed"), Ok(ConsoleCommand::Reset(ConsoleTarget::Aimed)));
+        assert_eq!(parse("help"), Ok(ConsoleCommand::Help));
+    }
+
+    #[test]
+    fn reports_incomplete_and_unknown_commands() {
+        assert!(parse("override all").unwrap_err().starts_with("Usage: override"));
+        assert_eq!(parse(" paint all "), Err("Unknown command `paint all`".to_string()));
+    }
+}
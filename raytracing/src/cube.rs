@@ -1,83 +1,251 @@
-use crate::material::Material;
+use crate::material::{Material, MaterialTable, Texture};
 use crate::ray_intersect::{Intersect, RayIntersect};
 use raylib::prelude::*;
 
+// Heightfield steps per parallax march, more = fewer stair-step artifacts at grazing angles
+const PARALLAX_LAYERS: usize = 12;
+
+// Texels of transparent blocks less opaque than this let rays through (holes in leaves)
+pub const ALPHA_CUTOFF: f32 = 0.5;
+
 #[derive(Clone)]
 pub struct Cube {
     pub center: Vector3,
     pub size: f32,
-    pub material: Material,
-    pub texture: Option<Image>,
+    pub material: u32, // Entry in the scene's `MaterialTable`
+    pub face_materials: Option<[u32; 6]>, // Per face (see `face_index`), replaces `material` when set
+    pub texture: Option<Texture>,
+    pub face_textures: Option<[Texture; 6]>, // Per face (see `face_index`), replaces `texture` when set
+    pub texture_rotation: u8, // Quarter turns applied to the UVs, breaks up tiling
+    pub emission_map: Option<Texture>, // Where on the face the material's emission shows
+    pub height_map: Option<Texture>, // Bright = raised, drives parallax mapping
+    pub parallax_depth: f32, // How deep the darkest texel sits below the face, 0 = flat
+    pub sky_visibility: [f32; 6], // Per face (see `face_index`), baked by `bake_sky_visibility`
+    pub id: u32,          // Stable per scene, assigned by `assign_ids`
+    pub material_id: u32, // Hash of the library preset name, 0 for materials from elsewhere
 }
 
 impl Cube {
-    pub fn new(center: Vector3, size: f32, material: Material) -> Self {
+    pub fn new(center: Vector3, size: f32, material: u32) -> Self {
         Self {
             center,
             size,
             material,
+            face_materials: None,
             texture: None,
+            face_textures: None,
+            texture_rotation: 0,
+            emission_map: None,
+            height_map: None,
+            parallax_depth: 0.0,
+            sky_visibility: [1.0; 6],
+            id: 0,
+            material_id: 0,
         }
     }
 
-    pub fn with_texture(center: Vector3, size: f32, material: Material, texture: Image) -> Self {
+    pub fn with_texture(center: Vector3, size: f32, material: u32, texture: Texture) -> Self {
         Self {
             center,
             size,
             material,
+            face_materials: None,
             texture: Some(texture),
+            face_textures: None,
+            texture_rotation: 0,
+            emission_map: None,
+            height_map: None,
+            parallax_depth: 0.0,
+            sky_visibility: [1.0; 6],
+            id: 0,
+            material_id: 0,
         }
     }
 
-    /// Proper UV calculation for each face
-    fn calculate_uv(&self, point: Vector3, normal: Vector3) -> (f32, f32) {
-        let local_point = point - self.center;
-        let half_size = self.size / 2.0;
-        
-        let (u, v) = if normal.x.abs() > 0.9 {
+    /// Index of a face from its outward normal: +X, -X, +Y, -Y, +Z, -Z
+    pub fn face_index(normal: Vector3) -> usize {
+        if normal.x.abs() > 0.9 {
+            if normal.x > 0.0 { 0 } else { 1 }
+        } else if normal.y.abs() > 0.9 {
+            if normal.y > 0.0 { 2 } else { 3 }
+        } else if normal.z > 0.0 {
+            4
+        } else {
+            5
+        }
+    }
+
+    /// Gives one face (see `face_index`) its own material, e.g. a glowing top on matte sides;
+    /// the other faces keep the cube's material as it is now
+    pub fn set_face_material(&mut self, face: usize, material: u32) {
+        let base = self.material;
+        self.face_materials.get_or_insert([base; 6])[face] = material;
+    }
+
+    /// Back to one material on every face
+    pub fn clear_face_materials(&mut self) {
+        self.face_materials = None;
+    }
+
+    /// Material table entry of the face with this outward normal
+    pub fn material_entry(&self, normal: Vector3) -> u32 {
+        match self.face_materials {
+            Some(ref faces) => faces[Self::face_index(normal)],
+            None => self.material,
+        }
+    }
+
+    /// Texture of the face with this outward normal, grass tops and log rings differ from
+    /// the sides
+    pub fn texture_for(&self, normal: Vector3) -> Option<&Texture> {
+        match self.face_textures {
+            Some(ref faces) => Some(&faces[Self::face_index(normal)]),
+            None => self.texture.as_ref(),
+        }
+    }
+
+    /// Emission summed over the six faces, zero for blocks that don't glow
+    pub fn total_emission(&self, materials: &MaterialTable) -> Vector3 {
+        match self.face_materials {
+            Some(ref faces) => faces.iter().fold(Vector3::zero(), |sum, &face| sum + materials[face].emission),
+            None => materials[self.material].emission * 6.0,
+        }
+    }
+
+    /// Shading step for a hit on this cube: parallax, texture and emission map lookups turn
+    /// the face's material into the one at the hit texel
+    pub fn surface(&self, intersect: &Intersect, ray_direction: &Vector3, materials: &MaterialTable) -> Material {
+        let (mut u, mut v) = intersect.uv;
+        if self.parallax_depth > 0.0 && self.height_map.is_some() {
+            (u, v) = self.parallax_uv(u, v, intersect.normal, ray_direction);
+        }
+        let (u, v) = self.rotate_uv(u, v);
+        let texture_color = self.sample_texture(intersect.normal, u, v);
+
+        let mut material = materials[self.material_entry(intersect.normal)];
+        material.diffuse = Vector3::new(
+            material.diffuse.x * texture_color.x,
+            material.diffuse.y * texture_color.y,
+            material.diffuse.z * texture_color.z,
+        );
+        if material.emission != Vector3::zero() {
+            let mask = self.sample_emission(u, v);
+            material.emission = Vector3::new(
+                material.emission.x * mask.x,
+                material.emission.y * mask.y,
+                material.emission.z * mask.z,
+            );
+        }
+        material
+    }
+
+    /// Distance to the box along a ray, without any texturing work
+    pub fn hit_distance(&self, ray_origin: &Vector3, ray_direction: &Vector3) -> Option<f32> {
+        self.ray_aabb_intersect(ray_origin, ray_direction).map(|(distance, _)| distance)
+    }
+
+    /// Directions in which u and v grow across a face, matching the texture layout
+    pub fn face_axes(normal: Vector3) -> (Vector3, Vector3) {
+        if normal.x.abs() > 0.9 {
             // X faces (left/right walls)
-            if normal.x > 0.0 {
-                ((-local_point.z + half_size) / self.size, (local_point.y + half_size) / self.size)
-            } else {
-                ((local_point.z + half_size) / self.size, (local_point.y + half_size) / self.size)
-            }
+            (Vector3::new(0.0, 0.0, -normal.x.signum()), Vector3::new(0.0, 1.0, 0.0))
         } else if normal.y.abs() > 0.9 {
             // Y faces (floor/ceiling)
-            if normal.y > 0.0 {
-                ((local_point.x + half_size) / self.size, (-local_point.z + half_size) / self.size)
-            } else {
-                ((local_point.x + half_size) / self.size, (local_point.z + half_size) / self.size)
-            }
+            (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -normal.y.signum()))
         } else {
             // Z faces (front/back walls)
-            if normal.z > 0.0 {
-                ((local_point.x + half_size) / self.size, (local_point.y + half_size) / self.size)
-            } else {
-                ((-local_point.x + half_size) / self.size, (local_point.y + half_size) / self.size)
-            }
-        };
-        
+            (Vector3::new(normal.z.signum(), 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0))
+        }
+    }
+
+    /// Proper UV calculation for each face
+    fn calculate_uv(&self, point: Vector3, normal: Vector3) -> (f32, f32) {
+        let local_point = point - self.center;
+        let half_size = self.size / 2.0;
+        let (tangent, bitangent) = Self::face_axes(normal);
+
+        let u = (local_point.dot(tangent) + half_size) / self.size;
+        let v = (local_point.dot(bitangent) + half_size) / self.size;
         (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0))
     }
 
+    /// Applies the block's quarter-turn texture rotation
+    fn rotate_uv(&self, u: f32, v: f32) -> (f32, f32) {
+        match self.texture_rotation % 4 {
+            1 => (v, 1.0 - u),
+            2 => (1.0 - u, 1.0 - v),
+            3 => (1.0 - v, u),
+            _ => (u, v),
+        }
+    }
+
+    /// Depth below the face at a texel, 0 = surface, 1 = deepest (dark texels)
+    fn surface_depth(&self, u: f32, v: f32) -> f32 {
+        let (u, v) = self.rotate_uv(u.clamp(0.0, 1.0), v.clamp(0.0, 1.0));
+        match self.height_map {
+            Some(ref map) => {
+                let height = map.sample(u, v);
+                1.0 - (height.x * 0.299 + height.y * 0.587 + height.z * 0.114)
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Parallax occlusion mapping: marches the view ray through the heightfield
+    /// under the face and returns the UV where it first dips below the surface
+    fn parallax_uv(&self, u: f32, v: f32, normal: Vector3, ray_direction: &Vector3) -> (f32, f32) {
+        let facing = ray_direction.dot(normal);
+        if facing > -1e-3 {
+            return (u, v);
+        }
+
+        let (tangent, bitangent) = Self::face_axes(normal);
+        let layer_depth = 1.0 / PARALLAX_LAYERS as f32;
+        let shift = self.parallax_depth / self.size * layer_depth / -facing;
+        let step_u = ray_direction.dot(tangent) * shift;
+        let step_v = ray_direction.dot(bitangent) * shift;
+
+        let (mut current_u, mut current_v) = (u, v);
+        let mut ray_depth = 0.0;
+        let mut surface = self.surface_depth(current_u, current_v);
+        while ray_depth < surface && ray_depth < 1.0 {
+            current_u += step_u;
+            current_v += step_v;
+            ray_depth += layer_depth;
+            surface = self.surface_depth(current_u, current_v);
+        }
+
+        // Interpolate between the last two layers for a smooth result
+        let after = surface - ray_depth;
+        let before = self.surface_depth(current_u - step_u, current_v - step_v) - (ray_depth - layer_depth);
+        let weight = if (after - before).abs() > 1e-6 { after / (after - before) } else { 0.0 };
+        let final_u = current_u - step_u * weight;
+        let final_v = current_v - step_v * weight;
+        (final_u.clamp(0.0, 1.0), final_v.clamp(0.0, 1.0))
+    }
+
     /// High quality texture sampling
-    fn sample_texture(&mut self, u: f32, v: f32) -> Vector3 {
-        if let Some(ref mut texture) = self.texture {
-            let u = u.clamp(0.0, 1.0);
-            let v = v.clamp(0.0, 1.0);
-            
-            let x = ((u * (texture.width - 1) as f32).round() as i32).clamp(0, texture.width - 1);
-            let y = ((v * (texture.height - 1) as f32).round() as i32).clamp(0, texture.height - 1);
-            
-            let color = texture.get_color(x, y);
-            
-            Vector3::new(
-                color.r as f32 / 255.0,
-                color.g as f32 / 255.0,
-                color.b as f32 / 255.0,
-            )
-        } else {
-            Vector3::new(1.0, 1.0, 1.0)
+    fn sample_texture(&self, normal: Vector3, u: f32, v: f32) -> Vector3 {
+        match self.texture_for(normal) {
+            Some(texture) => texture.sample(u, v),
+            None => Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Texture alpha where a ray hit the face, 1 for untextured blocks
+    pub fn alpha_at(&self, intersect: &Intersect) -> f32 {
+        let (u, v) = self.rotate_uv(intersect.uv.0, intersect.uv.1);
+        match self.texture_for(intersect.normal) {
+            Some(texture) => texture.texel_at(u, v).a as f32 / 255.0,
+            None => 1.0,
+        }
+    }
+
+    /// Emission mask at a texel, the whole face glows when there is no map
+    fn sample_emission(&self, u: f32, v: f32) -> Vector3 {
+        match self.emission_map {
+            Some(ref map) => map.sample(u, v),
+            None => Vector3::new(1.0, 1.0, 1.0),
         }
     }
 
@@ -142,23 +310,66 @@ impl Cube {
 }
 
 impl RayIntersect for Cube {
-    fn ray_intersect(&mut self, ray_origin: &Vector3, ray_direction: &Vector3) -> Intersect {
-        if let Some((distance, normal)) = self.ray_aabb_intersect(ray_origin, ray_direction) {
-            let point = *ray_origin + *ray_direction * distance;
-            
-            let (u, v) = self.calculate_uv(point, normal);
-            let texture_color = self.sample_texture(u, v);
-            
-            let mut textured_material = self.material;
-            textured_material.diffuse = Vector3::new(
-                textured_material.diffuse.x * texture_color.x,
-                textured_material.diffuse.y * texture_color.y,
-                textured_material.diffuse.z * texture_color.z,
-            );
-            
-            Intersect::new(point, normal, distance, textured_material)
-        } else {
-            Intersect::empty()
+    /// Geometry only: where the ray lands, on which face and at what UV. The material is
+    /// looked up through the object by `surface`, for the hits that get shaded.
+    fn ray_intersect(&self, ray_origin: &Vector3, ray_direction: &Vector3) -> Intersect {
+        let Some((distance, normal)) = self.ray_aabb_intersect(ray_origin, ray_direction) else {
+            return Intersect::empty();
+        };
+        let point = *ray_origin + *ray_direction * distance;
+        let face = Self::face_index(normal);
+
+        let mut intersect = Intersect::new(point, normal, distance);
+        intersect.uv = self.calculate_uv(point, normal);
+        intersect.face = face;
+        intersect.sky_visibility = self.sky_visibility[face];
+        intersect
+    }
+
+    fn hit_distance(&self, ray_origin: &Vector3, ray_direction: &Vector3) -> Option<f32> {
+        Cube::hit_distance(self, ray_origin, ray_direction)
+    }
+
+    fn surface(&self, intersect: &Intersect, ray_direction: &Vector3, materials: &MaterialTable) -> Material {
+        Cube::surface(self, intersect, ray_direction, materials)
+    }
+
+    fn material_for(&self, normal: Vector3, materials: &MaterialTable) -> Material {
+        materials[self.material_entry(normal)]
+    }
+
+    fn alpha_at(&self, intersect: &Intersect) -> f32 {
+        Cube::alpha_at(self, intersect)
+    }
+
+    // Only transparent textured blocks can have holes, the rest skip the full intersection
+    fn solid_hit_distance(&self, ray_origin: &Vector3, ray_direction: &Vector3, materials: &MaterialTable) -> Option<f32> {
+        let distance = self.hit_distance(ray_origin, ray_direction)?;
+        let transparent = match self.face_materials {
+            Some(ref faces) => faces.iter().any(|&face| materials[face].albedo[3] > 0.0),
+            None => materials[self.material].albedo[3] > 0.0,
+        };
+        if !transparent || (self.texture.is_none() && self.face_textures.is_none()) {
+            return Some(distance);
         }
+        let intersect = self.ray_intersect(ray_origin, ray_direction);
+        (!self.is_cut_out(&intersect, materials)).then_some(distance)
+    }
+
+    fn center(&self) -> Vector3 {
+        self.center
+    }
+
+    fn bounds(&self) -> (Vector3, Vector3) {
+        let half = Vector3::new(self.size, self.size, self.size) * 0.5;
+        (self.center - half, self.center + half)
     }
-}
\ No newline at end of file
+
+    fn as_cube(&self) -> Option<&Cube> {
+        Some(self)
+    }
+
+    fn as_cube_mut(&mut self) -> Option<&mut Cube> {
+        Some(self)
+    }
+}
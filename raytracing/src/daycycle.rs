@@ -0,0 +1,77 @@
+use crate::atmosphere::{sky_color, sun_color};
+use crate::light::{Light, LightKind};
+use crate::sky::Sky;
+use raylib::prelude::*;
+use std::f32::consts::PI;
+
+// Sun orbits around this point, which its parallel rays are aimed at
+const ORBIT_CENTER: Vector3 = Vector3 { x: 0.0, y: 5.0, z: 0.0 };
+const ORBIT_RADIUS: f32 = 15.0;
+const SUNLIGHT: LightKind = LightKind::Directional { target: ORBIT_CENTER };
+const SUNRISE_HOUR: f32 = 6.0;
+const SUNSET_HOUR: f32 = 18.0;
+
+/// Time of day in hours [0, 24), drives the sun/moon light and the sky tint
+#[derive(Debug, Clone, Copy)]
+pub struct DayCycle {
+    pub hour: f32,
+}
+
+impl DayCycle {
+    pub fn new(hour: f32) -> Self {
+        DayCycle {
+            hour: hour.rem_euclid(24.0),
+        }
+    }
+
+    /// Sun elevation in radians, negative while it is below the horizon
+    pub fn sun_elevation(&self) -> f32 {
+        self.sun_direction().y.asin()
+    }
+
+    fn sun_direction(&self) -> Vector3 {
+        let angle = (self.hour - SUNRISE_HOUR) / (SUNSET_HOUR - SUNRISE_HOUR) * PI;
+        Vector3::new(angle.cos(), angle.sin(), 0.3).normalized()
+    }
+
+    /// Sun during the day, a dim bluish moon from the opposite side at night
+    pub fn light(&self) -> Light {
+        let direction = self.sun_direction();
+        let elevation = self.sun_elevation();
+
+        if elevation > 0.0 {
+            // Through more air the lower it is: white at noon, orange and red near the horizon
+            let k = elevation.sin().sqrt();
+            let sun = sun_color(elevation) * 255.0;
+            let color = Color::new(sun.x.round() as u8, sun.y.round() as u8, sun.z.round() as u8, 255);
+            Light::new(ORBIT_CENTER + direction * ORBIT_RADIUS, color, 1.4 * k).with_kind(SUNLIGHT)
+        } else {
+            let moon_direction = -direction;
+            Light::new(
+                ORBIT_CENTER + moon_direction * ORBIT_RADIUS,
+                Color::new(150, 170, 255, 255),
+                0.2 * moon_direction.y.max(0.0).sqrt(),
+            )
+            .with_kind(SUNLIGHT)
+        }
+    }
+
+    /// Scattered sunlight by day, so the sky warms as the sun gets low; fades from the
+    /// sunset color to dark blue during twilight
+    pub fn sky(&self) -> Sky {
+        let elevation = self.sun_elevation();
+        let dusk = sky_color(0.0);
+        let night = Vector3::new(0.05, 0.05, 0.12);
+
+        let tint = if elevation > 0.0 {
+            sky_color(elevation)
+        } else if elevation > -0.2 {
+            let k = -elevation / 0.2;
+            dusk * (1.0 - k) + night * k
+        } else {
+            night
+        };
+
+        Sky { tint, ..Sky::default() }
+    }
+}
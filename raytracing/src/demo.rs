@@ -0,0 +1,290 @@
+// Demo scripts for unattended presentations, played with `--demo file.demo`:
+//
+//     0 scene cave-diorama
+//     0 cut 0 4 -10 1.57 -0.2      # eye x y z, yaw, pitch
+//     0 fly 6 5 3 -6 2.0 -0.1      # glide there over 6 seconds
+//     6 hour 17.5 4                # day cycle to 17:30 over 4 seconds (at once without)
+//     10 light off                 # also `light on`, `headlamp on|off`
+//     14 scene terrain
+//     20 loop                      # start over; without it the demo ends after its last event
+//
+// Each line is `<seconds> <event>`, seconds since the demo (or its last loop) started.
+
+use crate::camera::Camera;
+use crate::scenes::find_scene;
+use raylib::prelude::Vector3;
+use std::fs;
+use std::path::Path;
+
+/// Camera placement in a demo, the eye and the view angles
+#[derive(Debug, Clone, Copy)]
+pub struct DemoPose {
+    pub eye: Vector3,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl DemoPose {
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.eye;
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
+        camera.update_basis_vectors();
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DemoAction {
+    Scene(usize), // Gallery index
+    Cut(DemoPose),
+    Fly { seconds: f32, to: DemoPose },
+    Hour { hour: f32, seconds: f32 },
+    Light(bool),
+    Headlamp(bool),
+    Loop,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DemoEvent {
+    pub time: f32,
+    pub action: DemoAction,
+}
+
+/// A demo script, events in time order
+#[derive(Debug, Clone)]
+pub struct Demo {
+    pub events: Vec<DemoEvent>,
+}
+
+impl Demo {
+    /// Reads a demo script, errors carry the file name and line
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+}
+
+fn parse(text: &str) -> Result<Demo, String> {
+    let mut events = Vec::new();
+    for (index, raw_line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let numbers = |words: &[&str]| -> Result<Vec<f32>, String> {
+            words
+                .iter()
+                .map(|word| word.parse().map_err(|_| format!("line {}: `{}` is not a number", line_number, word)))
+                .collect()
+        };
+        let switch = |word: &str| match word {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(format!("line {}: expected on or off, got `{}`", line_number, word)),
+        };
+        let pose = |v: &[f32]| DemoPose { eye: Vector3::new(v[0], v[1], v[2]), yaw: v[3], pitch: v[4] };
+
+        let Some((time, event)) = words.split_first() else {
+            continue;
+        };
+        let time = numbers(&[*time])?[0];
+        let action = match event {
+            ["scene", name] => DemoAction::Scene(find_scene(name).ok_or_else(|| format!("line {}: unknown scene `{}`", line_number, name))?),
+            ["cut", rest @ ..] => match numbers(rest)?.as_slice() {
+                v @ [_, _, _, _, _] => DemoAction::Cut(pose(v)),
+                _ => return Err(format!("line {}: cut needs eye x y z, yaw and pitch", line_number)),
+            },
+            ["fly", rest @ ..] => match numbers(rest)?.as_slice() {
+                [seconds, v @ ..] if v.len() == 5 => DemoAction::Fly { seconds: seconds.max(0.0), to: pose(v) },
+                _ => return Err(format!("line {}: fly needs seconds, then eye x y z, yaw and pitch", line_number)),
+            },
+            ["hour", rest @ ..] => match numbers(rest)?[..] {
+                [hour] => DemoAction::Hour { hour, seconds: 0.0 },
+                [hour, seconds] => DemoAction::Hour { hour, seconds: seconds.max(0.0) },
+                _ => return Err(format!("line {}: hour needs the hour and optionally seconds", line_number)),
+            },
+            ["light", state] => DemoAction::Light(switch(state)?),
+            ["headlamp", state] => DemoAction::Headlamp(switch(state)?),
+            ["loop"] => DemoAction::Loop,
+            _ => return Err(format!("line {}: unknown event `{}`", line_number, event.join(" "))),
+        };
+        events.push(DemoEvent { time, action });
+    }
+    if events.is_empty() {
+        return Err("no events".to_string());
+    }
+    events.sort_by(|a, b| a.time.total_cmp(&b.time));
+    Ok(Demo { events })
+}
+
+/// What the viewer has to change for the demo; the camera is moved by the player itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DemoCue {
+    Scene(usize),
+    Hour(f32),
+    Light(bool),
+    Headlamp(bool),
+}
+
+// A camera glide in progress
+struct Flight {
+    from: Camera,
+    to: DemoPose,
+    started: f32,
+    seconds: f32,
+}
+
+// A time-of-day change in progress
+struct HourFade {
+    from: f32,
+    to: f32,
+    started: f32,
+    seconds: f32,
+}
+
+/// Plays a demo on the simulation clock in place of live input
+pub struct DemoPlayer {
+    demo: Demo,
+    next: usize,
+    clock: f32,
+    hour: Option<f32>,
+    flight: Option<Flight>,
+    fade: Option<HourFade>,
+}
+
+impl DemoPlayer {
+    pub fn new(demo: Demo) -> Self {
+        DemoPlayer { demo, next: 0, clock: 0.0, hour: None, flight: None, fade: None }
+    }
+
+    /// Advances one simulation step: fires the events that are due, moves the camera along
+    /// a glide and returns what else changed. Stops at a scene change, the events after it
+    /// fire on the next step once the new scene's camera is in place.
+    pub fn step(&mut self, dt: f32, camera: &mut Camera) -> Vec<DemoCue> {
+        self.clock += dt;
+        let mut cues = Vec::new();
+        while let Some(event) = self.demo.events.get(self.next).copied().filter(|event| event.time <= self.clock) {
+            self.next += 1;
+            match event.action {
+                DemoAction::Scene(index) => {
+                    self.flight = None;
+                    cues.push(DemoCue::Scene(index));
+                    return cues;
+                }
+                DemoAction::Cut(pose) => {
+                    self.flight = None;
+                    pose.apply(camera);
+                }
+                DemoAction::Fly { seconds, to } => {
+                    self.flight = Some(Flight { from: camera.clone(), to, started: event.time, seconds });
+                }
+                DemoAction::Hour { hour, seconds } => {
+                    let from = self.hour.unwrap_or(hour);
+                    self.fade = Some(HourFade { from, to: hour, started: event.time, seconds });
+                }
+                DemoAction::Light(on) => cues.push(DemoCue::Light(on)),
+                DemoAction::Headlamp(on) => cues.push(DemoCue::Headlamp(on)),
+                DemoAction::Loop => {
+                    self.next = 0;
+                    self.clock = 0.0;
+                    self.flight = None;
+                    self.fade = None;
+                    return cues;
+                }
+            }
+        }
+
+        if let Some(flight) = &self.flight {
+            let t = progress(self.clock, flight.started, flight.seconds);
+            let mut target = flight.from.clone();
+            flight.to.apply(&mut target);
+            *camera = Camera::interpolate(&flight.from, &target, t * t * (3.0 - 2.0 * t));
+            if t >= 1.0 {
+                self.flight = None;
+            }
+        }
+        if let Some(fade) = &self.fade {
+            let t = progress(self.clock, fade.started, fade.seconds);
+            let hour = fade.from + (fade.to - fade.from) * t;
+            self.hour = Some(hour);
+            cues.push(DemoCue::Hour(hour));
+            if t >= 1.0 {
+                self.fade = None;
+            }
+        }
+        cues
+    }
+
+    /// True once every event has fired and the last glide and fade are done
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.demo.events.len() && self.flight.is_none() && self.fade.is_none()
+    }
+}
+
+// How far along a transition that started at `started` and lasts `seconds` is, in [0, 1]
+fn progress(clock: f32, started: f32, seconds: f32) -> f32 {
+    if seconds <= 0.0 { 1.0 } else { ((clock - started) / seconds).clamp(0.0, 1.0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera::new(Vector3::new(9.0, 9.0, 9.0), Vector3::zero(), Vector3::new(0.0, 1.0, 0.0))
+    }
+
+    #[test]
+    fn reads_events_in_time_order() {
+        let demo = parse("14 scene terrain\n0 cut 0 4 -10 1.57 -0.2 # eye\n\n6 hour 17.5 4\n0 fly 6 5 3 -6 2 -0.1\n20 loop\n").unwrap();
+        let times: Vec<f32> = demo.events.iter().map(|event| event.time).collect();
+        assert_eq!(times, [0.0, 0.0, 6.0, 14.0, 20.0]);
+        assert!(matches!(demo.events[0].action, DemoAction::Cut(pose) if pose.eye == Vector3::new(0.0, 4.0, -10.0)));
+        assert!(matches!(demo.events[1].action, DemoAction::Fly { seconds: 6.0, to } if to.yaw == 2.0));
+        assert!(matches!(demo.events[2].action, DemoAction::Hour { hour: 17.5, seconds: 4.0 }));
+        assert!(matches!(demo.events[3].action, DemoAction::Scene(index) if index == find_scene("terrain").unwrap()));
+    }
+
+    #[test]
+    fn refuses_broken_events() {
+        let error = |text: &str| parse(text).unwrap_err();
+        assert_eq!(error("# nothing yet\n"), "no events");
+        assert_eq!(error("0 scene attic\n"), "line 1: unknown scene `attic`");
+        assert_eq!(error("0 light dim\n"), "line 1: expected on or off, got `dim`");
+        assert_eq!(error("soon loop\n"), "line 1: `soon` is not a number");
+        assert_eq!(error("0 cut 1 2 3\n"), "line 1: cut needs eye x y z, yaw and pitch");
+        assert_eq!(error("0 dance\n"), "line 1: unknown event `dance`");
+    }
+
+    #[test]
+    fn glides_the_camera_and_fades_the_hour() {
+        let demo = parse("0 cut 0 0 0 0 0\n0 fly 2 4 0 0 0 0\n0 hour 6\n2 hour 12 2\n").unwrap();
+        let mut player = DemoPlayer::new(demo);
+        let mut camera = camera();
+
+        assert_eq!(player.step(1.0, &mut camera), [DemoCue::Hour(6.0)]);
+        assert_eq!(camera.eye, Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(player.step(1.0, &mut camera), [DemoCue::Hour(6.0)]);
+        assert_eq!(camera.eye, Vector3::new(4.0, 0.0, 0.0));
+        assert_eq!(player.step(1.0, &mut camera), [DemoCue::Hour(9.0)]);
+        assert!(!player.is_finished());
+        assert_eq!(player.step(1.0, &mut camera), [DemoCue::Hour(12.0)]);
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn stops_at_a_scene_change_and_starts_over_at_a_loop() {
+        let demo = parse("0 scene terrain\n0 light off\n1 loop\n").unwrap();
+        let mut player = DemoPlayer::new(demo);
+        let mut camera = camera();
+
+        let terrain = find_scene("terrain").unwrap();
+        assert_eq!(player.step(1.0, &mut camera), [DemoCue::Scene(terrain)]);
+        // The loop is due in the same step and goes back to the first event
+        assert_eq!(player.step(0.0, &mut camera), [DemoCue::Light(false)]);
+        assert_eq!(player.step(0.0, &mut camera), [DemoCue::Scene(terrain)]);
+        assert!(!player.is_finished());
+    }
+}
@@ -0,0 +1,108 @@
+use crate::camera::Camera;
+use crate::color_ops::luminance;
+use crate::framebuffer::NO_OBJECT;
+use crate::material::MaterialTable;
+use crate::primary_ray;
+use crate::ray_intersect::SceneObject;
+use raylib::prelude::*;
+use std::f32::consts::PI;
+
+// À-Trous passes; each doubles the kernel spacing, 5 passes cover a 61 px footprint
+const ATROUS_ITERATIONS: u32 = 5;
+// Edge-stopping strengths: higher = stricter about crossing that kind of edge
+const NORMAL_POWER: f32 = 64.0;
+const DEPTH_SIGMA: f32 = 0.05; // Relative depth difference tolerated
+const COLOR_SIGMA: f32 = 0.5;  // Luminance difference tolerated on the first pass
+
+/// First-hit surface data that guides the filter across geometric edges
+#[derive(Debug, Clone, Copy)]
+pub struct Aov {
+    pub normal: Vector3,
+    pub depth: f32,  // Infinity for sky
+    pub object: u32, // Index of the object hit, `NO_OBJECT` for sky
+}
+
+/// Traces one primary ray through every pixel center for normals, depth and object IDs
+pub fn first_hit_aovs(objects: &[SceneObject], materials: &MaterialTable, camera: &Camera, width: u32, height: u32) -> Vec<Aov> {
+    let fov = PI / 3.0;
+    let mut aovs = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let direction = primary_ray(camera, x as f32 + 0.5, y as f32 + 0.5, width, height, fov);
+            let mut aov = Aov {
+                normal: Vector3::zero(),
+                depth: f32::INFINITY,
+                object: NO_OBJECT,
+            };
+            for (index, object) in objects.iter().enumerate() {
+                let intersect = object.ray_intersect(&camera.eye, &direction);
+                if intersect.is_intersecting && intersect.distance < aov.depth && !object.is_cut_out(&intersect, materials) {
+                    aov = Aov {
+                        normal: intersect.normal,
+                        depth: intersect.distance,
+                        object: index as u32,
+                    };
+                }
+            }
+            aovs.push(aov);
+        }
+    }
+    aovs
+}
+
+/// Edge-aware À-Trous wavelet filter over an accumulated HDR image. Neighbors only blend
+/// when they share the pixel's surface orientation, distance and roughly its brightness,
+/// so noise is averaged away while block edges and shadow boundaries stay sharp.
+pub fn denoise(colors: &[Vector3], aovs: &[Aov], width: u32, height: u32) -> Vec<Vector3> {
+    const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+    let mut current = colors.to_vec();
+    let mut next = vec![Vector3::zero(); current.len()];
+
+    for iteration in 0..ATROUS_ITERATIONS {
+        let step = 1i32 << iteration;
+        // Later passes see a smoother image, so color differences mean more
+        let color_sigma = COLOR_SIGMA / (1 << iteration) as f32;
+
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let center_index = (y as u32 * width + x as u32) as usize;
+                let center = aovs[center_index];
+                let center_color = current[center_index];
+                let center_luminance = luminance(center_color);
+
+                let mut sum = Vector3::zero();
+                let mut weight_sum = 0.0;
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let sx = x + dx * step;
+                        let sy = y + dy * step;
+                        if sx < 0 || sy < 0 || sx >= width as i32 || sy >= height as i32 {
+                            continue;
+                        }
+                        let index = (sy as u32 * width + sx as u32) as usize;
+                        let sample = aovs[index];
+                        let color = current[index];
+
+                        let kernel = KERNEL[dx.unsigned_abs() as usize] * KERNEL[dy.unsigned_abs() as usize];
+                        let normal_weight = center.normal.dot(sample.normal).max(0.0).powf(NORMAL_POWER);
+                        let depth_weight = if center.depth.is_infinite() || sample.depth.is_infinite() {
+                            if center.depth.is_infinite() == sample.depth.is_infinite() { 1.0 } else { 0.0 }
+                        } else {
+                            let relative = (center.depth - sample.depth).abs() / center.depth.max(1e-3);
+                            (-relative / DEPTH_SIGMA).exp()
+                        };
+                        let color_weight = (-(luminance(color) - center_luminance).abs() / color_sigma).exp();
+
+                        let weight = kernel * normal_weight * depth_weight * color_weight;
+                        sum = sum + color * weight;
+                        weight_sum += weight;
+                    }
+                }
+                next[center_index] = if weight_sum > 0.0 { sum / weight_sum } else { center_color };
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+    }
+    current
+}
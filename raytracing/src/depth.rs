@@ -0,0 +1,91 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Writes hit distances as a 16-bit grayscale PNG for compositing: 0 at the camera, 65535 at
+/// the farthest surface in the frame and for sky. The far distance is stored in a `Depth far`
+/// text chunk and returned, so values can be turned back into world units.
+pub fn write_depth_png(path: &Path, width: u32, height: u32, depths: &[f32]) -> io::Result<f32> {
+    let far = depths.iter().copied().filter(|depth| depth.is_finite()).fold(0.0f32, f32::max).ceil().max(1.0);
+
+    // Filter type 0 per row, then big-endian samples
+    let mut raw = Vec::with_capacity((height * (1 + width * 2)) as usize);
+    for row in depths.chunks(width as usize) {
+        raw.push(0);
+        for &depth in row {
+            let value = if depth.is_finite() { (depth / far).clamp(0.0, 1.0) } else { 1.0 };
+            raw.extend_from_slice(&((value * 65535.0).round() as u16).to_be_bytes());
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[16, 0, 0, 0, 0]); // 16-bit grayscale, deflate, no interlace
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"tEXt", format!("Depth far\0{}", far).as_bytes());
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    fs::write(path, png)?;
+    Ok(far)
+}
+
+/// Where the depth for an image goes: `shot.png` -> `shot_depth.png`
+pub fn depth_path(image_path: &Path) -> PathBuf {
+    let stem = image_path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    image_path.with_file_name(format!("{}_depth.png", stem))
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Zlib stream of uncompressed deflate blocks; depth rarely compresses well and this needs no dependency
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 65535 * 5 + 11);
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let length = block.len() as u16;
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(&(!length).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,44 @@
+use raylib::prelude::Vector3;
+
+/// Materials at least this refractive split their refractions by wavelength in spectral
+/// mode; water and glass bend colors too little to be worth the extra rays
+pub const DISPERSIVE_IOR: f32 = 1.8;
+
+// Abbe number shared by every material: how little the IOR changes across the spectrum.
+// Diamond's is 55, common glass is around 60.
+const ABBE_NUMBER: f32 = 55.0;
+// Fraunhofer lines the Abbe number is defined by, in nanometers: d (yellow) is where a
+// material's listed IOR applies, F (blue) and C (red) bound the spread
+const LINE_D: f32 = 587.6;
+const LINE_F: f32 = 486.1;
+const LINE_C: f32 = 656.3;
+
+/// Wavelengths traced in spectral mode and the color each one contributes, a coarse
+/// stand-in for the CIE curves from violet to red
+const SPECTRUM: [(f32, Vector3); 6] = [
+    (440.0, Vector3 { x: 0.3, y: 0.0, z: 1.0 }),
+    (480.0, Vector3 { x: 0.0, y: 0.4, z: 1.0 }),
+    (520.0, Vector3 { x: 0.0, y: 1.0, z: 0.3 }),
+    (560.0, Vector3 { x: 0.6, y: 1.0, z: 0.0 }),
+    (600.0, Vector3 { x: 1.0, y: 0.5, z: 0.0 }),
+    (650.0, Vector3 { x: 1.0, y: 0.0, z: 0.0 }),
+];
+
+/// The spectral samples with their colors scaled so each channel adds up to 1: white light
+/// split into them and recombined stays white
+pub fn spectral_samples() -> [(f32, Vector3); 6] {
+    let total = SPECTRUM.iter().fold(Vector3::zero(), |sum, &(_, color)| sum + color);
+    SPECTRUM.map(|(wavelength, color)| (wavelength, Vector3::new(color.x / total.x, color.y / total.y, color.z / total.z)))
+}
+
+/// IOR at `wavelength` nanometers of a material listed with `ior`, from Cauchy's equation
+/// n = A + B / wavelength^2 fitted to the IOR at the d line and the Abbe number. Air stays 1.
+pub fn dispersed_ior(ior: f32, wavelength: f32) -> f32 {
+    if ior <= 1.0 {
+        return ior;
+    }
+    let inverse_square = |wavelength: f32| 1.0 / (wavelength * wavelength);
+    let b = (ior - 1.0) / ABBE_NUMBER / (inverse_square(LINE_F) - inverse_square(LINE_C));
+    let a = ior - b * inverse_square(LINE_D);
+    a + b * inverse_square(wavelength)
+}
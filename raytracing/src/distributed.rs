@@ -0,0 +1,464 @@
+use crate::acceleration::Acceleration;
+use crate::camera::Camera;
+use crate::denoise::first_hit_aovs;
+use crate::material::{MaterialTable, TextureOptions};
+use crate::offline::{OfflineJob, bloom_emission, pixel_count, render_rows, value_after, write_image};
+use crate::post::PostProcess;
+use crate::ray_intersect::SceneObject;
+use crate::settings::{RenderOptions, ShadowBias};
+use crate::sky::{Sky, SkyGradient};
+use crate::{create_camera, load_scene};
+use raylib::prelude::*;
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+// Line protocol, one worker per connection:
+//   worker      -> coordinator  READY
+//   coordinator -> worker       JOB <width> <height> <samples> <turntable> <first_frame> <end_frame> <y_start> <y_end> <options> <sky>  |  DONE
+//   worker      -> coordinator  RESULT <frame> <y_start> <y_end>  followed by raw f32 RGB rows, once per frame
+//
+// `turntable` is the number of frames in a full orbit, 0 for a still image from the default camera.
+// `options` and `sky` are the coordinator's render options and sky as comma-separated numbers
+// (see `options_field` and `sky_field`), so workers trace what a local `--render` would.
+
+const DEFAULT_TILE_ROWS: u32 = 16;
+const DEFAULT_FRAMES_PER_JOB: u32 = 4;
+
+/// Which side of a distributed render this process plays
+pub enum Role {
+    Coordinator { address: String, tile_rows: u32, turntable: u32, frames_per_job: u32 },
+    Worker { address: String },
+}
+
+impl Role {
+    /// `--coordinator <addr> [--tile-rows N] [--turntable FRAMES [--frames-per-job N]]` (with the
+    /// usual `--render` flags) or `--worker <addr>`
+    pub fn from_args() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        if let Some(address) = value_after(&args, "--worker") {
+            return Some(Role::Worker {
+                address: address.to_string(),
+            });
+        }
+        let address = value_after(&args, "--coordinator")?;
+        let number_after = |flag: &str, default: u32| value_after(&args, flag).and_then(|n| n.parse().ok()).unwrap_or(default);
+        Some(Role::Coordinator {
+            address: address.to_string(),
+            tile_rows: number_after("--tile-rows", DEFAULT_TILE_ROWS).max(1),
+            turntable: number_after("--turntable", 0),
+            frames_per_job: number_after("--frames-per-job", DEFAULT_FRAMES_PER_JOB).max(1),
+        })
+    }
+}
+
+/// The default camera orbited around the point it looks at, `frame` of a `frames`-frame turn.
+/// With no turntable it is the default camera unchanged.
+pub fn turntable_camera(frame: u32, frames: u32) -> Camera {
+    let camera = create_camera();
+    if frames == 0 {
+        return camera;
+    }
+    let angle = TAU * frame as f32 / frames as f32;
+    let offset = camera.eye - camera.center;
+    let (sin, cos) = angle.sin_cos();
+    let orbit = Vector3::new(offset.x * cos - offset.z * sin, offset.y, offset.x * sin + offset.z * cos);
+    Camera::new(camera.center + orbit, camera.center, Vector3::new(0.0, 1.0, 0.0))
+}
+
+// Frames `first_frame..end_frame`, rows `y_start..y_end` of each
+#[derive(Clone, Copy)]
+struct Unit {
+    first_frame: u32,
+    end_frame: u32,
+    y_start: u32,
+    y_end: u32,
+}
+
+// A frame still waiting for some of its rows
+struct PartialFrame {
+    pixels: Vec<Vector3>,
+    rows_left: u32,
+}
+
+struct Progress {
+    pending: Vec<Unit>,
+    frames: HashMap<u32, PartialFrame>,
+    remaining: usize, // Units not yet fully received
+}
+
+struct CoordinatorState {
+    progress: Mutex<Progress>,
+    changed: Condvar, // Signalled when a unit is put back or finished
+    output: PathBuf,
+    post: PostProcess,
+    scene: Option<(Vec<SceneObject>, MaterialTable, Acceleration)>, // Only loaded for bloom's emission pass and sky cut-outs
+    job_width: u32,
+    job_height: u32,
+    samples: u32,
+    turntable: u32,
+    options: RenderOptions,
+    sky: Sky,
+}
+
+/// Hands out row bands (or, for a turntable, ranges of whole frames) to connecting workers and
+/// writes each image as soon as all of its rows are in
+pub fn run_coordinator(address: &str, job: &OfflineJob, tile_rows: u32, turntable: u32, frames_per_job: u32) -> io::Result<()> {
+    if pixel_count(job.width, job.height).is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "image too large"));
+    }
+    let listener = TcpListener::bind(address)?;
+
+    let mut units: Vec<Unit> = if turntable == 0 {
+        (0..job.height)
+            .step_by(tile_rows as usize)
+            .map(|y_start| Unit { first_frame: 0, end_frame: 1, y_start, y_end: (y_start + tile_rows).min(job.height) })
+            .collect()
+    } else {
+        (0..turntable)
+            .step_by(frames_per_job as usize)
+            .map(|first_frame| Unit { first_frame, end_frame: (first_frame + frames_per_job).min(turntable), y_start: 0, y_end: job.height })
+            .collect()
+    };
+    units.reverse(); // Popped from the back, so hand out the first rows and frames first
+    let unit_count = units.len();
+
+    let args: Vec<String> = std::env::args().collect();
+    let state = Arc::new(CoordinatorState {
+        progress: Mutex::new(Progress { pending: units, frames: HashMap::new(), remaining: unit_count }),
+        changed: Condvar::new(),
+        output: job.output.clone(),
+        post: job.post.clone(),
+        scene: (job.post.bloom > 0.0 || job.options.transparent_background).then(|| {
+            let (objects, materials) = load_scene(TextureOptions::from_args(&args));
+            let accel = Acceleration::build(&objects);
+            (objects, materials, accel)
+        }),
+        job_width: job.width,
+        job_height: job.height,
+        samples: job.samples,
+        turntable,
+        options: job.options,
+        sky: Sky::from_args(&args),
+    });
+    if turntable == 0 {
+        println!("Coordinator listening on {} - {} tiles of {} rows", address, unit_count, tile_rows);
+    } else {
+        println!("Coordinator listening on {} - {} turntable frames in {} jobs", address, turntable, unit_count);
+    }
+
+    let accept_state = Arc::clone(&state);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let worker_state = Arc::clone(&accept_state);
+            thread::spawn(move || {
+                if let Err(err) = serve_worker(stream, &worker_state) {
+                    println!("Worker connection lost: {}", err);
+                }
+            });
+        }
+    });
+
+    let mut progress = state.progress.lock().unwrap();
+    while progress.remaining > 0 {
+        progress = state.changed.wait(progress).unwrap();
+    }
+    Ok(())
+}
+
+// `out.png` for a still, `out_0000.png`, `out_0001.png`... for a turntable
+fn frame_path(output: &Path, frame: u32, turntable: u32) -> PathBuf {
+    if turntable == 0 {
+        return output.to_path_buf();
+    }
+    let stem = output.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let extension = output.extension().map(|extension| extension.to_string_lossy()).unwrap_or("png".into());
+    output.with_file_name(format!("{}_{:04}.{}", stem, frame, extension))
+}
+
+fn serve_worker(stream: TcpStream, state: &CoordinatorState) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let (width, height) = (state.job_width, state.job_height);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim() != "READY" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "expected READY"));
+    }
+    println!("Worker connected: {}", peer);
+
+    loop {
+        let unit = {
+            let mut progress = state.progress.lock().unwrap();
+            // Other workers may still drop out and put their units back, so wait rather than leave
+            loop {
+                if let Some(unit) = progress.pending.pop() {
+                    break Some(unit);
+                }
+                if progress.remaining == 0 {
+                    break None;
+                }
+                progress = state.changed.wait(progress).unwrap();
+            }
+        };
+        let Some(unit) = unit else {
+            writer.write_all(b"DONE\n")?;
+            return Ok(());
+        };
+
+        writeln!(
+            writer,
+            "JOB {} {} {} {} {} {} {} {} {} {}",
+            width,
+            height,
+            state.samples,
+            state.turntable,
+            unit.first_frame,
+            unit.end_frame,
+            unit.y_start,
+            unit.y_end,
+            options_field(&state.options),
+            sky_field(&state.sky)
+        )?;
+        for frame in unit.first_frame..unit.end_frame {
+            let colors = match receive_tile(&mut reader, width, frame, unit.y_start, unit.y_end) {
+                Ok(colors) => colors,
+                Err(err) => {
+                    // Put the frames not received yet back so another worker picks them up
+                    state.progress.lock().unwrap().pending.push(Unit { first_frame: frame, ..unit });
+                    state.changed.notify_all();
+                    return Err(err);
+                }
+            };
+
+            let mut progress = state.progress.lock().unwrap();
+            let partial = progress.frames.entry(frame).or_insert_with(|| PartialFrame {
+                pixels: vec![Vector3::zero(); width as usize * height as usize],
+                rows_left: height,
+            });
+            let offset = unit.y_start as usize * width as usize;
+            partial.pixels[offset..offset + colors.len()].copy_from_slice(&colors);
+            partial.rows_left -= unit.y_end - unit.y_start;
+            let finished = if partial.rows_left == 0 { progress.frames.remove(&frame) } else { None };
+            drop(progress);
+
+            if let Some(finished) = finished {
+                let path = frame_path(&state.output, frame, state.turntable);
+                let camera = turntable_camera(frame, state.turntable);
+                let emission = state.scene.as_ref().and_then(|(objects, materials, accel)| bloom_emission(&state.post, objects, materials, accel, &camera, width, height));
+                // With --transparent, pixel centers that see only sky become transparent, as in `offline::run`
+                let cutout: Option<Vec<u32>> = state.scene.as_ref().filter(|_| state.options.transparent_background).map(|(objects, materials, _)| {
+                    first_hit_aovs(objects, materials, &camera, width, height).iter().map(|aov| aov.object).collect()
+                });
+                write_image(&path, width, height, &finished.pixels, emission, &state.post, frame, cutout.as_deref());
+            }
+        }
+
+        let mut progress = state.progress.lock().unwrap();
+        progress.remaining -= 1;
+        if state.turntable == 0 {
+            println!("Rows {}..{} from {} ({} tiles left)", unit.y_start, unit.y_end, peer, progress.remaining);
+        } else {
+            println!("Frames {}..{} from {} ({} jobs left)", unit.first_frame, unit.end_frame, peer, progress.remaining);
+        }
+        state.changed.notify_all();
+    }
+}
+
+fn receive_tile(reader: &mut BufReader<TcpStream>, width: u32, frame: u32, y_start: u32, y_end: u32) -> io::Result<Vec<Vector3>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim() != format!("RESULT {} {} {}", frame, y_start, y_end) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected reply: {}", line.trim())));
+    }
+
+    let mut bytes = vec![0u8; (y_end - y_start) as usize * width as usize * 12];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(12)
+        .map(|chunk| {
+            Vector3::new(
+                f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]),
+                f32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]),
+                f32::from_le_bytes([chunk[8], chunk[9], chunk[10], chunk[11]]),
+            )
+        })
+        .collect())
+}
+
+/// Connects to a coordinator and renders whatever jobs it assigns until told DONE
+pub fn run_worker(address: &str) -> io::Result<()> {
+    let stream = TcpStream::connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let args: Vec<String> = std::env::args().collect();
+    let (objects, materials) = load_scene(TextureOptions::from_args(&args));
+    let accel = Acceleration::build(&objects);
+
+    writer.write_all(b"READY\n")?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["JOB", width, height, samples, turntable, first_frame, end_frame, y_start, y_end, options, sky] => {
+                let (width, height, samples) = (parse_field(width)?, parse_field(height)?, parse_field(samples)?);
+                let (turntable, first_frame, end_frame) = (parse_field(turntable)?, parse_field(first_frame)?, parse_field(end_frame)?);
+                let (y_start, y_end) = (parse_field(y_start)?, parse_field(y_end)?);
+                let (options, sky) = (parse_options(options)?, parse_sky(sky)?);
+                if y_start > y_end || y_end > height || pixel_count(width, height).is_none() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad job: {}", line.trim())));
+                }
+
+                for frame in first_frame..end_frame {
+                    println!("Rendering frame {} rows {}..{}", frame, y_start, y_end);
+                    let camera = turntable_camera(frame, turntable);
+                    let colors = render_rows(&objects, &materials, &accel, &camera, &options, &sky, width, height, y_start, y_end, samples);
+                    let mut bytes = Vec::with_capacity(colors.len() * 12);
+                    for color in &colors {
+                        bytes.extend_from_slice(&color.x.to_le_bytes());
+                        bytes.extend_from_slice(&color.y.to_le_bytes());
+                        bytes.extend_from_slice(&color.z.to_le_bytes());
+                    }
+                    writeln!(writer, "RESULT {} {} {}", frame, y_start, y_end)?;
+                    writer.write_all(&bytes)?;
+                }
+            }
+            ["DONE"] => {
+                println!("Coordinator finished the job");
+                return Ok(());
+            }
+            _ => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected message: {}", line.trim())));
+            }
+        }
+    }
+}
+
+fn parse_field(field: &str) -> io::Result<u32> {
+    field
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("bad number: {}", field)))
+}
+
+// The options a worker's tracing depends on. The ones applied when writing the image
+// (transparent background) stay with the coordinator.
+fn options_field(options: &RenderOptions) -> String {
+    let bias = &options.bias;
+    format!(
+        "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+        options.path_traced as u8,
+        options.shadows as u8,
+        options.reflections as u8,
+        options.spectral as u8,
+        options.max_reflection_depth,
+        options.max_refraction_depth,
+        options.ao_samples,
+        options.specular_range,
+        options.exposure,
+        bias.constant,
+        bias.slope,
+        bias.max,
+        bias.occluder_epsilon,
+    )
+}
+
+fn parse_options(field: &str) -> io::Result<RenderOptions> {
+    let values: Vec<&str> = field.split(',').collect();
+    let [path_traced, shadows, reflections, spectral, reflection_depth, refraction_depth, ao_samples, specular_range, exposure, constant, slope, max, occluder_epsilon] = values[..] else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad options: {}", field)));
+    };
+    let flag = |value: &str| parse_field(value).map(|value| value != 0);
+    Ok(RenderOptions {
+        path_traced: flag(path_traced)?,
+        shadows: flag(shadows)?,
+        reflections: flag(reflections)?,
+        spectral: flag(spectral)?,
+        max_reflection_depth: parse_field(reflection_depth)?,
+        max_refraction_depth: parse_field(refraction_depth)?,
+        ao_samples: parse_field(ao_samples)?,
+        specular_range: parse_number(specular_range)?,
+        exposure: parse_number(exposure)?,
+        bias: ShadowBias { constant: parse_number(constant)?, slope: parse_number(slope)?, max: parse_number(max)?, occluder_epsilon: parse_number(occluder_epsilon)? },
+        ..RenderOptions::default()
+    })
+}
+
+// Tint, then the gradient's ground, horizon and zenith colors and its three heights
+fn sky_field(sky: &Sky) -> String {
+    let gradient = &sky.gradient;
+    let numbers = [
+        sky.tint.x,
+        sky.tint.y,
+        sky.tint.z,
+        gradient.ground.x,
+        gradient.ground.y,
+        gradient.ground.z,
+        gradient.horizon.x,
+        gradient.horizon.y,
+        gradient.horizon.z,
+        gradient.zenith.x,
+        gradient.zenith.y,
+        gradient.zenith.z,
+        gradient.horizon_height,
+        gradient.band,
+        gradient.zenith_height,
+    ];
+    numbers.map(|number| number.to_string()).join(",")
+}
+
+fn parse_sky(field: &str) -> io::Result<Sky> {
+    let numbers = field.split(',').map(parse_number).collect::<io::Result<Vec<f32>>>()?;
+    let [tx, ty, tz, gx, gy, gz, hx, hy, hz, zx, zy, zz, horizon_height, band, zenith_height] = numbers[..] else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad sky: {}", field)));
+    };
+    Ok(Sky {
+        tint: Vector3::new(tx, ty, tz),
+        gradient: SkyGradient {
+            ground: Vector3::new(gx, gy, gz),
+            horizon: Vector3::new(hx, hy, hz),
+            zenith: Vector3::new(zx, zy, zz),
+            horizon_height,
+            band,
+            zenith_height,
+        },
+    })
+}
+
+fn parse_number(field: &str) -> io::Result<f32> {
+    field
+        .parse()
+        .ok()
+        .filter(|number: &f32| number.is_finite())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("bad number: {}", field)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_and_sky_survive_the_job_line() {
+        let options = RenderOptions { path_traced: true, shadows: false, ao_samples: 3, specular_range: 2.5, ..RenderOptions::default() };
+        let parsed = parse_options(&options_field(&options)).unwrap();
+        assert!(parsed.path_traced && !parsed.shadows && parsed.reflections);
+        assert_eq!((parsed.ao_samples, parsed.specular_range), (3, 2.5));
+        assert_eq!(parsed.bias.occluder_epsilon, options.bias.occluder_epsilon);
+
+        let sky = Sky { tint: Vector3::new(0.5, 0.4, 0.3), ..Sky::default() };
+        let parsed = parse_sky(&sky_field(&sky)).unwrap();
+        assert_eq!(parsed.tint, sky.tint);
+        assert_eq!(parsed.gradient, sky.gradient);
+        assert!(parse_sky("1,2,3").is_err());
+        assert!(parse_options("1,0,1,inf").is_err());
+    }
+}
@@ -0,0 +1,50 @@
+// How much of each new measurement goes into the cost estimate, and how far the scale
+// moves toward its goal per traced frame; low values keep the resolution from pumping
+const COST_SMOOTHING: f32 = 0.3;
+const SCALE_RESPONSE: f32 = 0.5;
+
+/// Picks the render scale from how long frames actually take to trace, to hold a target
+/// frame rate whatever the scene costs. Trace time grows with the pixel count, the square
+/// of the scale, so the measured time is normalized to a full-scale cost and the scale
+/// that fits the budget is its square root.
+#[derive(Debug, Clone)]
+pub struct ResolutionController {
+    target: f32, // Seconds per traced frame
+    min_scale: f32,
+    max_scale: f32,
+    scale: f32,
+    full_scale_cost: Option<f32>, // Smoothed seconds a frame would take at scale 1
+}
+
+impl ResolutionController {
+    pub fn new(target_fps: f32, min_scale: f32, max_scale: f32) -> Self {
+        ResolutionController {
+            target: 1.0 / target_fps.max(1.0),
+            min_scale,
+            max_scale,
+            scale: min_scale,
+            full_scale_cost: None,
+        }
+    }
+
+    /// Scale for the next frame
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Feeds back how long a finished frame took at the scale it was traced at
+    pub fn frame_traced(&mut self, seconds: f32, scale: f32) {
+        if seconds <= 0.0 || scale <= 0.0 {
+            return;
+        }
+        let cost = seconds / (scale * scale);
+        let smoothed = match self.full_scale_cost {
+            Some(previous) => previous + (cost - previous) * COST_SMOOTHING,
+            None => cost,
+        };
+        self.full_scale_cost = Some(smoothed);
+
+        let goal = (self.target / smoothed).sqrt().clamp(self.min_scale, self.max_scale);
+        self.scale = (self.scale + (goal - self.scale) * SCALE_RESPONSE).clamp(self.min_scale, self.max_scale);
+    }
+}
@@ -0,0 +1,427 @@
+use crate::cube::Cube;
+use crate::material::{Material, MaterialTable, TextureOptions};
+use crate::material_library::{MaterialLibrary, find_asset, material_id};
+use crate::offline::value_after;
+use crate::scenes::{GALLERY, find_scene};
+use crate::sphere::Sphere;
+use raylib::prelude::Vector3;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+
+// Cube corners closer than this to a grid line count as on it
+const GRID_EPSILON: f32 = 1e-3;
+
+// Quads around a sphere's equator and from pole to pole
+const SPHERE_SEGMENTS: usize = 24;
+const SPHERE_RINGS: usize = 12;
+
+/// Writes a scene as a Wavefront OBJ with its MTL and textures next to it:
+/// `--export-obj <file.obj> [--scene <name>]`
+pub struct ExportJob {
+    pub path: PathBuf,
+    pub scene: usize,
+    pub textures: TextureOptions,
+}
+
+impl ExportJob {
+    pub fn from_args() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        let path = PathBuf::from(value_after(&args, "--export-obj")?);
+        let scene = match value_after(&args, "--scene") {
+            Some(name) => find_scene(name).unwrap_or_else(|| {
+                println!("ERROR: unknown scene {}, exporting {}", name, GALLERY[0].name);
+                0
+            }),
+            None => 0,
+        };
+        Some(ExportJob { path, scene, textures: TextureOptions::from_args(&args) })
+    }
+}
+
+pub fn run_export(job: &ExportJob) -> io::Result<()> {
+    let library = MaterialLibrary::load(job.textures);
+    let mut table = library.table();
+    let entry = &GALLERY[job.scene];
+    let blocks = (entry.build)(&library, &mut table);
+    let props = (entry.props)(&library, &mut table);
+    let spheres: Vec<&Sphere> = props.iter().filter_map(|prop| prop.as_sphere()).collect();
+    let stats = export_obj(&blocks, &spheres, &table, &library, &job.path)?;
+    println!(
+        "Exported {} ({} cubes, {} spheres) to {}: {} faces, {} materials",
+        entry.name,
+        blocks.len(),
+        spheres.len(),
+        job.path.display(),
+        stats.faces,
+        stats.materials
+    );
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExportStats {
+    pub faces: usize,
+    pub materials: usize,
+}
+
+/// Converts the cubes into a greedy-meshed OBJ: faces hidden behind an opaque neighbor are
+/// dropped, and coplanar faces of the same material merge into one quad whose UVs run past
+/// 1, so the block texture repeats once per block with the texture set to wrap. Spheres are
+/// written as UV spheres. Writes `<name>.mtl` and copies the textures it uses beside the OBJ,
+/// top and bottom textures of a preset as materials of their own.
+pub fn export_obj(objects: &[Cube], spheres: &[&Sphere], table: &MaterialTable, library: &MaterialLibrary, path: &Path) -> io::Result<ExportStats> {
+    let mut materials: Vec<ExportMaterial> = Vec::new();
+    let mut material_index = |material: ExportMaterial| match materials.iter().position(|known| *known == material) {
+        Some(index) => index,
+        None => {
+            materials.push(material);
+            materials.len() - 1
+        }
+    };
+    let mut grids: HashMap<GridKey, Grid> = HashMap::new();
+    for object in objects {
+        let (key, cell) = grid_position(object);
+        let grid = grids.entry(key).or_insert_with(|| Grid { size: object.size, origin: key.origin(), cells: HashMap::new() });
+        let textured = object.texture.is_some() || object.face_textures.is_some();
+        let faces = std::array::from_fn(|face| {
+            material_index(ExportMaterial {
+                material: table[object.material_entry(FACE_NORMALS[face])],
+                preset: object.material_id,
+                texture: face_texture(library, object.material_id, face).filter(|_| textured),
+            })
+        });
+        grid.cells.insert(cell, faces);
+    }
+    // Props have no preset ID of their own, the preset is the one whose table entry they share
+    let sphere_materials: Vec<usize> = spheres
+        .iter()
+        .map(|sphere| {
+            let preset = library.names().map(|name| material_id(name)).find(|&id| table.preset(id) == Some(sphere.material)).unwrap_or(0);
+            material_index(ExportMaterial {
+                material: table[sphere.material],
+                preset,
+                texture: face_texture(library, preset, 0).filter(|_| sphere.texture.is_some()),
+            })
+        })
+        .collect();
+
+    let mut faces: Vec<Face> = Vec::new();
+    for grid in grids.values() {
+        grid.mesh(&materials, &mut faces);
+    }
+    for (sphere, &material) in spheres.iter().zip(&sphere_materials) {
+        sphere_faces(sphere, material, &mut faces);
+    }
+    faces.sort_by_key(|face| face.material);
+
+    // A preset's name goes to its first material, per-face variants of the same block get numbers
+    let mut names: Vec<String> = Vec::with_capacity(materials.len());
+    for (index, material) in materials.iter().enumerate() {
+        let name = library.name_of(material.preset).filter(|name| !names.iter().any(|taken| taken == name));
+        names.push(name.map_or_else(|| format!("material_{}", index), str::to_string));
+    }
+    let mtl_path = path.with_extension("mtl");
+    write_mtl(&mtl_path, &materials, &names)?;
+
+    let mtl_name = mtl_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut obj = format!("# Exported from the cube raytracer, {} faces\nmtllib {}\n", faces.len(), mtl_name);
+    let mut current = None;
+    let mut vertex_count = 0;
+    for face in &faces {
+        if current != Some(face.material) {
+            current = Some(face.material);
+            obj += &format!("usemtl {}\n", names[face.material]);
+        }
+        let mut indices = String::new();
+        for vertex in &face.vertices {
+            let (position, normal) = (vertex.position, vertex.normal);
+            obj += &format!("v {} {} {}\nvt {} {}\nvn {} {} {}\n", position.x, position.y, position.z, vertex.uv.0, vertex.uv.1, normal.x, normal.y, normal.z);
+            vertex_count += 1;
+            indices += &format!(" {0}/{0}/{0}", vertex_count);
+        }
+        obj += &format!("f{}\n", indices);
+    }
+    fs::write(path, obj)?;
+    Ok(ExportStats { faces: faces.len(), materials: materials.len() })
+}
+
+// Outward normals in `Cube::face_index` order: +X, -X, +Y, -Y, +Z, -Z
+const FACE_NORMALS: [Vector3; 6] = [
+    Vector3 { x: 1.0, y: 0.0, z: 0.0 },
+    Vector3 { x: -1.0, y: 0.0, z: 0.0 },
+    Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+    Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+    Vector3 { x: 0.0, y: 0.0, z: 1.0 },
+    Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+];
+
+// Cubes of one size whose corners sit on the same lattice mesh together
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct GridKey {
+    size: u32,         // f32 bits
+    offset: [i32; 3], // Lattice offset in thousandths of the size
+}
+
+impl GridKey {
+    fn origin(&self) -> Vector3 {
+        let size = f32::from_bits(self.size);
+        let [x, y, z] = self.offset.map(|offset| offset as f32 / 1000.0 * size);
+        Vector3::new(x, y, z)
+    }
+}
+
+struct Grid {
+    size: f32,
+    origin: Vector3,
+    cells: HashMap<[i32; 3], [usize; 6]>, // Material index per face
+}
+
+// One MTL material: its values, the preset it came from (0 for none) and the texture drawn on it
+#[derive(Debug, Clone, PartialEq)]
+struct ExportMaterial {
+    material: Material,
+    preset: u32,
+    texture: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Vertex {
+    position: Vector3,
+    uv: (f32, f32),
+    normal: Vector3,
+}
+
+// A polygon of the OBJ, counter-clockwise seen from outside: merged cube faces are quads,
+// the sphere's pole caps triangles
+struct Face {
+    vertices: Vec<Vertex>,
+    material: usize,
+}
+
+// Which lattice a cube is on and its cell there
+fn grid_position(object: &Cube) -> (GridKey, [i32; 3]) {
+    let corner = object.center - Vector3::new(object.size, object.size, object.size) * 0.5;
+    let cells = [corner.x, corner.y, corner.z].map(|value| value / object.size);
+    let cell = cells.map(|value| value.floor() as i32);
+    let offset = std::array::from_fn(|axis| {
+        let fraction = cells[axis] - cell[axis] as f32;
+        if fraction < GRID_EPSILON || fraction > 1.0 - GRID_EPSILON { 0 } else { (fraction * 1000.0).round() as i32 }
+    });
+    let cell = std::array::from_fn(|axis| if cells[axis] - cell[axis] as f32 > 1.0 - GRID_EPSILON { cell[axis] + 1 } else { cell[axis] });
+    (GridKey { size: object.size.to_bits(), offset }, cell)
+}
+
+impl Grid {
+    // Greedy meshing, one slice of cell faces at a time along each axis and direction
+    fn mesh(&self, materials: &[ExportMaterial], faces: &mut Vec<Face>) {
+        let Some(&first) = self.cells.keys().next() else {
+            return;
+        };
+        let (mut min, mut max) = (first, first);
+        for cell in self.cells.keys() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(cell[axis]);
+                max[axis] = max[axis].max(cell[axis]);
+            }
+        }
+
+        for face in 0..6 {
+            let axis = face / 2;
+            let step = if face % 2 == 0 { 1 } else { -1 };
+            let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+            let (u_len, v_len) = ((max[u_axis] - min[u_axis] + 1) as usize, (max[v_axis] - min[v_axis] + 1) as usize);
+
+            for slice in min[axis]..=max[axis] {
+                // Visible faces in this slice, by material
+                let mut mask: Vec<Option<usize>> = vec![None; u_len * v_len];
+                for v in 0..v_len {
+                    for u in 0..u_len {
+                        let mut cell = [0; 3];
+                        cell[axis] = slice;
+                        cell[u_axis] = min[u_axis] + u as i32;
+                        cell[v_axis] = min[v_axis] + v as i32;
+                        let Some(faces) = self.cells.get(&cell) else {
+                            continue;
+                        };
+                        let mut neighbor = cell;
+                        neighbor[axis] += step;
+                        let hidden = self.cells.get(&neighbor).is_some_and(|other| {
+                            let behind = other[opposite(face)];
+                            behind == faces[face] || materials[behind].material.albedo[3] <= 0.0
+                        });
+                        if !hidden {
+                            mask[v * u_len + u] = Some(faces[face]);
+                        }
+                    }
+                }
+
+                // Grow each unclaimed face into the widest, then tallest, run of its material
+                for v in 0..v_len {
+                    let mut u = 0;
+                    while u < u_len {
+                        let Some(material) = mask[v * u_len + u] else {
+                            u += 1;
+                            continue;
+                        };
+                        let mut width = 1;
+                        while u + width < u_len && mask[v * u_len + u + width] == Some(material) {
+                            width += 1;
+                        }
+                        let mut height = 1;
+                        while v + height < v_len && (u..u + width).all(|x| mask[(v + height) * u_len + x] == Some(material)) {
+                            height += 1;
+                        }
+                        for row in v..v + height {
+                            mask[row * u_len + u..row * u_len + u + width].fill(None);
+                        }
+
+                        let plane = slice + if step > 0 { 1 } else { 0 };
+                        let corner = |du: usize, dv: usize| {
+                            let mut point = [0.0; 3];
+                            point[axis] = plane as f32;
+                            point[u_axis] = (min[u_axis] + (u + du) as i32) as f32;
+                            point[v_axis] = (min[v_axis] + (v + dv) as i32) as f32;
+                            self.origin + Vector3::new(point[0], point[1], point[2]) * self.size
+                        };
+                        let (w, h) = (width as f32, height as f32);
+                        // Counter-clockwise seen from outside: u x v points along +axis
+                        let (corners, uvs) = if step > 0 {
+                            ([corner(0, 0), corner(width, 0), corner(width, height), corner(0, height)], [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)])
+                        } else {
+                            ([corner(0, 0), corner(0, height), corner(width, height), corner(width, 0)], [(0.0, 0.0), (0.0, h), (w, h), (w, 0.0)])
+                        };
+                        let vertices = corners.into_iter().zip(uvs).map(|(position, uv)| Vertex { position, uv, normal: FACE_NORMALS[face] }).collect();
+                        faces.push(Face { vertices, material });
+                        u += width;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn opposite(face: usize) -> usize {
+    face ^ 1
+}
+
+// Texture file a preset draws on one face (see `Cube::face_index`): its own top or bottom
+// texture where it has one, else its first texture
+fn face_texture(library: &MaterialLibrary, preset: u32, face: usize) -> Option<String> {
+    let preset = library.name_of(preset).and_then(|name| library.get(name))?;
+    let own = match face {
+        2 => preset.top_file.as_ref(),
+        3 => preset.bottom_file.as_ref(),
+        _ => None,
+    };
+    own.or(preset.texture_files.first()).cloned()
+}
+
+// A UV sphere with the renderer's spherical mapping, u around the equator and v from the
+// bottom pole up (OBJ's convention, the renderer's v runs top down)
+fn sphere_faces(sphere: &Sphere, material: usize, faces: &mut Vec<Face>) {
+    let vertex = |segment: usize, ring: usize| {
+        let u = segment as f32 / SPHERE_SEGMENTS as f32;
+        let v = 1.0 - ring as f32 / SPHERE_RINGS as f32;
+        let (theta, phi) = ((u - 0.5) * 2.0 * PI, (0.5 - v) * PI);
+        let normal = Vector3::new(phi.cos() * theta.cos(), -phi.sin(), phi.cos() * theta.sin());
+        Vertex { position: sphere.center + normal * sphere.radius, uv: (u, v), normal }
+    };
+    for ring in 0..SPHERE_RINGS {
+        for segment in 0..SPHERE_SEGMENTS {
+            let corners = [vertex(segment, ring), vertex(segment + 1, ring), vertex(segment + 1, ring + 1), vertex(segment, ring + 1)];
+            // The corners on a pole coincide there
+            let vertices = match ring {
+                0 => vec![corners[0], corners[2], corners[3]],
+                _ if ring == SPHERE_RINGS - 1 => vec![corners[0], corners[1], corners[2]],
+                _ => corners.to_vec(),
+            };
+            faces.push(Face { vertices, material });
+        }
+    }
+}
+
+// One `newmtl` per material, textures copied next to the file
+fn write_mtl(path: &Path, materials: &[ExportMaterial], names: &[String]) -> io::Result<()> {
+    let directory = path.parent().unwrap_or(Path::new("."));
+    let mut text = String::from("# Materials for the exported cube scene\n");
+    for (ExportMaterial { material, texture, .. }, name) in materials.iter().zip(names) {
+        let (diffuse, emission) = (material.diffuse, material.emission);
+        text += &format!("\nnewmtl {}\n", name);
+        text += &format!("Kd {} {} {}\n", diffuse.x, diffuse.y, diffuse.z);
+        text += &format!("Ks {0} {0} {0}\n", material.albedo[1].max(material.albedo[2]));
+        text += &format!("Ns {}\n", material.specular);
+        text += &format!("Ni {}\n", material.refractive_index);
+        text += &format!("d {}\n", 1.0 - material.albedo[3]);
+        if emission != Vector3::zero() {
+            text += &format!("Ke {} {} {}\n", emission.x, emission.y, emission.z);
+        }
+        if let Some(file) = texture {
+            match find_asset(file) {
+                Some(source) => {
+                    fs::copy(&source, directory.join(file))?;
+                    text += &format!("map_Kd {}\n", file);
+                }
+                None => println!("Texture {} for {} not found, exported untextured", file, name),
+            }
+        }
+    }
+    fs::write(path, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material(transparency: f32) -> ExportMaterial {
+        ExportMaterial {
+            material: Material::new(Vector3::new(0.5, 0.5, 0.5), 8.0, [0.9, 0.1, 0.0, transparency], 1.0),
+            preset: 0,
+            texture: None,
+        }
+    }
+
+    // Greedy-meshed unit blocks, each with one material index on all its faces
+    fn mesh(blocks: &[([i32; 3], usize)], materials: &[ExportMaterial]) -> Vec<Face> {
+        let cells = blocks.iter().map(|&(cell, material)| (cell, [material; 6])).collect();
+        let mut faces = Vec::new();
+        Grid { size: 1.0, origin: Vector3::zero(), cells }.mesh(materials, &mut faces);
+        faces
+    }
+
+    fn vertex_count(faces: &[Face]) -> usize {
+        faces.iter().map(|face| face.vertices.len()).sum()
+    }
+
+    #[test]
+    fn a_solid_block_of_one_material_is_one_quad_per_side() {
+        let cells = [[0, 0, 0], [1, 0, 0], [0, 1, 0], [1, 1, 0], [0, 0, 1], [1, 0, 1], [0, 1, 1], [1, 1, 1]];
+        let faces = mesh(&cells.map(|cell| (cell, 0)), &[material(0.0)]);
+        // 48 block faces, 24 of them hidden between neighbors, the rest merged 4 to a quad
+        assert_eq!((faces.len(), vertex_count(&faces)), (6, 24));
+        // The UVs run over both blocks, so the texture repeats per block
+        assert!(faces.iter().all(|face| face.vertices.iter().any(|vertex| vertex.uv == (2.0, 2.0))));
+    }
+
+    #[test]
+    fn faces_merge_only_within_a_material() {
+        // A row of two stone then two brick blocks: each long side is two quads, one per material
+        let row = [([0, 0, 0], 0), ([1, 0, 0], 0), ([2, 0, 0], 1), ([3, 0, 0], 1)];
+        let faces = mesh(&row, &[material(0.0), material(0.0)]);
+        assert_eq!((faces.len(), vertex_count(&faces)), (10, 40));
+        assert_eq!(faces.iter().filter(|face| face.material == 1).count(), 5);
+    }
+
+    #[test]
+    fn faces_behind_transparent_neighbors_stay() {
+        // Stone, then two glass blocks: the stone shows through the glass, the glass is hidden
+        // against the stone and between its own blocks
+        let row = [([0, 0, 0], 0), ([1, 0, 0], 1), ([2, 0, 0], 1)];
+        let faces = mesh(&row, &[material(0.0), material(0.8)]);
+        let stone = faces.iter().filter(|face| face.material == 0).count();
+        assert_eq!((stone, faces.len() - stone), (6, 5));
+        assert_eq!(vertex_count(&faces), 44);
+    }
+}
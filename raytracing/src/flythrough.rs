@@ -0,0 +1,373 @@
+use crate::acceleration::Acceleration;
+use crate::animation::Animator;
+use crate::camera::Camera;
+use crate::light::Light;
+use crate::material::TextureOptions;
+use crate::material_library::MaterialLibrary;
+use crate::offline::{bloom_emission, size_after, trace_sample, value_after, write_image};
+use crate::pathtrace::PathLights;
+use crate::physics::FallingBlocks;
+use crate::post::PostProcess;
+use crate::render_thread::BuiltScene;
+use crate::scenes::{GALLERY, find_scene, gallery_builder};
+use crate::script::ScriptScene;
+use crate::settings::RenderOptions;
+use crate::sky::Sky;
+use crate::{bake_sky_visibility, block_lights};
+use raylib::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+// Numbers per step line: time, eye xyz, yaw, pitch, light xyz, intensity, light rgb
+const STEP_FIELDS: usize = 13;
+
+/// Camera and main light at the end of one fixed simulation step
+#[derive(Debug, Clone, Copy)]
+pub struct FlythroughStep {
+    pub time: f32, // Simulation seconds
+    pub eye: Vector3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub light_position: Vector3,
+    pub light_intensity: f32,
+    pub light_color: Color,
+}
+
+impl FlythroughStep {
+    /// Puts the camera and the main light where they were when this step was recorded
+    pub fn apply(&self, camera: &mut Camera, light: &mut Light) {
+        camera.eye = self.eye;
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
+        camera.update_basis_vectors();
+        self.apply_light(light);
+    }
+
+    pub fn apply_light(&self, light: &mut Light) {
+        light.position = self.light_position;
+        light.intensity = self.light_intensity;
+        light.color = self.light_color;
+    }
+}
+
+/// A recorded flythrough: the scene it was recorded in and one entry per simulation step.
+/// Saved as text, `scene <name>` (or `script <file>` for a scene script or scene file) and
+/// `loaded <seconds>`, then one line of numbers per step; floats are written in their
+/// shortest exact form, so a replay lands on bit-identical cameras.
+#[derive(Debug, Clone)]
+pub struct Flythrough {
+    pub scene: String,
+    pub script: Option<PathBuf>, // The scene script or scene file, when the scene came from one
+    pub loaded: f32, // Simulation time the scene was loaded at, when its loose blocks started falling
+    pub steps: Vec<FlythroughStep>,
+}
+
+impl Flythrough {
+    pub fn new(scene: &str, script: Option<&Path>, loaded: f32) -> Self {
+        Flythrough { scene: scene.to_string(), script: script.map(Path::to_path_buf), loaded, steps: Vec::new() }
+    }
+
+    pub fn record(&mut self, time: f32, camera: &Camera, light: &Light) {
+        self.steps.push(FlythroughStep {
+            time,
+            eye: camera.eye,
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+            light_position: light.position,
+            light_intensity: light.intensity,
+            light_color: light.color,
+        });
+    }
+
+    /// Simulation seconds from the first step to the last
+    pub fn duration(&self) -> f32 {
+        match (self.steps.first(), self.steps.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0.0,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut text = String::from("# Flythrough: time eye.x eye.y eye.z yaw pitch light.x light.y light.z intensity r g b\n");
+        match &self.script {
+            Some(script) => text += &format!("script {}\n", script.display()),
+            None => text += &format!("scene {}\n", self.scene),
+        }
+        text += &format!("loaded {}\n", self.loaded);
+        for step in &self.steps {
+            let (eye, light, color) = (step.eye, step.light_position, step.light_color);
+            text += &format!(
+                "{} {} {} {} {} {} {} {} {} {} {} {} {}\n",
+                step.time, eye.x, eye.y, eye.z, step.yaw, step.pitch, light.x, light.y, light.z, step.light_intensity, color.r, color.g, color.b
+            );
+        }
+        fs::write(path, text)
+    }
+
+    /// Reads a saved flythrough, errors carry the file name and line
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Camera at simulation `time`, blended between the steps around it
+    pub fn camera_at(&self, time: f32) -> Option<Camera> {
+        let last = self.steps.len().checked_sub(1)?;
+        let next = self.steps.partition_point(|step| step.time <= time).min(last);
+        let (before, after) = (&self.steps[next.saturating_sub(1)], &self.steps[next]);
+        if after.time <= before.time {
+            return Some(camera_from(after));
+        }
+        let alpha = ((time - before.time) / (after.time - before.time)).clamp(0.0, 1.0);
+        Some(Camera::interpolate(&camera_from(before), &camera_from(after), alpha))
+    }
+}
+
+fn camera_from(step: &FlythroughStep) -> Camera {
+    let mut camera = Camera::new(step.eye, step.eye + Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+    camera.yaw = step.yaw;
+    camera.pitch = step.pitch;
+    camera.update_basis_vectors();
+    camera
+}
+
+fn parse(text: &str) -> Result<Flythrough, String> {
+    let mut flythrough = Flythrough::new("", None, 0.0);
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("scene ") {
+            flythrough.scene = name.trim().to_string();
+            continue;
+        }
+        if let Some(script) = line.strip_prefix("script ") {
+            flythrough.script = Some(PathBuf::from(script.trim()));
+            continue;
+        }
+        if let Some(loaded) = line.strip_prefix("loaded ") {
+            flythrough.loaded = loaded.trim().parse().map_err(|_| format!("line {}: `{}` is not a number", line_number, loaded.trim()))?;
+            continue;
+        }
+        let values: Vec<f32> = line
+            .split_whitespace()
+            .map(|value| value.parse().map_err(|_| format!("line {}: `{}` is not a number", line_number, value)))
+            .collect::<Result<_, _>>()?;
+        let Ok(v) = <[f32; STEP_FIELDS]>::try_from(values) else {
+            return Err(format!("line {}: expected {} numbers", line_number, STEP_FIELDS));
+        };
+        let channel = |value: f32| value.clamp(0.0, 255.0) as u8;
+        flythrough.steps.push(FlythroughStep {
+            time: v[0],
+            eye: Vector3::new(v[1], v[2], v[3]),
+            yaw: v[4],
+            pitch: v[5],
+            light_position: Vector3::new(v[6], v[7], v[8]),
+            light_intensity: v[9],
+            light_color: Color::new(channel(v[10]), channel(v[11]), channel(v[12]), 255),
+        });
+    }
+    if flythrough.steps.is_empty() {
+        return Err("no steps recorded".to_string());
+    }
+    Ok(flythrough)
+}
+
+/// Replays a flythrough one simulation step at a time in place of live input, timing the
+/// replay so runs can be compared for performance
+pub struct Playback {
+    flythrough: Flythrough,
+    next: usize,
+    started: Instant,
+    frames: u32,
+}
+
+impl Playback {
+    pub fn new(flythrough: Flythrough) -> Self {
+        Playback { flythrough, next: 0, started: Instant::now(), frames: 0 }
+    }
+
+    /// The recorded state for the next simulation step, `None` once the recording ran out
+    pub fn next_step(&mut self) -> Option<FlythroughStep> {
+        let step = self.flythrough.steps.get(self.next).copied();
+        self.next += 1;
+        step
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.flythrough.steps.len()
+    }
+
+    /// Counts a traced frame shown during the replay
+    pub fn frame_traced(&mut self) {
+        self.frames += 1;
+    }
+
+    /// One-line timing summary for comparing runs of the same recording
+    pub fn report(&self) -> String {
+        let seconds = self.started.elapsed().as_secs_f32();
+        format!(
+            "Flythrough: {} steps ({:.1}s simulated) in {:.1}s, {} frames traced, {:.1} fps",
+            self.flythrough.steps.len(),
+            self.flythrough.duration(),
+            seconds,
+            self.frames,
+            self.frames as f32 / seconds.max(1e-3)
+        )
+    }
+}
+
+/// Re-renders a recorded flythrough at offline quality:
+/// `--flythrough <file.fly> [--out dir] [--fps N] [--size WxH] [--samples N]`
+/// `[--sharpen 0-1] [--aberration 0-1] [--vignette 0-1] [--grain 0-1] [--bloom 0-1] [--lut <file.cube>]`
+pub struct FlythroughJob {
+    pub recording: PathBuf,
+    pub directory: PathBuf,
+    pub fps: f32,
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub post: PostProcess,
+    pub textures: TextureOptions,
+}
+
+impl FlythroughJob {
+    pub fn from_args() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        let recording = PathBuf::from(value_after(&args, "--flythrough")?);
+        let number = |flag: &str| value_after(&args, flag).and_then(|v| v.parse::<f32>().ok());
+
+        let (width, height) = size_after(&args);
+
+        Some(FlythroughJob {
+            directory: value_after(&args, "--out").map(PathBuf::from).unwrap_or_else(|| recording.with_extension("frames")),
+            recording,
+            fps: number("--fps").unwrap_or(30.0).max(1.0),
+            width,
+            height,
+            samples: number("--samples").map(|n| n as u32).unwrap_or(16).max(1),
+            post: PostProcess::from_args(&args),
+            textures: TextureOptions::from_args(&args),
+        })
+    }
+}
+
+/// Renders the recording at `job.fps`, writing `frame_NNNN.png` per frame. The scene animates
+/// and its loose blocks fall on the recorded simulation clock, as they did live: they start
+/// falling when the scene was loaded, not at the first recorded step.
+pub fn run_flythrough(job: &FlythroughJob) -> Result<(), String> {
+    let flythrough = Flythrough::load(&job.recording)?;
+    let library = MaterialLibrary::load(job.textures);
+    let (BuiltScene { mut objects, mut materials, timeline, .. }, mut light, lamps) = match &flythrough.script {
+        Some(path) => {
+            let mut script = ScriptScene::load(path)?;
+            let mut lights = std::mem::take(&mut script.lights).into_iter();
+            let light = lights.next().unwrap_or_else(|| (GALLERY[0].light)());
+            let lamps: Vec<Light> = lights.collect();
+            (script.into_builder()(&library), light, lamps)
+        }
+        None => {
+            let Some(index) = find_scene(&flythrough.scene) else {
+                return Err(format!("{} was recorded in `{}`, which is not a gallery scene", job.recording.display(), flythrough.scene));
+            };
+            let entry = &GALLERY[index];
+            (gallery_builder(index)(&library), (entry.light)(), (entry.lamps)())
+        }
+    };
+    fs::create_dir_all(&job.directory).map_err(|err| format!("Could not create {}: {}", job.directory.display(), err))?;
+
+    let animator = Animator::new(timeline, &mut objects, &mut materials);
+    let gravity = library.gravity_ids();
+    let mut falling = FallingBlocks::default();
+    falling.fast_forward(&mut objects, &gravity, flythrough.loaded, flythrough.steps[0].time);
+    let mut accel = Acceleration::build(&objects);
+    let args: Vec<String> = std::env::args().collect();
+    let sky = Sky::from_args(&args);
+
+    let start = flythrough.steps[0].time;
+    let frames = (flythrough.duration() * job.fps).floor() as u32 + 1;
+    for frame in 0..frames {
+        let time = start + frame as f32 / job.fps;
+        let Some(camera) = flythrough.camera_at(time) else {
+            break;
+        };
+        // The main light as of the last recorded step at or before this frame
+        let step = flythrough.steps.partition_point(|step| step.time <= time).saturating_sub(1);
+        flythrough.steps[step].apply_light(&mut light);
+
+        let mut moved = animator.apply(&mut objects, &mut materials, time);
+        moved.extend(falling.update(&mut objects, &gravity, time));
+        accel.update(&objects, &moved);
+        // Offline frames are slow enough that re-baking after every move costs little
+        bake_sky_visibility(&mut objects, &accel);
+        let options = RenderOptions { time, ..RenderOptions::default() };
+        let mut lights = vec![light.clone()];
+        lights.extend(lamps.iter().cloned());
+        lights.extend(block_lights(&objects, &materials));
+        let path_lights = PathLights::new(&objects, &materials, &lights);
+
+        let mut colors = Vec::with_capacity((job.width * job.height) as usize);
+        for y in 0..job.height {
+            for x in 0..job.width {
+                let mut sum = Vector3::zero();
+                for sample in 0..job.samples {
+                    sum = sum + trace_sample(&objects, &materials, Some(&accel), &camera, &lights, &path_lights, &sky, &options, x, y, job.width, job.height, sample);
+                }
+                colors.push(sum / job.samples as f32);
+            }
+        }
+
+        println!("Frame {}/{} at {:.2}s", frame + 1, frames, time - start);
+        let emission = bloom_emission(&job.post, &objects, &materials, &accel, &camera, job.width, job.height);
+        write_image(&job.directory.join(format!("frame_{:04}.png", frame)), job.width, job.height, &colors, emission, &job.post, frame, None);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORDING: &str = "\
+# Flythrough: time eye.x eye.y eye.z yaw pitch light.x light.y light.z intensity r g b
+script scenes/garden.ron
+loaded 0.5
+1 0 4 -12 1.5 0 0 10 0 1 255 240 200
+2 2 4 -10 2.5 -0.25 0 10 0 1 255 240 200
+";
+
+    #[test]
+    fn reads_the_scene_and_every_step() {
+        let flythrough = parse(RECORDING).unwrap();
+        assert_eq!(flythrough.script, Some(PathBuf::from("scenes/garden.ron")));
+        assert_eq!(flythrough.loaded, 0.5);
+        assert_eq!(flythrough.steps.len(), 2);
+        assert_eq!(flythrough.duration(), 1.0);
+        let step = flythrough.steps[1];
+        assert_eq!((step.eye, step.yaw, step.pitch), (Vector3::new(2.0, 4.0, -10.0), 2.5, -0.25));
+        assert_eq!(step.light_color, Color::new(255, 240, 200, 255));
+    }
+
+    #[test]
+    fn blends_the_camera_between_the_steps_around_a_time() {
+        let flythrough = parse(RECORDING).unwrap();
+        let camera = flythrough.camera_at(1.25).unwrap();
+        assert_eq!(camera.eye, Vector3::new(0.5, 4.0, -11.5));
+        assert_eq!((camera.yaw, camera.pitch), (1.75, -0.0625));
+        // Outside the recording the camera holds at the first or last step
+        assert_eq!(flythrough.camera_at(0.0).unwrap().eye, Vector3::new(0.0, 4.0, -12.0));
+        assert_eq!(flythrough.camera_at(9.0).unwrap().eye, Vector3::new(2.0, 4.0, -10.0));
+    }
+
+    #[test]
+    fn refuses_broken_recordings() {
+        let error = |text: &str| parse(text).err().unwrap();
+        assert_eq!(error("scene Cave diorama\n"), "no steps recorded");
+        assert_eq!(error("loaded soon\n"), "line 1: `soon` is not a number");
+        assert_eq!(error("1 2 3\n"), format!("line 1: expected {} numbers", STEP_FIELDS));
+    }
+}
@@ -1,34 +1,159 @@
 // framebuffer.rs
 
+use crate::hud::{Overlay, draw_overlay};
+use crate::recorder::Recorder;
+use crate::taa::TemporalHistory;
+use crate::tonemap::tonemap;
 use raylib::prelude::*;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
+/// `id_buffer` value for pixels that see sky
+pub const NO_OBJECT: u32 = u32::MAX;
+
+// Interlaced rendering: a skipped pixel whose previous-frame color is further than this (per
+// channel) from its freshly traced neighbours has moved, and is interpolated instead of woven
+const INTERLACE_MOTION_THRESHOLD: i32 = 24;
+
+/// Back buffer the tracer writes into, plus a shared front buffer holding the last
+/// completed frame. Only `swap_buffers` publishes pixels, so a partially rendered
+/// frame is never presented.
 pub struct Framebuffer {
     pub width: u32,
     pub height: u32,
-    pub color_buffer: Image,
+    pub color_buffer: Vec<Color>,
+    pub depth_buffer: Vec<f32>, // Primary hit distance per pixel, infinity for sky
+    pub id_buffer: Vec<u32>,    // Index of the object seen through each pixel, `NO_OBJECT` for sky
+    pub record_aovs: bool,      // The tracer only fills depth and IDs when asked, it costs an extra ray
+    pub emission_buffer: Vec<Vector3>, // Glow seen through each pixel (see `trace_emission`), empty when bloom is off
+    history: Vec<Color>,        // Previous interlaced frame, the other field is woven in from it
+    history_blocks: (u32, u32), // Block height and rows `history` was traced at
+    pub temporal: TemporalHistory, // Previous anti-aliased frame, when TAA is on
+    accumulation: Vec<Vector3>, // Sum of the linear colors traced since the view last changed
+    accumulated_frames: u32,    // Frames summed into `accumulation`
+    view_epoch: u64,            // View the accumulation belongs to, see `track_view`
+    front: Arc<FrontBuffer>,
     background_color: Color,
     current_color: Color,
 }
 
+/// Last completed frame, readable by the presenter while the next one is traced
+pub struct FrontBuffer {
+    frame: Mutex<Frame>,
+    generation: AtomicU64,
+}
+
+struct Frame {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+/// A finished frame uploaded to the GPU, drawn every loop until the next one arrives
+pub struct PresentedFrame {
+    pub texture: Texture2D,
+    pub width: u32,
+    pub height: u32,
+}
+
 impl Framebuffer {
     pub fn new(width: u32, height: u32) -> Self {
-        let color_buffer = Image::gen_image_color(width as i32, height as i32, Color::BLACK);
+        let color_buffer = vec![Color::BLACK; (width * height) as usize];
+        let front = Arc::new(FrontBuffer {
+            frame: Mutex::new(Frame {
+                width,
+                height,
+                pixels: color_buffer.clone(),
+            }),
+            generation: AtomicU64::new(0),
+        });
         Framebuffer {
             width,
             height,
             color_buffer,
+            depth_buffer: vec![f32::INFINITY; (width * height) as usize],
+            id_buffer: vec![NO_OBJECT; (width * height) as usize],
+            record_aovs: false,
+            emission_buffer: Vec::new(),
+            history: Vec::new(),
+            history_blocks: (0, 0),
+            temporal: TemporalHistory::default(),
+            accumulation: Vec::new(),
+            accumulated_frames: 0,
+            view_epoch: 0,
+            front,
             background_color: Color::BLACK,
             current_color: Color::WHITE,
         }
     }
 
     pub fn clear(&mut self) {
-        self.color_buffer = Image::gen_image_color(self.width as i32, self.height as i32, self.background_color);
+        self.color_buffer.clear();
+        self.color_buffer.resize((self.width * self.height) as usize, self.background_color);
+        self.depth_buffer.clear();
+        self.depth_buffer.resize((self.width * self.height) as usize, f32::INFINITY);
+        self.id_buffer.clear();
+        self.id_buffer.resize((self.width * self.height) as usize, NO_OBJECT);
+        self.emission_buffer.clear();
+    }
+
+    /// Reallocates the back buffer for a new size, the front keeps its frame until the next swap
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width.max(1);
+        self.height = height.max(1);
+        self.clear();
+        self.reset_accumulation();
+    }
+
+    /// Drops the accumulated frames when `epoch` differs from the last one seen; the viewer
+    /// bumps it whenever anything that changes the image does
+    pub fn track_view(&mut self, epoch: u64) {
+        if epoch != self.view_epoch {
+            self.view_epoch = epoch;
+            self.reset_accumulation();
+        }
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        self.accumulation.clear();
+        self.accumulated_frames = 0;
+    }
+
+    /// Frames averaged so far, also the sample index of the next one
+    pub fn accumulated_frames(&self) -> u32 {
+        self.accumulated_frames
+    }
+
+    /// Adds a full frame of linear colors (row-major) to the running sum and writes the
+    /// tonemapped average into the color buffer
+    pub fn accumulate(&mut self, colors: &[Vector3], exposure: f32) {
+        let pixel_count = (self.width * self.height) as usize;
+        if self.accumulation.len() != pixel_count {
+            self.accumulation = vec![Vector3::zero(); pixel_count];
+            self.accumulated_frames = 0;
+        }
+        self.accumulated_frames += 1;
+        let scale = 1.0 / self.accumulated_frames as f32;
+        for (index, (sum, &color)) in self.accumulation.iter_mut().zip(colors).enumerate() {
+            *sum = *sum + color;
+            let (x, y) = (index as u32 % self.width, index as u32 / self.width);
+            self.color_buffer[index] = tonemap(*sum * scale, exposure, x, y);
+        }
     }
 
     pub fn set_pixel(&mut self, x: u32, y: u32) {
         if x < self.width && y < self.height {
-            self.color_buffer.draw_pixel(x as i32, y as i32, self.current_color);
+            self.color_buffer[(y * self.width + x) as usize] = self.current_color;
+        }
+    }
+
+    /// Stores the first-hit distance and object for a pixel
+    pub fn set_aovs(&mut self, x: u32, y: u32, depth: f32, object: u32) {
+        if x < self.width && y < self.height {
+            let index = (y * self.width + x) as usize;
+            self.depth_buffer[index] = depth;
+            self.id_buffer[index] = object;
         }
     }
 
@@ -40,18 +165,198 @@ impl Framebuffer {
         self.current_color = color;
     }
 
-    pub fn _render_to_file(&self, file_path: &str) {
-        self.color_buffer.export_image(file_path);
+    /// Keeps the finished frame for the next interlaced field to weave into, or drops it
+    pub fn keep_history(&mut self, keep: bool) {
+        if keep {
+            self.history.clone_from(&self.color_buffer);
+        } else {
+            self.history.clear();
+        }
+    }
+
+    /// Fills the rows of blocks (`block_height` pixels tall, `block_rows` of them) an interlaced
+    /// frame skipped, every other one starting at `field ^ 1`: from the previous frame where the
+    /// image held still, else interpolated from the traced rows above and below. A previous
+    /// frame traced at another render scale has its blocks elsewhere and is dropped
+    pub fn fill_skipped_field(&mut self, block_height: u32, block_rows: u32, field: u32) {
+        if self.history_blocks != (block_height, block_rows) {
+            self.history.clear();
+            self.history_blocks = (block_height, block_rows);
+        }
+        let width = self.width as usize;
+        let woven = self.history.len() == self.color_buffer.len();
+        for block_row in (0..block_rows).filter(|row| row % 2 != field) {
+            let start_y = (block_row * block_height).min(self.height) as usize;
+            let end_y = ((block_row + 1) * block_height).min(self.height) as usize;
+            let above = start_y.checked_sub(1);
+            let below = (block_row + 1 < block_rows && end_y < self.height as usize).then_some(end_y);
+            let Some(nearest) = above.or(below) else {
+                continue;
+            };
+            for x in 0..width {
+                let bob = match (above, below) {
+                    (Some(a), Some(b)) => average(self.color_buffer[a * width + x], self.color_buffer[b * width + x]),
+                    _ => self.color_buffer[nearest * width + x],
+                };
+                let (depth, object) = (self.depth_buffer[nearest * width + x], self.id_buffer[nearest * width + x]);
+                for y in start_y..end_y {
+                    let index = y * width + x;
+                    let still = woven && max_difference(self.history[index], bob) <= INTERLACE_MOTION_THRESHOLD;
+                    self.color_buffer[index] = if still { self.history[index] } else { bob };
+                    self.depth_buffer[index] = depth;
+                    self.id_buffer[index] = object;
+                }
+            }
+        }
+    }
+
+    /// Makes every pixel whose primary ray missed all geometry fully transparent;
+    /// needs a frame rendered with `record_aovs`
+    pub fn cut_out_sky(&mut self) {
+        for (pixel, &object) in self.color_buffer.iter_mut().zip(&self.id_buffer) {
+            if object == NO_OBJECT {
+                *pixel = Color::BLANK;
+            }
+        }
+    }
+
+    /// Handle to the front buffer for the presenting thread
+    pub fn front(&self) -> Arc<FrontBuffer> {
+        Arc::clone(&self.front)
+    }
+
+    /// Publishes the completed back buffer; the old front becomes the next back buffer
+    pub fn swap_buffers(&mut self) {
+        {
+            let mut frame = self.front.frame.lock().unwrap();
+            std::mem::swap(&mut frame.pixels, &mut self.color_buffer);
+            frame.width = self.width;
+            frame.height = self.height;
+        }
+        self.front.generation.fetch_add(1, Ordering::Release);
+        self.clear();
+    }
+
+    /// Copies the back buffer into a raylib image for export
+    pub fn to_image(&self) -> Image {
+        pixels_to_image(self.width, self.height, &self.color_buffer)
+    }
+
+    pub fn render_to_file(&self, file_path: &str) {
+        self.to_image().export_image(file_path);
+    }
+}
+
+impl FrontBuffer {
+    /// Increases every time a new frame is swapped in
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Copies the front buffer into a raylib image for export
+    pub fn to_image(&self) -> Image {
+        let frame = self.frame.lock().unwrap();
+        pixels_to_image(frame.width, frame.height, &frame.pixels)
     }
 
-    pub fn swap_buffers(
+    /// Hands the front buffer to a recorder started while it is on screen; any newer frame
+    /// reaches the recorder through `upload` before it is next presented
+    pub fn record(&self, recorder: &mut Recorder) {
+        let frame = self.frame.lock().unwrap();
+        recorder.frame_uploaded(frame.width, frame.height, &frame.pixels);
+    }
+
+    /// Uploads the current front buffer to a GPU texture, done once per finished frame. A
+    /// recorder gets the same pixels under the same lock, so it records what is displayed.
+    pub fn upload(
         &self,
         window: &mut RaylibHandle,
         raylib_thread: &RaylibThread,
-    ) {
-        if let Ok(texture) = window.load_texture_from_image(raylib_thread, &self.color_buffer) {
-            let mut renderer = window.begin_drawing(raylib_thread);
-            renderer.draw_texture(&texture, 0, 0, Color::WHITE);
+        recorder: Option<&mut Recorder>,
+    ) -> Option<PresentedFrame> {
+        let (width, height, image) = {
+            let frame = self.frame.lock().unwrap();
+            if let Some(recorder) = recorder {
+                recorder.frame_uploaded(frame.width, frame.height, &frame.pixels);
+            }
+            (frame.width, frame.height, pixels_to_image(frame.width, frame.height, &frame.pixels))
+        };
+        window
+            .load_texture_from_image(raylib_thread, &image)
+            .ok()
+            .map(|texture| PresentedFrame { texture, width, height })
+    }
+}
+
+/// Packed RGB bytes of `pixels`, nearest-resampled to the requested size
+pub(crate) fn rgb_bytes(width: u32, height: u32, pixels: &[Color], out_width: u32, out_height: u32) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity((out_width * out_height * 3) as usize);
+    for y in 0..out_height {
+        let source_y = (y * height / out_height).min(height - 1);
+        for x in 0..out_width {
+            let source_x = (x * width / out_width).min(width - 1);
+            let color = pixels[(source_y * width + source_x) as usize];
+            bytes.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+    }
+    bytes
+}
+
+pub(crate) fn pixels_to_image(width: u32, height: u32, pixels: &[Color]) -> Image {
+    let mut image = Image::gen_image_color(width as i32, height as i32, Color::BLACK);
+    for y in 0..height {
+        for x in 0..width {
+            image.draw_pixel(x as i32, y as i32, pixels[(y * width + x) as usize]);
         }
     }
+    image
+}
+
+/// Draws the latest frame letterboxed to the window plus the overlay; called every loop so input keeps polling.
+/// A recorder is then fed the frame on screen, which only fails if ffmpeg does.
+pub fn present(
+    window: &mut RaylibHandle,
+    raylib_thread: &RaylibThread,
+    frame: Option<&PresentedFrame>,
+    overlay: &Overlay,
+    recorder: Option<&mut Recorder>,
+) -> io::Result<()> {
+    let window_width = window.get_screen_width() as f32;
+    let window_height = window.get_screen_height() as f32;
+
+    let mut renderer = window.begin_drawing(raylib_thread);
+    renderer.clear_background(Color::BLACK);
+
+    if let Some(frame) = frame {
+        // Scale the internal resolution to the window, letterboxed to keep aspect
+        let scale = (window_width / frame.width as f32).min(window_height / frame.height as f32);
+        let dest_width = frame.width as f32 * scale;
+        let dest_height = frame.height as f32 * scale;
+        let source = Rectangle::new(0.0, 0.0, frame.width as f32, frame.height as f32);
+        let dest = Rectangle::new(
+            (window_width - dest_width) * 0.5,
+            (window_height - dest_height) * 0.5,
+            dest_width,
+            dest_height,
+        );
+        renderer.draw_texture_pro(&frame.texture, source, dest, Vector2::zero(), 0.0, Color::WHITE);
+    }
+
+    draw_overlay(&mut renderer, overlay, window_width as i32, window_height as i32);
+    drop(renderer);
+
+    match recorder {
+        Some(recorder) => recorder.write_frame(),
+        None => Ok(()),
+    }
+}
+
+fn average(a: Color, b: Color) -> Color {
+    let mean = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+    Color::new(mean(a.r, b.r), mean(a.g, b.g), mean(a.b, b.b), mean(a.a, b.a))
+}
+
+fn max_difference(a: Color, b: Color) -> i32 {
+    let diff = |a: u8, b: u8| (a as i32 - b as i32).abs();
+    diff(a.r, b.r).max(diff(a.g, b.g)).max(diff(a.b, b.b))
 }
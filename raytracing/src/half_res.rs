@@ -0,0 +1,73 @@
+use raylib::prelude::Vector3;
+
+// Relative depth difference up to which a half-resolution sample counts as the same surface
+const DEPTH_TOLERANCE: f32 = 0.1;
+
+/// Reflections and refractions traced at half resolution, one sample per 2x2 pixels at
+/// the top-left pixel. Each sample keeps the distance and object its primary ray hit, so
+/// upsampling only blends light from the surface the pixel itself sees.
+pub struct SecondaryBuffer {
+    pub width: u32,
+    pub height: u32,
+    colors: Vec<Vector3>,
+    depths: Vec<f32>, // Infinity for sky
+    objects: Vec<u32>,
+}
+
+impl SecondaryBuffer {
+    /// Buffer for a frame traced at `width` x `height` pixels (the render grid below full scale)
+    pub fn new(width: u32, height: u32) -> Self {
+        let (width, height) = (width.div_ceil(2).max(1), height.div_ceil(2).max(1));
+        let size = (width * height) as usize;
+        SecondaryBuffer {
+            width,
+            height,
+            colors: vec![Vector3::zero(); size],
+            depths: vec![f32::INFINITY; size],
+            objects: vec![0; size],
+        }
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Vector3, depth: f32, object: u32) {
+        let index = (y * self.width + x) as usize;
+        self.colors[index] = color;
+        self.depths[index] = depth;
+        self.objects[index] = object;
+    }
+
+    /// Secondary light at traced pixel (x, y) that sees `object` at `depth`:
+    /// bilinear over the four nearest samples, leaving out those on another surface.
+    /// When none match the nearest sample is used as is.
+    pub fn upsample(&self, x: u32, y: u32, depth: f32, object: u32) -> Vector3 {
+        let (fx, fy) = (x as f32 * 0.5, y as f32 * 0.5);
+        let (x0, y0) = ((fx as u32).min(self.width - 1), (fy as u32).min(self.height - 1));
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+
+        let mut sum = Vector3::zero();
+        let mut total = 0.0;
+        for (sx, sy, weight) in [(x0, y0, (1.0 - tx) * (1.0 - ty)), (x1, y0, tx * (1.0 - ty)), (x0, y1, (1.0 - tx) * ty), (x1, y1, tx * ty)] {
+            let index = (sy * self.width + sx) as usize;
+            if weight > 0.0 && self.same_surface(index, depth, object) {
+                sum = sum + self.colors[index] * weight;
+                total += weight;
+            }
+        }
+        if total > 1e-4 {
+            sum / total
+        } else {
+            self.colors[(y0 * self.width + x0) as usize]
+        }
+    }
+
+    fn same_surface(&self, index: usize, depth: f32, object: u32) -> bool {
+        if self.objects[index] != object {
+            return false;
+        }
+        let sample = self.depths[index];
+        if sample.is_infinite() || depth.is_infinite() {
+            return sample.is_infinite() && depth.is_infinite();
+        }
+        (sample - depth).abs() <= DEPTH_TOLERANCE * depth.max(1e-3)
+    }
+}
@@ -0,0 +1,73 @@
+use raylib::prelude::*;
+
+// On-screen text, in window pixels
+const FONT_SIZE: i32 = 20;
+const MARGIN: i32 = 10;
+const LINE_SPACING: i32 = 4;
+
+// Panel text (help, scene menu), smaller so the whole binding map fits
+const PANEL_FONT_SIZE: i32 = 16;
+const PANEL_COLUMN_GAP: i32 = 24;
+const PANEL_BACKDROP: Color = Color::new(0, 0, 0, 190);
+
+// Crosshair arm length and the gap left around the exact center
+const CROSSHAIR_SIZE: i32 = 8;
+const CROSSHAIR_GAP: i32 = 3;
+
+/// What gets drawn over the presented frame
+#[derive(Default)]
+pub struct Overlay {
+    pub lines: Vec<String>, // Status lines, top left
+    pub crosshair: bool,
+    pub panel: Vec<String>, // Help or scene menu, drawn centered over everything else when not empty
+}
+
+/// Draws the overlay in window coordinates, text with a drop shadow so it reads on the sky too
+pub fn draw_overlay(renderer: &mut RaylibDrawHandle, overlay: &Overlay, window_width: i32, window_height: i32) {
+    let mut y = MARGIN;
+    for line in &overlay.lines {
+        renderer.draw_text(line, MARGIN + 1, y + 1, FONT_SIZE, Color::BLACK);
+        renderer.draw_text(line, MARGIN, y, FONT_SIZE, Color::WHITE);
+        y += FONT_SIZE + LINE_SPACING;
+    }
+
+    if overlay.crosshair {
+        let (x, y) = (window_width / 2, window_height / 2);
+        for (color, offset) in [(Color::BLACK, 1), (Color::WHITE, 0)] {
+            let (x, y) = (x + offset, y + offset);
+            renderer.draw_line(x - CROSSHAIR_SIZE, y, x - CROSSHAIR_GAP, y, color);
+            renderer.draw_line(x + CROSSHAIR_GAP, y, x + CROSSHAIR_SIZE, y, color);
+            renderer.draw_line(x, y - CROSSHAIR_SIZE, x, y - CROSSHAIR_GAP, color);
+            renderer.draw_line(x, y + CROSSHAIR_GAP, x, y + CROSSHAIR_SIZE, color);
+        }
+    }
+
+    if !overlay.panel.is_empty() {
+        draw_panel(renderer, &overlay.panel, window_width, window_height);
+    }
+}
+
+// Lines flow top to bottom in as many columns as the window height needs
+fn draw_panel(renderer: &mut RaylibDrawHandle, lines: &[String], window_width: i32, window_height: i32) {
+    let line_height = PANEL_FONT_SIZE + LINE_SPACING;
+    let rows = (((window_height - 2 * MARGIN) / line_height).max(1) as usize).min(lines.len());
+    let columns: Vec<&[String]> = lines.chunks(rows).collect();
+    let widths: Vec<i32> = columns
+        .iter()
+        .map(|column| column.iter().map(|line| measure_text(line, PANEL_FONT_SIZE)).max().unwrap_or(0))
+        .collect();
+
+    let panel_width = widths.iter().sum::<i32>() + PANEL_COLUMN_GAP * (columns.len() as i32 - 1) + 2 * MARGIN;
+    let panel_height = rows as i32 * line_height + 2 * MARGIN;
+    let left = ((window_width - panel_width) / 2).max(0);
+    let top = ((window_height - panel_height) / 2).max(0);
+    renderer.draw_rectangle(left, top, panel_width, panel_height, PANEL_BACKDROP);
+
+    let mut x = left + MARGIN;
+    for (column, width) in columns.iter().zip(&widths) {
+        for (row, line) in column.iter().enumerate() {
+            renderer.draw_text(line, x, top + MARGIN + row as i32 * line_height, PANEL_FONT_SIZE, Color::WHITE);
+        }
+        x += width + PANEL_COLUMN_GAP;
+    }
+}
@@ -0,0 +1,71 @@
+use crate::cube::Cube;
+use crate::framebuffer::{Framebuffer, NO_OBJECT, pixels_to_image};
+use crate::ray_intersect::SceneObject;
+use crate::rng::mix64;
+use raylib::prelude::*;
+use std::path::{Path, PathBuf};
+
+// Outline drawn around the blocks using the selected material
+const SELECTION_COLOR: Color = Color::new(255, 220, 40, 255);
+
+/// False color for an ID: the same ID always gets the same color, so a mask is one color key.
+/// 0 (no ID) stays black.
+pub fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::BLACK;
+    }
+    let hash = mix64(id as u64);
+    Color::new((hash >> 16) as u8 | 0x20, (hash >> 24) as u8 | 0x20, (hash >> 32) as u8 | 0x20, 255)
+}
+
+/// Where the ID passes for an image go: `shot.png` -> `shot_ids.png` and `shot_materials.png`
+pub fn id_paths(image_path: &Path) -> (PathBuf, PathBuf) {
+    let stem = image_path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    (
+        image_path.with_file_name(format!("{}_ids.png", stem)),
+        image_path.with_file_name(format!("{}_materials.png", stem)),
+    )
+}
+
+/// Writes an object ID pass and a material ID pass next to `image_path` from per-pixel object
+/// indices (`NO_OBJECT` for sky)
+pub fn write_id_passes(image_path: &Path, width: u32, height: u32, objects_seen: &[u32], objects: &[SceneObject]) {
+    // Only blocks carry IDs, other shapes come out black like the sky
+    let color_of = |index: u32, id: fn(&Cube) -> u32| match objects.get(index as usize).and_then(|object| object.as_cube()) {
+        Some(object) if index != NO_OBJECT => id_color(id(object)),
+        _ => Color::BLACK,
+    };
+    // Object IDs start at 0, shift them so the first block isn't black like the sky
+    let object_colors: Vec<Color> = objects_seen.iter().map(|&index| color_of(index, |object| object.id + 1)).collect();
+    let material_colors: Vec<Color> = objects_seen.iter().map(|&index| color_of(index, |object| object.material_id)).collect();
+
+    let (ids_path, materials_path) = id_paths(image_path);
+    pixels_to_image(width, height, &object_colors).export_image(&ids_path.to_string_lossy());
+    pixels_to_image(width, height, &material_colors).export_image(&materials_path.to_string_lossy());
+    println!("Wrote {} and {}", ids_path.display(), materials_path.display());
+}
+
+/// Outlines every block whose material matches `selected`, using the frame's ID buffer
+pub fn highlight_selection(framebuffer: &mut Framebuffer, objects: &[SceneObject], selected: u32) {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let is_selected = |x: u32, y: u32| {
+        let index = framebuffer.id_buffer[(y * width + x) as usize];
+        index != NO_OBJECT && objects.get(index as usize).and_then(|object| object.as_cube()).is_some_and(|object| object.material_id == selected)
+    };
+    let mut edges = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            if !is_selected(x, y) {
+                continue;
+            }
+            let on_border = x == 0 || y == 0 || x + 1 == width || y + 1 == height;
+            if on_border || !is_selected(x - 1, y) || !is_selected(x + 1, y) || !is_selected(x, y - 1) || !is_selected(x, y + 1) {
+                edges.push((x, y));
+            }
+        }
+    }
+    framebuffer.set_current_color(SELECTION_COLOR);
+    for (x, y) in edges {
+        framebuffer.set_pixel(x, y);
+    }
+}
@@ -0,0 +1,285 @@
+use raylib::prelude::*;
+
+/// Everything the keyboard does in the interactive viewer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    LookLeft,
+    LookRight,
+    LookUp,
+    LookDown,
+    ToggleHelp,
+    SceneMenu,
+    ReloadScript,
+    ToggleFullscreen,
+    CycleResolution,
+    ToggleInterlaced,
+    ToggleHalfResSecondary,
+    ToggleTaa,
+    ToggleLightProbes,
+    ToggleGlossyReflections,
+    ToggleSpectral,
+    CycleAmbientOcclusion,
+    TogglePathTracing,
+    CycleReflectionDepth,
+    CycleRefractionDepth,
+    CycleFpsCap,
+    ToggleRecording,
+    ToggleFlythroughRecording,
+    PlayFlythrough,
+    Screenshot,
+    SaveScene,
+    AddBookmark,
+    ToggleSplitView,
+    NextComparison,
+    ShadowBiasDown,
+    ShadowBiasUp,
+    ExposureDown,
+    ExposureUp,
+    CycleSharpen,
+    CycleAberration,
+    CycleVignette,
+    CycleGrain,
+    CycleBloom,
+    CycleLightAnimation,
+    ToggleLightEdit,
+    ToggleLight,
+    LightDimmer,
+    LightBrighter,
+    CycleLightColor,
+    ToggleHeadlamp,
+    ToggleLightGizmos,
+    ReloadMaterials,
+    SelectBlock,
+    PlaceBlock,
+    RemoveBlock,
+    ReflectivityDown,
+    ReflectivityUp,
+    TransparencyDown,
+    TransparencyUp,
+    SpecularDown,
+    SpecularUp,
+}
+
+/// Modifier that has to be held with a binding's key; `None` means neither Shift nor Alt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    None,
+    Shift,
+    Alt,
+}
+
+pub struct Binding {
+    pub action: Action,
+    pub key: KeyboardKey,
+    pub modifier: Modifier,
+    pub description: &'static str,
+}
+
+const fn bind(action: Action, key: KeyboardKey, description: &'static str) -> Binding {
+    Binding { action, key, modifier: Modifier::None, description }
+}
+
+const fn bind_with(modifier: Modifier, action: Action, key: KeyboardKey, description: &'static str) -> Binding {
+    Binding { action, key, modifier, description }
+}
+
+/// The binding map: input handling and the F1 help overlay both read it, in this order.
+/// An action may have several bindings.
+pub const BINDINGS: &[Binding] = &[
+    bind(Action::MoveForward, KeyboardKey::KEY_W, "Move forward (the light in light edit mode)"),
+    bind(Action::MoveBack, KeyboardKey::KEY_S, "Move back"),
+    bind(Action::MoveLeft, KeyboardKey::KEY_A, "Move left"),
+    bind(Action::MoveRight, KeyboardKey::KEY_D, "Move right"),
+    bind(Action::MoveUp, KeyboardKey::KEY_Q, "Move up"),
+    bind(Action::MoveDown, KeyboardKey::KEY_E, "Move down"),
+    bind(Action::LookLeft, KeyboardKey::KEY_LEFT, "Look left"),
+    bind(Action::LookRight, KeyboardKey::KEY_RIGHT, "Look right"),
+    bind(Action::LookUp, KeyboardKey::KEY_UP, "Look up"),
+    bind(Action::LookDown, KeyboardKey::KEY_DOWN, "Look down"),
+    bind(Action::ToggleHelp, KeyboardKey::KEY_F1, "Show/hide this help"),
+    bind(Action::SceneMenu, KeyboardKey::KEY_M, "Scene menu (number keys pick a scene)"),
+    bind(Action::ReloadScript, KeyboardKey::KEY_F12, "Re-run the --script scene script"),
+    bind_with(Modifier::Alt, Action::ToggleFullscreen, KeyboardKey::KEY_ENTER, "Fullscreen"),
+    bind(Action::CycleResolution, KeyboardKey::KEY_F2, "Cycle render resolution"),
+    bind(Action::CycleFpsCap, KeyboardKey::KEY_F3, "Cycle FPS cap"),
+    bind(Action::ToggleInterlaced, KeyboardKey::KEY_I, "Interlaced rendering (half the rows per frame)"),
+    bind(Action::ToggleHalfResSecondary, KeyboardKey::KEY_J, "Half-resolution reflections and refractions"),
+    bind_with(Modifier::Shift, Action::ToggleTaa, KeyboardKey::KEY_J, "Temporal anti-aliasing"),
+    bind(Action::CycleReflectionDepth, KeyboardKey::KEY_Y, "Cycle reflection depth"),
+    bind_with(Modifier::Shift, Action::CycleRefractionDepth, KeyboardKey::KEY_Y, "Cycle refraction depth"),
+    bind(Action::ToggleRecording, KeyboardKey::KEY_F9, "Start/stop video recording"),
+    bind(Action::ToggleFlythroughRecording, KeyboardKey::KEY_R, "Start/stop recording a flythrough"),
+    bind_with(Modifier::Shift, Action::PlayFlythrough, KeyboardKey::KEY_R, "Replay the last flythrough"),
+    bind(Action::Screenshot, KeyboardKey::KEY_T, "Screenshot with depth and ID passes"),
+    bind_with(Modifier::Shift, Action::SaveScene, KeyboardKey::KEY_T, "Save the scene to a .ron scene file"),
+    bind_with(Modifier::Shift, Action::AddBookmark, KeyboardKey::KEY_B, "Bookmark the camera for --render-bookmarks"),
+    bind(Action::ToggleSplitView, KeyboardKey::KEY_F5, "A/B split view (drag to move the divider)"),
+    bind(Action::NextComparison, KeyboardKey::KEY_F6, "Next A/B comparison"),
+    bind(Action::ShadowBiasDown, KeyboardKey::KEY_LEFT_BRACKET, "Halve shadow bias"),
+    bind(Action::ShadowBiasUp, KeyboardKey::KEY_RIGHT_BRACKET, "Double shadow bias"),
+    bind_with(Modifier::Shift, Action::ExposureDown, KeyboardKey::KEY_MINUS, "Exposure down"),
+    bind_with(Modifier::Shift, Action::ExposureUp, KeyboardKey::KEY_EQUAL, "Exposure up"),
+    bind(Action::ExposureDown, KeyboardKey::KEY_KP_SUBTRACT, "Exposure down"),
+    bind(Action::ExposureUp, KeyboardKey::KEY_KP_ADD, "Exposure up"),
+    bind(Action::CycleSharpen, KeyboardKey::KEY_U, "Cycle sharpening"),
+    bind(Action::CycleAberration, KeyboardKey::KEY_P, "Cycle chromatic aberration"),
+    bind(Action::CycleVignette, KeyboardKey::KEY_F10, "Cycle vignette"),
+    bind(Action::CycleGrain, KeyboardKey::KEY_F11, "Cycle film grain"),
+    bind_with(Modifier::Shift, Action::CycleBloom, KeyboardKey::KEY_U, "Cycle bloom around glowing blocks"),
+    bind(Action::CycleLightAnimation, KeyboardKey::KEY_L, "Cycle light animation"),
+    bind(Action::ToggleLightEdit, KeyboardKey::KEY_F4, "Light edit mode (movement keys move the light)"),
+    bind(Action::ToggleLight, KeyboardKey::KEY_O, "Light on/off"),
+    bind(Action::LightDimmer, KeyboardKey::KEY_MINUS, "Light intensity down"),
+    bind(Action::LightBrighter, KeyboardKey::KEY_EQUAL, "Light intensity up"),
+    bind(Action::CycleLightColor, KeyboardKey::KEY_K, "Cycle light color"),
+    bind(Action::ToggleHeadlamp, KeyboardKey::KEY_H, "Headlamp"),
+    bind(Action::ToggleLightGizmos, KeyboardKey::KEY_G, "Show light positions"),
+    bind_with(Modifier::Shift, Action::ToggleLightProbes, KeyboardKey::KEY_G, "Bounce light from baked light probes"),
+    bind(Action::ReloadMaterials, KeyboardKey::KEY_F7, "Reload materials.txt"),
+    bind(Action::SelectBlock, KeyboardKey::KEY_F8, "Select the block under the crosshair"),
+    bind(Action::PlaceBlock, KeyboardKey::KEY_INSERT, "Place a copy of the block under the crosshair against its face"),
+    bind(Action::RemoveBlock, KeyboardKey::KEY_DELETE, "Remove the block under the crosshair"),
+    bind(Action::ReflectivityDown, KeyboardKey::KEY_Z, "Selected material: less reflective"),
+    bind(Action::ReflectivityUp, KeyboardKey::KEY_X, "Selected material: more reflective"),
+    bind_with(Modifier::Shift, Action::ToggleGlossyReflections, KeyboardKey::KEY_X, "Glossy reflections from a prefiltered capture"),
+    bind(Action::TransparencyDown, KeyboardKey::KEY_C, "Selected material: less transparent"),
+    bind(Action::TransparencyUp, KeyboardKey::KEY_V, "Selected material: more transparent"),
+    bind_with(Modifier::Shift, Action::ToggleSpectral, KeyboardKey::KEY_V, "Spectral dispersion in diamond"),
+    bind(Action::CycleAmbientOcclusion, KeyboardKey::KEY_F, "Cycle ambient occlusion rays (off, 4, 8, 16)"),
+    bind_with(Modifier::Shift, Action::TogglePathTracing, KeyboardKey::KEY_F, "Path tracing (converges while the view holds still)"),
+    bind(Action::SpecularDown, KeyboardKey::KEY_B, "Selected material: duller highlight"),
+    bind(Action::SpecularUp, KeyboardKey::KEY_N, "Selected material: sharper highlight"),
+];
+
+/// True on the frame any of the action's bindings is pressed with its modifier
+pub fn pressed(window: &RaylibHandle, action: Action) -> bool {
+    bindings_for(action).any(|binding| modifier_held(window, binding.modifier) && window.is_key_pressed(binding.key))
+}
+
+/// True while any of the action's keys is held, modifiers ignored (for continuous movement)
+pub fn held(window: &RaylibHandle, action: Action) -> bool {
+    bindings_for(action).any(|binding| window.is_key_down(binding.key))
+}
+
+/// True while any bound key or number key is down or the mouse moves or clicks, for waking
+/// the viewer from idle
+pub fn any_activity(window: &RaylibHandle) -> bool {
+    let mouse = window.get_mouse_delta();
+    BINDINGS.iter().any(|binding| window.is_key_down(binding.key))
+        || number_pressed(window).is_some()
+        || mouse.x != 0.0
+        || mouse.y != 0.0
+        || window.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT)
+}
+
+/// Number key pressed this frame, 1-9, for picking menu entries
+pub fn number_pressed(window: &RaylibHandle) -> Option<usize> {
+    use KeyboardKey::*;
+    let keys = [KEY_ONE, KEY_TWO, KEY_THREE, KEY_FOUR, KEY_FIVE, KEY_SIX, KEY_SEVEN, KEY_EIGHT, KEY_NINE];
+    keys.iter().position(|&key| window.is_key_pressed(key)).map(|index| index + 1)
+}
+
+/// One `key: description` line per binding, in map order
+pub fn help_lines() -> Vec<String> {
+    BINDINGS
+        .iter()
+        .map(|binding| {
+            let modifier = match binding.modifier {
+                Modifier::None => "",
+                Modifier::Shift => "Shift+",
+                Modifier::Alt => "Alt+",
+            };
+            format!("{}{}: {}", modifier, key_name(binding.key), binding.description)
+        })
+        .collect()
+}
+
+fn bindings_for(action: Action) -> impl Iterator<Item = &'static Binding> {
+    BINDINGS.iter().filter(move |binding| binding.action == action)
+}
+
+fn modifier_held(window: &RaylibHandle, modifier: Modifier) -> bool {
+    let shift = window.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) || window.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT);
+    let alt = window.is_key_down(KeyboardKey::KEY_LEFT_ALT) || window.is_key_down(KeyboardKey::KEY_RIGHT_ALT);
+    match modifier {
+        Modifier::None => !shift && !alt,
+        Modifier::Shift => shift,
+        Modifier::Alt => alt,
+    }
+}
+
+fn key_name(key: KeyboardKey) -> &'static str {
+    use KeyboardKey::*;
+    match key {
+        KEY_A => "A",
+        KEY_B => "B",
+        KEY_C => "C",
+        KEY_D => "D",
+        KEY_E => "E",
+        KEY_F => "F",
+        KEY_G => "G",
+        KEY_H => "H",
+        KEY_I => "I",
+        KEY_J => "J",
+        KEY_K => "K",
+        KEY_L => "L",
+        KEY_M => "M",
+        KEY_N => "N",
+        KEY_O => "O",
+        KEY_P => "P",
+        KEY_Q => "Q",
+        KEY_R => "R",
+        KEY_S => "S",
+        KEY_T => "T",
+        KEY_U => "U",
+        KEY_V => "V",
+        KEY_W => "W",
+        KEY_X => "X",
+        KEY_Y => "Y",
+        KEY_Z => "Z",
+        KEY_ZERO => "0",
+        KEY_ONE => "1",
+        KEY_TWO => "2",
+        KEY_THREE => "3",
+        KEY_FOUR => "4",
+        KEY_FIVE => "5",
+        KEY_SIX => "6",
+        KEY_SEVEN => "7",
+        KEY_EIGHT => "8",
+        KEY_NINE => "9",
+        KEY_F1 => "F1",
+        KEY_F2 => "F2",
+        KEY_F3 => "F3",
+        KEY_F4 => "F4",
+        KEY_F5 => "F5",
+        KEY_F6 => "F6",
+        KEY_F7 => "F7",
+        KEY_F8 => "F8",
+        KEY_F9 => "F9",
+        KEY_F10 => "F10",
+        KEY_F11 => "F11",
+        KEY_F12 => "F12",
+        KEY_LEFT => "Left",
+        KEY_RIGHT => "Right",
+        KEY_UP => "Up",
+        KEY_DOWN => "Down",
+        KEY_ENTER => "Enter",
+        KEY_SPACE => "Space",
+        KEY_TAB => "Tab",
+        KEY_INSERT => "Insert",
+        KEY_DELETE => "Delete",
+        KEY_MINUS => "-",
+        KEY_EQUAL => "=",
+        KEY_LEFT_BRACKET => "[",
+        KEY_RIGHT_BRACKET => "]",
+        KEY_KP_ADD => "Keypad +",
+        KEY_KP_SUBTRACT => "Keypad -",
+        _ => "?",
+    }
+}
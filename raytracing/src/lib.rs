@@ -0,0 +1,1541 @@
+use raylib::prelude::*;
+use std::f32::consts::PI;
+
+pub mod acceleration;
+pub mod animation;
+pub mod atmosphere;
+pub mod bookmarks;
+pub mod bvh;
+pub mod camera;
+pub mod caustics;
+pub mod color_ops;
+pub mod console;
+pub mod cube;
+pub mod daycycle;
+pub mod demo;
+pub mod denoise;
+pub mod depth;
+pub mod dispersion;
+pub mod distributed;
+pub mod dynamic_resolution;
+pub mod export;
+pub mod flythrough;
+pub mod framebuffer;
+pub mod half_res;
+pub mod hud;
+pub mod id_pass;
+pub mod input;
+pub mod light;
+pub mod light_animation;
+pub mod light_sampling;
+pub mod lut;
+pub mod material;
+pub mod material_library;
+pub mod medium;
+pub mod metadata;
+pub mod offline;
+pub mod pathtrace;
+pub mod physics;
+pub mod portals;
+pub mod post;
+pub mod probes;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod ray_intersect;
+pub mod recorder;
+pub mod reflection_probe;
+pub mod render_thread;
+pub mod rng;
+pub mod sampler;
+pub mod scene_file;
+pub mod scenes;
+pub mod script;
+pub mod settings;
+pub mod sky;
+pub mod sphere;
+pub mod taa;
+pub mod timestep;
+pub mod tonemap;
+pub mod voxel_grid;
+
+use acceleration::{Acceleration, candidates};
+use framebuffer::{Framebuffer, NO_OBJECT};
+use half_res::SecondaryBuffer;
+use ray_intersect::{Intersect, SceneObject};
+use cube::Cube;
+use camera::Camera;
+use caustics::caustic_intensity;
+use dispersion::{DISPERSIVE_IOR, dispersed_ior, spectral_samples};
+use light::{Falloff, Light, LightKind};
+use light_sampling::{PowerCdf, light_weight};
+use material::{Material, MaterialTable, TextureOptions};
+use material_library::MaterialLibrary;
+use medium::IorStack;
+use offline::trace_sample;
+use pathtrace::PathLights;
+use portals::{Portal, Portals};
+use probes::BakedLighting;
+use render_thread::BuiltScene;
+use reflection_probe::{GLOSSY_EXPONENT, cone_angle};
+use rng::Rng;
+use sampler::{Sampler, stratified_2d};
+use settings::{RenderOptions, ShadowBias, SplitView};
+use sky::Sky;
+use tonemap::tonemap;
+use rayon::prelude::*;
+
+// Default offset for rays leaving a surface, tunable through ShadowBias
+pub const ORIGIN_BIAS: f32 = 1e-4;
+
+// Strength of the sky's image-based ambient light
+const ENVIRONMENT_LIGHT: f32 = 0.35;
+
+// Rays per cube face when baking how much sky each face sees, as a stratified grid
+const SKY_VISIBILITY_GRID: (u32, u32) = (8, 6);
+const SKY_VISIBILITY_SAMPLES: u32 = SKY_VISIBILITY_GRID.0 * SKY_VISIBILITY_GRID.1;
+
+// Performance settings - adjusted for reflections
+pub const MAX_RAY_DEPTH: u32 = 2;        // Default reflection and refraction bounce limits (was 0)
+const FRUSTUM_CULLING: bool = true;
+
+// Above this many lights reaching a hit, shading picks this many by power instead of
+// looping over all
+const DIRECT_LIGHT_SAMPLES: usize = 4;
+// Shadow rays per side of the grid spread over an area light, for surfaces seen directly
+const AREA_SHADOW_GRID: u32 = 3;
+// How far a glowing block's light reaches, in block sizes
+const BLOCK_LIGHT_RANGE: f32 = 8.0;
+// Pixels between the primary rays that trace emission for bloom
+const EMISSION_STEP: u32 = 2;
+// Length of ambient occlusion rays, about a block so only nearby geometry darkens a crease
+const AO_RADIUS: f32 = 1.0;
+
+// Seed for every stochastic effect, same seed + same frame index = same image
+pub const RENDER_SEED: u64 = 0x5EED_D10A_4A11;
+
+#[inline]
+pub(crate) fn offset_origin(intersect: &Intersect, direction: &Vector3, bias: &ShadowBias) -> Vector3 {
+    let cos_angle = direction.dot(intersect.normal);
+    let offset = intersect.normal * bias.offset(cos_angle, intersect.shadow_bias);
+    if direction.dot(intersect.normal) < 0.0 {
+        intersect.point - offset
+    } else {
+        intersect.point + offset
+    }
+}
+
+#[inline]
+pub(crate) fn reflect(incident: &Vector3, normal: &Vector3) -> Vector3 {
+    *incident - *normal * 2.0 * incident.dot(*normal)
+}
+
+// Waves summed into rippling water: direction angle (radians), wavelength, speed and
+// relative slope, long slow swells under short quick chop
+const RIPPLE_WAVES: [(f32, f32, f32, f32); 3] = [(0.4, 1.8, 0.6, 1.0), (2.2, 0.9, 0.9, 0.6), (4.1, 0.45, 1.4, 0.35)];
+
+// Shading normal of a rippling face, tilted by the slope of waves travelling across it.
+// The face itself stays flat, only the directions of bounced and bent rays change.
+fn ripple_normal(point: Vector3, normal: Vector3, amplitude: f32, time: f32) -> Vector3 {
+    let (tangent, bitangent) = Cube::face_axes(normal);
+    let (u, v) = (point.dot(tangent), point.dot(bitangent));
+    let mut slope = Vector3::zero();
+    for (angle, wavelength, speed, weight) in RIPPLE_WAVES {
+        let direction = tangent * angle.cos() + bitangent * angle.sin();
+        let phase = (u * angle.cos() + v * angle.sin() - speed * time) * std::f32::consts::TAU / wavelength;
+        slope = slope + direction * (weight * phase.cos());
+    }
+    (normal - slope * amplitude).normalized()
+}
+
+// Snell's law with `normal` facing the incoming ray, None on total internal reflection
+#[inline]
+fn refract(incident: &Vector3, normal: &Vector3, eta: f32) -> Option<Vector3> {
+    let cos_i = -incident.dot(*normal);
+    let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+    if k < 0.0 {
+        None
+    } else {
+        Some(*incident * eta + *normal * (eta * cos_i - k.sqrt()))
+    }
+}
+
+// Schlick's approximation of the share of light reflected going from IOR `n1` into `n2`,
+// `cos_i` being the cosine of the angle of incidence. Out of a denser medium the angle of
+// the refracted ray is the one that counts, and past the critical angle everything reflects.
+#[inline]
+fn schlick(cos_i: f32, n1: f32, n2: f32) -> f32 {
+    let r0 = ((n1 - n2) / (n1 + n2)).powi(2);
+    let cos = if n1 > n2 {
+        let sin_t2 = (n1 / n2).powi(2) * (1.0 - cos_i * cos_i);
+        if sin_t2 > 1.0 {
+            return 1.0;
+        }
+        (1.0 - sin_t2).sqrt()
+    } else {
+        cos_i
+    };
+    r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+}
+
+// Optimized shadow casting - simplified for performance.
+// Water and glass let light through instead of blocking it, rippling water focuses it
+// into a moving caustic pattern on the surface below.
+// Area lights are sampled on a grid of jittered points across them (`grid` per side), each
+// shadow ray seeing the light or not, so the average is the penumbra. `skip` is an object
+// that never blocks the light, e.g. the leaf whose back face it shines through; a block
+// light's own glowing block never blocks it either.
+fn cast_shadow(
+    intersect: &Intersect,
+    light: &Light,
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    portals: Option<&Portals>,
+    bias: &ShadowBias,
+    time: f32,
+    grid: u32,
+    skip: Option<usize>,
+    rng: &mut Rng,
+) -> f32 {
+    let (light_dir, light_distance) = light.towards(intersect.point);
+
+    // Out of the light's range there is nothing to occlude
+    if light.range().is_some_and(|range| light_distance >= range) {
+        return 1.0;
+    }
+
+    match light.kind {
+        LightKind::Area { .. } if grid > 1 => {
+            let samples = grid * grid;
+            let total: f32 = (0..samples)
+                .map(|index| {
+                    let (s, t) = stratified_2d(index, grid, grid, (rng.next_f32(), rng.next_f32()));
+                    let (sample_dir, sample_distance) = light.towards_sample(intersect.point, s, t);
+                    shadow_along(intersect, sample_dir, sample_distance, objects, materials, accel, portals, bias, time, [skip, light.emitter])
+                })
+                .sum();
+            total / samples as f32
+        }
+        _ => shadow_along(intersect, light_dir, light_distance, objects, materials, accel, portals, bias, time, [skip, light.emitter]),
+    }
+}
+
+// Shadow intensity of one ray from the hit point towards a point `light_distance` away,
+// walking past the objects in `skip`
+fn shadow_along(
+    intersect: &Intersect,
+    light_dir: Vector3,
+    light_distance: f32,
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    portals: Option<&Portals>,
+    bias: &ShadowBias,
+    time: f32,
+    skip: [Option<usize>; 2],
+) -> f32 {
+    let shadow_ray_origin = offset_origin(intersect, &light_dir, bias);
+
+    // Check every object the grid or BVH finds between the point and the light; the first opaque
+    // one ends the walk, water and glass only dim the light
+    let mut transmission = 1.0;
+    let mut ripple: Option<f32> = None; // Strongest ripple among the water and glass crossed
+    let mut blocked = false;
+    let culled = portals.and_then(|portals| portals.culled_cell(shadow_ray_origin, light_dir));
+    candidates(accel, objects.len(), shadow_ray_origin, light_dir, light_distance, |index| {
+        if portals.is_some_and(|portals| portals.is_culled(culled, index)) || skip.contains(&Some(index)) {
+            return Some(light_distance);
+        }
+        let object = &objects[index];
+        let shadow_intersect = object.ray_intersect(&shadow_ray_origin, &light_dir);
+        if shadow_intersect.is_intersecting && shadow_intersect.distance < light_distance - bias.occluder_epsilon {
+            let material = object.material_for(shadow_intersect.normal, materials);
+            // Transparent blocks only shade where their texture is opaque, so leaves cast
+            // their pattern rather than a square
+            if object.is_cut_out(&shadow_intersect, materials) {
+                return Some(light_distance);
+            }
+            if material.albedo[3] <= 0.0 || material.refractive_index <= 1.0 {
+                blocked = true;
+                return None;
+            }
+            transmission *= material.albedo[3];
+            ripple = Some(ripple.unwrap_or(0.0).max(material.ripple));
+        }
+        Some(light_distance)
+    });
+    if blocked {
+        return 0.8; // Reduced shadow intensity
+    }
+    match ripple {
+        Some(ripple) => (1.0 - transmission * caustic_intensity(intersect.point, light_dir, ripple, time)).min(0.8),
+        None => 0.0,
+    }
+}
+
+/// Share of the hemisphere above a hit left open by nearby objects: `samples` short
+/// cosine-weighted rays, stratified over a grid, each counting as blocked when something lies
+/// within AO_RADIUS. Darkens the creases where blocks meet, which the per-face sky visibility
+/// bake is too coarse to show.
+fn ambient_occlusion(intersect: &Intersect, objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, bias: &ShadowBias, samples: u32, rng: &mut Rng) -> f32 {
+    let normal = intersect.normal;
+    let helper = if normal.y.abs() < 0.9 { Vector3::new(0.0, 1.0, 0.0) } else { Vector3::new(1.0, 0.0, 0.0) };
+    let tangent = helper.cross(normal).normalized();
+    let bitangent = normal.cross(tangent);
+    let columns = (samples as f32).sqrt().ceil() as u32;
+    let rows = samples.div_ceil(columns);
+
+    let mut open = 0;
+    for sample in 0..samples {
+        let (d1, d2) = stratified_2d(sample, columns, rows, (rng.next_f32(), rng.next_f32()));
+        let r = d1.sqrt();
+        let phi = 2.0 * PI * d2;
+        let direction = (tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * (1.0 - r * r).max(0.0).sqrt()).normalized();
+        let origin = offset_origin(intersect, &direction, bias);
+        let mut blocked = false;
+        candidates(accel, objects.len(), origin, direction, AO_RADIUS, |index| {
+            if objects[index].solid_hit_distance(&origin, &direction, materials).is_some_and(|distance| distance < AO_RADIUS) {
+                blocked = true;
+                return None;
+            }
+            Some(AO_RADIUS)
+        });
+        if !blocked {
+            open += 1;
+        }
+    }
+    open as f32 / samples as f32
+}
+
+// Distance to and index of the first object along a ray, (infinity, NO_OBJECT) for sky
+fn primary_hit(objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, origin: &Vector3, direction: &Vector3) -> (f32, u32) {
+    let mut closest = (f32::INFINITY, NO_OBJECT);
+    candidates(accel, objects.len(), *origin, *direction, f32::INFINITY, |index| {
+        if let Some(distance) = objects[index].solid_hit_distance(origin, direction, materials).filter(|&distance| distance < closest.0) {
+            closest = (distance, index as u32);
+        }
+        Some(closest.0)
+    });
+    closest
+}
+
+/// Light given off by the surface seen through each pixel, emission maps applied: what bloom
+/// spreads around glowing blocks. Traced every `EMISSION_STEP` pixels, the blur hides the steps.
+pub fn trace_emission(objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, camera: &Camera, width: u32, height: u32) -> Vec<Vector3> {
+    let fov = PI / 3.0;
+    let columns = width.div_ceil(EMISSION_STEP);
+    let samples: Vec<Vector3> = (0..height.div_ceil(EMISSION_STEP))
+        .into_par_iter()
+        .flat_map_iter(|row| {
+            (0..columns).map(move |column| {
+                let (x, y) = ((column * EMISSION_STEP).min(width - 1), (row * EMISSION_STEP).min(height - 1));
+                let direction = primary_ray(camera, x as f32, y as f32, width, height, fov);
+                let (_, index) = primary_hit(objects, materials, accel, &camera.eye, &direction);
+                let Some(object) = objects.get(index as usize) else {
+                    return Vector3::zero();
+                };
+                let intersect = object.ray_intersect(&camera.eye, &direction);
+                object.surface(&intersect, &direction, materials).emission
+            })
+        })
+        .collect();
+    let mut emission = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            emission.push(samples[((y / EMISSION_STEP) * columns + x / EMISSION_STEP) as usize]);
+        }
+    }
+    emission
+}
+
+/// Index of the nearest object hit by a ray, used to pick what the camera is looking at;
+/// walks the acceleration structure like the primary rays do
+pub fn closest_object(objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, origin: &Vector3, direction: &Vector3) -> Option<usize> {
+    let (_, index) = primary_hit(objects, materials, accel, origin, direction);
+    (index != NO_OBJECT).then_some(index as usize)
+}
+
+// Frustum culling - less aggressive to prevent holes
+fn is_in_frustum(cube_center: Vector3, _cube_size: f32, camera: &Camera, _fov: f32, _aspect: f32) -> bool {
+    if !FRUSTUM_CULLING {
+        return true;
+    }
+    
+    let to_cube = cube_center - camera.eye;
+    let distance = to_cube.length();
+    
+    // More conservative distance culling
+    if distance > 35.0 {
+        return false;
+    }
+    
+    // Very conservative frustum check - only cull objects clearly behind
+    let forward_dot = to_cube.normalized().dot(camera.forward);
+    if forward_dot < -0.5 {  // Much more lenient
+        return false;
+    }
+    
+    true
+}
+
+// Simplified lighting model: diffuse and specular from one light at a hit point
+fn shade_light(
+    intersect: &Intersect,
+    material: &Material,
+    light: &Light,
+    ray_origin: &Vector3,
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    portals: Option<&Portals>,
+    options: &RenderOptions,
+    depth: u32,
+    rng: &mut Rng,
+) -> (Vector3, Vector3) {
+    if light.intensity <= 0.0 {
+        return (Vector3::zero(), Vector3::zero());
+    }
+
+    let (light_dir, light_distance) = light.towards(intersect.point);
+    
+    // Simplified shadow calculation; only what the camera sees directly gets soft shadows
+    let grid = if depth == 0 { AREA_SHADOW_GRID } else { 1 };
+    let shadow_intensity = if !options.shadows {
+        0.0
+    } else {
+        cast_shadow(intersect, light, objects, materials, accel, portals, &options.bias, options.time, grid, None, rng)
+    };
+    
+    let light_visibility = 1.0 - shadow_intensity;
+    let spot_factor = light.spot.map_or(1.0, |spot| spot.factor(-light_dir));
+    let distance_falloff = light.attenuation(light_distance) * spot_factor;
+    
+    // Wrap lighting lets translucent materials pick up light slightly past the terminator
+    let translucency = material.translucency;
+    let n_dot_l = intersect.normal.dot(light_dir);
+    let diffuse_intensity = ((n_dot_l + translucency) / (1.0 + translucency)).max(0.0);
+    let light_intensity = light.intensity * light_visibility * distance_falloff;
+    let light_color = Vector3::new(
+        light.color.r as f32 / 255.0,
+        light.color.g as f32 / 255.0,
+        light.color.b as f32 / 255.0,
+    );
+    
+    // Tinted by the light's color, so a sunset sun or a colored lamp stains what it lights
+    let base = material.diffuse;
+    let tinted = Vector3::new(base.x * light_color.x, base.y * light_color.y, base.z * light_color.z);
+    let mut diffuse = tinted * (diffuse_intensity * light_intensity);
+
+    // Thin-slab transmission: light behind a leaf bleeds through its back face. The leaf
+    // itself would block the shadow ray from its back face, so it is skipped; anything else
+    // between the leaf and the light still shades it.
+    if translucency > 0.0 && n_dot_l < 0.0 {
+        let transmission_visibility = if options.shadows {
+            1.0 - cast_shadow(intersect, light, objects, materials, accel, portals, &options.bias, options.time, grid, Some(intersect.object), rng)
+        } else {
+            1.0
+        };
+        let transmitted = -n_dot_l * translucency * light.intensity * transmission_visibility * distance_falloff;
+        diffuse = diffuse + tinted * transmitted;
+    }
+    
+    // Very simplified specular - only for surfaces within `specular_range`, and everywhere under the sun
+    let sunlight = matches!(light.kind, LightKind::Directional { .. });
+    let in_range = options.specular_range <= 0.0 || light_distance < options.specular_range;
+    let specular = if (in_range || sunlight) && depth == 0 {
+        let view_dir = (*ray_origin - intersect.point).normalized();
+        let reflect_dir = reflect(&-light_dir, &intersect.normal).normalized();
+        let specular_intensity = view_dir.dot(reflect_dir).max(0.0).powf(20.0);
+        light_color * (specular_intensity * light_intensity * 0.2)
+    } else {
+        Vector3::zero()
+    };
+
+    (diffuse, specular)
+}
+
+// Enhanced ray casting with reflections and transparency, starting in air
+pub fn cast_ray(
+    ray_origin: &Vector3,
+    ray_direction: &Vector3,
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    portals: Option<&Portals>,
+    baked: BakedLighting,
+    lights: &[Light],
+    sky: &Sky,
+    options: &RenderOptions,
+    depth: u32,
+    camera: &Camera,
+    fov: f32,
+    aspect: f32,
+    rng: &mut Rng,
+) -> Vector3 {
+    trace_ray(ray_origin, ray_direction, objects, materials, accel, portals, baked, lights, sky, options, depth, camera, fov, aspect, rng, IorStack::air(), Bounces::default(), None)
+}
+
+// cast_ray for a camera ray that also reports what it hit first, (distance, object index) or
+// (infinity, NO_OBJECT) for sky, so depth and IDs don't need a ray of their own
+fn cast_primary_ray(
+    ray_origin: &Vector3,
+    ray_direction: &Vector3,
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    portals: Option<&Portals>,
+    baked: BakedLighting,
+    lights: &[Light],
+    sky: &Sky,
+    options: &RenderOptions,
+    camera: &Camera,
+    fov: f32,
+    aspect: f32,
+    rng: &mut Rng,
+) -> (Vector3, (f32, u32)) {
+    let mut first_hit = (f32::INFINITY, NO_OBJECT);
+    let color = trace_ray(ray_origin, ray_direction, objects, materials, accel, portals, baked, lights, sky, options, 0, camera, fov, aspect, rng, IorStack::air(), Bounces::default(), Some(&mut first_hit));
+    (color, first_hit)
+}
+
+// Reflection and refraction bounces taken so far along a ray path, each against its own
+// limit, and the wavelength the path carries once spectral mode split it
+#[derive(Debug, Clone, Copy, Default)]
+struct Bounces {
+    reflection: u32,
+    refraction: u32,
+    wavelength: Option<f32>,
+}
+
+// Recursive part of cast_ray, `media` holds the transparent materials the ray is inside and
+// `first_hit` (camera rays only) receives the distance and index of the object it hits
+fn trace_ray(
+    ray_origin: &Vector3,
+    ray_direction: &Vector3,
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    portals: Option<&Portals>,
+    baked: BakedLighting,
+    lights: &[Light],
+    sky: &Sky,
+    options: &RenderOptions,
+    depth: u32,
+    camera: &Camera,
+    fov: f32,
+    aspect: f32,
+    rng: &mut Rng,
+    media: IorStack,
+    bounces: Bounces,
+    first_hit: Option<&mut (f32, u32)>,
+) -> Vector3 {
+
+    let mut intersect = Intersect::empty();
+    let mut zbuffer = f32::INFINITY;
+
+    // Find closest intersection - check the visible objects along the ray, nearest first
+    let culled = portals.and_then(|portals| portals.culled_cell(*ray_origin, *ray_direction));
+    candidates(accel, objects.len(), *ray_origin, *ray_direction, zbuffer, |index| {
+        let object = &objects[index];
+        // Only use conservative frustum culling
+        let (min, max) = object.bounds();
+        if !is_in_frustum(object.center(), (max - min).x, camera, fov, aspect) {
+            return Some(zbuffer);
+        }
+        // Objects on the other side of a portal the ray doesn't pass through
+        if portals.is_some_and(|portals| portals.is_culled(culled, index)) {
+            return Some(zbuffer);
+        }
+
+        // Holes in leaf textures show what is behind them
+        let mut i = object.ray_intersect(ray_origin, ray_direction);
+        if i.is_intersecting && i.distance < zbuffer && !object.is_cut_out(&i, materials) {
+            zbuffer = i.distance;
+            i.object = index;
+            intersect = i;
+        }
+        Some(zbuffer)
+    });
+
+    if !intersect.is_intersecting {
+        return sky.sample(*ray_direction);
+    }
+    if let Some(first_hit) = first_hit {
+        *first_hit = (intersect.distance, intersect.object as u32);
+    }
+    // Only the visible hit pays for texture lookups
+    let material = objects[intersect.object].surface(&intersect, ray_direction, materials);
+    intersect.shadow_bias = material.shadow_bias;
+
+    // Ambient comes from the sky itself, so it warms at dusk and darkens at night; with
+    // ambient occlusion on, surfaces the camera sees directly also darken in creases
+    let occlusion = if options.ao_samples > 0 && depth == 0 {
+        ambient_occlusion(&intersect, objects, materials, accel, &options.bias, options.ao_samples, rng)
+    } else {
+        1.0
+    };
+    let irradiance = sky.irradiance(intersect.normal);
+    let base_color = material.diffuse;
+    let ambient = Vector3::new(
+        irradiance.x * base_color.x,
+        irradiance.y * base_color.y,
+        irradiance.z * base_color.z,
+    ) * (ENVIRONMENT_LIGHT * intersect.sky_visibility * occlusion);
+
+    // Light bounced off nearby blocks, from the probe grid when one is baked
+    let bounce = baked.irradiance.map_or(Vector3::zero(), |probes| {
+        let light = probes.bounce_light(intersect.point, intersect.normal);
+        Vector3::new(light.x * base_color.x, light.y * base_color.y, light.z * base_color.z) * occlusion
+    });
+
+    // Direct light: every light that reaches the hit when there are few, else a few picked
+    // by power. Lights with a range (glowing blocks, the headlamp) weigh what they deliver
+    // here and drop out past their range, so a cave full of lava still traces at most
+    // `DIRECT_LIGHT_SAMPLES` shadow rays per hit.
+    let mut diffuse = Vector3::zero();
+    let mut specular = Vector3::zero();
+    let mut shade = |light: &Light, weight: f32, rng: &mut Rng| {
+        let (light_diffuse, light_specular) = shade_light(&intersect, &material, light, ray_origin, objects, materials, accel, portals, options, depth, rng);
+        diffuse = diffuse + light_diffuse * weight;
+        specular = specular + light_specular * weight;
+    };
+    let weights: Vec<f32> = lights.iter().map(|light| light_weight(light, intersect.point)).collect();
+    if weights.iter().filter(|&&weight| weight > 0.0).count() <= DIRECT_LIGHT_SAMPLES {
+        for (light, _) in lights.iter().zip(&weights).filter(|&(_, &weight)| weight > 0.0) {
+            shade(light, 1.0, rng);
+        }
+    } else {
+        let light_cdf = PowerCdf::new(weights.into_iter());
+        for _ in 0..DIRECT_LIGHT_SAMPLES {
+            let Some((index, probability)) = light_cdf.sample(rng.next_f32()) else {
+                break;
+            };
+            shade(&lights[index], 1.0 / (probability * DIRECT_LIGHT_SAMPLES as f32), rng);
+        }
+    }
+
+    // Water bends its reflections and refractions with animated waves
+    let shading_normal = if material.ripple > 0.0 {
+        ripple_normal(intersect.point, intersect.normal, material.ripple, options.time)
+    } else {
+        intersect.normal
+    };
+
+    // Which side of the face the ray is on and the media on either side of it
+    let ior = material.refractive_index.max(1.0);
+    let entering = ray_direction.dot(intersect.normal) < 0.0;
+    let (facing_normal, flat_normal, next_media) = if entering {
+        (shading_normal, intersect.normal, media.entered(ior))
+    } else {
+        (-shading_normal, -intersect.normal, media.exited(ior))
+    };
+
+    // Glass, water and diamond split what they don't absorb or scatter between reflection
+    // and transmission by view angle: little reflection head-on, a mirror at grazing angles
+    // and past the critical angle. Without reflections left to trace they keep fixed weights.
+    let albedo = material.albedo;
+    let can_reflect = options.reflections && bounces.reflection < options.max_reflection_depth;
+    let (reflect_weight, refract_weight) = if can_reflect && albedo[3] > 0.0 && ior > 1.0 {
+        let cos_i = (-ray_direction.dot(facing_normal)).clamp(0.0, 1.0);
+        let fresnel = schlick(cos_i, media.current(), next_media.current());
+        let share = albedo[2] + albedo[3];
+        (share * fresnel, share * (1.0 - fresnel))
+    } else {
+        (albedo[2], albedo[3])
+    };
+
+    // Reflections for reflective materials (diamonds) and metals
+    let metallic = material.metallic;
+    let mut reflection_color = Vector3::zero();
+    if can_reflect && (reflect_weight > 0.0 || metallic > 0.0) {
+        let mut reflect_dir = reflect(ray_direction, &shading_normal).normalized();
+        // A wave steep enough to send the bounce into the surface reflects like flat water
+        if reflect_dir.dot(intersect.normal) * ray_direction.dot(intersect.normal) > 0.0 {
+            reflect_dir = reflect(ray_direction, &intersect.normal).normalized();
+        }
+        // Dull surfaces reflect a blurred cone, read from the prefiltered capture instead of traced
+        reflection_color = match baked.reflections.filter(|_| material.specular < GLOSSY_EXPONENT) {
+            Some(probe) => probe.sample(intersect.point, reflect_dir, cone_angle(material.specular)),
+            None => {
+                let reflect_origin = offset_origin(&intersect, &reflect_dir, &options.bias);
+                trace_ray(&reflect_origin, &reflect_dir, objects, materials, accel, portals, baked, lights, sky, options, depth + 1, camera, fov, aspect, rng, media, Bounces { reflection: bounces.reflection + 1, ..bounces }, None)
+            }
+        };
+    }
+
+    // Metals have no diffuse term and tint what they reflect with their base color
+    let base = material.diffuse;
+    let metal_color = Vector3::new(
+        (reflection_color.x + specular.x) * base.x,
+        (reflection_color.y + specular.y) * base.y,
+        (reflection_color.z + specular.z) * base.z,
+    );
+
+    // Refraction/transparency for transparent materials (leaves, glass, water).
+    // Entering pushes the material's IOR, leaving pops it, so the IOR on the far side of
+    // a face is whatever medium still surrounds the ray (glass under water, not air).
+    let mut refract_color = Vector3::zero();
+    if refract_weight > 0.0 && bounces.refraction < options.max_refraction_depth {
+        let mut refract_at = |wavelength: Option<f32>| {
+            let eta = match wavelength {
+                Some(wavelength) => dispersed_ior(media.current(), wavelength) / dispersed_ior(next_media.current(), wavelength),
+                None => media.current() / next_media.current(),
+            };
+            // Snell's law bends the ray into the next medium; past the critical angle total
+            // internal reflection keeps it in the current one. Returns whether it got through.
+            let bend = |normal: &Vector3| match refract(ray_direction, normal, eta) {
+                Some(direction) => (direction.normalized(), next_media, true),
+                None => (reflect(ray_direction, normal).normalized(), media, false),
+            };
+            let (mut refract_dir, mut after_media, through) = bend(&facing_normal);
+            // A wave steep enough to send the ray back the wrong way across the face bends it
+            // like flat water instead
+            if (refract_dir.dot(flat_normal) < 0.0) != through {
+                (refract_dir, after_media, _) = bend(&flat_normal);
+            }
+            let refract_origin = offset_origin(&intersect, &refract_dir, &options.bias);
+            let next_bounces = Bounces { refraction: bounces.refraction + 1, wavelength, ..bounces };
+            trace_ray(&refract_origin, &refract_dir, objects, materials, accel, portals, baked, lights, sky, options, depth + 1, camera, fov, aspect, rng, after_media, next_bounces, None)
+        };
+
+        // Spectral mode splits white light entering a diamond into wavelengths that each bend
+        // by their own IOR, then adds them back up weighted by the color each one is
+        refract_color = if options.spectral && bounces.wavelength.is_none() && ior >= DISPERSIVE_IOR {
+            spectral_samples().into_iter().fold(Vector3::zero(), |sum, (wavelength, weight)| {
+                let color = refract_at(Some(wavelength));
+                sum + Vector3::new(color.x * weight.x, color.y * weight.y, color.z * weight.z)
+            })
+        } else {
+            refract_at(bounces.wavelength)
+        };
+    }
+
+    let dielectric_color = diffuse * albedo[0] + specular * albedo[1] + reflection_color * reflect_weight + refract_color * refract_weight;
+    let final_color = dielectric_color * (1.0 - metallic) + metal_color * metallic + ambient + bounce + material.emission;
+    
+    Vector3::new(
+        final_color.x.min(1.0),
+        final_color.y.min(1.0),
+        final_color.z.min(1.0)
+    )
+}
+
+// Primary ray through a (possibly fractional) pixel position
+pub fn primary_ray(camera: &Camera, px: f32, py: f32, width: u32, height: u32, fov: f32) -> Vector3 {
+    let aspect_ratio = width as f32 / height as f32;
+    let perspective_scale = (fov * 0.5).tan();
+
+    let screen_x = (2.0 * px) / width as f32 - 1.0;
+    let screen_y = -(2.0 * py) / height as f32 + 1.0;
+    let screen_x = screen_x * aspect_ratio * perspective_scale;
+    let screen_y = screen_y * perspective_scale;
+
+    let ray_direction = Vector3::new(screen_x, screen_y, -1.0).normalized();
+    camera.basis_change(&ray_direction)
+}
+
+// Picks which side of the A/B split a screen column belongs to
+fn options_for_column<'a>(x: u32, width: u32, options: &'a RenderOptions, split: Option<&'a SplitView>) -> &'a RenderOptions {
+    match split {
+        Some(split) if (x as f32) < split.divider * width as f32 => &split.left,
+        Some(split) => &split.right,
+        None => options,
+    }
+}
+
+// Screen position of a world point, None when it is behind the camera
+fn project_to_screen(camera: &Camera, point: Vector3, width: u32, height: u32, fov: f32) -> Option<(f32, f32)> {
+    let to_point = point - camera.eye;
+    let depth = to_point.dot(camera.forward);
+    if depth <= 1e-3 {
+        return None;
+    }
+    // Inverse of primary_ray
+    let aspect_ratio = width as f32 / height as f32;
+    let perspective_scale = (fov * 0.5).tan();
+    let screen_x = to_point.dot(camera.right) / depth / (aspect_ratio * perspective_scale);
+    let screen_y = to_point.dot(camera.up) / depth / perspective_scale;
+    Some(((screen_x + 1.0) * 0.5 * width as f32, (1.0 - screen_y) * 0.5 * height as f32))
+}
+
+/// Draws a small disc in each light's color where it sits on screen, skipping lights
+/// hidden behind geometry; switched-off lights are drawn grey
+pub fn draw_light_gizmos(framebuffer: &mut Framebuffer, objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, camera: &Camera, lights: &[Light]) {
+    let width = framebuffer.width;
+    let height = framebuffer.height;
+    let fov = PI / 3.0;
+
+    for light in lights {
+        let to_light = light.position - camera.eye;
+        let distance = to_light.length();
+        // Lights carried by the camera (headlamp) would cover the whole view
+        if distance < 0.5 {
+            continue;
+        }
+        let Some((center_x, center_y)) = project_to_screen(camera, light.position, width, height, fov) else {
+            continue;
+        };
+        let direction = to_light / distance;
+        let hidden = closest_object(objects, materials, accel, &camera.eye, &direction)
+            .is_some_and(|index| objects[index].ray_intersect(&camera.eye, &direction).distance < distance);
+        if hidden {
+            continue;
+        }
+
+        // Roughly a 0.2 unit ball, never smaller than a few pixels
+        let radius = (0.2 / distance * height as f32).max(3.0);
+        let fill = if light.intensity > 0.0 { light.color } else { Color::new(90, 90, 90, 255) };
+        let x_range = (center_x - radius - 1.0).max(0.0) as u32..((center_x + radius + 1.0).max(0.0) as u32).min(width);
+        let y_range = (center_y - radius - 1.0).max(0.0) as u32..((center_y + radius + 1.0).max(0.0) as u32).min(height);
+        for y in y_range {
+            for x in x_range.clone() {
+                let offset = ((x as f32 + 0.5 - center_x).powi(2) + (y as f32 + 0.5 - center_y).powi(2)).sqrt();
+                if offset <= radius {
+                    // Dark ring so the marker reads against bright sky too
+                    framebuffer.set_current_color(if offset > radius - 1.5 { Color::BLACK } else { fill });
+                    framebuffer.set_pixel(x, y);
+                }
+            }
+        }
+    }
+}
+
+/// Bakes the fraction of the sky each cube face can see, so faces under the top floor get
+/// less ambient light than ones under the hole. Cosine-weighted rays from random points
+/// on each face, stratified over both, walked through `accel` so any shape in the scene
+/// (props too) can block them; run again whenever objects move.
+pub fn bake_sky_visibility(objects: &mut [SceneObject], accel: &Acceleration) {
+    let faces = [
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(-1.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(0.0, -1.0, 0.0),
+        Vector3::new(0.0, 0.0, 1.0),
+        Vector3::new(0.0, 0.0, -1.0),
+    ];
+    let scene: &[SceneObject] = objects;
+    let visibility: Vec<Option<[f32; 6]>> = (0..scene.len())
+        .into_par_iter()
+        .map(|index| {
+            let object = scene[index].as_cube()?;
+            let mut rng = Rng::new(RENDER_SEED, index as u64);
+            let mut visibility = [1.0f32; 6];
+            for normal in faces {
+                let (tangent, bitangent) = Cube::face_axes(normal);
+                let face_center = object.center + normal * (object.size * 0.5 + ORIGIN_BIAS * 10.0);
+                let mut open = 0;
+                let (columns, rows) = SKY_VISIBILITY_GRID;
+                for sample in 0..SKY_VISIBILITY_SAMPLES {
+                    // Positions and directions each cover their own grid; 7 is coprime with the
+                    // sample count, so pairing cell i with cell 7i keeps the two uncorrelated
+                    let (u, v) = stratified_2d(sample * 7 % SKY_VISIBILITY_SAMPLES, columns, rows, (rng.next_f32(), rng.next_f32()));
+                    let origin = face_center
+                        + tangent * ((u - 0.5) * 0.9 * object.size)
+                        + bitangent * ((v - 0.5) * 0.9 * object.size);
+                    // Cosine-weighted direction around the face normal
+                    let (d1, d2) = stratified_2d(sample, columns, rows, (rng.next_f32(), rng.next_f32()));
+                    let r = d1.sqrt();
+                    let phi = 2.0 * PI * d2;
+                    let direction = (tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * (1.0 - r * r).max(0.0).sqrt()).normalized();
+                    let mut blocked = false;
+                    accel.traverse(origin, direction, f32::INFINITY, |other| {
+                        blocked = scene[other].hit_distance(&origin, &direction).is_some();
+                        (!blocked).then_some(f32::INFINITY)
+                    });
+                    if !blocked {
+                        open += 1;
+                    }
+                }
+                visibility[Cube::face_index(normal)] = open as f32 / SKY_VISIBILITY_SAMPLES as f32;
+            }
+            Some(visibility)
+        })
+        .collect();
+
+    for (object, faces) in objects.iter_mut().zip(visibility) {
+        if let (Some(cube), Some(faces)) = (object.as_cube_mut(), faces) {
+            cube.sky_visibility = faces;
+        }
+    }
+}
+
+/// Numbers objects in scene order, so ID passes from different runs of the same scene line
+/// up; material IDs are set when blocks are built from a preset (`MaterialLibrary::cube`)
+pub fn assign_ids(objects: &mut [Cube]) {
+    for (index, object) in objects.iter_mut().enumerate() {
+        object.id = index as u32;
+    }
+}
+
+// Fixed adaptive rendering with proper black screen elimination
+pub fn render_adaptive(
+    framebuffer: &mut Framebuffer, 
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    portals: Option<&Portals>,
+    baked: BakedLighting,
+    camera: &Camera, 
+    lights: &[Light],
+    sky: &Sky,
+    options: &RenderOptions,
+    split: Option<&SplitView>,
+    render_scale: f32,
+    frame_index: u32,
+) {
+    let width = framebuffer.width;
+    let height = framebuffer.height;
+    let aspect_ratio = width as f32 / height as f32;
+    let fov = PI / 3.0;
+
+    // The path tracer converges over frames instead of trading resolution for speed: one
+    // sample per pixel each frame, averaged with the ones before while the view holds still.
+    // A still view in the Whitted model is refined the same way, with jittered primary rays
+    // so edges and soft shadows keep smoothing out.
+    if options.path_traced || options.progressive {
+        framebuffer.temporal.clear();
+        let sample = framebuffer.accumulated_frames();
+        let path_lights = &PathLights::new(objects, materials, lights);
+        let colors: Vec<Vector3> = (0..height)
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                (0..width).map(move |x| {
+                    if options.path_traced {
+                        return trace_sample(objects, materials, accel, camera, lights, path_lights, sky, options, x, y, width, height, sample);
+                    }
+                    let (jitter_x, jitter_y) = Sampler::for_pixel(RENDER_SEED, x, y, sample).next_2d();
+                    let direction = primary_ray(camera, x as f32 + jitter_x - 0.5, y as f32 + jitter_y - 0.5, width, height, fov);
+                    let mut rng = Rng::for_pixel(RENDER_SEED, x, y, sample);
+                    let pixel_options = options_for_column(x, width, options, split);
+                    cast_ray(&camera.eye, &direction, objects, materials, accel, portals, baked, lights, sky, pixel_options, 0, camera, fov, aspect_ratio, &mut rng)
+                })
+            })
+            .collect();
+        framebuffer.accumulate(&colors, options.exposure);
+        if framebuffer.record_aovs {
+            for y in 0..height {
+                for x in 0..width {
+                    let direction = primary_ray(camera, x as f32, y as f32, width, height, fov);
+                    let (depth, object) = primary_hit(objects, materials, accel, &camera.eye, &direction);
+                    framebuffer.set_aovs(x, y, depth, object);
+                }
+            }
+        }
+        return;
+    }
+
+    // Ensure minimum render size and handle edge cases
+    let render_width = ((width as f32 * render_scale).round() as u32).max(1).min(width);
+    let render_height = ((height as f32 * render_scale).round() as u32).max(1).min(height);
+
+    // Interlaced: only every other row (of pixels or blocks) is traced, alternating per frame
+    let interlaced = options.interlaced;
+    let field = frame_index % 2;
+    let in_field = |row: &u32| !interlaced || row % 2 == field;
+
+    // If render scale is close to 1.0, just render at full resolution
+    if render_scale >= 0.95 {
+        // Reflections and refractions at half resolution, direct light at every pixel
+        let grid = RenderGrid { columns: width, rows: height, step_x: 1, step_y: 1 };
+        let secondary = options.half_res_secondary.then(|| {
+            trace_secondary(objects, materials, accel, portals, baked, camera, lights, sky, options, split, width, height, grid, frame_index)
+        });
+
+        // Temporal anti-aliasing moves the primary rays around inside their pixels frame to frame
+        let jitter = if options.taa { taa::jitter(frame_index) } else { (0.0, 0.0) };
+
+        // Full resolution rendering, rows traced in parallel and written back in order
+        let record_aovs = framebuffer.record_aovs || options.taa;
+        let rows: Vec<u32> = (0..height).filter(in_field).collect();
+        let traced: Vec<Vec<(Color, Option<(f32, u32)>)>> = rows
+            .par_iter()
+            .map(|&y| {
+                (0..width)
+                    .map(|x| {
+                        let rotated_direction = primary_ray(camera, x as f32 + jitter.0, y as f32 + jitter.1, width, height, fov);
+
+                        let mut rng = Rng::for_pixel(RENDER_SEED, x, y, frame_index);
+                        let pixel_options = options_for_column(x, width, options, split);
+                        let (pixel_color_v3, hit) = match &secondary {
+                            Some(buffer) => {
+                                let direct_options = pixel_options.without_secondary();
+                                let (direct, (depth, object)) = cast_primary_ray(&camera.eye, &rotated_direction, objects, materials, accel, portals, baked, lights, sky, &direct_options, camera, fov, aspect_ratio, &mut rng);
+                                (direct + buffer.upsample(x, y, depth, object), Some((depth, object)))
+                            }
+                            None if record_aovs => {
+                                let (color, hit) = cast_primary_ray(&camera.eye, &rotated_direction, objects, materials, accel, portals, baked, lights, sky, pixel_options, camera, fov, aspect_ratio, &mut rng);
+                                (color, Some(hit))
+                            }
+                            None => (cast_ray(&camera.eye, &rotated_direction, objects, materials, accel, portals, baked, lights, sky, pixel_options, 0, camera, fov, aspect_ratio, &mut rng), None),
+                        };
+                        (tonemap(pixel_color_v3, options.exposure, x, y), hit.filter(|_| record_aovs))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        for (y, row) in rows.into_iter().zip(traced) {
+            for (x, (pixel_color, hit)) in (0..width).zip(row) {
+                framebuffer.set_current_color(pixel_color);
+                framebuffer.set_pixel(x, y);
+                if let Some((depth, object)) = hit {
+                    framebuffer.set_aovs(x, y, depth, object);
+                }
+            }
+        }
+        if interlaced {
+            framebuffer.fill_skipped_field(1, height, field);
+        }
+        if options.taa {
+            framebuffer.temporal.resolve(&mut framebuffer.color_buffer, &framebuffer.depth_buffer, grid, width, height, camera, fov, jitter);
+        } else {
+            framebuffer.temporal.clear();
+        }
+    } else {
+        // Lower resolution rendering with proper upscaling
+        let step_x = (width as f32 / render_width as f32).ceil() as u32;
+        let step_y = (height as f32 / render_height as f32).ceil() as u32;
+
+        // Reflections and refractions at half the render resolution, direct light in every block
+        let grid = RenderGrid { columns: render_width, rows: render_height, step_x, step_y };
+        let secondary = options.half_res_secondary.then(|| {
+            trace_secondary(objects, materials, accel, portals, baked, camera, lights, sky, options, split, width, height, grid, frame_index)
+        });
+
+        // Temporal anti-aliasing moves each block's ray around inside the block frame to frame,
+        // so the history gathers what the block covers instead of only its center
+        let jitter = if options.taa { taa::jitter(frame_index) } else { (0.0, 0.0) };
+
+        let record_aovs = framebuffer.record_aovs || options.taa;
+        let rows: Vec<u32> = (0..render_height).filter(in_field).collect();
+        let traced: Vec<Vec<(Color, f32, u32)>> = rows
+            .par_iter()
+            .map(|&y| {
+                (0..render_width)
+                    .map(|x| {
+                        // Calculate the center of the block we're rendering
+                        let (center_x, center_y) = grid.center(x, y);
+                        let (sample_x, sample_y) = grid.sample_point(x, y, jitter);
+
+                        let rotated_direction = primary_ray(camera, sample_x, sample_y, width, height, fov);
+
+                        let mut rng = Rng::for_pixel(RENDER_SEED, center_x, center_y, frame_index);
+                        let pixel_options = options_for_column(center_x, width, options, split);
+                        let (pixel_color_v3, (depth, object)) = match &secondary {
+                            Some(buffer) => {
+                                let direct_options = pixel_options.without_secondary();
+                                let (direct, (depth, object)) = cast_primary_ray(&camera.eye, &rotated_direction, objects, materials, accel, portals, baked, lights, sky, &direct_options, camera, fov, aspect_ratio, &mut rng);
+                                (direct + buffer.upsample(x, y, depth, object), (depth, object))
+                            }
+                            None => cast_primary_ray(&camera.eye, &rotated_direction, objects, materials, accel, portals, baked, lights, sky, pixel_options, camera, fov, aspect_ratio, &mut rng),
+                        };
+                        let pixel_color = tonemap(pixel_color_v3, options.exposure, center_x, center_y);
+                        let (depth, object) = if record_aovs { (depth, object) } else { (f32::INFINITY, NO_OBJECT) };
+                        (pixel_color, depth, object)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        for (y, row) in rows.into_iter().zip(traced) {
+            for (x, (pixel_color, depth, object)) in (0..render_width).zip(row) {
+                framebuffer.set_current_color(pixel_color);
+
+                // Fill the entire block, ensuring we cover all pixels
+                let start_x = x * step_x;
+                let start_y = y * step_y;
+                let end_x = ((x + 1) * step_x).min(width);
+                let end_y = ((y + 1) * step_y).min(height);
+                
+                for pixel_y in start_y..end_y {
+                    for pixel_x in start_x..end_x {
+                        framebuffer.set_pixel(pixel_x, pixel_y);
+                        framebuffer.set_aovs(pixel_x, pixel_y, depth, object);
+                    }
+                }
+            }
+        }
+        if interlaced {
+            framebuffer.fill_skipped_field(step_y, render_height, field);
+        }
+        
+        // Fill any remaining pixels if there are gaps due to rounding
+        let last_rendered_x = render_width * step_x;
+        let last_rendered_y = render_height * step_y;
+        
+        // Fill remaining right edge
+        if last_rendered_x < width {
+            // Use the last column's color
+            if render_width > 0 {
+                let last_col_x = (render_width - 1) * step_x;
+                let last_col_y = 0;
+                let rotated_direction = primary_ray(camera, last_col_x as f32, last_col_y as f32, width, height, fov);
+                let mut rng = Rng::for_pixel(RENDER_SEED, last_col_x, last_col_y, frame_index);
+                let pixel_color_v3 = cast_ray(&camera.eye, &rotated_direction, objects, materials, accel, portals, baked, lights, sky, options_for_column(last_col_x, width, options, split), 0, camera, fov, aspect_ratio, &mut rng);
+                let pixel_color = tonemap(pixel_color_v3, options.exposure, last_col_x, last_col_y);
+                framebuffer.set_current_color(pixel_color);
+                
+                for y in 0..height {
+                    for x in last_rendered_x..width {
+                        framebuffer.set_pixel(x, y);
+                    }
+                }
+            }
+        }
+        
+        // Fill remaining bottom edge
+        if last_rendered_y < height {
+            // Use the last row's color
+            if render_height > 0 {
+                let last_row_x = 0;
+                let last_row_y = (render_height - 1) * step_y;
+                let rotated_direction = primary_ray(camera, last_row_x as f32, last_row_y as f32, width, height, fov);
+                let mut rng = Rng::for_pixel(RENDER_SEED, last_row_x, last_row_y, frame_index);
+                let pixel_color_v3 = cast_ray(&camera.eye, &rotated_direction, objects, materials, accel, portals, baked, lights, sky, options_for_column(last_row_x, width, options, split), 0, camera, fov, aspect_ratio, &mut rng);
+                let pixel_color = tonemap(pixel_color_v3, options.exposure, last_row_x, last_row_y);
+                framebuffer.set_current_color(pixel_color);
+                
+                for y in last_rendered_y..height {
+                    for x in 0..last_rendered_x {
+                        framebuffer.set_pixel(x, y);
+                    }
+                }
+            }
+        }
+
+        if options.taa {
+            resolve_blocks(framebuffer, grid, camera, fov, jitter);
+        } else {
+            framebuffer.temporal.clear();
+        }
+    }
+
+    framebuffer.keep_history(interlaced);
+
+    // Divider line between the A and B halves
+    if let Some(split) = split {
+        let divider_x = ((split.divider * width as f32) as u32).min(width - 1);
+        framebuffer.set_current_color(Color::WHITE);
+        for y in 0..height {
+            framebuffer.set_pixel(divider_x, y);
+        }
+    }
+}
+
+// Temporal anti-aliasing at render scale: one history sample per block, blended on the
+// block grid and spread back over each block's pixels
+fn resolve_blocks(framebuffer: &mut Framebuffer, grid: RenderGrid, camera: &Camera, fov: f32, jitter: (f32, f32)) {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let block_pixel = |x: u32, y: u32| ((y * grid.step_y).min(height - 1) * width + (x * grid.step_x).min(width - 1)) as usize;
+    let cells = || (0..grid.rows).flat_map(|y| (0..grid.columns).map(move |x| (x, y)));
+    let mut colors: Vec<Color> = cells().map(|(x, y)| framebuffer.color_buffer[block_pixel(x, y)]).collect();
+    let depths: Vec<f32> = cells().map(|(x, y)| framebuffer.depth_buffer[block_pixel(x, y)]).collect();
+    framebuffer.temporal.resolve(&mut colors, &depths, grid, width, height, camera, fov, jitter);
+    for ((x, y), color) in cells().zip(colors) {
+        framebuffer.set_current_color(color);
+        for pixel_y in (y * grid.step_y)..((y + 1) * grid.step_y).min(height) {
+            for pixel_x in (x * grid.step_x)..((x + 1) * grid.step_x).min(width) {
+                framebuffer.set_pixel(pixel_x, pixel_y);
+            }
+        }
+    }
+}
+
+// The pixels a frame traces: every pixel, or below full render scale one per block of
+// `step_x` × `step_y` pixels, through the block's center
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RenderGrid {
+    pub(crate) columns: u32,
+    pub(crate) rows: u32,
+    step_x: u32,
+    step_y: u32,
+}
+
+impl RenderGrid {
+    fn center(&self, x: u32, y: u32) -> (u32, u32) {
+        (x * self.step_x + self.step_x / 2, y * self.step_y + self.step_y / 2)
+    }
+
+    /// Screen position a block's primary ray goes through, `jitter` (in blocks) off its center
+    pub(crate) fn sample_point(&self, x: u32, y: u32, jitter: (f32, f32)) -> (f32, f32) {
+        let (center_x, center_y) = self.center(x, y);
+        (center_x as f32 + jitter.0 * self.step_x as f32, center_y as f32 + jitter.1 * self.step_y as f32)
+    }
+
+    /// Inverse of `sample_point`: the fractional block whose jittered ray goes through a
+    /// screen position
+    pub(crate) fn block_at(&self, px: f32, py: f32, jitter: (f32, f32)) -> (f32, f32) {
+        let to_block = |p: f32, step: u32| (p - (step / 2) as f32) / step as f32;
+        (to_block(px, self.step_x) - jitter.0, to_block(py, self.step_y) - jitter.1)
+    }
+}
+
+// Secondary light for the half-resolution buffer: at every other traced pixel of every other
+// traced row, the full trace minus the same ray's direct light (same random numbers, so only
+// the reflections and refractions are left)
+fn trace_secondary(
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    portals: Option<&Portals>,
+    baked: BakedLighting,
+    camera: &Camera,
+    lights: &[Light],
+    sky: &Sky,
+    options: &RenderOptions,
+    split: Option<&SplitView>,
+    width: u32,
+    height: u32,
+    grid: RenderGrid,
+    frame_index: u32,
+) -> SecondaryBuffer {
+    let aspect_ratio = width as f32 / height as f32;
+    let fov = PI / 3.0;
+    let mut buffer = SecondaryBuffer::new(grid.columns, grid.rows);
+    let columns = buffer.width;
+    let traced: Vec<Vec<(Vector3, f32, u32)>> = (0..buffer.height)
+        .into_par_iter()
+        .map(|sample_y| {
+            (0..columns)
+                .map(|sample_x| {
+                    let (x, y) = grid.center((sample_x * 2).min(grid.columns - 1), (sample_y * 2).min(grid.rows - 1));
+                    let direction = primary_ray(camera, x as f32, y as f32, width, height, fov);
+                    let pixel_options = options_for_column(x, width, options, split);
+                    let direct_options = pixel_options.without_secondary();
+
+                    let mut rng = Rng::for_pixel(RENDER_SEED, x, y, frame_index);
+                    let full = cast_ray(&camera.eye, &direction, objects, materials, accel, portals, baked, lights, sky, pixel_options, 0, camera, fov, aspect_ratio, &mut rng);
+                    let mut rng = Rng::for_pixel(RENDER_SEED, x, y, frame_index);
+                    let (direct, (depth, object)) = cast_primary_ray(&camera.eye, &direction, objects, materials, accel, portals, baked, lights, sky, &direct_options, camera, fov, aspect_ratio, &mut rng);
+                    (full - direct, depth, object)
+                })
+                .collect()
+        })
+        .collect();
+    for (sample_y, row) in (0..buffer.height).zip(traced) {
+        for (sample_x, (secondary, depth, object)) in (0..columns).zip(row) {
+            buffer.set(sample_x, sample_y, secondary, depth, object);
+        }
+    }
+    buffer
+}
+
+/// Blocks of the cave elevator platform, the last objects `create_diorama` builds
+pub const ELEVATOR_BLOCKS: usize = 4;
+
+// Create complete diorama with trees
+pub fn create_diorama(library: &MaterialLibrary, materials: &mut MaterialTable) -> Vec<Cube> {
+    let mut cubes = Vec::new();
+    let cube_size = 1.0;
+    let floor_size = 10; 
+    let wall_height = 5;  
+    let start_offset = -((floor_size - 1) as f32 * cube_size) / 2.0;
+    
+    // Materials and textures come from the material library file
+    if !library.has_block("piedra") {
+        println!("ERROR: Could not load Piedra texture!");
+        return cubes;
+    }
+    let has = |name: &str| library.has_block(name);
+    
+    // Diamond spots on floor
+    let diamond_spots = vec![
+        (2, 3), (7, 2), (4, 6), (8, 7)
+    ];
+    // Lava pool in the back of the cave, it lights the cave from below
+    let lava_spots = vec![(6, 8), (7, 8)];
+    
+    // 1. BOTTOM FLOOR (complete)
+    for x in 0..floor_size {
+        for z in 0..floor_size {
+            let pos_x = start_offset + x as f32 * cube_size;
+            let pos_z = start_offset + z as f32 * cube_size;
+            let pos_y = -cube_size / 2.0;
+            
+            let is_diamond = diamond_spots.contains(&(x, z));
+            let is_lava = lava_spots.contains(&(x, z));
+            
+            let name = match (has("diamante"), has("lava")) {
+                (true, _) if is_diamond => "diamante",
+                (_, true) if is_lava => "lava",
+                _ => "piedra",
+            };
+            
+            cubes.extend(library.cube(name, Vector3::new(pos_x, pos_y, pos_z), cube_size, materials));
+        }
+    }
+    
+    // 2. WALLS (3 walls - no front wall)
+    // Left wall
+    for y in 0..wall_height {
+        for z in 0..floor_size {
+            let pos_x = start_offset;
+            let pos_z = start_offset + z as f32 * cube_size;
+            let pos_y = cube_size / 2.0 + y as f32 * cube_size;
+            
+            cubes.extend(library.cube("piedra", Vector3::new(pos_x, pos_y, pos_z), cube_size, materials));
+        }
+    }
+    
+    // Right wall
+    for y in 0..wall_height {
+        for z in 0..floor_size {
+            let pos_x = start_offset + (floor_size - 1) as f32 * cube_size;
+            let pos_z = start_offset + z as f32 * cube_size;
+            let pos_y = cube_size / 2.0 + y as f32 * cube_size;
+            
+            cubes.extend(library.cube("piedra", Vector3::new(pos_x, pos_y, pos_z), cube_size, materials));
+        }
+    }
+    
+    // Back wall
+    for y in 0..wall_height {
+        for x in 1..(floor_size-1) {
+            let pos_x = start_offset + x as f32 * cube_size;
+            let pos_z = start_offset + (floor_size - 1) as f32 * cube_size;
+            let pos_y = cube_size / 2.0 + y as f32 * cube_size;
+            
+            cubes.extend(library.cube("piedra", Vector3::new(pos_x, pos_y, pos_z), cube_size, materials));
+        }
+    }
+    
+    // 3. TOP FLOOR - COMPLETE with ALL border cubes
+    if has("tierra") {
+        let top_y = cube_size / 2.0 + wall_height as f32 * cube_size;
+        
+        // 4x3 hole in center
+        let hole_center_x = floor_size / 2;
+        let hole_center_z = floor_size / 2;
+        let hole_start_x = hole_center_x - 2; // 4 wide
+        let hole_start_z = hole_center_z - 1; // 3 deep
+        let hole_end_x = hole_start_x + 4;
+        let hole_end_z = hole_start_z + 3;
+        
+        // Add EVERY top cube except hole
+        for x in 0..floor_size {
+            for z in 0..floor_size {
+                let in_hole = x >= hole_start_x && x < hole_end_x && 
+                             z >= hole_start_z && z < hole_end_z;
+                
+                if !in_hole {
+                    let pos_x = start_offset + x as f32 * cube_size;
+                    let pos_z = start_offset + z as f32 * cube_size;
+                    
+                    cubes.extend(library.cube("tierra", Vector3::new(pos_x, top_y, pos_z), cube_size, materials));
+                }
+            }
+        }
+        
+        println!("TOP FLOOR: {} tierra cubes with complete borders", 
+                 (floor_size * floor_size) - (4 * 3));
+    }
+
+    // 4. ADD MINECRAFT-STYLE TREES on top floor
+    if has("tronco") && has("hojas") {
+        let top_y = cube_size / 2.0 + wall_height as f32 * cube_size;
+        
+        // Tree positions - 3 trees around the hole
+        let tree_positions = vec![
+            (1, 1),  // Front-left of the diorama
+            (8, 2),  // Front-right 
+            (2, 8),  // Back-left
+        ];
+        
+        for (tree_x, tree_z) in tree_positions {
+            let tree_world_x = start_offset + tree_x as f32 * cube_size;
+            let tree_world_z = start_offset + tree_z as f32 * cube_size;
+            
+            // TRUNK - 3 cubes tall (raised higher so it's visible)
+            for trunk_height in 0..3 {
+                let trunk_y = top_y + cube_size + trunk_height as f32 * cube_size;
+                
+                cubes.extend(library.cube("tronco", Vector3::new(tree_world_x, trunk_y, tree_world_z), cube_size, materials));
+            }
+            
+            // LEAVES - Start at top of trunk, raised higher
+            let leaves_center_y = top_y + cube_size + 2.0 * cube_size; // Top of 3-block trunk
+            
+            // 3x3 leaves pattern for 2 layers only (middle and top) - no bottom layer
+            for dy in 1..3 { // Start from layer 1, not 0
+                for dx in -1i32..=1i32 {
+                    for dz in -1i32..=1i32 {
+                        let leaf_x = tree_world_x + dx as f32 * cube_size;
+                        let leaf_y = leaves_center_y + (dy as f32 - 1.0) * cube_size;
+                        let leaf_z = tree_world_z + dz as f32 * cube_size;
+                        
+                        // Create a more natural tree shape - fewer leaves on edges
+                        let is_edge = dx.abs() == 1 && dz.abs() == 1;
+                        let is_top_layer = dy == 2;
+                        let is_center = dx == 0 && dz == 0;
+                        
+                        // Skip top corners for natural look
+                        if is_edge && is_top_layer && !is_center {
+                            continue; 
+                        }
+                        
+                        cubes.extend(library.cube("hojas", Vector3::new(leaf_x, leaf_y, leaf_z), cube_size, materials));
+                    }
+                }
+            }
+            
+            // Add a single crown leaf on top of the tree
+            let crown_y = leaves_center_y + 1.0 * cube_size;
+            cubes.extend(library.cube("hojas", Vector3::new(tree_world_x, crown_y, tree_world_z), cube_size, materials));
+        }
+        
+        println!("TREES: Added 3 Minecraft-style trees with elevated canopy");
+        println!("Each tree: 3 trunk cubes + ~15 leaf cubes + 1 crown");
+    } else {
+        println!("TREES: Tronco or Hojas texture not found - skipping trees");
+    }
+
+    // 5. SKYLIGHT POOL - water fills the hole, the light above shines through it and
+    // throws caustics on the cave floor
+    let top_y = cube_size / 2.0 + wall_height as f32 * cube_size;
+    for x in 3..7 {
+        for z in 4..7 {
+            let pos_x = start_offset + x as f32 * cube_size;
+            let pos_z = start_offset + z as f32 * cube_size;
+            cubes.extend(library.cube("agua", Vector3::new(pos_x, top_y, pos_z), cube_size, materials));
+        }
+    }
+
+    // 6. GLOWSTONE - a glowing block in the back-left corner of the cave
+    let (pos_x, pos_z) = (start_offset + cube_size, start_offset + 8.0 * cube_size);
+    cubes.extend(library.cube("piedra_luminosa", Vector3::new(pos_x, cube_size / 2.0, pos_z), cube_size, materials));
+
+    // 7. ELEVATOR - 2x2 iron platform on the cave floor under the hole, the scene
+    // gallery animates it up the shaft; kept last so its indices are known, so it is built
+    // with the built-in iron when the library has none
+    let iron = MaterialLibrary::builtin_material("hierro").expect("the built-in library has hierro");
+    for (x, z) in [(4, 4), (5, 4), (4, 5), (5, 5)] {
+        let center = Vector3::new(start_offset + x as f32 * cube_size, cube_size / 2.0, start_offset + z as f32 * cube_size);
+        cubes.push(library.cube("hierro", center, cube_size, materials).unwrap_or_else(|| Cube::new(center, cube_size, materials.share(iron))));
+    }
+
+    finish_scene(&mut cubes, library);
+
+    println!("TOTAL CUBES: {}", cubes.len());
+    cubes
+}
+
+/// Portals of the diorama's cave, matching `create_diorama`'s layout: the open front and
+/// the hole in the top floor. The cave is the space between the walls and under the top floor.
+pub fn diorama_portals() -> Portals {
+    let half = 5.0; // Half the floor's width
+    let height = 5.0; // Walls
+    let interior = (Vector3::new(-half + 1.0, 0.0, -half), Vector3::new(half - 1.0, height, half - 1.0));
+    let outer = (Vector3::new(-half, -1.0, -half), Vector3::new(half, height + 1.0, half));
+    let front = Portal::new(Vector3::new(-half + 1.0, 0.0, -half), Vector3::new(half - 1.0, height, -half));
+    let hole = Portal::new(Vector3::new(-2.0, height, -1.0), Vector3::new(2.0, height, 2.0));
+    Portals::new(interior, outer, vec![front, hole])
+}
+
+/// Last steps every built set of blocks goes through: texture variants and rotations per
+/// block so stone doesn't tile visibly, and IDs. Sky visibility is baked once the whole
+/// scene, props included, is together (see `bake_objects`).
+pub fn finish_scene(cubes: &mut [Cube], library: &MaterialLibrary) {
+    library.vary_textures(cubes);
+    assign_ids(cubes);
+}
+
+/// Bakes sky visibility (darkens caves away from openings) over a scene's final object list
+pub fn bake_objects(objects: &mut [SceneObject]) {
+    let accel = Acceleration::build(objects);
+    bake_sky_visibility(objects, &accel);
+}
+
+// Loads the material library and its textures and builds the diorama as the gallery does,
+// props included, with the material table its objects index into
+pub fn load_scene(textures: TextureOptions) -> (Vec<SceneObject>, MaterialTable) {
+    let library = MaterialLibrary::load(textures);
+    let BuiltScene { objects, materials, .. } = scenes::gallery_builder(0)(&library);
+    (objects, materials)
+}
+
+// Camera positioned in front of the diorama for better initial view
+pub fn create_camera() -> Camera {
+    Camera::new(
+        Vector3::new(0.0, 4.0, -12.0),  // Front view, slightly elevated
+        Vector3::new(0.0, 3.0, 0.0),    // Looking at center of scene
+        Vector3::new(0.0, 1.0, 0.0),
+    )
+}
+
+// Sunlight from ABOVE the hole shining straight DOWN into the cave, parallel rays so the
+// hole's shadow edges line up with the hole wherever the light is
+pub fn create_light() -> Light {
+    Light::new(
+        Vector3::new(0.0, 10.0, 0.0),
+        Color::new(255, 255, 200, 255), 
+        2.0,
+    )
+    .with_kind(LightKind::Directional { target: Vector3::zero() })
+}
+
+/// Warm lamp just above the glowing ball inside the cave mouth, so the ball actually lights
+/// the cave; short range so it leaves the outside to the main light
+pub fn create_cave_lamp() -> Light {
+    Light::new(Vector3::new(3.0, 0.8, -3.3), Color::new(255, 190, 110, 255), 1.5)
+        .with_falloff(Falloff::RangeLimited { k: 0.1, range: 7.0 })
+        .with_kind(LightKind::Area { u: Vector3::new(0.5, 0.0, 0.0), v: Vector3::new(0.0, 0.0, 0.5) })
+}
+
+/// Every light of the diorama, the main light first
+pub fn create_lights() -> Vec<Light> {
+    vec![create_light(), create_cave_lamp()]
+}
+
+/// A light for every glowing block, so glowstone and lava light up what's around them
+/// instead of only glowing themselves. Each is a square the size of the block through its
+/// center, as bright as its faces on average. The path tracer samples the blocks directly and
+/// doesn't need these.
+pub fn block_lights(objects: &[SceneObject], materials: &MaterialTable) -> Vec<Light> {
+    objects
+        .iter()
+        .enumerate()
+        .filter_map(|(index, object)| Some((index, object.as_cube()?)))
+        .filter_map(|(index, object)| {
+            let emission = object.total_emission(materials) / 6.0;
+            let peak = emission.x.max(emission.y).max(emission.z);
+            if peak <= 0.0 {
+                return None;
+            }
+            let channel = |value: f32| (value / peak * 255.0).round() as u8;
+            let color = Color::new(channel(emission.x), channel(emission.y), channel(emission.z), 255);
+            let size = object.size;
+            Some(
+                Light::new(object.center, color, peak * size * size)
+                    .with_falloff(Falloff::RangeLimited { k: 1.0, range: BLOCK_LIGHT_RANGE * size })
+                    .with_kind(LightKind::Area { u: Vector3::new(size, 0.0, 0.0), v: Vector3::new(0.0, 0.0, size) })
+                    .with_emitter(index),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schlick_reflects_more_at_grazing_angles() {
+        // Air to glass head-on reflects about 4%, and the share rises to all of it at grazing
+        let head_on = schlick(1.0, 1.0, 1.5);
+        assert!((head_on - 0.04).abs() < 1e-6);
+        assert!(schlick(0.5, 1.0, 1.5) > head_on);
+        assert!((schlick(0.0, 1.0, 1.5) - 1.0).abs() < 1e-6);
+        // Same medium on both sides, nothing is reflected head-on
+        assert_eq!(schlick(1.0, 1.33, 1.33), 0.0);
+    }
+
+    #[test]
+    fn schlick_reflects_everything_past_the_critical_angle() {
+        // Glass to air: the critical angle is asin(1 / 1.5), about 41.8°
+        let critical_cos = (1.0 - (1.0f32 / 1.5).powi(2)).sqrt();
+        assert_eq!(schlick(critical_cos - 0.01, 1.5, 1.0), 1.0);
+        assert!(schlick(critical_cos + 0.01, 1.5, 1.0) < 1.0);
+        // Head-on it matches going the other way
+        assert!((schlick(1.0, 1.5, 1.0) - schlick(1.0, 1.0, 1.5)).abs() < 1e-6);
+    }
+}
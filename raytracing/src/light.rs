@@ -1,9 +1,120 @@
 use raylib::prelude::*;
 
+/// How a light's intensity drops with distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Falloff {
+    /// `1 / (1 + k·d²)`, never reaches zero so shadows are traced at any distance
+    InverseSquare { k: f32 },
+    /// Fades linearly from full intensity at the light to zero at `range`
+    Linear { range: f32 },
+    /// Inverse-square that is smoothly windowed to exactly zero at `range`
+    RangeLimited { k: f32, range: f32 },
+}
+
+impl Falloff {
+    pub fn attenuation(&self, distance: f32) -> f32 {
+        match *self {
+            Falloff::InverseSquare { k } => 1.0 / (1.0 + distance * distance * k),
+            Falloff::Linear { range } => (1.0 - distance / range).max(0.0),
+            Falloff::RangeLimited { k, range } => {
+                let window = (1.0 - (distance / range).powi(4)).max(0.0);
+                window * window / (1.0 + distance * distance * k)
+            }
+        }
+    }
+
+    /// Distance past which the light contributes nothing, if any
+    pub fn range(&self) -> Option<f32> {
+        match *self {
+            Falloff::InverseSquare { .. } => None,
+            Falloff::Linear { range } | Falloff::RangeLimited { range, .. } => Some(range),
+        }
+    }
+
+    /// Reads `inverse_square K`, `linear RANGE` or `limited K RANGE`, the form `Display` writes
+    pub fn parse(text: &str) -> Option<Falloff> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let numbers: Vec<f32> = words.iter().skip(1).map(|word| word.parse().ok()).collect::<Option<_>>()?;
+        match (words.first().copied()?, numbers.as_slice()) {
+            ("inverse_square", &[k]) => Some(Falloff::InverseSquare { k }),
+            ("linear", &[range]) if range > 0.0 => Some(Falloff::Linear { range }),
+            ("limited", &[k, range]) if range > 0.0 => Some(Falloff::RangeLimited { k, range }),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Falloff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Falloff::InverseSquare { k } => write!(f, "inverse_square {}", k),
+            Falloff::Linear { range } => write!(f, "linear {}", range),
+            Falloff::RangeLimited { k, range } => write!(f, "limited {} {}", k, range),
+        }
+    }
+}
+
+impl Default for Falloff {
+    // The curve the diorama was originally tuned with
+    fn default() -> Self {
+        Falloff::InverseSquare { k: 0.005 }
+    }
+}
+
+/// Restricts a light to a cone, fading out between the inner and outer angle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spot {
+    pub direction: Vector3,
+    pub inner_cos: f32,
+    pub outer_cos: f32,
+}
+
+impl Spot {
+    pub fn new(direction: Vector3, inner_angle: f32, outer_angle: f32) -> Self {
+        Spot {
+            direction: direction.normalized(),
+            inner_cos: inner_angle.cos(),
+            outer_cos: outer_angle.cos(),
+        }
+    }
+
+    /// 1 inside the inner cone, 0 outside the outer one; `to_point` points away from the light
+    pub fn factor(&self, to_point: Vector3) -> f32 {
+        let cos = self.direction.dot(to_point);
+        let t = ((cos - self.outer_cos) / (self.inner_cos - self.outer_cos).max(1e-4)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+/// Where a light's rays come from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    /// Shines every way from its position and fades with distance
+    Point,
+    /// Sunlight: parallel rays along the line from its position to `target`, equally strong
+    /// everywhere; the position only sets the direction (and where the gizmo is drawn)
+    Directional { target: Vector3 },
+    /// Rectangle centered on its position with edges `u` and `v`, shining from every point
+    /// of it, so its shadows blur the farther they fall from what casts them
+    Area { u: Vector3, v: Vector3 },
+}
+
+// Headlamp: a wide torch beam that fades out well before the far walls of the cave
+const HEADLAMP_INTENSITY: f32 = 2.0;
+const HEADLAMP_INNER_ANGLE: f32 = 0.35;
+const HEADLAMP_OUTER_ANGLE: f32 = 0.6;
+const HEADLAMP_RANGE: f32 = 14.0;
+
+#[derive(Clone, PartialEq)]
 pub struct Light {
     pub position: Vector3,
     pub color: Color,
     pub intensity: f32,
+    pub falloff: Falloff,
+    pub spot: Option<Spot>,
+    pub kind: LightKind,
+    pub animated: bool, // Follows the camera or an animation; light probes don't rebake as it changes
+    pub emitter: Option<usize>, // The glowing block a block light stands for, which doesn't shadow it
 }
 
 impl Light {
@@ -12,6 +123,83 @@ impl Light {
             position,
             color,
             intensity,
+            falloff: Falloff::default(),
+            spot: None,
+            kind: LightKind::Point,
+            animated: false,
+            emitter: None,
         }
     }
-}
\ No newline at end of file
+
+    /// Spot light at the viewer pointing where they look
+    pub fn headlamp(eye: Vector3, forward: Vector3) -> Self {
+        Light {
+            animated: true,
+            ..Light::new(eye, Color::new(255, 244, 220, 255), HEADLAMP_INTENSITY)
+                .with_falloff(Falloff::RangeLimited { k: 0.02, range: HEADLAMP_RANGE })
+                .with_spot(Spot::new(forward, HEADLAMP_INNER_ANGLE, HEADLAMP_OUTER_ANGLE))
+        }
+    }
+
+    pub fn with_falloff(mut self, falloff: Falloff) -> Self {
+        self.falloff = falloff;
+        self
+    }
+
+    pub fn with_spot(mut self, spot: Spot) -> Self {
+        self.spot = Some(spot);
+        self
+    }
+
+    pub fn with_kind(mut self, kind: LightKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_emitter(mut self, object: usize) -> Self {
+        self.emitter = Some(object);
+        self
+    }
+
+    /// Unit direction from `point` towards the light (an area light's center) and the
+    /// distance to it, infinite for a directional light
+    pub fn towards(&self, point: Vector3) -> (Vector3, f32) {
+        match self.kind {
+            LightKind::Point | LightKind::Area { .. } => {
+                let to_light = self.position - point;
+                let distance = to_light.length();
+                (to_light / distance, distance)
+            }
+            LightKind::Directional { target } => ((self.position - target).normalized(), f32::INFINITY),
+        }
+    }
+
+    /// `towards` the point at `(s, t)` in [0, 1)² across an area light; other lights have
+    /// only the one point
+    pub fn towards_sample(&self, point: Vector3, s: f32, t: f32) -> (Vector3, f32) {
+        match self.kind {
+            LightKind::Area { u, v } => {
+                let to_light = self.position + u * (s - 0.5) + v * (t - 0.5) - point;
+                let distance = to_light.length();
+                (to_light / distance, distance)
+            }
+            _ => self.towards(point),
+        }
+    }
+
+    /// Share of the intensity left `distance` away; directional lights don't fall off
+    pub fn attenuation(&self, distance: f32) -> f32 {
+        match self.kind {
+            LightKind::Point | LightKind::Area { .. } => self.falloff.attenuation(distance),
+            LightKind::Directional { .. } => 1.0,
+        }
+    }
+
+    /// Distance past which the light contributes nothing, if any
+    pub fn range(&self) -> Option<f32> {
+        match self.kind {
+            LightKind::Point | LightKind::Area { .. } => self.falloff.range(),
+            LightKind::Directional { .. } => None,
+        }
+    }
+}
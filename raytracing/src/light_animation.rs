@@ -0,0 +1,88 @@
+use crate::light::Light;
+use crate::rng::mix64;
+use raylib::prelude::*;
+use std::f32::consts::TAU;
+
+/// Circles the light around `center` in the horizontal plane
+#[derive(Debug, Clone, Copy)]
+pub struct Orbit {
+    pub center: Vector3,
+    pub radius: f32,
+    pub period: f32, // Seconds per revolution
+}
+
+/// Sinusoidal intensity change, `amount` = fraction of the base intensity
+#[derive(Debug, Clone, Copy)]
+pub struct Pulse {
+    pub amount: f32,
+    pub period: f32,
+}
+
+/// Random torch-like flicker; the same seed always flickers the same way
+#[derive(Debug, Clone, Copy)]
+pub struct Flicker {
+    pub amount: f32,
+    pub rate: f32, // New random target this many times per second
+    pub seed: u64,
+}
+
+/// Animation applied on top of a light's base settings, evaluated from simulation time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LightAnimation {
+    pub orbit: Option<Orbit>,
+    pub pulse: Option<Pulse>,
+    pub flicker: Option<Flicker>,
+}
+
+impl LightAnimation {
+    /// Light at `time` seconds, derived from `base` so repeated calls never drift
+    pub fn apply(&self, base: &Light, time: f32) -> Light {
+        let mut light = base.clone();
+
+        if let Some(orbit) = self.orbit {
+            let angle = time / orbit.period * TAU;
+            light.position = Vector3::new(
+                orbit.center.x + orbit.radius * angle.cos(),
+                base.position.y,
+                orbit.center.z + orbit.radius * angle.sin(),
+            );
+        }
+
+        let mut scale = 1.0;
+        if let Some(pulse) = self.pulse {
+            scale *= 1.0 + pulse.amount * (time / pulse.period * TAU).sin();
+        }
+        if let Some(flicker) = self.flicker {
+            // Smoothly blend between per-tick random values so it flickers without popping
+            let tick = time * flicker.rate;
+            let index = tick.floor() as i64 as u64;
+            let t = tick.fract();
+            let t = t * t * (3.0 - 2.0 * t);
+            let noise = flicker_noise(flicker.seed, index) * (1.0 - t) + flicker_noise(flicker.seed, index + 1) * t;
+            scale *= 1.0 - flicker.amount * noise;
+        }
+        light.intensity = base.intensity * scale.max(0.0);
+        light.animated |= self.orbit.is_some() || self.pulse.is_some() || self.flicker.is_some();
+        light
+    }
+}
+
+// Random value in [0, 1) for one flicker tick
+fn flicker_noise(seed: u64, index: u64) -> f32 {
+    (mix64(seed ^ mix64(index)) >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Presets cycled through in the interactive viewer
+pub const LIGHT_ANIMATION_PRESETS: [(&str, LightAnimation); 4] = [
+    ("Static", LightAnimation { orbit: None, pulse: None, flicker: None }),
+    (
+        "Orbit",
+        LightAnimation {
+            orbit: Some(Orbit { center: Vector3 { x: 0.0, y: 0.0, z: 0.0 }, radius: 3.0, period: 12.0 }),
+            pulse: None,
+            flicker: None,
+        },
+    ),
+    ("Pulse", LightAnimation { orbit: None, pulse: Some(Pulse { amount: 0.4, period: 3.0 }), flicker: None }),
+    ("Torch flicker", LightAnimation { orbit: None, pulse: None, flicker: Some(Flicker { amount: 0.35, rate: 12.0, seed: 0x70C4 }) }),
+];
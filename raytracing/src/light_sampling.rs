@@ -0,0 +1,110 @@
+use crate::color_ops::ColorOps;
+use crate::light::Light;
+use raylib::prelude::Vector3;
+
+/// Picks lights in proportion to their power, so a frame with dozens of torches traces a
+/// fixed number of shadow rays per hit and the bright lights still get most of them
+pub struct PowerCdf {
+    cdf: Vec<f32>,
+    total: f32,
+}
+
+impl PowerCdf {
+    pub fn new(weights: impl Iterator<Item = f32>) -> Self {
+        let mut total = 0.0;
+        let cdf = weights
+            .map(|weight| {
+                total += weight.max(0.0);
+                total
+            })
+            .collect();
+        PowerCdf { cdf, total }
+    }
+
+    pub fn from_lights(lights: &[Light]) -> Self {
+        Self::new(lights.iter().map(light_power))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total <= 0.0
+    }
+
+    /// Index for a uniform `u` in [0, 1) and the probability it had of being picked
+    pub fn sample(&self, u: f32) -> Option<(usize, f32)> {
+        if self.is_empty() {
+            return None;
+        }
+        let target = u * self.total;
+        let index = self.cdf.partition_point(|&edge| edge <= target).min(self.cdf.len() - 1);
+        Some((index, self.probability(index)))
+    }
+
+    pub fn probability(&self, index: usize) -> f32 {
+        let previous = if index == 0 { 0.0 } else { self.cdf[index - 1] };
+        (self.cdf[index] - previous) / self.total
+    }
+}
+
+/// Rough emitted power: intensity times the brightness of the light's color
+pub fn light_power(light: &Light) -> f32 {
+    light.intensity.max(0.0) * light.color.luminance()
+}
+
+/// How much a light is worth sampling at `point`: its power, attenuated there for lights with
+/// a range and 0 past it, so only the block lights near a hit compete for its shadow rays
+pub fn light_weight(light: &Light, point: Vector3) -> f32 {
+    match light.range() {
+        None => light_power(light),
+        Some(range) => {
+            let distance = light.towards(point).1;
+            if distance < range { light_power(light) * light.attenuation(distance) } else { 0.0 }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::Falloff;
+    use raylib::prelude::Color;
+
+    #[test]
+    fn picks_each_index_in_proportion_to_its_weight() {
+        let cdf = PowerCdf::new([1.0, 0.0, 3.0].into_iter());
+        assert_eq!(cdf.probability(0), 0.25);
+        assert_eq!(cdf.probability(1), 0.0);
+        assert_eq!(cdf.probability(2), 0.75);
+        assert_eq!(cdf.sample(0.0), Some((0, 0.25)));
+        assert_eq!(cdf.sample(0.2), Some((0, 0.25)));
+        // A zero weight is never picked, its share goes to the next index
+        assert_eq!(cdf.sample(0.25), Some((2, 0.75)));
+        assert_eq!(cdf.sample(0.99), Some((2, 0.75)));
+
+        let mut picked = [0; 3];
+        for step in 0..1000 {
+            picked[cdf.sample(step as f32 / 1000.0).unwrap().0] += 1;
+        }
+        assert_eq!(picked, [250, 0, 750]);
+    }
+
+    #[test]
+    fn samples_nothing_without_weight() {
+        assert!(PowerCdf::new(std::iter::empty()).sample(0.5).is_none());
+        let cdf = PowerCdf::new([0.0, -2.0].into_iter());
+        assert!(cdf.is_empty());
+        assert!(cdf.sample(0.5).is_none());
+    }
+
+    #[test]
+    fn weighs_ranged_lights_by_their_power_where_they_reach() {
+        let light = Light::new(Vector3::zero(), Color::new(255, 255, 255, 255), 2.0);
+        let power = light_power(&light);
+        assert!((power - 2.0).abs() < 1e-4);
+        // Unlimited lights weigh their full power anywhere
+        assert_eq!(light_weight(&light, Vector3::new(50.0, 0.0, 0.0)), power);
+
+        let ranged = Light { falloff: Falloff::Linear { range: 4.0 }, ..light };
+        assert_eq!(light_weight(&ranged, Vector3::new(1.0, 0.0, 0.0)), power * 0.75);
+        assert_eq!(light_weight(&ranged, Vector3::new(5.0, 0.0, 0.0)), 0.0);
+    }
+}
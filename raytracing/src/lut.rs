@@ -0,0 +1,157 @@
+use raylib::prelude::{Color, Vector3};
+use std::fs;
+
+/// A 3D color lookup table in the Adobe/Resolve `.cube` format, applied to display colors
+pub struct Lut3d {
+    pub title: Option<String>,
+    size: usize,
+    domain_min: Vector3,
+    domain_max: Vector3,
+    table: Vec<Vector3>, // Red varies fastest, then green, then blue
+}
+
+impl Lut3d {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        parse(&text).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    /// Graded color for an 8-bit input, trilinearly interpolated between table entries
+    pub fn apply(&self, color: Color) -> Color {
+        let input = Vector3::new(color.r as f32, color.g as f32, color.b as f32) / 255.0;
+        let range = self.domain_max - self.domain_min;
+        let scale = (self.size - 1) as f32;
+        let cell = |value: f32, min: f32, range: f32| {
+            let position = ((value - min) / range).clamp(0.0, 1.0) * scale;
+            let low = (position.floor() as usize).min(self.size - 2);
+            (low, position - low as f32)
+        };
+        let (r, fr) = cell(input.x, self.domain_min.x, range.x);
+        let (g, fg) = cell(input.y, self.domain_min.y, range.y);
+        let (b, fb) = cell(input.z, self.domain_min.z, range.z);
+
+        let lerp = |a: Vector3, b: Vector3, t: f32| a + (b - a) * t;
+        let at = |r: usize, g: usize, b: usize| self.table[(b * self.size + g) * self.size + r];
+        let c00 = lerp(at(r, g, b), at(r + 1, g, b), fr);
+        let c10 = lerp(at(r, g + 1, b), at(r + 1, g + 1, b), fr);
+        let c01 = lerp(at(r, g, b + 1), at(r + 1, g, b + 1), fr);
+        let c11 = lerp(at(r, g + 1, b + 1), at(r + 1, g + 1, b + 1), fr);
+        let graded = lerp(lerp(c00, c10, fg), lerp(c01, c11, fg), fb);
+
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Color::new(channel(graded.x), channel(graded.y), channel(graded.z), color.a)
+    }
+}
+
+pub fn parse(text: &str) -> Result<Lut3d, String> {
+    let mut title = None;
+    let mut size = None;
+    let mut domain_min = Vector3::zero();
+    let mut domain_max = Vector3::one();
+    let mut table = Vec::new();
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let keyword = parts.next().unwrap_or("");
+        match keyword {
+            "TITLE" => title = Some(line["TITLE".len()..].trim().trim_matches('"').to_string()),
+            "LUT_3D_SIZE" => {
+                let value = parts.next().and_then(|n| n.parse::<usize>().ok());
+                match value {
+                    Some(n) if (2..=256).contains(&n) => size = Some(n),
+                    _ => return Err(format!("line {}: LUT_3D_SIZE must be between 2 and 256", line_number)),
+                }
+            }
+            "DOMAIN_MIN" => domain_min = parse_triple(line, line_number)?,
+            "DOMAIN_MAX" => domain_max = parse_triple(line, line_number)?,
+            "LUT_1D_SIZE" => return Err(format!("line {}: 1D LUTs are not supported", line_number)),
+            _ if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                // Other metadata (LUT_3D_INPUT_RANGE, vendor keywords) doesn't affect the table
+            }
+            _ => table.push(parse_triple(line, line_number)?),
+        }
+    }
+
+    let size = size.ok_or("missing LUT_3D_SIZE")?;
+    if table.len() != size * size * size {
+        return Err(format!("expected {} entries for size {}, found {}", size * size * size, size, table.len()));
+    }
+    let range = domain_max - domain_min;
+    if range.x <= 0.0 || range.y <= 0.0 || range.z <= 0.0 {
+        return Err("DOMAIN_MAX must be above DOMAIN_MIN".to_string());
+    }
+    Ok(Lut3d { title, size, domain_min, domain_max, table })
+}
+
+fn parse_triple(line: &str, line_number: usize) -> Result<Vector3, String> {
+    let numbers: Vec<f32> = line
+        .split_whitespace()
+        .filter(|part| !part.starts_with(|c: char| c.is_ascii_alphabetic()))
+        .map(|part| part.parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("line {}: `{}` is not a number list", line_number, line))?;
+    match numbers[..] {
+        [r, g, b] => Ok(Vector3::new(r, g, b)),
+        _ => Err(format!("line {}: expected 3 numbers", line_number)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2×2×2 table, red fastest, that swaps red and blue
+    const SWAP_RED_BLUE: &str = "\
+TITLE \"Swap\"
+# A comment
+LUT_3D_SIZE 2
+0 0 0
+0 0 1
+0 1 0
+0 1 1
+1 0 0
+1 0 1
+1 1 0
+1 1 1
+";
+
+    #[test]
+    fn reads_the_title_and_the_table() {
+        let lut = parse(SWAP_RED_BLUE).unwrap();
+        assert_eq!(lut.title.as_deref(), Some("Swap"));
+        assert_eq!(lut.table.len(), 8);
+        assert_eq!(lut.table[1], Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn grades_colors_between_the_table_entries() {
+        let lut = parse(SWAP_RED_BLUE).unwrap();
+        assert_eq!(lut.apply(Color::new(255, 0, 0, 200)), Color::new(0, 0, 255, 200));
+        assert_eq!(lut.apply(Color::new(51, 102, 204, 255)), Color::new(204, 102, 51, 255));
+    }
+
+    #[test]
+    fn maps_the_domain_onto_the_table() {
+        let text = format!("DOMAIN_MIN 0 0 0\nDOMAIN_MAX 0.5 0.5 0.5\n{}", SWAP_RED_BLUE);
+        let lut = parse(&text).unwrap();
+        // Inputs above the domain clamp to its top
+        assert_eq!(lut.apply(Color::new(64, 0, 255, 255)), Color::new(255, 0, 128, 255));
+    }
+
+    #[test]
+    fn refuses_broken_tables() {
+        let error = |text: &str| parse(text).err().unwrap();
+        assert_eq!(error("0 0 0\n"), "missing LUT_3D_SIZE");
+        assert!(error("LUT_3D_SIZE 1\n").contains("between 2 and 256"));
+        assert!(error("LUT_1D_SIZE 16\n").contains("not supported"));
+        assert!(error("LUT_3D_SIZE 2\n0 0 0\n").contains("expected 8 entries"));
+        assert!(error("LUT_3D_SIZE 2\n0 zero 0\n").starts_with("line 2:"));
+        assert!(error(&format!("DOMAIN_MIN 1 0 0\n{}", SWAP_RED_BLUE)).contains("DOMAIN_MAX"));
+    }
+}
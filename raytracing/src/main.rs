@@ -1,787 +1,1063 @@
 use raylib::prelude::*;
-use std::f32::consts::PI;
 
-mod framebuffer;
-mod ray_intersect;
-mod cube;
-mod camera;
-mod light;
-mod material;
-
-use framebuffer::Framebuffer;
-use ray_intersect::{Intersect, RayIntersect};
-use cube::Cube;
-use camera::Camera;
-use light::Light;
-use material::{Material, vector3_to_color};
-
-const ORIGIN_BIAS: f32 = 1e-4;
+use raytracing::animation::Timeline;
+use raytracing::bookmarks::{self, BOOKMARKS_FILE, BookmarkJob, Bookmarks};
+use raytracing::camera::Camera;
+use raytracing::console::{CONSOLE_HELP, Console, ConsoleCommand, ConsoleTarget};
+use raytracing::daycycle::DayCycle;
+use raytracing::demo::{Demo, DemoCue, DemoPlayer};
+use raytracing::distributed::{self, Role};
+use raytracing::dynamic_resolution::ResolutionController;
+use raytracing::export::{self, ExportJob};
+use raytracing::flythrough::{self, Flythrough, FlythroughJob, Playback};
+use raytracing::framebuffer::{PresentedFrame, present};
+use raytracing::hud::Overlay;
+use raytracing::input::{self, Action};
+use raytracing::offline::{self, OfflineJob, TimelapseJob};
+use raytracing::post::{EFFECT_STRENGTHS, PostProcess};
+use raytracing::light::Light;
+use raytracing::light_animation::LIGHT_ANIMATION_PRESETS;
+use raytracing::material::TextureOptions;
+use raytracing::material_library::{LibraryWatcher, MaterialEdit};
+use raytracing::recorder::{RECORDING_FPS, Recorder};
+use raytracing::render_thread::{OverrideTarget, RenderJob, RenderThread, SaveSceneRequest, SceneBuilder, SceneCommand, ScreenshotRequest};
+use raytracing::scenes::{GALLERY, find_scene, gallery_builder};
+use raytracing::script::ScriptScene;
+use raytracing::settings::{SETTINGS_FILE, SavedView, Settings};
+use raytracing::sky::Sky;
+use raytracing::timestep::FixedTimestep;
+use raytracing::tonemap::EXPOSURE_STEP;
+use std::path::{Path, PathBuf};
 
 // Performance settings - adjusted for reflections
+// Dynamic resolution: the render scale follows measured trace times to hold a frame rate
 const ADAPTIVE_RENDER: bool = true;
-const MIN_RENDER_SCALE: f32 = 0.125; // Even lower for moving
-const MID_RENDER_SCALE: f32 = 0.5;   // Medium quality
+const DEFAULT_TARGET_FPS: f32 = 30.0;
+const MIN_RENDER_SCALE: f32 = 0.125;
 const MAX_RENDER_SCALE: f32 = 0.75;  // Reduced max quality
-const MAX_RAY_DEPTH: u32 = 2;        // Enable reflections (was 0)
-const FRUSTUM_CULLING: bool = true;
-const EARLY_RAY_TERMINATION: bool = false; // Disabled - causing holes
-
-fn procedural_sky(dir: Vector3) -> Vector3 {
-    let d = dir.normalized();
-    let t = (d.y + 1.0) * 0.5;
-
-    let green = Vector3::new(0.1, 0.6, 0.2);
-    let white = Vector3::new(1.0, 1.0, 1.0);
-    let blue = Vector3::new(0.3, 0.5, 1.0);
-
-    if t < 0.54 {
-        let k = t / 0.55;
-        green * (1.0 - k) + white * k
-    } else if t < 0.55 {
-        white
-    } else if t < 0.8 {
-        let k = (t - 0.55) / 0.25;
-        white * (1.0 - k) + blue * k
-    } else {
-        blue
+
+// Idle: after this many frames without input on a still scene one last full-quality frame
+// is traced and stays up, the window only redraws this often
+const IDLE_AFTER_FRAMES: u32 = 30;
+// Animated scenes (the water and the elevator of the cave) idle too, after longer without
+// input; the simulation clock stops meanwhile so the image they leave up stays true
+const IDLE_ANIMATED_AFTER_FRAMES: u32 = 600;
+const IDLE_FPS: u32 = 5;
+// A still view keeps being refined until this many frames are averaged, the path tracer
+// (noisier per frame) goes on longer
+const PROGRESSIVE_FRAMES: u32 = 64;
+const PATH_TRACE_FRAMES: u32 = 1024;
+
+// Simulation runs at a fixed rate, speeds are per simulated second
+const SIMULATION_HZ: f32 = 60.0;
+const MOVEMENT_SPEED: f32 = 6.0;
+const ROTATION_SPEED: f32 = 1.2;
+
+// Step sizes for live material tweaks
+const ALBEDO_STEP: f32 = 0.05;
+const SPECULAR_STEP: f32 = 8.0;
+
+// Light editing: movement speed, intensity step and the colors K cycles through
+const LIGHT_MOVE_SPEED: f32 = 4.0;
+const LIGHT_INTENSITY_STEP: f32 = 0.25;
+const LIGHT_COLOR_PRESETS: [(&str, (u8, u8, u8)); 4] = [
+    ("Warm white", (255, 255, 200)),
+    ("Torch", (255, 170, 90)),
+    ("Moonlight", (170, 200, 255)),
+    ("White", (255, 255, 255)),
+];
+
+// Moves the light with the camera's WASD/Q/E scheme while light edit mode is on
+fn update_light(window: &RaylibHandle, light: &mut Light, camera: &Camera, dt: f32) -> bool {
+    let step_move = LIGHT_MOVE_SPEED * dt;
+    // Horizontal movement follows the view so W always pushes the light away from you
+    let forward = Vector3::new(camera.forward.x, 0.0, camera.forward.z).normalized();
+    let right = Vector3::new(camera.right.x, 0.0, camera.right.z).normalized();
+    let up = Vector3::new(0.0, 1.0, 0.0);
+
+    let moves = [
+        (Action::MoveForward, forward),
+        (Action::MoveBack, -forward),
+        (Action::MoveLeft, -right),
+        (Action::MoveRight, right),
+        (Action::MoveUp, up),
+        (Action::MoveDown, -up),
+    ];
+    let mut light_moved = false;
+    for (action, direction) in moves {
+        if input::held(window, action) {
+            light.position = light.position + direction * step_move;
+            light_moved = true;
+        }
+    }
+    light_moved
+}
+
+// Applies held movement keys for one fixed simulation step
+fn update_camera(window: &RaylibHandle, camera: &mut Camera, dt: f32) -> bool {
+    let step_move = MOVEMENT_SPEED * dt;
+    let step_rotate = ROTATION_SPEED * dt;
+    let mut camera_moved = false;
+
+    if input::held(window, Action::MoveForward) {
+        camera.move_forward(step_move);
+        camera_moved = true;
     }
+    if input::held(window, Action::MoveBack) {
+        camera.move_forward(-step_move);
+        camera_moved = true;
+    }
+    if input::held(window, Action::MoveLeft) {
+        camera.move_right(-step_move);
+        camera_moved = true;
+    }
+    if input::held(window, Action::MoveRight) {
+        camera.move_right(step_move);
+        camera_moved = true;
+    }
+    if input::held(window, Action::MoveUp) {
+        camera.move_up(step_move);
+        camera_moved = true;
+    }
+    if input::held(window, Action::MoveDown) {
+        camera.move_up(-step_move);
+        camera_moved = true;
+    }
+    if input::held(window, Action::LookLeft) {
+        camera.rotate(-step_rotate, 0.0);
+        camera_moved = true;
+    }
+    if input::held(window, Action::LookRight) {
+        camera.rotate(step_rotate, 0.0);
+        camera_moved = true;
+    }
+    if input::held(window, Action::LookUp) {
+        camera.rotate(0.0, step_rotate);
+        camera_moved = true;
+    }
+    if input::held(window, Action::LookDown) {
+        camera.rotate(0.0, -step_rotate);
+        camera_moved = true;
+    }
+
+    camera_moved
 }
 
-#[inline]
-fn offset_origin(intersect: &Intersect, direction: &Vector3) -> Vector3 {
-    let offset = intersect.normal * ORIGIN_BIAS;
-    if direction.dot(intersect.normal) < 0.0 {
-        intersect.point - offset
-    } else {
-        intersect.point + offset
+// Scene gallery panel, the current scene marked
+fn scene_menu_lines(current: usize) -> Vec<String> {
+    let mut lines = vec![format!("Scenes - press 1-{} to load, M to close", GALLERY.len())];
+    for (index, entry) in GALLERY.iter().enumerate() {
+        let marker = if index == current { " *" } else { "" };
+        lines.push(format!("{}: {}{}", index + 1, entry.name, marker));
     }
+    lines
 }
 
-#[inline]
-fn reflect(incident: &Vector3, normal: &Vector3) -> Vector3 {
-    *incident - *normal * 2.0 * incident.dot(*normal)
+// Swaps in a gallery scene with its own camera, light and lamps, dropping the script's lights and tracks
+fn load_gallery_scene(index: usize, settings: &Settings, camera: &mut Camera, base_light: &mut Light, extra_lights: &mut Vec<Light>, timeline: &mut Timeline, render_thread: &RenderThread) {
+    *camera = (GALLERY[index].camera)();
+    *base_light = gallery_light(index, settings);
+    *extra_lights = (GALLERY[index].lamps)();
+    *timeline = Timeline::default();
+    render_thread.command(SceneCommand::LoadScene(index));
 }
 
-// Optimized shadow casting - simplified for performance
-fn cast_shadow(
-    intersect: &Intersect,
-    light: &Light,
-    objects: &mut [Cube],
-) -> f32 {
-    let light_dir = (light.position - intersect.point).normalized();
-    let light_distance = (light.position - intersect.point).length();
-    let shadow_ray_origin = offset_origin(intersect, &light_dir);
-
-    // Early exit for distant lights
-    if light_distance > 25.0 {
-        return 0.2; // Light shadow for distant surfaces
+// The scene's main light, with the falloff from the settings file if one is set there
+fn gallery_light(index: usize, settings: &Settings) -> Light {
+    let mut light = (GALLERY[index].light)();
+    if let Some(falloff) = settings.light_falloff {
+        light.falloff = falloff;
     }
+    light
+}
 
-    // Check all objects for shadows - no early termination to prevent holes
-    for object in objects.iter_mut() {
-        let shadow_intersect = object.ray_intersect(&shadow_ray_origin, &light_dir);
-        if shadow_intersect.is_intersecting && shadow_intersect.distance < light_distance - 0.01 {
-            return 0.8; // Reduced shadow intensity
+// Runs a scene script, reporting errors so a broken script doesn't end the session
+fn load_script(path: &Path) -> Option<ScriptScene> {
+    match ScriptScene::load(path) {
+        Ok(script) => {
+            println!("Script {}: {} blocks, {} lights", path.display(), script.block_count(), script.lights.len());
+            Some(script)
+        }
+        Err(err) => {
+            println!("ERROR: {}", err);
+            None
         }
     }
-    0.0
 }
 
-// Frustum culling - less aggressive to prevent holes
-fn is_in_frustum(cube_center: Vector3, _cube_size: f32, camera: &Camera, _fov: f32, _aspect: f32) -> bool {
-    if !FRUSTUM_CULLING {
-        return true;
-    }
-    
-    let to_cube = cube_center - camera.eye;
-    let distance = to_cube.length();
-    
-    // More conservative distance culling
-    if distance > 35.0 {
-        return false;
-    }
-    
-    // Very conservative frustum check - only cull objects clearly behind
-    let forward_dot = to_cube.normalized().dot(camera.forward);
-    if forward_dot < -0.5 {  // Much more lenient
-        return false;
+// The first script light becomes the main light the hotkeys edit, the rest are returned as is
+fn take_script_lights(script: &mut ScriptScene, base_light: &mut Light) -> Vec<Light> {
+    let mut lights = std::mem::take(&mut script.lights).into_iter();
+    if let Some(first) = lights.next() {
+        *base_light = first;
     }
-    
-    true
+    lights.collect()
 }
 
-// Enhanced ray casting with reflections and transparency
-pub fn cast_ray(
-    ray_origin: &Vector3,
-    ray_direction: &Vector3,
-    objects: &mut [Cube],
-    light: &Light,
-    depth: u32,
-    camera: &Camera,
-    fov: f32,
-    aspect: f32,
-) -> Vector3 {
-    if depth > MAX_RAY_DEPTH {
-        return procedural_sky(*ray_direction);
+// Next entry of the post-effect strength cycle, wrapping back to off
+fn next_strength(current: f32) -> f32 {
+    let index = EFFECT_STRENGTHS.iter().position(|&strength| strength > current).unwrap_or(0);
+    EFFECT_STRENGTHS[index]
+}
+
+fn main() {
+    // Distributed render: this process is a worker or the coordinator
+    match Role::from_args() {
+        Some(Role::Worker { address }) => {
+            if let Err(err) = distributed::run_worker(&address) {
+                println!("ERROR: worker failed: {}", err);
+            }
+            return;
+        }
+        Some(Role::Coordinator { address, tile_rows, turntable, frames_per_job }) => {
+            match OfflineJob::from_args() {
+                Some(job) => {
+                    if let Err(err) = distributed::run_coordinator(&address, &job, tile_rows, turntable, frames_per_job) {
+                        println!("ERROR: coordinator failed: {}", err);
+                    }
+                }
+                None => println!("ERROR: --coordinator needs --render <out.png>"),
+            }
+            return;
+        }
+        None => {}
     }
 
-    let mut intersect = Intersect::empty();
-    let mut zbuffer = f32::INFINITY;
+    // Headless day-cycle image sequence
+    if let Some(job) = TimelapseJob::from_args() {
+        if let Err(err) = offline::run_timelapse(&job) {
+            println!("ERROR: timelapse failed: {}", err);
+        }
+        return;
+    }
 
-    // Find closest intersection - check all visible objects
-    for object in objects.iter_mut() {
-        // Only use conservative frustum culling
-        if !is_in_frustum(object.center, object.size, camera, fov, aspect) {
-            continue;
+    // Scene to OBJ for Blender or game engines
+    if let Some(job) = ExportJob::from_args() {
+        if let Err(err) = export::run_export(&job) {
+            println!("ERROR: export failed: {}", err);
         }
-        
-        let i = object.ray_intersect(ray_origin, ray_direction);
-        if i.is_intersecting && i.distance < zbuffer {
-            zbuffer = i.distance;
-            intersect = i;
+        return;
+    }
+
+    // Headless render of every camera bookmark
+    if let Some(job) = BookmarkJob::from_args() {
+        if let Err(err) = job.and_then(|job| bookmarks::run_bookmarks(&job)) {
+            println!("ERROR: bookmark render failed: {}", err);
         }
+        return;
     }
 
-    if !intersect.is_intersecting {
-        return procedural_sky(*ray_direction);
+    // Headless re-render of a recorded flythrough
+    if let Some(job) = FlythroughJob::from_args() {
+        if let Err(err) = flythrough::run_flythrough(&job) {
+            println!("ERROR: flythrough render failed: {}", err);
+        }
+        return;
     }
 
-    // Simplified lighting model
-    let light_dir = (light.position - intersect.point).normalized();
-    let light_distance = (light.position - intersect.point).length();
-    
-    // Brighter ambient for better visibility
-    let ambient = Vector3::new(0.1, 0.1, 0.15);
-    
-    // Simplified shadow calculation
-    let shadow_intensity = if light_distance < 20.0 {
-        cast_shadow(&intersect, light, objects)
-    } else {
-        0.1 // Very light shadow for distant surfaces
-    };
-    
-    let light_visibility = 1.0 - shadow_intensity;
-    let distance_falloff = 1.0 / (1.0 + light_distance * light_distance * 0.005);
-    
-    let diffuse_intensity = intersect.normal.dot(light_dir).max(0.0);
-    let light_intensity = light.intensity * light_visibility * distance_falloff;
-    
-    let diffuse = intersect.material.diffuse * (diffuse_intensity * light_intensity);
-    
-    // Very simplified specular - only for close surfaces
-    let specular = if light_distance < 8.0 && depth == 0 {
-        let view_dir = (*ray_origin - intersect.point).normalized();
-        let reflect_dir = reflect(&-light_dir, &intersect.normal).normalized();
-        let specular_intensity = view_dir.dot(reflect_dir).max(0.0).powf(20.0);
-        
-        let light_color_v3 = Vector3::new(
-            light.color.r as f32 / 255.0, 
-            light.color.g as f32 / 255.0, 
-            light.color.b as f32 / 255.0
-        );
-        light_color_v3 * (specular_intensity * light_intensity * 0.2)
-    } else {
-        Vector3::zero()
-    };
+    // Headless high-sample render, no window needed
+    if let Some(job) = OfflineJob::from_args() {
+        offline::run(&job);
+        return;
+    }
 
-    // Reflections for reflective materials (diamonds)
-    let mut reflection_color = Vector3::zero();
-    if intersect.material.albedo[2] > 0.0 && depth < MAX_RAY_DEPTH {
-        let reflect_dir = reflect(ray_direction, &intersect.normal).normalized();
-        let reflect_origin = offset_origin(&intersect, &reflect_dir);
-        reflection_color = cast_ray(&reflect_origin, &reflect_dir, objects, light, depth + 1, camera, fov, aspect);
+    // Settings from the last run (--default-settings ignores them), flags switch things on top
+    let args: Vec<String> = std::env::args().collect();
+    let settings_path = Path::new(SETTINGS_FILE);
+    let mut settings = if args.iter().any(|arg| arg == "--default-settings") { Settings::new() } else { Settings::load(settings_path) };
+    let (window_width, window_height) = settings.window_size;
+    let mut post = PostProcess::from_args(&args);
+    if args.iter().any(|arg| arg == "--vsync") {
+        settings.vsync = true;
     }
+    settings.options.transparent_background |= args.iter().any(|arg| arg == "--transparent");
+    settings.options.interlaced |= args.iter().any(|arg| arg == "--interlaced");
+    settings.options.half_res_secondary |= args.iter().any(|arg| arg == "--half-res-secondary");
+    settings.options.taa |= args.iter().any(|arg| arg == "--taa");
+    settings.options.light_probes |= args.iter().any(|arg| arg == "--light-probes");
+    settings.options.glossy_reflections |= args.iter().any(|arg| arg == "--glossy-reflections");
+    settings.options.spectral |= args.iter().any(|arg| arg == "--spectral");
+    settings.options.path_traced |= args.iter().any(|arg| arg == "--path-trace");
+    settings.options = settings.options.with_quality_args(&args);
+    let mut sky = Sky::from_args(&args);
+
+    // --scene <name> starts straight into a gallery scene, otherwise the scene menu opens
+    // over the default scene
+    let requested_scene = offline::value_after(&args, "--scene").map(|name| {
+        find_scene(name).unwrap_or_else(|| {
+            println!("ERROR: unknown scene {}, loading {}", name, GALLERY[0].name);
+            0
+        })
+    });
+    let mut scene_index = requested_scene.unwrap_or(0);
 
-    // Refraction/transparency for transparent materials (leaves)
-    let mut refract_color = Vector3::zero();
-    if intersect.material.albedo[3] > 0.0 && depth < MAX_RAY_DEPTH {
-        // Simple transparency - just continue the ray through the object
-        let refract_origin = offset_origin(&intersect, ray_direction);
-        refract_color = cast_ray(&refract_origin, ray_direction, objects, light, depth + 1, camera, fov, aspect);
+    // --play <file.fly> replays a recorded flythrough from the start, in the scene it was recorded in
+    let played = offline::value_after(&args, "--play").and_then(|path| match Flythrough::load(Path::new(path)) {
+        Ok(flythrough) => Some(flythrough),
+        Err(err) => {
+            println!("ERROR: {}", err);
+            None
+        }
+    });
+    if let Some(flythrough) = played.as_ref().filter(|flythrough| flythrough.script.is_none()) {
+        match find_scene(&flythrough.scene) {
+            Some(index) => scene_index = index,
+            None => println!("ERROR: the flythrough was recorded in unknown scene `{}`, loading {}", flythrough.scene, GALLERY[scene_index].name),
+        }
     }
 
-    let albedo = intersect.material.albedo;
-    let final_color = diffuse * albedo[0] + specular * albedo[1] + reflection_color * albedo[2] + refract_color * albedo[3] + ambient;
-    
-    Vector3::new(
-        final_color.x.min(1.0),
-        final_color.y.min(1.0),
-        final_color.z.min(1.0)
-    )
-}
+    // --demo <file.demo> runs a scripted presentation: camera glides, scene swaps, time of day
+    let demo = offline::value_after(&args, "--demo").and_then(|path| match Demo::load(Path::new(path)) {
+        Ok(demo) => Some(demo),
+        Err(err) => {
+            println!("ERROR: {}", err);
+            None
+        }
+    });
 
-// Fixed adaptive rendering with proper black screen elimination
-pub fn render_adaptive(
-    framebuffer: &mut Framebuffer, 
-    objects: &mut [Cube], 
-    camera: &Camera, 
-    light: &Light,
-    render_scale: f32,
-) {
-    let width = framebuffer.width;
-    let height = framebuffer.height;
-    let aspect_ratio = width as f32 / height as f32;
-    let fov = PI / 3.0;
-    let perspective_scale = (fov * 0.5).tan();
-
-    // Ensure minimum render size and handle edge cases
-    let render_width = ((width as f32 * render_scale).round() as u32).max(1).min(width);
-    let render_height = ((height as f32 * render_scale).round() as u32).max(1).min(height);
-
-    // If render scale is close to 1.0, just render at full resolution
-    if render_scale >= 0.95 {
-        // Full resolution rendering
-        for y in 0..height {
-            for x in 0..width {
-                let screen_x = (2.0 * x as f32) / width as f32 - 1.0;
-                let screen_y = -(2.0 * y as f32) / height as f32 + 1.0;
-                let screen_x = screen_x * aspect_ratio * perspective_scale;
-                let screen_y = screen_y * perspective_scale;
-
-                let ray_direction = Vector3::new(screen_x, screen_y, -1.0).normalized();
-                let rotated_direction = camera.basis_change(&ray_direction);
-
-                let pixel_color_v3 = cast_ray(&camera.eye, &rotated_direction, objects, light, 0, camera, fov, aspect_ratio);
-                let pixel_color = vector3_to_color(pixel_color_v3);
-
-                framebuffer.set_current_color(pixel_color);
-                framebuffer.set_pixel(x, y);
-            }
+    // --script <file.rhai> builds the scene from a script instead, --scene-file <file.ron> (or
+    // .json) from a scene description; F12 re-runs either. A replay brings the one it was
+    // recorded in
+    let script_path = offline::value_after(&args, "--script")
+        .or_else(|| offline::value_after(&args, "--scene-file"))
+        .map(PathBuf::from)
+        .or_else(|| played.as_ref().and_then(|flythrough| flythrough.script.clone()));
+    let script = script_path.as_deref().and_then(load_script);
+
+    // Without a scene, script or replay asked for, pick up where the last run left off
+    let saved_view = settings
+        .view
+        .clone()
+        .filter(|_| requested_scene.is_none() && script.is_none() && played.is_none() && demo.is_none())
+        .and_then(|view| find_scene(&view.scene).map(|index| (index, view)));
+    if let Some((index, _)) = &saved_view {
+        scene_index = *index;
+    }
+    let mut show_scene_menu = requested_scene.is_none() && script.is_none() && played.is_none() && demo.is_none() && saved_view.is_none();
+
+    let mut builder = raylib::init();
+    builder
+        .size(window_width, window_height)
+        .title("Optimized Cave Diorama")
+        .resizable()
+        .log_level(TraceLogLevel::LOG_WARNING);
+    if settings.vsync {
+        builder.vsync();
+    }
+    let (mut window, thread) = builder.build();
+
+    // Cap the frame rate so a static image doesn't keep a core at 100%
+    window.set_target_fps(settings.fps_cap());
+
+    let (mut render_width, mut render_height) = settings.render_resolution(window_width as u32, window_height as u32);
+    let mut presented_frame: Option<PresentedFrame> = None;
+    let mut presented_generation = 0;
+    let mut recorder: Option<Recorder> = None;
+
+    // Starting view and lights come from the script if there is one, else the gallery scene.
+    // Animation presets run on top of the main light, driven by simulation time
+    let mut camera = (GALLERY[scene_index].camera)();
+    if let Some((_, view)) = &saved_view {
+        view.apply(&mut camera);
+    }
+    // The main light is the one the hotkeys edit, scene lamps and further script lights stay put
+    let mut base_light = gallery_light(scene_index, &settings);
+    let mut extra_lights = (GALLERY[scene_index].lamps)();
+    let mut timeline = Timeline::default();
+    let build_scene: SceneBuilder = match script {
+        Some(mut script) => {
+            camera = script.camera.take().unwrap_or(camera);
+            extra_lights = take_script_lights(&mut script, &mut base_light);
+            timeline = script.timeline.clone();
+            script.into_builder()
         }
-    } else {
-        // Lower resolution rendering with proper upscaling
-        let step_x = (width as f32 / render_width as f32).ceil() as u32;
-        let step_y = (height as f32 / render_height as f32).ceil() as u32;
-
-        for y in 0..render_height {
-            for x in 0..render_width {
-                // Calculate the center of the block we're rendering
-                let center_x = (x * step_x) + step_x / 2;
-                let center_y = (y * step_y) + step_y / 2;
-                
-                let screen_x = (2.0 * center_x as f32) / width as f32 - 1.0;
-                let screen_y = -(2.0 * center_y as f32) / height as f32 + 1.0;
-                let screen_x = screen_x * aspect_ratio * perspective_scale;
-                let screen_y = screen_y * perspective_scale;
-
-                let ray_direction = Vector3::new(screen_x, screen_y, -1.0).normalized();
-                let rotated_direction = camera.basis_change(&ray_direction);
-
-                let pixel_color_v3 = cast_ray(&camera.eye, &rotated_direction, objects, light, 0, camera, fov, aspect_ratio);
-                let pixel_color = vector3_to_color(pixel_color_v3);
-
-                framebuffer.set_current_color(pixel_color);
-                
-                // Fill the entire block, ensuring we cover all pixels
-                let start_x = x * step_x;
-                let start_y = y * step_y;
-                let end_x = ((x + 1) * step_x).min(width);
-                let end_y = ((y + 1) * step_y).min(height);
-                
-                for pixel_y in start_y..end_y {
-                    for pixel_x in start_x..end_x {
-                        framebuffer.set_pixel(pixel_x, pixel_y);
+        None => gallery_builder(scene_index),
+    };
+
+    // The render thread loads and owns the scene from here on; it animates the objects,
+    // the light tracks are applied here where the lights are built
+    // --threads N overrides the saved thread count for this run
+    let threads = offline::value_after(&args, "--threads").and_then(|count| count.parse().ok()).unwrap_or(settings.threads);
+    let render_thread = RenderThread::spawn(build_scene, threads, TextureOptions::from_args(&args));
+    let mut library_watcher = LibraryWatcher::new();
+    let mut last_watch_time = std::time::Instant::now();
+
+    // --target-fps N: frame rate the dynamic resolution aims for
+    let target_fps = offline::value_after(&args, "--target-fps").and_then(|fps| fps.parse().ok()).unwrap_or(DEFAULT_TARGET_FPS);
+    let mut resolution = ResolutionController::new(target_fps, MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+
+    let mut light_animation_index = 0;
+    let mut light_edit = false;
+    let mut light_enabled = true;
+    let mut light_color_index = 0;
+    let mut headlamp = false;
+    let mut light_gizmos = false;
+    let mut show_help = false;
+
+    // Flythroughs: R records camera and light per simulation step, Shift+R replays the last one
+    let mut flythrough_recording: Option<Flythrough> = None;
+    let mut last_flythrough: Option<Flythrough> = None;
+    // A replay starts with the loose blocks where they had fallen to when recording began
+    if let Some(flythrough) = &played {
+        render_thread.command(SceneCommand::FastForward(flythrough.loaded, flythrough.steps[0].time));
+    }
+    let mut playback = played.map(Playback::new);
+    let mut demo_player = demo.map(DemoPlayer::new);
+
+    let mut timestep = FixedTimestep::new(SIMULATION_HZ);
+    let mut previous_camera = camera.clone();
+    let mut sim_time = 0.0f32;
+    let mut scene_loaded_at = 0.0f32; // Simulation time of the last scene load, saved with recordings
+
+    println!("\n=== OPTIMIZED CAVE DIORAMA ===");
+    println!("F1: Show controls | M: Scene menu | ESC: Exit");
+    println!("Scene: --scene <name>, one of {}", GALLERY.iter().map(|entry| entry.name).collect::<Vec<_>>().join(", "));
+    println!("Script: --script scene.rhai (build with --features scripting)");
+    println!("Scene file: --scene-file my.ron or my.json (blocks, fills, materials, lights and camera, F12 reloads)");
+    println!("Headless: --render out.png [--size WxH] [--samples N] [--checkpoint file] [--resume] [--path-trace] [--denoise] [--adaptive] [--depth] [--ids] [--transparent]");
+    println!("Post effects (any mode): [--sharpen 0-1] [--aberration 0-1] [--vignette 0-1] [--grain 0-1] [--lut file.cube]");
+    println!("Flythrough: R records, Shift+R replays, --play file.fly | Headless: --flythrough file.fly [--out dir] [--fps N] [--size WxH] [--samples N]");
+    println!("Demo: --demo file.demo (scripted camera glides, scene swaps, time of day and light changes)");
+    println!("Sky: --sky file (ground/horizon/zenith colors and band heights, sky.txt by default)");
+    println!("Export: --export-obj scene.obj [--scene name] (greedy-meshed, with .mtl and textures)");
+    println!("Bookmarks: Shift+B saves one | Headless: --render-bookmarks dir [--bookmarks file] [--size WxH] [--samples N]");
+    println!("Timelapse: --timelapse dir [--frames N] [--from H] [--to H] [--size WxH] [--samples N]");
+    println!("Distributed: add --coordinator host:port [--tile-rows N] [--turntable FRAMES [--frames-per-job N]], workers run --worker host:port");
+    println!("OPTIMIZATIONS:");
+    println!("- Dynamic resolution (render scale holds {} fps, --target-fps N)", target_fps);
+    println!("- Background render thread (input never waits on the tracer)");
+    println!("- Parallel tracing (rows split across {} threads, --threads N or `threads` in settings.txt)", if threads == 0 { rayon::current_num_threads() } else { threads });
+    println!("- Idle mode (a still image stops the tracer and drops to {} fps)", IDLE_FPS);
+    println!("- Frustum culling (skip off-screen objects)");
+    println!("- Voxel grid / BVH (rays only test the objects along their path, rebuilt as blocks move)");
+    println!("- Russian roulette (the path tracer ends weak paths early, reweighting the ones it keeps)");
+    println!("- Light probes (Shift+G or --light-probes: baked bounce light, rebaked when the lights change)");
+    println!("- Glossy reflections (Shift+X or --glossy-reflections: dull metals read a mipmapped capture of the scene)");
+    println!("- Spectral dispersion (Shift+V or --spectral: light through diamond split into six wavelengths)");
+    println!("- Ambient occlusion (F or --ao N: N short rays per pixel darken the creases between blocks)");
+    println!("- Path tracing (Shift+F or --path-trace: bounced light and soft shadows that converge while the view holds still)");
+    println!("- Temporal anti-aliasing (Shift+J or --taa: jittered rays blended with the reprojected last frame)");
+    println!("- Compact textures (--compact-textures keeps an 8-bit palette index per texel, 16 bits past 256 colors)");
+    println!("- Bilinear texture filtering (--bilinear, or `filter` per material: no shimmer while moving)");
+    println!("- Optimized lighting calculations");
+
+    let mut frame_count = 0;
+    let mut traced_count = 0;
+    let mut frame_index: u32 = 0;
+    let mut last_fps_time = std::time::Instant::now();
+    let mut frames_without_input = 0;
+    let mut idle = false;
+    // Bumped whenever the image changes, the path tracer averages frames of one epoch
+    let mut view_epoch: u64 = 0;
+    let mut last_view = (camera.eye, camera.forward);
+    let mut last_lights: Vec<Light> = Vec::new();
+    let console = Console::spawn();
+    println!("Type `help` in this terminal for console commands");
+
+    while !window.window_should_close() {
+        // Fixed-timestep simulation, independent of how long the trace takes
+        timestep.begin_frame();
+        let mut camera_moved = false;
+        while timestep.step() {
+            previous_camera = camera.clone();
+            // A replay or a demo stands in for live input, on the recorded clock
+            if let Some(step) = playback.as_mut().and_then(Playback::next_step) {
+                step.apply(&mut camera, &mut base_light);
+                sim_time = step.time;
+                camera_moved = true;
+            } else if let Some(player) = demo_player.as_mut() {
+                for cue in player.step(timestep.dt, &mut camera) {
+                    match cue {
+                        DemoCue::Scene(index) => {
+                            scene_index = index;
+                            load_gallery_scene(index, &settings, &mut camera, &mut base_light, &mut extra_lights, &mut timeline, &render_thread);
+                            previous_camera = camera.clone();
+                            scene_loaded_at = sim_time;
+                        }
+                        DemoCue::Hour(hour) => {
+                            let cycle = DayCycle::new(hour);
+                            base_light = cycle.light();
+                            sky = Sky { gradient: sky.gradient, ..cycle.sky() };
+                        }
+                        DemoCue::Light(on) => light_enabled = on,
+                        DemoCue::Headlamp(on) => headlamp = on,
                     }
                 }
+                sim_time += timestep.dt;
+                camera_moved = true;
+            } else {
+                if light_edit {
+                    camera_moved |= update_light(&window, &mut base_light, &camera, timestep.dt);
+                } else {
+                    camera_moved |= update_camera(&window, &mut camera, timestep.dt);
+                }
+                if !idle {
+                    sim_time += timestep.dt;
+                }
+            }
+            if let Some(recording) = flythrough_recording.as_mut() {
+                recording.record(sim_time, &camera, &base_light);
             }
         }
-        
-        // Fill any remaining pixels if there are gaps due to rounding
-        let last_rendered_x = render_width * step_x;
-        let last_rendered_y = render_height * step_y;
-        
-        // Fill remaining right edge
-        if last_rendered_x < width {
-            // Use the last column's color
-            if render_width > 0 {
-                let last_col_x = (render_width - 1) * step_x;
-                let last_col_y = 0;
-                let screen_x = (2.0 * last_col_x as f32) / width as f32 - 1.0;
-                let screen_y = -(2.0 * last_col_y as f32) / height as f32 + 1.0;
-                let screen_x = screen_x * aspect_ratio * perspective_scale;
-                let screen_y = screen_y * perspective_scale;
-
-                let ray_direction = Vector3::new(screen_x, screen_y, -1.0).normalized();
-                let rotated_direction = camera.basis_change(&ray_direction);
-                let pixel_color_v3 = cast_ray(&camera.eye, &rotated_direction, objects, light, 0, camera, fov, aspect_ratio);
-                let pixel_color = vector3_to_color(pixel_color_v3);
-                framebuffer.set_current_color(pixel_color);
-                
-                for y in 0..height {
-                    for x in last_rendered_x..width {
-                        framebuffer.set_pixel(x, y);
-                    }
-                }
+        if let Some(finished) = playback.take_if(|active| active.is_finished()) {
+            println!("{}", finished.report());
+        }
+        if demo_player.take_if(|active| active.is_finished()).is_some() {
+            println!("Demo finished, the controls are yours");
+        }
+        let render_camera = Camera::interpolate(&previous_camera, &camera, timestep.alpha());
+
+        if camera_moved || input::any_activity(&window) {
+            frames_without_input = 0;
+        } else {
+            frames_without_input += 1;
+        }
+
+        // Render scale from the dynamic resolution controller, fed with trace times below
+        let mut render_scale = if ADAPTIVE_RENDER { resolution.scale() } else { MAX_RENDER_SCALE };
+
+        // Alt+Enter: toggle fullscreen
+        if input::pressed(&window, Action::ToggleFullscreen) {
+            window.toggle_fullscreen();
+        }
+
+        // F1: help overlay listing the binding map
+        if input::pressed(&window, Action::ToggleHelp) {
+            show_help = !show_help;
+        }
+
+        // M: scene menu, a number key swaps the scene and resets the camera and light
+        if input::pressed(&window, Action::SceneMenu) {
+            show_scene_menu = !show_scene_menu;
+        }
+        if show_scene_menu {
+            if let Some(entry) = input::number_pressed(&window).map(|number| number - 1).filter(|&index| index < GALLERY.len()) {
+                scene_index = entry;
+                load_gallery_scene(scene_index, &settings, &mut camera, &mut base_light, &mut extra_lights, &mut timeline, &render_thread);
+                previous_camera = camera.clone();
+                scene_loaded_at = sim_time;
+                show_scene_menu = false;
+                frames_without_input = 0;
             }
         }
-        
-        // Fill remaining bottom edge
-        if last_rendered_y < height {
-            // Use the last row's color
-            if render_height > 0 {
-                let last_row_x = 0;
-                let last_row_y = (render_height - 1) * step_y;
-                let screen_x = (2.0 * last_row_x as f32) / width as f32 - 1.0;
-                let screen_y = -(2.0 * last_row_y as f32) / height as f32 + 1.0;
-                let screen_x = screen_x * aspect_ratio * perspective_scale;
-                let screen_y = screen_y * perspective_scale;
-
-                let ray_direction = Vector3::new(screen_x, screen_y, -1.0).normalized();
-                let rotated_direction = camera.basis_change(&ray_direction);
-                let pixel_color_v3 = cast_ray(&camera.eye, &rotated_direction, objects, light, 0, camera, fov, aspect_ratio);
-                let pixel_color = vector3_to_color(pixel_color_v3);
-                framebuffer.set_current_color(pixel_color);
-                
-                for y in last_rendered_y..height {
-                    for x in 0..last_rendered_x {
-                        framebuffer.set_pixel(x, y);
+
+        // F12: re-run the scene script after editing it; the camera stays where it is
+        if input::pressed(&window, Action::ReloadScript) {
+            match script_path.as_deref() {
+                Some(path) => {
+                    if let Some(mut script) = load_script(path) {
+                        extra_lights = take_script_lights(&mut script, &mut base_light);
+                        timeline = script.timeline.clone();
+                        render_thread.command(SceneCommand::Replace(script.into_builder()));
+                        scene_loaded_at = sim_time;
+                        frames_without_input = 0;
                     }
                 }
+                None => println!("No scene script, start with --script <file.rhai> or --scene-file <file.ron>"),
             }
         }
-    }
-}
 
-// Create complete diorama with trees
-fn create_diorama(
-    piedra_texture: Image, 
-    diamante_texture: Option<Image>, 
-    tierra_texture: Option<Image>,
-    tronco_texture: Option<Image>,
-    hojas_texture: Option<Image>
-) -> Vec<Cube> {
-    let mut cubes = Vec::new();
-    let cube_size = 1.0;
-    let floor_size = 10; 
-    let wall_height = 5;  
-    let start_offset = -((floor_size - 1) as f32 * cube_size) / 2.0;
-    
-    // Materials with special properties
-    let piedra_material = Material::new(
-        Vector3::new(0.8, 0.8, 0.8),
-        32.0,
-        [0.9, 0.1, 0.0, 0.0],  // diffuse, specular, reflection, transparency
-        1.0,
-    );
-    
-    // Diamond material - highly reflective and shiny
-    let diamante_material = Material::new(
-        Vector3::new(0.9, 0.9, 1.0),
-        128.0,
-        [0.2, 0.3, 0.5, 0.0],  // Less diffuse, more reflection (50%)
-        2.42,  // Diamond refractive index
-    );
-    
-    let tierra_material = Material::new(
-        Vector3::new(0.6, 0.4, 0.2),
-        16.0,
-        [0.9, 0.1, 0.0, 0.0],
-        1.0,
-    );
-
-    let tronco_material = Material::new(
-        Vector3::new(0.5, 0.3, 0.2),
-        16.0,
-        [0.9, 0.1, 0.0, 0.0],
-        1.0,
-    );
-
-    // Leaves material - semi-transparent to let light through
-    let hojas_material = Material::new(
-        Vector3::new(0.2, 0.7, 0.2),
-        8.0,
-        [0.6, 0.1, 0.0, 0.3],  // 30% transparent to simulate leaves
-        1.0,
-    );
-    
-    // Diamond spots on floor
-    let diamond_spots = vec![
-        (2, 3), (7, 2), (4, 6), (8, 7)
-    ];
-    
-    // 1. BOTTOM FLOOR (complete)
-    for x in 0..floor_size {
-        for z in 0..floor_size {
-            let pos_x = start_offset + x as f32 * cube_size;
-            let pos_z = start_offset + z as f32 * cube_size;
-            let pos_y = -cube_size / 2.0;
-            
-            let is_diamond = diamond_spots.contains(&(x, z));
-            
-            let cube = if is_diamond && diamante_texture.is_some() {
-                Cube::with_texture(
-                    Vector3::new(pos_x, pos_y, pos_z),
-                    cube_size,
-                    diamante_material,
-                    diamante_texture.as_ref().unwrap().clone(),
-                )
-            } else {
-                Cube::with_texture(
-                    Vector3::new(pos_x, pos_y, pos_z),
-                    cube_size,
-                    piedra_material,
-                    piedra_texture.clone(),
-                )
-            };
-            
-            cubes.push(cube);
+        // F2: cycle internal render resolution
+        let mut resolution_changed = false;
+        if input::pressed(&window, Action::CycleResolution) {
+            settings.cycle_resolution();
+            resolution_changed = true;
+            println!("Render resolution: {}", settings.resolution_label());
         }
-    }
-    
-    // 2. WALLS (3 walls - no front wall)
-    // Left wall
-    for y in 0..wall_height {
-        for z in 0..floor_size {
-            let pos_x = start_offset;
-            let pos_z = start_offset + z as f32 * cube_size;
-            let pos_y = cube_size / 2.0 + y as f32 * cube_size;
-            
-            cubes.push(Cube::with_texture(
-                Vector3::new(pos_x, pos_y, pos_z),
-                cube_size,
-                piedra_material,
-                piedra_texture.clone(),
-            ));
+
+        // F3: cycle frame rate cap
+        if input::pressed(&window, Action::CycleFpsCap) {
+            settings.cycle_fps_cap();
+            window.set_target_fps(settings.fps_cap());
+            match settings.fps_cap() {
+                0 => println!("FPS cap: uncapped"),
+                cap => println!("FPS cap: {}", cap),
+            }
         }
-    }
-    
-    // Right wall
-    for y in 0..wall_height {
-        for z in 0..floor_size {
-            let pos_x = start_offset + (floor_size - 1) as f32 * cube_size;
-            let pos_z = start_offset + z as f32 * cube_size;
-            let pos_y = cube_size / 2.0 + y as f32 * cube_size;
-            
-            cubes.push(Cube::with_texture(
-                Vector3::new(pos_x, pos_y, pos_z),
-                cube_size,
-                piedra_material,
-                piedra_texture.clone(),
-            ));
+
+        // I: interlaced rendering, odd and even rows on alternate frames
+        if input::pressed(&window, Action::ToggleInterlaced) {
+            settings.options.interlaced = !settings.options.interlaced;
+            println!("Interlaced: {}", if settings.options.interlaced { "on" } else { "off" });
         }
-    }
-    
-    // Back wall
-    for y in 0..wall_height {
-        for x in 1..(floor_size-1) {
-            let pos_x = start_offset + x as f32 * cube_size;
-            let pos_z = start_offset + (floor_size - 1) as f32 * cube_size;
-            let pos_y = cube_size / 2.0 + y as f32 * cube_size;
-            
-            cubes.push(Cube::with_texture(
-                Vector3::new(pos_x, pos_y, pos_z),
-                cube_size,
-                piedra_material,
-                piedra_texture.clone(),
-            ));
+
+        // J: reflections and refractions at half resolution
+        if input::pressed(&window, Action::ToggleHalfResSecondary) {
+            settings.options.half_res_secondary = !settings.options.half_res_secondary;
+            println!("Half-resolution secondary rays: {}", if settings.options.half_res_secondary { "on" } else { "off" });
         }
-    }
-    
-    // 3. TOP FLOOR - COMPLETE with ALL border cubes
-    if let Some(tierra_tex) = tierra_texture {
-        let top_y = cube_size / 2.0 + wall_height as f32 * cube_size;
-        
-        // 4x3 hole in center
-        let hole_center_x = floor_size / 2;
-        let hole_center_z = floor_size / 2;
-        let hole_start_x = hole_center_x - 2; // 4 wide
-        let hole_start_z = hole_center_z - 1; // 3 deep
-        let hole_end_x = hole_start_x + 4;
-        let hole_end_z = hole_start_z + 3;
-        
-        // Add EVERY top cube except hole
-        for x in 0..floor_size {
-            for z in 0..floor_size {
-                let in_hole = x >= hole_start_x && x < hole_end_x && 
-                             z >= hole_start_z && z < hole_end_z;
-                
-                if !in_hole {
-                    let pos_x = start_offset + x as f32 * cube_size;
-                    let pos_z = start_offset + z as f32 * cube_size;
-                    
-                    cubes.push(Cube::with_texture(
-                        Vector3::new(pos_x, top_y, pos_z),
-                        cube_size,
-                        tierra_material,
-                        tierra_tex.clone(),
-                    ));
+
+        // Shift+J: temporal anti-aliasing
+        if input::pressed(&window, Action::ToggleTaa) {
+            settings.options.taa = !settings.options.taa;
+            println!("Temporal anti-aliasing: {}", if settings.options.taa { "on" } else { "off" });
+        }
+
+        // Y / Shift+Y: how many times rays may reflect / refract
+        if input::pressed(&window, Action::CycleReflectionDepth) {
+            settings.cycle_reflection_depth();
+            println!("Reflection depth: {}", settings.options.max_reflection_depth);
+        }
+        if input::pressed(&window, Action::CycleRefractionDepth) {
+            settings.cycle_refraction_depth();
+            println!("Refraction depth: {}", settings.options.max_refraction_depth);
+        }
+
+        // F9: start/stop recording the session to video
+        if input::pressed(&window, Action::ToggleRecording) {
+            match recorder.take() {
+                Some(active) => {
+                    let (path, frames) = (active.path.clone(), active.frames);
+                    match active.finish() {
+                        Ok(()) => println!("Saved recording {} ({} frames)", path, frames),
+                        Err(err) => println!("Recording failed: {}", err),
+                    }
+                }
+                None => {
+                    let timestamp = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                    let path = format!("session_{}.mp4", timestamp);
+                    match Recorder::start(&path, render_width, render_height) {
+                        Ok(mut active) => {
+                            println!("Recording to {} at {} fps", path, RECORDING_FPS);
+                            render_thread.front().record(&mut active);
+                            recorder = Some(active);
+                        }
+                        Err(err) => println!("Could not start ffmpeg: {}", err),
+                    }
                 }
             }
         }
-        
-        println!("TOP FLOOR: {} tierra cubes with complete borders", 
-                 (floor_size * floor_size) - (4 * 3));
-    }
 
-    // 4. ADD MINECRAFT-STYLE TREES on top floor
-    if let (Some(tronco_tex), Some(hojas_tex)) = (tronco_texture, hojas_texture) {
-        let top_y = cube_size / 2.0 + wall_height as f32 * cube_size;
-        
-        // Tree positions - 3 trees around the hole
-        let tree_positions = vec![
-            (1, 1),  // Front-left of the diorama
-            (8, 2),  // Front-right 
-            (2, 8),  // Back-left
-        ];
-        
-        for (tree_x, tree_z) in tree_positions {
-            let tree_world_x = start_offset + tree_x as f32 * cube_size;
-            let tree_world_z = start_offset + tree_z as f32 * cube_size;
-            
-            // TRUNK - 3 cubes tall (raised higher so it's visible)
-            for trunk_height in 0..3 {
-                let trunk_y = top_y + cube_size + trunk_height as f32 * cube_size;
-                
-                cubes.push(Cube::with_texture(
-                    Vector3::new(tree_world_x, trunk_y, tree_world_z),
-                    cube_size,
-                    tronco_material,
-                    tronco_tex.clone(),
-                ));
-            }
-            
-            // LEAVES - Start at top of trunk, raised higher
-            let leaves_center_y = top_y + cube_size + 2.0 * cube_size; // Top of 3-block trunk
-            
-            // 3x3 leaves pattern for 2 layers only (middle and top) - no bottom layer
-            for dy in 1..3 { // Start from layer 1, not 0
-                for dx in -1i32..=1i32 {
-                    for dz in -1i32..=1i32 {
-                        let leaf_x = tree_world_x + dx as f32 * cube_size;
-                        let leaf_y = leaves_center_y + (dy as f32 - 1.0) * cube_size;
-                        let leaf_z = tree_world_z + dz as f32 * cube_size;
-                        
-                        // Create a more natural tree shape - fewer leaves on edges
-                        let is_edge = dx.abs() == 1 && dz.abs() == 1;
-                        let is_top_layer = dy == 2;
-                        let is_center = dx == 0 && dz == 0;
-                        
-                        // Skip top corners for natural look
-                        if is_edge && is_top_layer && !is_center {
-                            continue; 
-                        }
-                        
-                        cubes.push(Cube::with_texture(
-                            Vector3::new(leaf_x, leaf_y, leaf_z),
-                            cube_size,
-                            hojas_material,
-                            hojas_tex.clone(),
-                        ));
+        // R: start/stop recording a flythrough, saved when stopped
+        if input::pressed(&window, Action::ToggleFlythroughRecording) {
+            match flythrough_recording.take() {
+                Some(recording) => {
+                    let timestamp = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                    let path = PathBuf::from(format!("flythrough_{}.fly", timestamp));
+                    match recording.save(&path) {
+                        Ok(()) => println!("Saved flythrough {} ({} steps, {:.1}s)", path.display(), recording.steps.len(), recording.duration()),
+                        Err(err) => println!("Could not save flythrough: {}", err),
                     }
+                    last_flythrough = Some(recording);
+                }
+                None => {
+                    flythrough_recording = Some(Flythrough::new(GALLERY[scene_index].name, script_path.as_deref(), scene_loaded_at));
+                    playback = None;
+                    println!("Recording flythrough, R to stop");
                 }
             }
-            
-            // Add a single crown leaf on top of the tree
-            let crown_y = leaves_center_y + 1.0 * cube_size;
-            cubes.push(Cube::with_texture(
-                Vector3::new(tree_world_x, crown_y, tree_world_z),
-                cube_size,
-                hojas_material,
-                hojas_tex.clone(),
-            ));
         }
-        
-        println!("TREES: Added 3 Minecraft-style trees with elevated canopy");
-        println!("Each tree: 3 trunk cubes + ~15 leaf cubes + 1 crown");
-    } else {
-        println!("TREES: Tronco or Hojas texture not found - skipping trees");
-    }
-    
-    println!("TOTAL CUBES: {}", cubes.len());
-    cubes
-}
 
-fn main() {
-    let window_width = 800;
-    let window_height = 600;
- 
-    let (mut window, thread) = raylib::init()
-        .size(window_width, window_height)
-        .title("Optimized Cave Diorama")
-        .log_level(TraceLogLevel::LOG_WARNING)
-        .build();
+        // Shift+R: replay the last flythrough recorded this session
+        if input::pressed(&window, Action::PlayFlythrough) && flythrough_recording.is_none() {
+            match last_flythrough.clone() {
+                Some(recording) => {
+                    println!("Replaying flythrough ({} steps)", recording.steps.len());
+                    playback = Some(Playback::new(recording));
+                }
+                None => println!("No flythrough yet, R records one"),
+            }
+        }
 
-    let mut framebuffer = Framebuffer::new(window_width as u32, window_height as u32);
+        // F5: A/B split screen, F6: next comparison, drag with the mouse to move the divider
+        if input::pressed(&window, Action::ToggleSplitView) {
+            settings.split_enabled = !settings.split_enabled;
+            if settings.split_enabled {
+                println!("Split view: {}", settings.comparison_label());
+            } else {
+                println!("Split view off");
+            }
+        }
+        if settings.split_enabled {
+            if input::pressed(&window, Action::NextComparison) {
+                settings.cycle_comparison();
+                println!("Split view: {}", settings.comparison_label());
+            }
+            if window.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT) {
+                // Undo the letterboxing applied when presenting
+                let window_w = window.get_screen_width() as f32;
+                let window_h = window.get_screen_height() as f32;
+                let scale = (window_w / render_width as f32).min(window_h / render_height as f32);
+                let offset_x = (window_w - render_width as f32 * scale) * 0.5;
+                settings.divider = ((window.get_mouse_x() as f32 - offset_x) / (render_width as f32 * scale)).clamp(0.0, 1.0);
+            }
+        }
 
-    // Load textures
-    let piedra_paths = ["src/assets/Piedra.png", "./src/assets/Piedra.png", "./assets/Piedra.png"];
-    let diamante_paths = ["src/assets/Diamante.png", "./src/assets/Diamante.png", "./assets/Diamante.png"];
-    let tierra_paths = ["src/assets/Tierra.png", "./src/assets/Tierra.png", "./assets/Tierra.png"];
-    let tronco_paths = ["src/assets/Tronco.png", "./src/assets/Tronco.png", "./assets/Tronco.png"];
-    let hojas_paths = ["src/assets/Hojas.png", "./src/assets/Hojas.png", "./assets/Hojas.png"];
+        // [ and ]: halve/double the shadow bias to trade acne against peter-panning
+        let bias_up = input::pressed(&window, Action::ShadowBiasUp);
+        if bias_up || input::pressed(&window, Action::ShadowBiasDown) {
+            settings.scale_bias(if bias_up { 2.0 } else { 0.5 });
+            println!("Shadow bias: {:.6} (slope {:.6})", settings.options.bias.constant, settings.options.bias.slope);
+        }
 
-    let mut piedra_texture = None;
-    for path in &piedra_paths {
-        if let Ok(image) = Image::load_image(path) {
-            println!("Loaded Piedra from: {}", path);
-            piedra_texture = Some(image);
-            break;
+        // F10/F11: step the vignette/film grain strength
+        if input::pressed(&window, Action::CycleVignette) {
+            post.vignette = next_strength(post.vignette);
+            println!("Vignette: {:.2}", post.vignette);
+        }
+        if input::pressed(&window, Action::CycleGrain) {
+            post.grain = next_strength(post.grain);
+            println!("Film grain: {:.2}", post.grain);
         }
-    }
 
-    let mut diamante_texture = None;
-    for path in &diamante_paths {
-        if let Ok(image) = Image::load_image(path) {
-            println!("Loaded Diamante from: {}", path);
-            diamante_texture = Some(image);
-            break;
+        // U: step the sharpening strength
+        if input::pressed(&window, Action::CycleSharpen) {
+            post.sharpen = next_strength(post.sharpen);
+            println!("Sharpening: {:.2}", post.sharpen);
         }
-    }
 
-    let mut tierra_texture = None;
-    for path in &tierra_paths {
-        if let Ok(image) = Image::load_image(path) {
-            println!("Loaded Tierra from: {}", path);
-            tierra_texture = Some(image);
-            break;
+        // Shift+U: step the bloom strength
+        if input::pressed(&window, Action::CycleBloom) {
+            post.bloom = next_strength(post.bloom);
+            println!("Bloom: {:.2}", post.bloom);
         }
-    }
 
-    let mut tronco_texture = None;
-    for path in &tronco_paths {
-        if let Ok(image) = Image::load_image(path) {
-            println!("Loaded Tronco from: {}", path);
-            tronco_texture = Some(image);
-            break;
+        // P: step the chromatic aberration strength
+        if input::pressed(&window, Action::CycleAberration) {
+            post.aberration = next_strength(post.aberration);
+            println!("Chromatic aberration: {:.2}", post.aberration);
         }
-    }
 
-    let mut hojas_texture = None;
-    for path in &hojas_paths {
-        if let Ok(image) = Image::load_image(path) {
-            println!("Loaded Hojas from: {}", path);
-            hojas_texture = Some(image);
-            break;
+        // L: cycle the light animation preset
+        if input::pressed(&window, Action::CycleLightAnimation) {
+            light_animation_index = (light_animation_index + 1) % LIGHT_ANIMATION_PRESETS.len();
+            println!("Light animation: {}", LIGHT_ANIMATION_PRESETS[light_animation_index].0);
         }
-    }
 
-    let mut objects = if let Some(piedra) = piedra_texture {
-        create_diorama(piedra, diamante_texture, tierra_texture, tronco_texture, hojas_texture)
-    } else {
-        println!("ERROR: Could not load Piedra texture!");
-        vec![]
-    };
+        // F4: WASD/Q/E drive the light instead of the camera; O, -/= and K work in any mode
+        if input::pressed(&window, Action::ToggleLightEdit) {
+            light_edit = !light_edit;
+            println!("Light edit mode: {}", if light_edit { "on" } else { "off" });
+        }
+        if input::pressed(&window, Action::ToggleLight) {
+            light_enabled = !light_enabled;
+            println!("Light: {}", if light_enabled { "on" } else { "off" });
+        }
+        // Shift+=/Shift+- or keypad +/-: exposure compensation, the plain keys stay on light intensity
+        if input::pressed(&window, Action::ExposureUp) {
+            settings.adjust_exposure(EXPOSURE_STEP);
+        }
+        if input::pressed(&window, Action::ExposureDown) {
+            settings.adjust_exposure(-EXPOSURE_STEP);
+        }
+        if input::pressed(&window, Action::LightBrighter) {
+            base_light.intensity += LIGHT_INTENSITY_STEP;
+            println!("Light intensity: {:.2}", base_light.intensity);
+        }
+        if input::pressed(&window, Action::LightDimmer) {
+            base_light.intensity = (base_light.intensity - LIGHT_INTENSITY_STEP).max(0.0);
+            println!("Light intensity: {:.2}", base_light.intensity);
+        }
+        if input::pressed(&window, Action::CycleLightColor) {
+            light_color_index = (light_color_index + 1) % LIGHT_COLOR_PRESETS.len();
+            let (name, (r, g, b)) = LIGHT_COLOR_PRESETS[light_color_index];
+            base_light.color = Color::new(r, g, b, 255);
+            println!("Light color: {}", name);
+        }
 
-    // Camera positioned in front of the diorama for better initial view
-    let mut camera = Camera::new(
-        Vector3::new(0.0, 4.0, -12.0),  // Front view, slightly elevated
-        Vector3::new(0.0, 3.0, 0.0),    // Looking at center of scene
-        Vector3::new(0.0, 1.0, 0.0),
-    );
+        // H: headlamp following the rendered camera, for the parts of the cave no light reaches
+        if input::pressed(&window, Action::ToggleHeadlamp) {
+            headlamp = !headlamp;
+            println!("Headlamp: {}", if headlamp { "on" } else { "off" });
+        }
 
-    // Store previous camera position for movement detection
-    let mut prev_camera_pos = camera.eye;
-    let mut prev_camera_angles = (camera.yaw, camera.pitch);
+        if input::pressed(&window, Action::ToggleLightGizmos) {
+            light_gizmos = !light_gizmos;
+        }
+        // Shift+G: indirect light from a probe grid, baked on the render thread
+        if input::pressed(&window, Action::ToggleLightProbes) {
+            settings.options.light_probes = !settings.options.light_probes;
+            println!("Light probes: {}", if settings.options.light_probes { "on" } else { "off" });
+        }
 
-    // Light positioned ABOVE the hole to shine DOWN into cave
-    let light = Light::new(
-        Vector3::new(0.0, 10.0, 0.0),
-        Color::new(255, 255, 200, 255), 
-        3.0,
-    );
+        // Shift+X: blurred reflections on dull metals, from a cubemap captured around the camera
+        if input::pressed(&window, Action::ToggleGlossyReflections) {
+            settings.options.glossy_reflections = !settings.options.glossy_reflections;
+            println!("Glossy reflections: {}", if settings.options.glossy_reflections { "on" } else { "off" });
+        }
 
-    let movement_speed = 0.3;
-    let rotation_speed = 0.03;
+        // Shift+V: rainbow fringes in diamond, six refracted rays per hit instead of one
+        if input::pressed(&window, Action::ToggleSpectral) {
+            settings.options.spectral = !settings.options.spectral;
+            println!("Spectral dispersion: {}", if settings.options.spectral { "on" } else { "off" });
+        }
 
-    println!("\n=== OPTIMIZED CAVE DIORAMA ===");
-    println!("WASD: Move | Q/E: Up/Down | Arrows: Look | ESC: Exit");
-    println!("OPTIMIZATIONS:");
-    println!("- Adaptive rendering (lower res when moving)");
-    println!("- Frustum culling (skip off-screen objects)");
-    println!("- Early ray termination");
-    println!("- Distance-based LOD");
-    println!("- Optimized lighting calculations");
+        // F: darker creases where blocks meet, more rays give smoother shading
+        if input::pressed(&window, Action::CycleAmbientOcclusion) {
+            settings.cycle_ao_samples();
+            match settings.options.ao_samples {
+                0 => println!("Ambient occlusion: off"),
+                samples => println!("Ambient occlusion: {} rays", samples),
+            }
+        }
 
-    let mut frame_count = 0;
-    let mut last_fps_time = std::time::Instant::now();
-    let mut frames_since_movement = 0;
+        // Shift+F: the path tracer instead of the Whitted model, averaging frames while still
+        if input::pressed(&window, Action::TogglePathTracing) {
+            settings.options.path_traced = !settings.options.path_traced;
+            println!("Path tracing: {}", if settings.options.path_traced { "on" } else { "off" });
+        }
 
-    while !window.window_should_close() {
-        let mut camera_moved = false;
+        let mut light = LIGHT_ANIMATION_PRESETS[light_animation_index].1.apply(&base_light, sim_time);
+        if !light_enabled {
+            light.intensity = 0.0;
+        }
+        let mut lights = vec![light];
+        lights.extend(extra_lights.iter().cloned());
+        timeline.apply_lights(&mut lights, sim_time);
+        if headlamp {
+            lights.push(Light::headlamp(render_camera.eye, render_camera.forward));
+        }
 
-        // Camera controls
-        if window.is_key_down(KeyboardKey::KEY_W) {
-            camera.move_forward(movement_speed);
-            camera_moved = true;
+        // F7 or saving materials.txt reloads the library, F8 picks the material to tweak live
+        if input::pressed(&window, Action::ReloadMaterials) {
+            render_thread.command(SceneCommand::ReloadMaterials);
         }
-        if window.is_key_down(KeyboardKey::KEY_S) {
-            camera.move_forward(-movement_speed);
-            camera_moved = true;
+        if last_watch_time.elapsed().as_secs_f32() >= 1.0 {
+            last_watch_time = std::time::Instant::now();
+            if library_watcher.changed() {
+                render_thread.command(SceneCommand::ReloadMaterials);
+                frames_without_input = 0;
+            }
         }
-        if window.is_key_down(KeyboardKey::KEY_A) {
-            camera.move_right(-movement_speed);
-            camera_moved = true;
+        if input::pressed(&window, Action::SelectBlock) {
+            render_thread.command(SceneCommand::Select(camera.clone()));
         }
-        if window.is_key_down(KeyboardKey::KEY_D) {
-            camera.move_right(movement_speed);
-            camera_moved = true;
+        if input::pressed(&window, Action::PlaceBlock) {
+            render_thread.command(SceneCommand::PlaceBlock(camera.clone()));
         }
-        if window.is_key_down(KeyboardKey::KEY_Q) {
-            camera.move_up(movement_speed);
-            camera_moved = true;
+        if input::pressed(&window, Action::RemoveBlock) {
+            render_thread.command(SceneCommand::RemoveBlock(camera.clone()));
         }
-        if window.is_key_down(KeyboardKey::KEY_E) {
-            camera.move_up(-movement_speed);
-            camera_moved = true;
+
+        // Terminal console: material overrides for debugging shading
+        for command in console.poll() {
+            let target = |target: ConsoleTarget| match target {
+                ConsoleTarget::Aimed => OverrideTarget::Aimed(camera.clone()),
+                ConsoleTarget::All => OverrideTarget::All,
+                ConsoleTarget::Block(name) => OverrideTarget::Block(name),
+            };
+            match command {
+                ConsoleCommand::Override { target: objects, material } => {
+                    render_thread.command(SceneCommand::OverrideMaterial(target(objects), material));
+                }
+                ConsoleCommand::Reset(objects) => render_thread.command(SceneCommand::ResetMaterial(target(objects))),
+                ConsoleCommand::Help => println!("{}", CONSOLE_HELP),
+            }
+            frames_without_input = 0;
         }
-        if window.is_key_down(KeyboardKey::KEY_LEFT) {
-            camera.rotate(-rotation_speed, 0.0);
-            camera_moved = true;
+
+        // T: screenshot of the next frame, with a 16-bit depth PNG and its render metadata alongside
+        if input::pressed(&window, Action::Screenshot) {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let scene = match script_path.as_deref() {
+                Some(path) => path.display().to_string(),
+                None => GALLERY[scene_index].name.to_string(),
+            };
+            render_thread.command(SceneCommand::Screenshot(ScreenshotRequest {
+                path: format!("screenshot_{}.png", timestamp).into(),
+                scene,
+                script: script_path.clone(),
+            }));
         }
-        if window.is_key_down(KeyboardKey::KEY_RIGHT) {
-            camera.rotate(rotation_speed, 0.0);
-            camera_moved = true;
+        // Shift+T: save the blocks, main and scene lights and the camera as a scene file that
+        // --scene-file loads again
+        if input::pressed(&window, Action::SaveScene) {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let mut lights = vec![base_light.clone()];
+            lights.extend(extra_lights.iter().cloned());
+            render_thread.command(SceneCommand::SaveScene(SaveSceneRequest {
+                path: format!("scene_{}.ron", timestamp).into(),
+                camera: camera.clone(),
+                lights,
+            }));
         }
-        if window.is_key_down(KeyboardKey::KEY_UP) {
-            camera.rotate(0.0, rotation_speed);
-            camera_moved = true;
+        // Shift+B: bookmark the view, `--render-bookmarks <dir>` renders them all headless
+        if input::pressed(&window, Action::AddBookmark) {
+            if script_path.is_some() {
+                println!("Bookmarks need a gallery scene, script scenes can't be rebuilt by name");
+            } else {
+                let path = Path::new(BOOKMARKS_FILE);
+                match Bookmarks::load(path) {
+                    Ok(mut saved) => {
+                        let name = saved.add(GALLERY[scene_index].name, &camera);
+                        match saved.save(path) {
+                            Ok(()) => println!("Bookmarked {} in {}", name, BOOKMARKS_FILE),
+                            Err(err) => println!("Could not save bookmarks: {}", err),
+                        }
+                    }
+                    Err(err) => println!("ERROR: {}", err),
+                }
+            }
         }
-        if window.is_key_down(KeyboardKey::KEY_DOWN) {
-            camera.rotate(0.0, -rotation_speed);
-            camera_moved = true;
+        let edits = [
+            (Action::ReflectivityDown, MaterialEdit::Reflectivity(-ALBEDO_STEP)),
+            (Action::ReflectivityUp, MaterialEdit::Reflectivity(ALBEDO_STEP)),
+            (Action::TransparencyDown, MaterialEdit::Transparency(-ALBEDO_STEP)),
+            (Action::TransparencyUp, MaterialEdit::Transparency(ALBEDO_STEP)),
+            (Action::SpecularDown, MaterialEdit::Specular(-SPECULAR_STEP)),
+            (Action::SpecularUp, MaterialEdit::Specular(SPECULAR_STEP)),
+        ];
+        for (action, edit) in edits {
+            if input::pressed(&window, action) {
+                render_thread.command(SceneCommand::Edit(edit));
+            }
         }
 
-        // Detect movement for adaptive rendering
-        let pos_changed = (camera.eye - prev_camera_pos).length() > 0.01;
-        let angle_changed = ((camera.yaw - prev_camera_angles.0).abs() > 0.001) || 
-                           ((camera.pitch - prev_camera_angles.1).abs() > 0.001);
-        
-        if pos_changed || angle_changed || camera_moved {
-            frames_since_movement = 0;
-        } else {
-            frames_since_movement += 1;
+        // Match the framebuffer to the window/setting so aspect ratio stays correct
+        if window.is_window_resized() || resolution_changed {
+            let new_width = window.get_screen_width();
+            let new_height = window.get_screen_height();
+            if new_width > 0 && new_height > 0 {
+                (render_width, render_height) = settings.render_resolution(new_width as u32, new_height as u32);
+                frames_without_input = 0;
+            }
         }
 
-        // Adaptive render scale with more gradual transitions
-        let render_scale = if ADAPTIVE_RENDER {
-            if frames_since_movement < 3 {
-                MIN_RENDER_SCALE // Very low quality while actively moving
-            } else if frames_since_movement < 8 {
-                MID_RENDER_SCALE // Medium quality shortly after stopping
-            } else {
-                MAX_RENDER_SCALE // Highest quality when still
-            }
-        } else {
-            MAX_RENDER_SCALE
-        };
+        // Nothing to trace once a still view is up: trace it once more at full quality, then skip
+        // the tracer and slow the loop down until input wakes it. Animated scenes wait longer
+        // and pause their animation while idle
+        let changing = render_thread.is_animated() || light_animation_index != 0 || !timeline.lights.is_empty();
+        let animated = changing || post.grain > 0.0;
+        // Averaging starts over when the camera or a light changes; the render thread starts
+        // it over itself when the scene moves objects
+        let view = (render_camera.eye, render_camera.forward);
+        if frames_without_input == 0 || view != last_view || lights != last_lights {
+            view_epoch += 1;
+        }
+        last_view = view;
+        last_lights.clone_from(&lights);
+        let recording = recorder.is_some() || flythrough_recording.is_some() || playback.is_some() || demo_player.is_some();
+        // A still view is averaged over jittered full-resolution frames instead of staying at
+        // the last adaptive frame; the path tracer always averages. Still means the camera and
+        // the input are, an animated scene refines too and starts over when something moves
+        let still = !recording && frames_without_input >= IDLE_AFTER_FRAMES;
+        let path_traced = settings.options.path_traced;
+        let target_frames = if path_traced { PATH_TRACE_FRAMES } else { PROGRESSIVE_FRAMES };
+        let converging = (path_traced || still) && render_thread.accumulated_frames() < target_frames;
+        let busy = recording || converging;
+        let idle_after = if animated { IDLE_ANIMATED_AFTER_FRAMES } else { IDLE_AFTER_FRAMES };
+        let now_idle = !busy && frames_without_input >= idle_after;
+        let entering_idle = now_idle && !idle;
+        let mut options = settings.options;
+        options.progressive = still && !path_traced;
+        if entering_idle {
+            render_scale = MAX_RENDER_SCALE;
+            options.interlaced = false;
+            options.half_res_secondary = false;
+        }
+        if now_idle != idle {
+            idle = now_idle;
+            window.set_target_fps(if idle { IDLE_FPS } else { settings.fps_cap() });
+        }
 
         // Render with adaptive quality
-        framebuffer.clear();
-        render_adaptive(&mut framebuffer, &mut objects, &camera, &light, render_scale);
-        framebuffer.swap_buffers(&mut window, &thread);
+        if !idle || entering_idle {
+            render_thread.submit(RenderJob {
+                camera: render_camera,
+                lights,
+                sky,
+                options,
+                split: settings.split_view(),
+                post: post.clone(),
+                width: render_width,
+                height: render_height,
+                render_scale,
+                frame_index,
+                time: sim_time,
+                light_gizmos,
+                view_epoch,
+            });
+        }
 
-        // Update previous camera state
-        prev_camera_pos = camera.eye;
-        prev_camera_angles = (camera.yaw, camera.pitch);
+        // Present the newest finished frame, or keep showing the previous one
+        let front = render_thread.front();
+        if front.generation() != presented_generation {
+            presented_generation = front.generation();
+            presented_frame = front.upload(&mut window, &thread, recorder.as_mut());
+            traced_count += 1;
+            if let Some(timing) = render_thread.last_frame_timing() {
+                resolution.frame_traced(timing.seconds, timing.scale);
+            }
+            if let Some(active) = playback.as_mut() {
+                active.frame_traced();
+            }
+        }
+        let mut overlay = Overlay {
+            lines: vec![format!("EV {:+.1}", settings.options.exposure)],
+            crosshair: true,
+            panel: if show_scene_menu {
+                scene_menu_lines(scene_index)
+            } else if show_help {
+                input::help_lines()
+            } else {
+                Vec::new()
+            },
+        };
+        if flythrough_recording.is_some() {
+            overlay.lines.push("REC flythrough".to_string());
+        } else if playback.is_some() {
+            overlay.lines.push("Replaying flythrough".to_string());
+        } else if demo_player.is_some() {
+            overlay.lines.push("Demo".to_string());
+        }
+        match render_thread.accumulated_frames() {
+            0 => {}
+            frames if path_traced => overlay.lines.push(format!("Path tracing, {} frames", frames)),
+            frames => overlay.lines.push(format!("Refining, {} frames", frames)),
+        }
+        if let Some(target) = render_thread.target() {
+            overlay.lines.push(format!(
+                "{} at ({:.1}, {:.1}, {:.1}), {:.1} away",
+                target.block, target.center.x, target.center.y, target.center.z, target.distance
+            ));
+        }
+        // Feeds the presented frame to ffmpeg while recording
+        if let Err(err) = present(&mut window, &thread, presented_frame.as_ref(), &overlay, recorder.as_mut()) {
+            println!("Recording stopped: {}", err);
+            recorder = None;
+        }
+
+        frame_index = frame_index.wrapping_add(1);
 
         // FPS monitoring
         frame_count += 1;
         if last_fps_time.elapsed().as_secs() >= 2 {
-            println!("FPS: {} | Scale: {:.2} | Cubes: {} | Pos: ({:.1}, {:.1}, {:.1})", 
-                    frame_count / 2, render_scale, objects.len(), 
-                    camera.eye.x, camera.eye.y, camera.eye.z);
+            println!("FPS: {} | Traced: {} | Scale: {:.2} | Cubes: {} | Pos: ({:.1}, {:.1}, {:.1}) | Sim: {:.1}s", 
+                    frame_count / 2, traced_count / 2, render_scale, render_thread.object_count(), 
+                    camera.eye.x, camera.eye.y, camera.eye.z, sim_time);
             frame_count = 0;
+            traced_count = 0;
             last_fps_time = std::time::Instant::now();
         }
     }
-}
\ No newline at end of file
+
+    // Remember the quality settings, window and view for next time; script scenes can't be
+    // reloaded by name, so their view isn't kept
+    settings.window_size = (window.get_screen_width(), window.get_screen_height());
+    settings.view = script_path.is_none().then(|| SavedView::new(GALLERY[scene_index].name, &camera));
+    match settings.save(settings_path) {
+        Ok(()) => println!("Saved settings to {}", SETTINGS_FILE),
+        Err(err) => println!("Could not save settings: {}", err),
+    }
+
+    // Let ffmpeg finalize the file if the window closes mid-recording
+    if let Some(active) = recorder {
+        let path = active.path.clone();
+        match active.finish() {
+            Ok(()) => println!("Saved recording {}", path),
+            Err(err) => println!("Recording failed: {}", err),
+        }
+    }
+}
@@ -1,11 +1,19 @@
-use raylib::prelude::{Color, Vector3};
+use raylib::prelude::{Color, Image, Vector3};
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+use std::sync::Arc;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Material {
     pub diffuse: Vector3,
     pub albedo: [f32; 4],
     pub specular: f32,
     pub refractive_index: f32,
+    pub emission: Vector3, // Light given off, masked per texel by the emission map
+    pub metallic: f32,     // 1 = metal: no diffuse, reflections tinted by the diffuse color
+    pub translucency: f32, // How much light from behind bleeds through (leaves)
+    pub shadow_bias: f32,  // Extra ray offset for bumpy/parallax surfaces prone to acne
+    pub ripple: f32,       // Strength of the animated wave normals (water), 0 = flat
 }
 
 impl Material {
@@ -15,6 +23,24 @@ impl Material {
             albedo,
             specular,
             refractive_index,
+            emission: Vector3::zero(),
+            metallic: 0.0,
+            translucency: 0.0,
+            shadow_bias: 0.0,
+            ripple: 0.0,
+        }
+    }
+
+    /// Stand-in looks for material overrides when debugging shading: `mirror`, `chrome`,
+    /// `glass` and `matte`
+    pub fn look(name: &str) -> Option<Self> {
+        let white = Vector3::new(0.95, 0.95, 0.95);
+        match name {
+            "mirror" => Some(Material::new(white, 256.0, [0.0, 0.1, 0.9, 0.0], 1.0)),
+            "chrome" => Some(Material { metallic: 1.0, ..Material::new(white, 128.0, [0.0, 0.3, 1.0, 0.0], 1.0) }),
+            "glass" => Some(Material::new(Vector3::new(0.9, 0.95, 1.0), 96.0, [0.05, 0.3, 0.1, 0.8], 1.5)),
+            "matte" => Some(Material::new(Vector3::new(0.8, 0.8, 0.8), 1.0, [1.0, 0.0, 0.0, 0.0], 1.0)),
+            _ => None,
         }
     }
 
@@ -24,7 +50,83 @@ impl Material {
             albedo: [0.0, 0.0, 0.0, 0.0],
             specular: 0.0,
             refractive_index: 0.0,
+            emission: Vector3::zero(),
+            metallic: 0.0,
+            translucency: 0.0,
+            shadow_bias: 0.0,
+            ripple: 0.0,
+        }
+    }
+}
+
+/// Every material a scene is drawn with. Blocks and props hold an index into it rather than
+/// a copy, so changing an entry restyles every object using it at once. Library presets get
+/// one entry each (see `MaterialLibrary::table`), found again through their material ID.
+#[derive(Debug, Clone, Default)]
+pub struct MaterialTable {
+    materials: Vec<Material>,
+    presets: HashMap<u32, u32>, // Entry of each library preset, by material ID
+    shared: Vec<u32>,           // Entries handed out by `share`
+}
+
+impl MaterialTable {
+    /// A new entry of its own, for objects whose material changes apart from the rest
+    pub fn add(&mut self, material: Material) -> u32 {
+        self.materials.push(material);
+        (self.materials.len() - 1) as u32
+    }
+
+    /// An entry for a material that isn't a preset, shared with the blocks built earlier
+    /// with the same values so a script's thousand plain blocks don't need a thousand entries
+    pub fn share(&mut self, material: Material) -> u32 {
+        if let Some(&entry) = self.shared.iter().find(|&&entry| self[entry] == material) {
+            return entry;
         }
+        let entry = self.add(material);
+        self.shared.push(entry);
+        entry
+    }
+
+    /// The entry of the preset with this material ID
+    pub fn preset(&self, id: u32) -> Option<u32> {
+        self.presets.get(&id).copied()
+    }
+
+    /// Gives a preset its material, in place when it already has an entry
+    pub fn set_preset(&mut self, id: u32, material: Material) -> u32 {
+        match self.preset(id) {
+            Some(entry) => {
+                self.materials[entry as usize] = material;
+                entry
+            }
+            None => {
+                let entry = self.add(material);
+                self.presets.insert(id, entry);
+                entry
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+}
+
+impl Index<u32> for MaterialTable {
+    type Output = Material;
+
+    fn index(&self, entry: u32) -> &Material {
+        &self.materials[entry as usize]
+    }
+}
+
+impl IndexMut<u32> for MaterialTable {
+    fn index_mut(&mut self, entry: u32) -> &mut Material {
+        &mut self.materials[entry as usize]
     }
 }
 
@@ -35,4 +137,257 @@ pub fn vector3_to_color(v: Vector3) -> Color {
         (v.z * 255.0).min(255.0) as u8,
         255,
     )
-}
\ No newline at end of file
+}
+/// A texture's pixels, copied out of the raylib image once at load time so sampling needs
+/// no mutable access and the objects holding it can be shared between render threads.
+/// Cloning only bumps a reference count: every block drawn with a texture shares its texels.
+#[derive(Clone)]
+pub struct Texture {
+    pub width: i32,
+    pub height: i32,
+    pub filter: TextureFilter,
+    texels: Arc<Texels>,
+}
+
+/// How `Texture::sample` reads between texel centers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextureFilter {
+    Nearest,  // Crisp pixel art, shimmers as the camera moves
+    Bilinear, // Blends the four surrounding texels
+}
+
+impl TextureFilter {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "nearest" => Some(TextureFilter::Nearest),
+            "bilinear" => Some(TextureFilter::Bilinear),
+            _ => None,
+        }
+    }
+}
+
+/// How textures are stored and filtered when loaded. `--compact-textures` keeps texels
+/// in less memory, `--bilinear` filters every texture by default instead of nearest-texel
+/// lookups.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureOptions {
+    pub compact: bool,
+    pub filter: TextureFilter,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        TextureOptions { compact: false, filter: TextureFilter::Nearest }
+    }
+}
+
+impl TextureOptions {
+    pub fn from_args(args: &[String]) -> Self {
+        let has = |flag: &str| args.iter().any(|arg| arg == flag);
+        TextureOptions {
+            compact: has("--compact-textures"),
+            filter: if has("--bilinear") { TextureFilter::Bilinear } else { TextureFilter::Nearest },
+        }
+    }
+}
+
+// Row-major texels. Compact textures with at most 256 colors (most block textures) keep
+// an 8-bit index into their palette, losslessly; the rest keep 16 bits per texel instead
+// of 32, decoded again on every sample, with a 1-bit alpha for cutouts when any texel is
+// transparent.
+enum Texels {
+    Rgba8(Vec<[u8; 4]>),
+    Indexed { palette: Vec<[u8; 4]>, indices: Vec<u8> },
+    Rgb565(Vec<u16>),
+    Rgba5551(Vec<u16>),
+}
+
+impl Texture {
+    pub fn from_image(image: &mut Image, options: TextureOptions) -> Self {
+        let (width, height) = (image.width, image.height);
+        let mut colors = Vec::with_capacity((width * height).max(0) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                colors.push(image.get_color(x, y));
+            }
+        }
+        Texture::from_colors(width, height, &colors, options)
+    }
+
+    // Row-major colors stored the way `options` asks
+    fn from_colors(width: i32, height: i32, colors: &[Color], options: TextureOptions) -> Self {
+        let rgba: Vec<[u8; 4]> = colors.iter().map(|color| [color.r, color.g, color.b, color.a]).collect();
+        let texels = if !options.compact {
+            Texels::Rgba8(rgba)
+        } else if let Some((palette, indices)) = palettize(&rgba) {
+            Texels::Indexed { palette, indices }
+        } else if colors.iter().any(|color| color.a < 255) {
+            Texels::Rgba5551(colors.iter().map(|color| pack(color, [5, 5, 5]) << 1 | u16::from(color.a >= 128)).collect())
+        } else {
+            Texels::Rgb565(colors.iter().map(|color| pack(color, [5, 6, 5])).collect())
+        };
+        Texture { width, height, filter: options.filter, texels: Arc::new(texels) }
+    }
+
+    /// The texel at (x, y), which must be inside the texture
+    pub fn texel(&self, x: i32, y: i32) -> Color {
+        let index = (y * self.width + x) as usize;
+        match self.texels.as_ref() {
+            Texels::Rgba8(texels) => {
+                let [r, g, b, a] = texels[index];
+                Color::new(r, g, b, a)
+            }
+            Texels::Indexed { palette, indices } => {
+                let [r, g, b, a] = palette[indices[index] as usize];
+                Color::new(r, g, b, a)
+            }
+            Texels::Rgb565(texels) => {
+                let texel = texels[index];
+                Color::new(unpack(texel >> 11, 5), unpack(texel >> 5, 6), unpack(texel, 5), 255)
+            }
+            Texels::Rgba5551(texels) => {
+                let texel = texels[index];
+                let alpha = if texel & 1 == 1 { 255 } else { 0 };
+                Color::new(unpack(texel >> 11, 5), unpack(texel >> 6, 5), unpack(texel >> 1, 5), alpha)
+            }
+        }
+    }
+
+    /// The texel nearest to a UV, alpha included
+    pub fn texel_at(&self, u: f32, v: f32) -> Color {
+        let u = u.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
+        let x = ((u * (self.width - 1) as f32).round() as i32).clamp(0, self.width - 1);
+        let y = ((v * (self.height - 1) as f32).round() as i32).clamp(0, self.height - 1);
+        self.texel(x, y)
+    }
+
+    /// Color at a UV in [0, 1], filtered with the texture's `filter`
+    pub fn sample(&self, u: f32, v: f32) -> Vector3 {
+        match self.filter {
+            TextureFilter::Nearest => color_to_vector3(self.texel_at(u, v)),
+            TextureFilter::Bilinear => self.sample_bilinear(u, v),
+        }
+    }
+
+    // Same texel centers as `texel_at`, edges clamp instead of wrapping since each face
+    // holds the texture exactly once
+    fn sample_bilinear(&self, u: f32, v: f32) -> Vector3 {
+        let x = u.clamp(0.0, 1.0) * (self.width - 1).max(0) as f32;
+        let y = v.clamp(0.0, 1.0) * (self.height - 1).max(0) as f32;
+        let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let top = color_to_vector3(self.texel(x0, y0)) * (1.0 - fx) + color_to_vector3(self.texel(x1, y0)) * fx;
+        let bottom = color_to_vector3(self.texel(x0, y1)) * (1.0 - fx) + color_to_vector3(self.texel(x1, y1)) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+fn color_to_vector3(color: Color) -> Vector3 {
+    Vector3::new(color.r as f32 / 255.0, color.g as f32 / 255.0, color.b as f32 / 255.0)
+}
+
+// The distinct colors of a texture and each texel's index into them, when there are few
+// enough to index with a byte
+fn palettize(texels: &[[u8; 4]]) -> Option<(Vec<[u8; 4]>, Vec<u8>)> {
+    let mut palette = Vec::new();
+    let mut lookup = HashMap::new();
+    let mut indices = Vec::with_capacity(texels.len());
+    for texel in texels {
+        let index = match lookup.get(texel) {
+            Some(&index) => index,
+            None => {
+                let index = u8::try_from(palette.len()).ok()?;
+                palette.push(*texel);
+                lookup.insert(*texel, index);
+                index
+            }
+        };
+        indices.push(index);
+    }
+    Some((palette, indices))
+}
+
+// Packs RGB into 16 bits with the given bits per channel, red highest
+fn pack(color: &Color, bits: [u32; 3]) -> u16 {
+    let quantize = |value: u8, bits: u32| ((value as u32 * ((1 << bits) - 1) + 127) / 255) as u16;
+    quantize(color.r, bits[0]) << (bits[1] + bits[2]) | quantize(color.g, bits[1]) << bits[2] | quantize(color.b, bits[2])
+}
+
+// A channel of `bits` bits (the lowest ones of `value`) back to 8 bits
+fn unpack(value: u16, bits: u32) -> u8 {
+    let max = (1u32 << bits) - 1;
+    (((value as u32 & max) * 255 + max / 2) / max) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPACT: TextureOptions = TextureOptions { compact: true, filter: TextureFilter::Nearest };
+
+    // 17 rows of 16 distinct colors, too many for a palette; `alpha` per texel index
+    fn many_colors(alpha: impl Fn(usize) -> u8) -> Vec<Color> {
+        (0..17 * 16).map(|index| Color::new((index / 16 * 15) as u8, (index % 16 * 17) as u8, (index * 3 % 256) as u8, alpha(index))).collect()
+    }
+
+    fn texels(texture: &Texture) -> Vec<Color> {
+        (0..texture.height).flat_map(|y| (0..texture.width).map(move |x| texture.texel(x, y))).collect()
+    }
+
+    // Every channel within `tolerance` of the original, alpha as `expected_alpha` says
+    fn assert_close(decoded: &[Color], original: &[Color], tolerance: [u8; 3], expected_alpha: impl Fn(u8) -> u8) {
+        for (decoded, original) in decoded.iter().zip(original) {
+            let channels = [(decoded.r, original.r), (decoded.g, original.g), (decoded.b, original.b)];
+            for ((decoded_channel, original_channel), tolerance) in channels.into_iter().zip(tolerance) {
+                assert!(decoded_channel.abs_diff(original_channel) <= tolerance, "{:?} decoded as {:?}", original, decoded);
+            }
+            assert_eq!(decoded.a, expected_alpha(original.a), "{:?} decoded as {:?}", original, decoded);
+        }
+    }
+
+    #[test]
+    fn uncompacted_and_palettized_texels_are_lossless() {
+        let colors = many_colors(|index| (index * 5 % 256) as u8);
+        assert_eq!(texels(&Texture::from_colors(16, 17, &colors, TextureOptions::default())), colors);
+
+        // 256 colors still fit a palette
+        let few = &colors[..256];
+        let texture = Texture::from_colors(16, 16, few, COMPACT);
+        assert!(matches!(texture.texels.as_ref(), Texels::Indexed { .. }));
+        assert_eq!(texels(&texture), few);
+    }
+
+    #[test]
+    fn opaque_texels_keep_565_precision() {
+        let colors = many_colors(|_| 255);
+        let texture = Texture::from_colors(16, 17, &colors, COMPACT);
+        assert!(matches!(texture.texels.as_ref(), Texels::Rgb565(_)));
+        // Half a step of 5 and 6 bits
+        assert_close(&texels(&texture), &colors, [4, 2, 4], |_| 255);
+    }
+
+    #[test]
+    fn transparent_texels_keep_5551_precision_and_a_cutout_alpha() {
+        let colors = many_colors(|index| (index % 256) as u8);
+        let texture = Texture::from_colors(16, 17, &colors, COMPACT);
+        assert!(matches!(texture.texels.as_ref(), Texels::Rgba5551(_)));
+        assert_close(&texels(&texture), &colors, [4, 4, 4], |alpha| if alpha >= 128 { 255 } else { 0 });
+    }
+
+    #[test]
+    fn channels_pack_and_unpack_to_the_nearest_level() {
+        for bits in [5, 6] {
+            let step = 255.0 / ((1 << bits) - 1) as f32;
+            for value in 0..=255u8 {
+                // Blue is the lowest channel, the one `unpack` reads unshifted
+                let decoded = unpack(pack(&Color::new(0, 0, value, 255), [5, 5, bits]), bits);
+                assert!((decoded as f32 - value as f32).abs() <= step / 2.0, "{} at {} bits decoded as {}", value, bits, decoded);
+            }
+            // Black and white survive exactly
+            assert_eq!(unpack(pack(&Color::new(0, 0, 255, 255), [5, 5, bits]), bits), 255);
+            assert_eq!(unpack(pack(&Color::new(0, 0, 0, 255), [5, 5, bits]), bits), 0);
+        }
+    }
+}
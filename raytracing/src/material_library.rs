@@ -0,0 +1,477 @@
+use crate::cube::Cube;
+use crate::material::{Material, MaterialTable, Texture, TextureFilter, TextureOptions};
+use crate::ray_intersect::SceneObject;
+use crate::rng::mix64;
+use raylib::prelude::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::SystemTime;
+
+// Same search paths the textures have always used
+const ASSET_DIRS: [&str; 3] = ["src/assets", "./src/assets", "./assets"];
+pub const LIBRARY_FILE: &str = "materials.txt";
+
+// Compiled-in copy so the diorama still renders if the file is missing
+const BUILTIN_LIBRARY: &str = include_str!("assets/materials.txt");
+
+/// A named material plus the texture variants it is drawn with
+#[derive(Clone)]
+pub struct MaterialPreset {
+    pub material: Material,
+    pub texture_files: Vec<String>,
+    pub textures: Vec<Texture>,
+    pub top_file: Option<String>, // Top and bottom faces, the sides keep `textures`
+    pub top_texture: Option<Texture>,
+    pub bottom_file: Option<String>,
+    pub bottom_texture: Option<Texture>,
+    pub rotate: bool, // Random quarter turns per block, off for textures with a direction (log grain)
+    pub emission_file: Option<String>,
+    pub emission_map: Option<Texture>,
+    pub parallax: f32,
+    pub height_file: Option<String>,
+    pub height_map: Option<Texture>,
+    pub gravity: bool, // Falls when nothing is under it, like sand
+    pub filter: Option<TextureFilter>, // Overrides the global `--bilinear` choice for this preset's textures
+    pub edited: bool, // Tweaked live since the file was read, saved scenes keep its values
+}
+
+impl MaterialPreset {
+    /// Gives a block one of the texture variants and rotations, picked from its position
+    /// so the same block always looks the same
+    fn style(&self, object: &mut Cube) {
+        object.emission_map = self.emission_map.clone();
+        if self.textures.is_empty() {
+            return;
+        }
+        let hash = block_hash(object.center);
+        let texture = &self.textures[(hash % self.textures.len() as u64) as usize];
+        object.texture = Some(texture.clone());
+        object.face_textures = match (&self.top_texture, &self.bottom_texture) {
+            (None, None) => None,
+            (top, bottom) => {
+                let top = top.as_ref().unwrap_or(texture).clone();
+                let bottom = bottom.as_ref().unwrap_or(texture).clone();
+                let side = texture.clone();
+                Some([side.clone(), side.clone(), top, bottom, side.clone(), side])
+            }
+        };
+        object.texture_rotation = if self.rotate { ((hash >> 32) % 4) as u8 } else { 0 };
+
+        // Without a dedicated height map the texture's own brightness stands in for height
+        object.parallax_depth = self.parallax;
+        object.height_map = match (&self.height_map, self.parallax > 0.0) {
+            (Some(map), true) => Some(map.clone()),
+            (None, true) => Some(texture.clone()),
+            (_, false) => None,
+        };
+    }
+}
+
+/// Named material presets, loaded from `materials.txt` and referenced by name from scenes
+pub struct MaterialLibrary {
+    presets: BTreeMap<String, MaterialPreset>,
+    pub source: Option<String>,
+    pub textures: TextureOptions, // How its textures were loaded, reused on reload
+}
+
+impl MaterialLibrary {
+    /// Loads the library file and its textures, falling back to the built-in presets
+    pub fn load(textures: TextureOptions) -> Self {
+        let mut library = match find_asset(LIBRARY_FILE) {
+            Some(path) => match fs::read_to_string(&path).map_err(|err| err.to_string()).and_then(|text| parse(&text)) {
+                Ok(presets) => {
+                    println!("Loaded {} materials from: {}", presets.len(), path);
+                    MaterialLibrary {
+                        presets,
+                        source: Some(path),
+                        textures,
+                    }
+                }
+                Err(err) => {
+                    println!("ERROR: {}: {} - using built-in materials", path, err);
+                    Self::builtin()
+                }
+            },
+            None => Self::builtin(),
+        };
+        library.textures = textures;
+        library.load_textures();
+        library
+    }
+
+    pub fn builtin() -> Self {
+        MaterialLibrary {
+            presets: parse(BUILTIN_LIBRARY).expect("built-in material library is valid"),
+            source: None,
+            textures: TextureOptions::default(),
+        }
+    }
+
+    /// A preset's material as the built-in library file has it, for blocks a scene can't do
+    /// without when a custom library leaves their preset out
+    pub fn builtin_material(name: &str) -> Option<Material> {
+        Self::builtin().presets.remove(name).map(|preset| preset.material)
+    }
+
+    fn load_textures(&mut self) {
+        let load = |file: &str| load_texture(file, self.textures);
+        for preset in self.presets.values_mut() {
+            preset.textures = preset.texture_files.iter().filter_map(|file| load(file)).collect();
+            preset.emission_map = preset.emission_file.as_deref().and_then(load);
+            preset.height_map = preset.height_file.as_deref().and_then(load);
+            preset.top_texture = preset.top_file.as_deref().and_then(load);
+            preset.bottom_texture = preset.bottom_file.as_deref().and_then(load);
+            if let Some(filter) = preset.filter {
+                let caps = preset.top_texture.iter_mut().chain(preset.bottom_texture.iter_mut());
+                let maps = preset.emission_map.iter_mut().chain(preset.height_map.iter_mut()).chain(caps);
+                for texture in preset.textures.iter_mut().chain(maps) {
+                    texture.filter = filter;
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MaterialPreset> {
+        self.presets.get(name)
+    }
+
+    /// Material table entry and texture for a block, `None` if the preset or its texture is
+    /// missing
+    pub fn block(&self, name: &str, materials: &MaterialTable) -> Option<(u32, &Texture)> {
+        let entry = materials.preset(material_id(name))?;
+        self.presets.get(name)?.textures.first().map(|texture| (entry, texture))
+    }
+
+    /// Whether the preset exists and has its texture, what `block` needs
+    pub fn has_block(&self, name: &str) -> bool {
+        self.presets.get(name).is_some_and(|preset| !preset.textures.is_empty())
+    }
+
+    /// A material table with an entry for every preset, for a scene built from this library
+    pub fn table(&self) -> MaterialTable {
+        let mut materials = MaterialTable::default();
+        for (name, preset) in &self.presets {
+            materials.set_preset(material_id(name), preset.material);
+        }
+        materials
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.presets.keys()
+    }
+
+    /// A block of the named preset, textured when the preset has a texture, drawn with the
+    /// preset's entry in `materials` and tagged with the preset's ID
+    pub fn cube(&self, name: &str, center: Vector3, size: f32, materials: &MaterialTable) -> Option<Cube> {
+        let preset = self.presets.get(name)?;
+        let entry = materials.preset(material_id(name))?;
+        let mut cube = match preset.textures.first() {
+            Some(texture) => Cube::with_texture(center, size, entry, texture.clone()),
+            None => Cube::new(center, size, entry),
+        };
+        cube.material_id = material_id(name);
+        Some(cube)
+    }
+
+    /// Name of the preset with this ID (see `material_id`), `None` for 0 and unknown IDs
+    pub fn name_of(&self, id: u32) -> Option<&str> {
+        self.presets.keys().find(|name| material_id(name) == id).map(String::as_str)
+    }
+
+    /// Material IDs of the presets that fall when unsupported
+    pub fn gravity_ids(&self) -> Vec<u32> {
+        self.presets.iter().filter(|(_, preset)| preset.gravity).map(|(name, _)| material_id(name)).collect()
+    }
+
+    /// Spreads each preset's texture variants and rotations over the objects built from it
+    pub fn vary_textures(&self, objects: &mut [Cube]) {
+        for object in objects {
+            self.restyle_block(self, object);
+        }
+    }
+
+    /// Moves blocks built from `previous` over to this library's version of each preset: the
+    /// table entries take the new materials, the blocks the new textures
+    pub fn restyle(&self, previous: &MaterialLibrary, objects: &mut [SceneObject], materials: &mut MaterialTable) -> usize {
+        for (name, preset) in &self.presets {
+            materials.set_preset(material_id(name), preset.material);
+        }
+        let mut changed = 0;
+        for object in objects.iter_mut().filter_map(|object| object.as_cube_mut()) {
+            if self.restyle_block(previous, object) {
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    fn restyle_block(&self, previous: &MaterialLibrary, object: &mut Cube) -> bool {
+        let Some(preset) = previous.name_of(object.material_id).and_then(|name| self.presets.get(name)) else {
+            return false;
+        };
+        preset.style(object);
+        true
+    }
+
+    /// Applies an edit to a preset and to its table entry, which every block drawn with it
+    /// reads from
+    pub fn edit(&mut self, name: &str, edit: MaterialEdit, materials: &mut MaterialTable) -> Option<Material> {
+        let preset = self.presets.get_mut(name)?;
+        edit.apply(&mut preset.material);
+        preset.edited = true;
+        materials.set_preset(material_id(name), preset.material);
+        Some(preset.material)
+    }
+}
+
+/// One step of a live material tweak
+#[derive(Debug, Clone, Copy)]
+pub enum MaterialEdit {
+    Reflectivity(f32),
+    Transparency(f32),
+    Specular(f32),
+}
+
+impl MaterialEdit {
+    fn apply(self, material: &mut Material) {
+        match self {
+            MaterialEdit::Reflectivity(delta) => material.albedo[2] = (material.albedo[2] + delta).clamp(0.0, 1.0),
+            MaterialEdit::Transparency(delta) => material.albedo[3] = (material.albedo[3] + delta).clamp(0.0, 1.0),
+            MaterialEdit::Specular(delta) => material.specular = (material.specular + delta).max(1.0),
+        }
+    }
+}
+
+/// Polls the library file's modification time so edits on disk can be picked up live
+pub struct LibraryWatcher {
+    path: Option<String>,
+    modified: Option<SystemTime>,
+}
+
+impl LibraryWatcher {
+    pub fn new() -> Self {
+        let path = find_asset(LIBRARY_FILE);
+        let modified = path.as_deref().and_then(modified_time);
+        LibraryWatcher { path, modified }
+    }
+
+    /// True once per change of the file on disk
+    pub fn changed(&mut self) -> bool {
+        let Some(modified) = self.path.as_deref().and_then(modified_time) else {
+            return false;
+        };
+        if self.modified == Some(modified) {
+            return false;
+        }
+        self.modified = Some(modified);
+        true
+    }
+}
+
+impl Default for LibraryWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Stable per-block hash from the block's grid cell
+fn block_hash(center: Vector3) -> u64 {
+    let x = (center.x * 2.0).round() as i64 as u64;
+    let y = (center.y * 2.0).round() as i64 as u64;
+    let z = (center.z * 2.0).round() as i64 as u64;
+    mix64(x ^ mix64(y ^ mix64(z)))
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+pub fn find_asset(file: &str) -> Option<String> {
+    ASSET_DIRS
+        .iter()
+        .map(|dir| format!("{}/{}", dir, file))
+        .find(|path| std::path::Path::new(path).exists())
+}
+
+/// Loads a texture's pixels, stored and filtered as `options` asks. Compact textures
+/// keep a byte per texel when they have at most 256 colors, 16 bits otherwise: blocks
+/// share their preset's texels, so this quarters or halves what the library holds.
+pub fn load_texture(file: &str, options: TextureOptions) -> Option<Texture> {
+    for dir in &ASSET_DIRS {
+        let path = format!("{}/{}", dir, file);
+        if let Ok(mut image) = Image::load_image(&path) {
+            println!("Loaded {} from: {}", file, path);
+            return Some(Texture::from_image(&mut image, options));
+        }
+    }
+    println!("WARNING: Could not load texture {}", file);
+    None
+}
+
+/// FNV-1a hash of a preset name, so a material keeps its ID when presets are added or removed
+pub fn material_id(name: &str) -> u32 {
+    name.bytes()
+        .fold(0x811C_9DC5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+        .max(1)
+}
+
+/// Parses `[name]` sections of `key = value` lines; `#` starts a comment
+pub fn parse(text: &str) -> Result<BTreeMap<String, MaterialPreset>, String> {
+    let mut presets = BTreeMap::new();
+    let mut current: Option<(String, MaterialPreset)> = None;
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            if let Some((name, preset)) = current.take() {
+                presets.insert(name, preset);
+            }
+            let preset = MaterialPreset {
+                material: Material::new(Vector3::new(1.0, 1.0, 1.0), 0.0, [1.0, 0.0, 0.0, 0.0], 1.0),
+                texture_files: Vec::new(),
+                textures: Vec::new(),
+                top_file: None,
+                top_texture: None,
+                bottom_file: None,
+                bottom_texture: None,
+                rotate: false,
+                emission_file: None,
+                emission_map: None,
+                parallax: 0.0,
+                height_file: None,
+                height_map: None,
+                gravity: false,
+                filter: None,
+                edited: false,
+            };
+            current = Some((name.trim().to_string(), preset));
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected `key = value`", line_number));
+        };
+        let Some((_, preset)) = current.as_mut() else {
+            return Err(format!("line {}: `{}` outside of a [material] section", line_number, key.trim()));
+        };
+        let value = value.trim();
+        let material = &mut preset.material;
+
+        match key.trim() {
+            "diffuse" => {
+                let [r, g, b] = parse_floats::<3>(value, line_number)?;
+                material.diffuse = Vector3::new(r, g, b);
+            }
+            "specular" => material.specular = parse_floats::<1>(value, line_number)?[0],
+            "albedo" => material.albedo = parse_floats::<4>(value, line_number)?,
+            "metallic" => material.metallic = parse_floats::<1>(value, line_number)?[0].clamp(0.0, 1.0),
+            "translucency" => material.translucency = parse_floats::<1>(value, line_number)?[0].clamp(0.0, 1.0),
+            "shadow_bias" => material.shadow_bias = parse_floats::<1>(value, line_number)?[0].max(0.0),
+            "ripple" => material.ripple = parse_floats::<1>(value, line_number)?[0].max(0.0),
+            "refractive_index" => material.refractive_index = parse_floats::<1>(value, line_number)?[0],
+            "emission" => {
+                let [r, g, b] = parse_floats::<3>(value, line_number)?;
+                material.emission = Vector3::new(r, g, b);
+            }
+            "emission_map" => preset.emission_file = Some(value.to_string()),
+            "parallax" => preset.parallax = parse_floats::<1>(value, line_number)?[0],
+            "height_map" => preset.height_file = Some(value.to_string()),
+            "texture" => preset.texture_files = value.split_whitespace().map(str::to_string).collect(),
+            "top_texture" => preset.top_file = Some(value.to_string()),
+            "bottom_texture" => preset.bottom_file = Some(value.to_string()),
+            "rotate" => preset.rotate = parse_bool(value, line_number)?,
+            "gravity" => preset.gravity = parse_bool(value, line_number)?,
+            "filter" => {
+                let filter = TextureFilter::parse(value)
+                    .ok_or_else(|| format!("line {}: `{}` is not nearest/bilinear", line_number, value))?;
+                preset.filter = Some(filter);
+            }
+            other => return Err(format!("line {}: unknown key `{}`", line_number, other)),
+        }
+    }
+
+    if let Some((name, preset)) = current {
+        presets.insert(name, preset);
+    }
+    Ok(presets)
+}
+
+fn parse_bool(value: &str, line_number: usize) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "1" => Ok(true),
+        "false" | "no" | "0" => Ok(false),
+        _ => Err(format!("line {}: `{}` is not true/false", line_number, value)),
+    }
+}
+
+fn parse_floats<const N: usize>(value: &str, line_number: usize) -> Result<[f32; N], String> {
+    let numbers: Vec<f32> = value
+        .split_whitespace()
+        .map(|part| part.parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("line {}: `{}` is not a number list", line_number, value))?;
+    numbers
+        .try_into()
+        .map_err(|_| format!("line {}: expected {} numbers", line_number, N))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_key_of_a_preset() {
+        let presets = parse(
+            "# comment\n[lava]\ndiffuse = 1 0.3 0.1\nspecular = 12\nalbedo = 0.9 0.1 0 0\nemission = 2 0.6 0.1 # glows\n\
+             metallic = 3\ntexture = a.png b.png\ntop_texture = top.png\nrotate = yes\ngravity = 0\nfilter = bilinear\n",
+        )
+        .unwrap();
+        let lava = &presets["lava"];
+        assert_eq!(lava.material.diffuse, Vector3::new(1.0, 0.3, 0.1));
+        assert_eq!(lava.material.specular, 12.0);
+        assert_eq!(lava.material.albedo, [0.9, 0.1, 0.0, 0.0]);
+        assert_eq!(lava.material.emission, Vector3::new(2.0, 0.6, 0.1));
+        assert_eq!(lava.material.metallic, 1.0); // Clamped
+        assert_eq!(lava.texture_files, ["a.png", "b.png"]);
+        assert_eq!(lava.top_file.as_deref(), Some("top.png"));
+        assert!(lava.rotate && !lava.gravity);
+        assert_eq!(lava.filter, Some(TextureFilter::Bilinear));
+    }
+
+    #[test]
+    fn keeps_every_section() {
+        let presets = parse("[a]\nspecular = 1\n[b]\n[c]\nspecular = 3\n").unwrap();
+        assert_eq!(presets.keys().collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert_eq!(presets["c"].material.specular, 3.0);
+    }
+
+    #[test]
+    fn reports_the_failing_line() {
+        assert_eq!(parse("[a]\nshine = 3\n").err().unwrap(), "line 2: unknown key `shine`");
+        assert_eq!(parse("specular = 3\n").err().unwrap(), "line 1: `specular` outside of a [material] section");
+        assert_eq!(parse("[a]\n\ndiffuse = 1 1\n").err().unwrap(), "line 3: expected 3 numbers");
+        assert_eq!(parse("[a]\nspecular = high\n").err().unwrap(), "line 2: `high` is not a number list");
+        assert_eq!(parse("[a]\nrotate = maybe\n").err().unwrap(), "line 2: `maybe` is not true/false");
+        assert_eq!(parse("[a]\ndiffuse\n").err().unwrap(), "line 2: expected `key = value`");
+    }
+
+    #[test]
+    fn builtin_library_parses() {
+        let presets = parse(BUILTIN_LIBRARY).unwrap();
+        assert!(presets.contains_key("piedra"));
+        // The diorama's elevator falls back on it
+        assert!(MaterialLibrary::builtin_material("hierro").is_some_and(|iron| iron.metallic == 1.0));
+    }
+
+    #[test]
+    fn material_ids_are_stable_and_never_zero() {
+        assert_eq!(material_id("piedra"), material_id("piedra"));
+        assert_ne!(material_id("piedra"), material_id("tierra"));
+        assert_ne!(material_id(""), 0);
+    }
+}
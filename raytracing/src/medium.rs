@@ -0,0 +1,88 @@
+// Deepest nesting of transparent media tracked at once (e.g. air > water > glass > ...)
+const MAX_NESTED_MEDIA: usize = 4;
+
+/// Refractive indices of the transparent materials a ray is currently inside,
+/// innermost last. Outside everything the ray is in air (IOR 1.0).
+#[derive(Debug, Clone, Copy)]
+pub struct IorStack {
+    iors: [f32; MAX_NESTED_MEDIA],
+    len: usize,
+}
+
+impl IorStack {
+    pub fn air() -> Self {
+        IorStack {
+            iors: [1.0; MAX_NESTED_MEDIA],
+            len: 0,
+        }
+    }
+
+    /// IOR of the innermost medium
+    pub fn current(&self) -> f32 {
+        if self.len == 0 { 1.0 } else { self.iors[self.len - 1] }
+    }
+
+    /// Stack after crossing into a medium; when full the innermost entry is replaced
+    pub fn entered(&self, ior: f32) -> Self {
+        let mut next = *self;
+        if next.len == MAX_NESTED_MEDIA {
+            next.len -= 1;
+        }
+        next.iors[next.len] = ior;
+        next.len += 1;
+        next
+    }
+
+    /// Stack after leaving a medium. The innermost entry with that IOR is removed, not just
+    /// the top, so leaving water while still inside overlapping glass keeps the glass.
+    pub fn exited(&self, ior: f32) -> Self {
+        let mut next = *self;
+        if let Some(index) = next.iors[..next.len].iter().rposition(|&entry| (entry - ior).abs() < 1e-4) {
+            next.iors.copy_within(index + 1..next.len, index);
+            next.len -= 1;
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WATER: f32 = 1.33;
+    const GLASS: f32 = 1.5;
+
+    #[test]
+    fn nested_media_unwind_innermost_first() {
+        let in_glass = IorStack::air().entered(WATER).entered(GLASS);
+        assert_eq!(in_glass.current(), GLASS);
+        assert_eq!(in_glass.exited(GLASS).current(), WATER);
+        assert_eq!(in_glass.exited(GLASS).exited(WATER).current(), 1.0);
+    }
+
+    #[test]
+    fn leaving_an_outer_medium_first_keeps_the_inner_one() {
+        // Glass that pokes out of the water: the ray leaves the water while still in the glass
+        let in_glass = IorStack::air().entered(WATER).entered(GLASS);
+        let left_water = in_glass.exited(WATER);
+        assert_eq!(left_water.current(), GLASS);
+        assert_eq!(left_water.len, 1);
+        assert_eq!(left_water.exited(GLASS).current(), 1.0);
+    }
+
+    #[test]
+    fn leaving_a_medium_never_entered_changes_nothing() {
+        let air = IorStack::air().exited(GLASS);
+        assert_eq!((air.current(), air.len), (1.0, 0));
+        let in_water = IorStack::air().entered(WATER).exited(GLASS);
+        assert_eq!((in_water.current(), in_water.len), (WATER, 1));
+    }
+
+    #[test]
+    fn a_full_stack_replaces_its_innermost_medium() {
+        let full = (0..MAX_NESTED_MEDIA).fold(IorStack::air(), |stack, _| stack.entered(WATER));
+        let deeper = full.entered(GLASS);
+        assert_eq!((deeper.current(), deeper.len), (GLASS, MAX_NESTED_MEDIA));
+        assert_eq!(deeper.exited(GLASS).current(), WATER);
+    }
+}
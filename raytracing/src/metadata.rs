@@ -0,0 +1,113 @@
+use crate::camera::Camera;
+use crate::light::{Light, LightKind};
+use crate::settings::RenderOptions;
+use raylib::prelude::Vector3;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything that went into a screenshot, saved next to it so the shot can be rendered
+/// again exactly: the view, lights, seed and frame, the quality switches and hashes of
+/// the files the scene came from (a changed hash means the scene itself changed since)
+pub struct ShotMetadata<'a> {
+    pub scene: &'a str,
+    pub scene_files: Vec<PathBuf>, // Scene script and material library, when loaded from disk
+    pub camera: &'a Camera,
+    pub lights: &'a [Light],
+    pub options: &'a RenderOptions,
+    pub width: u32,
+    pub height: u32,
+    pub seed: u64,
+    pub frame_index: u32,
+}
+
+impl ShotMetadata<'_> {
+    /// Writes the metadata as JSON beside the image and returns where it went
+    pub fn write(&self, image_path: &Path) -> io::Result<PathBuf> {
+        let path = metadata_path(image_path);
+        fs::write(&path, self.to_json())?;
+        Ok(path)
+    }
+
+    fn to_json(&self) -> String {
+        let vector = |v: Vector3| format!("[{}, {}, {}]", v.x, v.y, v.z);
+        let camera = self.camera;
+        let options = self.options;
+
+        let lights: Vec<String> = self
+            .lights
+            .iter()
+            .map(|light| {
+                let kind = match light.kind {
+                    LightKind::Point => "\"point\"".to_string(),
+                    LightKind::Directional { target } => format!("\"directional\", \"target\": {}", vector(target)),
+                    LightKind::Area { u, v } => format!("\"area\", \"u\": {}, \"v\": {}", vector(u), vector(v)),
+                };
+                format!(
+                    "{{ \"kind\": {}, \"position\": {}, \"color\": [{}, {}, {}], \"intensity\": {} }}",
+                    kind,
+                    vector(light.position),
+                    light.color.r,
+                    light.color.g,
+                    light.color.b,
+                    light.intensity
+                )
+            })
+            .collect();
+        let files: Vec<String> = self
+            .scene_files
+            .iter()
+            .map(|path| {
+                let hash = fs::read(path).map(|bytes| format!("\"{:016x}\"", fnv1a64(&bytes))).unwrap_or_else(|_| "null".to_string());
+                format!("{{ \"path\": \"{}\", \"fnv1a64\": {} }}", escape(&path.to_string_lossy()), hash)
+            })
+            .collect();
+
+        let mut json = String::from("{\n");
+        json += &format!("  \"scene\": \"{}\",\n", escape(self.scene));
+        json += &format!("  \"scene_files\": [{}],\n", files.join(", "));
+        json += &format!("  \"size\": [{}, {}],\n", self.width, self.height);
+        json += &format!("  \"seed\": {},\n", self.seed);
+        json += &format!("  \"frame_index\": {},\n", self.frame_index);
+        json += &format!("  \"time\": {},\n", options.time);
+        json += &format!(
+            "  \"camera\": {{ \"eye\": {}, \"center\": {}, \"up\": {}, \"yaw\": {}, \"pitch\": {} }},\n",
+            vector(camera.eye),
+            vector(camera.center),
+            vector(camera.up),
+            camera.yaw,
+            camera.pitch
+        );
+        json += &format!("  \"lights\": [{}],\n", lights.join(", "));
+        json += &format!(
+            "  \"quality\": {{ \"path_traced\": {}, \"shadows\": {}, \"reflections\": {}, \"reflection_depth\": {}, \"refraction_depth\": {}, \"ao_samples\": {}, \"exposure\": {}, \"bias\": [{}, {}] }}\n",
+            options.path_traced,
+            options.shadows,
+            options.reflections,
+            options.max_reflection_depth,
+            options.max_refraction_depth,
+            options.ao_samples,
+            options.exposure,
+            options.bias.constant,
+            options.bias.slope
+        );
+        json += "}\n";
+        json
+    }
+}
+
+/// Where the metadata for an image goes: `shot.png` -> `shot.json`
+pub fn metadata_path(image_path: &Path) -> PathBuf {
+    image_path.with_extension("json")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// 64-bit FNV-1a, enough to tell whether a scene file changed
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0xCBF2_9CE4_8422_2325u64, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
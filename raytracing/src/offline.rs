@@ -0,0 +1,660 @@
+use crate::acceleration::Acceleration;
+use crate::camera::Camera;
+use crate::color_ops::luminance;
+use crate::daycycle::DayCycle;
+use crate::denoise::{denoise, first_hit_aovs};
+use crate::depth::{depth_path, write_depth_png};
+use crate::framebuffer::Framebuffer;
+use crate::id_pass::write_id_passes;
+use crate::light::Light;
+use crate::material::{MaterialTable, TextureOptions};
+use crate::pathtrace::{PathLights, trace_path};
+use crate::post::PostProcess;
+use crate::probes::BakedLighting;
+use crate::ray_intersect::SceneObject;
+use crate::rng::Rng;
+use crate::sampler::Sampler;
+use crate::settings::RenderOptions;
+use crate::sky::Sky;
+use crate::tonemap::tonemap;
+use crate::{RENDER_SEED, block_lights, cast_ray, create_camera, create_cave_lamp, create_lights, load_scene, primary_ray, trace_emission};
+use raylib::prelude::*;
+use rayon::prelude::*;
+use std::f32::consts::PI;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"RTCK";
+const CHECKPOINT_VERSION: u32 = 3;
+const CHECKPOINT_HEADER_SIZE: usize = 44; // Magic, version, size, samples, `CheckpointOptions`
+const CHECKPOINT_PIXEL_SIZE: usize = 20; // RGB sum, luminance² sum, sample count
+
+// Adaptive sampling: uniform passes before any pixel may stop, and the relative
+// error below which a pixel counts as converged
+const ADAPTIVE_MIN_SAMPLES: u32 = 8;
+const ADAPTIVE_ERROR_THRESHOLD: f32 = 0.02;
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A headless render described on the command line:
+/// `--render <out.png> [--size WxH] [--samples N] [--checkpoint <file>] [--resume] [--path-trace]`
+/// `[--denoise] [--adaptive] [--depth] [--ids] [--transparent]`
+/// `[--bloom 0-1] [--sharpen 0-1] [--aberration 0-1] [--vignette 0-1] [--grain 0-1] [--lut <file.cube>]`
+pub struct OfflineJob {
+    pub output: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub checkpoint: PathBuf,
+    pub resume: bool,
+    pub options: RenderOptions,
+    pub denoise: bool,
+    pub adaptive: bool,
+    pub depth: bool, // Also write `<output>_depth.png`
+    pub ids: bool,   // Also write `<output>_ids.png` and `<output>_materials.png`
+    pub post: PostProcess,
+}
+
+impl OfflineJob {
+    pub fn from_args() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        let output = PathBuf::from(value_after(&args, "--render")?);
+
+        let (width, height) = size_after(&args);
+        let samples = value_after(&args, "--samples")
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(64);
+        let checkpoint = value_after(&args, "--checkpoint")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| output.with_extension("ckpt"));
+
+        Some(OfflineJob {
+            output,
+            width,
+            height,
+            samples,
+            checkpoint,
+            resume: args.iter().any(|arg| arg == "--resume"),
+            options: RenderOptions {
+                path_traced: args.iter().any(|arg| arg == "--path-trace"),
+                transparent_background: args.iter().any(|arg| arg == "--transparent"),
+                ..RenderOptions::default()
+            }
+            .with_quality_args(&args),
+            denoise: args.iter().any(|arg| arg == "--denoise"),
+            adaptive: args.iter().any(|arg| arg == "--adaptive"),
+            depth: args.iter().any(|arg| arg == "--depth"),
+            ids: args.iter().any(|arg| arg == "--ids"),
+            post: PostProcess::from_args(&args),
+        })
+    }
+}
+
+/// Fixed-camera time-of-day sequence:
+/// `--timelapse <dir> [--frames N] [--from H] [--to H] [--size WxH] [--samples N]`
+/// `[--bloom 0-1] [--sharpen 0-1] [--aberration 0-1] [--vignette 0-1] [--grain 0-1] [--lut <file.cube>]`
+pub struct TimelapseJob {
+    pub directory: PathBuf,
+    pub frames: u32,
+    pub from_hour: f32,
+    pub to_hour: f32,
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub post: PostProcess,
+}
+
+impl TimelapseJob {
+    pub fn from_args() -> Option<Self> {
+        let args: Vec<String> = std::env::args().collect();
+        let directory = PathBuf::from(value_after(&args, "--timelapse")?);
+        let number = |flag: &str| value_after(&args, flag).and_then(|v| v.parse::<f32>().ok());
+
+        let (width, height) = size_after(&args);
+
+        Some(TimelapseJob {
+            directory,
+            frames: number("--frames").map(|n| n as u32).unwrap_or(48).max(1),
+            from_hour: number("--from").unwrap_or(5.0),  // Before dawn
+            to_hour: number("--to").unwrap_or(22.0),     // Well into the night
+            width,
+            height,
+            samples: number("--samples").map(|n| n as u32).unwrap_or(4).max(1),
+            post: PostProcess::from_args(&args),
+        })
+    }
+}
+
+pub fn value_after<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let index = args.iter().position(|arg| arg == flag)?;
+    args.get(index + 1).map(|value| value.as_str())
+}
+
+/// Image size from `--size WxH`, 800x600 without one. Empty or too large sizes are reported
+/// and replaced by the default rather than rendered.
+pub fn size_after(args: &[String]) -> (u32, u32) {
+    const DEFAULT_SIZE: (u32, u32) = (800, 600);
+    let Some(size) = value_after(args, "--size") else {
+        return DEFAULT_SIZE;
+    };
+    let parsed = size.split_once('x').and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)));
+    match parsed {
+        Some((width, height)) if width > 0 && height > 0 && pixel_count(width, height).is_some() => (width, height),
+        _ => {
+            println!("ERROR: --size {} is not a valid size, rendering {}x{}", size, DEFAULT_SIZE.0, DEFAULT_SIZE.1);
+            DEFAULT_SIZE
+        }
+    }
+}
+
+/// Pixels in a width x height image, `None` if the count doesn't fit in memory indices
+pub fn pixel_count(width: u32, height: u32) -> Option<usize> {
+    (width as usize).checked_mul(height as usize).filter(|&count| count <= u32::MAX as usize)
+}
+
+/// The render options that change what a sample traces, kept in the checkpoint header so a
+/// resume never averages passes traced with different ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CheckpointOptions {
+    max_reflection_depth: u32,
+    max_refraction_depth: u32,
+    ao_samples: u32,
+    flags: u32, // Shadows, reflections, path tracing, light probes, glossy reflections, spectral
+    specular_range: f32,
+    time: f32,
+}
+
+impl CheckpointOptions {
+    pub fn new(options: &RenderOptions) -> Self {
+        let flags = [options.shadows, options.reflections, options.path_traced, options.light_probes, options.glossy_reflections, options.spectral];
+        CheckpointOptions {
+            max_reflection_depth: options.max_reflection_depth,
+            max_refraction_depth: options.max_refraction_depth,
+            ao_samples: options.ao_samples,
+            flags: flags.iter().enumerate().map(|(bit, &on)| (on as u32) << bit).sum(),
+            specular_range: options.specular_range,
+            time: options.time,
+        }
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        let values = [self.max_reflection_depth, self.max_refraction_depth, self.ao_samples, self.flags, self.specular_range.to_bits(), self.time.to_bits()];
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn read(bytes: &[u8], offset: usize) -> Self {
+        CheckpointOptions {
+            max_reflection_depth: read_u32(bytes, offset),
+            max_refraction_depth: read_u32(bytes, offset + 4),
+            ao_samples: read_u32(bytes, offset + 8),
+            flags: read_u32(bytes, offset + 12),
+            specular_range: read_f32(bytes, offset + 16),
+            time: read_f32(bytes, offset + 20),
+        }
+    }
+}
+
+/// Running per-pixel sum of samples, averaged when written out. Squared luminance is kept
+/// too so each pixel's noise can be estimated for adaptive sampling.
+pub struct Accumulation {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32, // Passes completed; a pass may skip pixels that already converged
+    pub options: CheckpointOptions,
+    pub sums: Vec<Vector3>,
+    pub luminance_squares: Vec<f32>,
+    pub counts: Vec<u32>,
+}
+
+impl Accumulation {
+    pub fn new(width: u32, height: u32, options: CheckpointOptions) -> Self {
+        let pixel_count = width as usize * height as usize;
+        Accumulation {
+            width,
+            height,
+            samples: 0,
+            options,
+            sums: vec![Vector3::zero(); pixel_count],
+            luminance_squares: vec![0.0; pixel_count],
+            counts: vec![0; pixel_count],
+        }
+    }
+
+    pub fn add(&mut self, index: usize, color: Vector3) {
+        let luminance = luminance(color);
+        self.sums[index] = self.sums[index] + color;
+        self.luminance_squares[index] += luminance * luminance;
+        self.counts[index] += 1;
+    }
+
+    pub fn average(&self, index: usize) -> Vector3 {
+        if self.counts[index] == 0 {
+            return Vector3::zero();
+        }
+        self.sums[index] / self.counts[index] as f32
+    }
+
+    /// Standard error of the pixel's mean luminance relative to its brightness
+    pub fn relative_error(&self, index: usize) -> f32 {
+        let n = self.counts[index] as f32;
+        if n < 2.0 {
+            return f32::INFINITY;
+        }
+        let mean = luminance(self.sums[index]) / n;
+        let variance = ((self.luminance_squares[index] / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        (variance / n).sqrt() / (mean + 0.01)
+    }
+
+    /// Why this checkpoint can't continue a render of that size and options, `None` if it can
+    pub fn mismatch(&self, width: u32, height: u32, options: &CheckpointOptions) -> Option<String> {
+        if (self.width, self.height) != (width, height) {
+            Some(format!("Checkpoint is {}x{}, job is {}x{}", self.width, self.height, width, height))
+        } else if self.options != *options {
+            Some("Checkpoint was traced with other render options".to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Writes header + raw sums, via a temp file so an interrupted write never corrupts the last checkpoint
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(CHECKPOINT_HEADER_SIZE + self.sums.len() * CHECKPOINT_PIXEL_SIZE);
+        bytes.extend_from_slice(CHECKPOINT_MAGIC);
+        bytes.extend_from_slice(&CHECKPOINT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.width.to_le_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.extend_from_slice(&self.samples.to_le_bytes());
+        self.options.write(&mut bytes);
+        for index in 0..self.sums.len() {
+            let sum = self.sums[index];
+            bytes.extend_from_slice(&sum.x.to_le_bytes());
+            bytes.extend_from_slice(&sum.y.to_le_bytes());
+            bytes.extend_from_slice(&sum.z.to_le_bytes());
+            bytes.extend_from_slice(&self.luminance_squares[index].to_le_bytes());
+            bytes.extend_from_slice(&self.counts[index].to_le_bytes());
+        }
+
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, &bytes)?;
+        fs::rename(&temp_path, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+        if bytes.len() < CHECKPOINT_HEADER_SIZE || &bytes[0..4] != CHECKPOINT_MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+        if read_u32(&bytes, 4) != CHECKPOINT_VERSION {
+            return Err(invalid("unsupported checkpoint version"));
+        }
+
+        let width = read_u32(&bytes, 8);
+        let height = read_u32(&bytes, 12);
+        let samples = read_u32(&bytes, 16);
+        // The header is untrusted, so its size is checked before it sizes anything
+        let pixel_count = pixel_count(width, height).filter(|&count| count > 0).ok_or_else(|| invalid("bad checkpoint size"))?;
+        let expected = pixel_count.checked_mul(CHECKPOINT_PIXEL_SIZE).and_then(|size| size.checked_add(CHECKPOINT_HEADER_SIZE));
+        if expected != Some(bytes.len()) {
+            return Err(invalid("truncated checkpoint"));
+        }
+
+        let mut accumulation = Accumulation::new(width, height, CheckpointOptions::read(&bytes, 20));
+        accumulation.samples = samples;
+        for index in 0..pixel_count {
+            let offset = CHECKPOINT_HEADER_SIZE + index * CHECKPOINT_PIXEL_SIZE;
+            accumulation.sums[index] = Vector3::new(
+                read_f32(&bytes, offset),
+                read_f32(&bytes, offset + 4),
+                read_f32(&bytes, offset + 8),
+            );
+            accumulation.luminance_squares[index] = read_f32(&bytes, offset + 12);
+            accumulation.counts[index] = read_u32(&bytes, offset + 16);
+        }
+        Ok(accumulation)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_bits(read_u32(bytes, offset))
+}
+
+/// Renders `job.samples` jittered passes, checkpointing periodically so the job can be resumed
+pub fn run(job: &OfflineJob) {
+    let args: Vec<String> = std::env::args().collect();
+    let (objects, materials) = load_scene(TextureOptions::from_args(&args));
+    let accel = Acceleration::build(&objects);
+    let camera = create_camera();
+    let mut lights = create_lights();
+    if !job.options.path_traced {
+        lights.extend(block_lights(&objects, &materials));
+    }
+    let sky = Sky::from_args(&args);
+
+    let options = CheckpointOptions::new(&job.options);
+    let mut accumulation = Accumulation::new(job.width, job.height, options);
+    if job.resume {
+        match Accumulation::load(&job.checkpoint) {
+            Ok(loaded) => match loaded.mismatch(job.width, job.height, &options) {
+                None => {
+                    println!("Resuming from {} at sample {}", job.checkpoint.display(), loaded.samples);
+                    accumulation = loaded;
+                }
+                Some(reason) => println!("{} - starting over", reason),
+            },
+            Err(err) => println!("Could not resume from {}: {} - starting over", job.checkpoint.display(), err),
+        }
+    }
+
+    let path_lights = PathLights::new(&objects, &materials, &lights);
+    let mut last_checkpoint = Instant::now();
+    while accumulation.samples < job.samples {
+        // With --adaptive, later passes only revisit pixels that are still noisy
+        let adaptive_pass = job.adaptive && accumulation.samples >= ADAPTIVE_MIN_SAMPLES;
+        // Rows are traced in parallel, then added in order
+        let mut pass: Vec<Option<Vector3>> = vec![None; accumulation.sums.len()];
+        pass.par_chunks_mut(job.width as usize).enumerate().for_each(|(y, row)| {
+            for (x, color) in row.iter_mut().enumerate() {
+                let index = y * job.width as usize + x;
+                if adaptive_pass && accumulation.relative_error(index) < ADAPTIVE_ERROR_THRESHOLD {
+                    continue;
+                }
+                // Each pixel continues its own sample sequence
+                let sample = accumulation.counts[index];
+                *color = Some(trace_sample(&objects, &materials, Some(&accel), &camera, &lights, &path_lights, &sky, &job.options, x as u32, y as u32, job.width, job.height, sample));
+            }
+        });
+        let mut traced = 0;
+        for (index, color) in pass.into_iter().enumerate() {
+            if let Some(color) = color {
+                accumulation.add(index, color);
+                traced += 1;
+            }
+        }
+        accumulation.samples += 1;
+        println!("Sample {}/{} ({} pixels traced)", accumulation.samples, job.samples, traced);
+        if traced == 0 {
+            println!("All pixels converged");
+            break;
+        }
+
+        if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+            match accumulation.save(&job.checkpoint) {
+                Ok(()) => println!("Checkpoint written to {}", job.checkpoint.display()),
+                Err(err) => println!("WARNING: checkpoint failed: {}", err),
+            }
+            last_checkpoint = Instant::now();
+        }
+    }
+
+    if let Err(err) = accumulation.save(&job.checkpoint) {
+        println!("WARNING: checkpoint failed: {}", err);
+    }
+
+    let mut colors: Vec<Vector3> = (0..accumulation.sums.len()).map(|i| accumulation.average(i)).collect();
+    let aovs = (job.denoise || job.depth || job.ids || job.options.transparent_background).then(|| first_hit_aovs(&objects, &materials, &camera, job.width, job.height));
+    if let Some(aovs) = aovs.as_ref().filter(|_| job.denoise) {
+        println!("Denoising...");
+        colors = denoise(&colors, aovs, job.width, job.height);
+    }
+    // With --transparent, pixel centers that see only sky become transparent
+    let cutout: Option<Vec<u32>> = aovs
+        .as_ref()
+        .filter(|_| job.options.transparent_background)
+        .map(|aovs| aovs.iter().map(|aov| aov.object).collect());
+    let emission = bloom_emission(&job.post, &objects, &materials, &accel, &camera, job.width, job.height);
+    write_image(&job.output, job.width, job.height, &colors, emission, &job.post, 0, cutout.as_deref());
+    if let Some(aovs) = aovs.as_ref().filter(|_| job.depth) {
+        let depths: Vec<f32> = aovs.iter().map(|aov| aov.depth).collect();
+        let depth_path = depth_path(&job.output);
+        match write_depth_png(&depth_path, job.width, job.height, &depths) {
+            Ok(far) => println!("Wrote {} (depth 0-{} units)", depth_path.display(), far),
+            Err(err) => println!("WARNING: depth export failed: {}", err),
+        }
+    }
+    if let Some(aovs) = aovs.as_ref().filter(|_| job.ids) {
+        let objects_seen: Vec<u32> = aovs.iter().map(|aov| aov.object).collect();
+        write_id_passes(&job.output, job.width, job.height, &objects_seen, &objects);
+    }
+}
+
+/// One jittered sample for pixel (x, y); the sample sequence depends only on pixel and sample index
+/// so every machine or resumed run traces exactly the same rays
+pub fn trace_sample(
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    camera: &Camera,
+    lights: &[Light],
+    path_lights: &PathLights,
+    sky: &Sky,
+    options: &RenderOptions,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    sample: u32,
+) -> Vector3 {
+    let fov = PI / 3.0;
+    let aspect_ratio = width as f32 / height as f32;
+    // Sobol jitter spreads a pixel's samples evenly instead of clumping them
+    let mut sampler = Sampler::for_pixel(RENDER_SEED, x, y, sample);
+    let (jitter_x, jitter_y) = sampler.next_2d();
+    let direction = primary_ray(camera, x as f32 + jitter_x, y as f32 + jitter_y, width, height, fov);
+    if options.path_traced {
+        trace_path(camera.eye, direction, objects, materials, accel, lights, path_lights, sky, options, &mut sampler)
+    } else {
+        let mut rng = Rng::for_pixel(RENDER_SEED, x, y, sample);
+        cast_ray(&camera.eye, &direction, objects, materials, accel, None, BakedLighting::default(), lights, sky, options, 0, camera, fov, aspect_ratio, &mut rng)
+    }
+}
+
+/// Averages `samples` passes for rows `y_start..y_end` seen from `camera`, used by
+/// distributed workers. Lights are the ones `run` uses, so tiles match a local render with
+/// the same options and sky; rows are traced in parallel.
+#[allow(clippy::too_many_arguments)]
+pub fn render_rows(
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: &Acceleration,
+    camera: &Camera,
+    options: &RenderOptions,
+    sky: &Sky,
+    width: u32,
+    height: u32,
+    y_start: u32,
+    y_end: u32,
+    samples: u32,
+) -> Vec<Vector3> {
+    let mut lights = create_lights();
+    if !options.path_traced {
+        lights.extend(block_lights(objects, materials));
+    }
+    let lights = &lights;
+    let path_lights = &PathLights::new(objects, materials, lights);
+    (y_start..y_end)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            (0..width).map(move |x| {
+                let mut sum = Vector3::zero();
+                for sample in 0..samples {
+                    sum = sum + trace_sample(objects, materials, Some(accel), camera, lights, path_lights, sky, options, x, y, width, height, sample);
+                }
+                sum / samples.max(1) as f32
+            })
+        })
+        .collect()
+}
+
+/// The emission pass bloom needs (see `trace_emission`), `None` when `post` has bloom off
+pub fn bloom_emission(post: &PostProcess, objects: &[SceneObject], materials: &MaterialTable, accel: &Acceleration, camera: &Camera, width: u32, height: u32) -> Option<Vec<Vector3>> {
+    (post.bloom > 0.0).then(|| trace_emission(objects, materials, Some(accel), camera, width, height))
+}
+
+/// Writes a PNG, RGBA with sky cut out when `objects_seen` (per-pixel object index) is given.
+/// `emission` (see `bloom_emission`) is what bloom spreads, without it bloom is skipped.
+#[allow(clippy::too_many_arguments)]
+pub fn write_image(
+    path: &Path,
+    width: u32,
+    height: u32,
+    colors: &[Vector3],
+    emission: Option<Vec<Vector3>>,
+    post: &PostProcess,
+    frame_index: u32,
+    objects_seen: Option<&[u32]>,
+) {
+    let mut framebuffer = Framebuffer::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            framebuffer.set_current_color(tonemap(colors[(y * width + x) as usize], 0.0, x, y));
+            framebuffer.set_pixel(x, y);
+        }
+    }
+    framebuffer.emission_buffer = emission.unwrap_or_default();
+    post.apply(&mut framebuffer, frame_index);
+    if let Some(objects_seen) = objects_seen {
+        framebuffer.id_buffer.copy_from_slice(objects_seen);
+        framebuffer.cut_out_sky();
+    }
+    framebuffer.render_to_file(&path.to_string_lossy());
+    println!("Wrote {}", path.display());
+}
+
+/// Steps the sun across the day with the camera held still, writing `frame_NNNN.png` per step
+pub fn run_timelapse(job: &TimelapseJob) -> io::Result<()> {
+    fs::create_dir_all(&job.directory)?;
+    let args: Vec<String> = std::env::args().collect();
+    let (objects, materials) = load_scene(TextureOptions::from_args(&args));
+    let accel = Acceleration::build(&objects);
+    let camera = create_camera();
+    let base_sky = Sky::from_args(&args);
+
+    for frame in 0..job.frames {
+        let t = if job.frames > 1 { frame as f32 / (job.frames - 1) as f32 } else { 0.0 };
+        let cycle = DayCycle::new(job.from_hour + (job.to_hour - job.from_hour) * t);
+        let mut lights = vec![cycle.light(), create_cave_lamp()];
+        lights.extend(block_lights(&objects, &materials));
+        let sky = Sky { gradient: base_sky.gradient, ..cycle.sky() };
+        let path_lights = PathLights::new(&objects, &materials, &lights);
+
+        let options = RenderOptions::default();
+        let mut colors = vec![Vector3::zero(); (job.width * job.height) as usize];
+        colors.par_chunks_mut(job.width as usize).enumerate().for_each(|(y, row)| {
+            for (x, color) in row.iter_mut().enumerate() {
+                let mut sum = Vector3::zero();
+                for sample in 0..job.samples {
+                    sum = sum + trace_sample(&objects, &materials, Some(&accel), &camera, &lights, &path_lights, &sky, &options, x as u32, y as u32, job.width, job.height, sample);
+                }
+                *color = sum / job.samples as f32;
+            }
+        });
+
+        println!("Frame {}/{} at {:05.2}h", frame + 1, job.frames, cycle.hour);
+        let emission = bloom_emission(&job.post, &objects, &materials, &accel, &camera, job.width, job.height);
+        write_image(&job.directory.join(format!("frame_{:04}.png", frame)), job.width, job.height, &colors, emission, &job.post, frame, None);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A checkpoint path of its own per test, so tests running in parallel don't share files
+    fn checkpoint_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("raytracing_{}_{}.ckpt", name, std::process::id()))
+    }
+
+    fn header(magic: &[u8; 4], version: u32, width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = magic.to_vec();
+        for value in [version, width, height, 1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        CheckpointOptions::new(&RenderOptions::default()).write(&mut bytes);
+        bytes
+    }
+
+    fn load_error(name: &str, bytes: &[u8]) -> String {
+        let path = checkpoint_path(name);
+        fs::write(&path, bytes).unwrap();
+        let error = Accumulation::load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        error.to_string()
+    }
+
+    #[test]
+    fn checkpoints_round_trip() {
+        let options = CheckpointOptions::new(&RenderOptions { path_traced: true, ao_samples: 4, ..RenderOptions::default() });
+        let mut accumulation = Accumulation::new(3, 2, options);
+        accumulation.add(0, Vector3::new(0.25, 0.5, 1.0));
+        accumulation.add(0, Vector3::new(0.75, 0.5, 0.0));
+        accumulation.add(5, Vector3::new(2.0, 0.0, 0.125));
+        accumulation.samples = 2;
+
+        let path = checkpoint_path("round_trip");
+        accumulation.save(&path).unwrap();
+        let loaded = Accumulation::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.width, loaded.height, loaded.samples), (3, 2, 2));
+        assert_eq!(loaded.options, options);
+        assert_eq!(loaded.sums, accumulation.sums);
+        assert_eq!(loaded.luminance_squares, accumulation.luminance_squares);
+        assert_eq!(loaded.counts, [2, 0, 0, 0, 0, 1]);
+        assert_eq!(loaded.average(0), Vector3::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn resumes_only_with_the_same_size_and_options() {
+        let options = RenderOptions::default();
+        let accumulation = Accumulation::new(3, 2, CheckpointOptions::new(&options));
+        assert_eq!(accumulation.mismatch(3, 2, &CheckpointOptions::new(&options)), None);
+        assert_eq!(accumulation.mismatch(4, 2, &CheckpointOptions::new(&options)).unwrap(), "Checkpoint is 3x2, job is 4x2");
+        let changes = [
+            RenderOptions { shadows: !options.shadows, ..options },
+            RenderOptions { path_traced: !options.path_traced, ..options },
+            RenderOptions { ao_samples: options.ao_samples + 8, ..options },
+            RenderOptions { max_reflection_depth: options.max_reflection_depth + 1, ..options },
+        ];
+        for changed in changes {
+            assert_eq!(accumulation.mismatch(3, 2, &CheckpointOptions::new(&changed)).unwrap(), "Checkpoint was traced with other render options");
+        }
+    }
+
+    #[test]
+    fn refuses_foreign_old_and_damaged_checkpoints() {
+        assert_eq!(load_error("magic", &header(b"PNG ", CHECKPOINT_VERSION, 1, 1)), "not a checkpoint file");
+        assert_eq!(load_error("short", b"RTCK"), "not a checkpoint file");
+        assert_eq!(load_error("version", &header(CHECKPOINT_MAGIC, CHECKPOINT_VERSION - 1, 1, 1)), "unsupported checkpoint version");
+        // A header that promises one pixel more than the file holds
+        let mut bytes = header(CHECKPOINT_MAGIC, CHECKPOINT_VERSION, 2, 1);
+        bytes.extend_from_slice(&[0; CHECKPOINT_PIXEL_SIZE]);
+        assert_eq!(load_error("truncated", &bytes), "truncated checkpoint");
+    }
+
+    #[test]
+    fn refuses_header_sizes_before_allocating_them() {
+        assert_eq!(load_error("empty", &header(CHECKPOINT_MAGIC, CHECKPOINT_VERSION, 0, 4)), "bad checkpoint size");
+        assert_eq!(load_error("huge", &header(CHECKPOINT_MAGIC, CHECKPOINT_VERSION, u32::MAX, u32::MAX)), "bad checkpoint size");
+    }
+
+    #[test]
+    fn reads_sizes_and_refuses_bad_ones() {
+        let args = |line: &str| -> Vec<String> { line.split_whitespace().map(String::from).collect() };
+        assert_eq!(size_after(&args("viewer --size 320x240")), (320, 240));
+        assert_eq!(size_after(&args("viewer")), (800, 600));
+        assert_eq!(size_after(&args("viewer --size 0x240")), (800, 600));
+        assert_eq!(size_after(&args("viewer --size 100000x100000")), (800, 600));
+        assert_eq!(pixel_count(65536, 65536), None);
+    }
+}
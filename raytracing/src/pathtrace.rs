@@ -0,0 +1,312 @@
+use crate::acceleration::{Acceleration, candidates};
+use crate::color_ops::luminance;
+use crate::cube::Cube;
+use crate::light::Light;
+use crate::light_sampling::PowerCdf;
+use crate::material::{Material, MaterialTable};
+use crate::ray_intersect::{Intersect, SceneObject};
+use crate::sampler::Sampler;
+use crate::settings::RenderOptions;
+use crate::sky::Sky;
+use crate::{offset_origin, reflect};
+use raylib::prelude::*;
+use std::f32::consts::PI;
+
+// Bounces before a path is cut off, and when Russian roulette starts culling weak paths
+const PATH_MAX_BOUNCES: u32 = 8;
+const RUSSIAN_ROULETTE_START: u32 = 3;
+
+/// What the path tracer samples light from, gathered once per frame so a path doesn't scan
+/// the scene: lights and emissive blocks, each picked by power
+pub struct PathLights {
+    light_cdf: PowerCdf,
+    emitters: Vec<usize>, // Objects the emitter CDF picks from, by slot
+    emitter_cdf: PowerCdf,
+    slots: Vec<Option<u32>>, // Emitter slot of each object, for the MIS weight of BSDF hits
+}
+
+impl PathLights {
+    /// Emissive blocks are picked by emitted power (brightness times surface area). Other
+    /// glowing shapes aren't light-sampled, BSDF rays still find them.
+    pub fn new(objects: &[SceneObject], materials: &MaterialTable, lights: &[Light]) -> Self {
+        let emitters: Vec<usize> = (0..objects.len())
+            .filter(|&index| objects[index].as_cube().is_some_and(|object| object.total_emission(materials) != Vector3::zero()))
+            .collect();
+        let emitter_cdf = PowerCdf::new(emitters.iter().filter_map(|&index| objects[index].as_cube()).map(|object| {
+            luminance(object.total_emission(materials)) * object.size * object.size
+        }));
+        let mut slots = vec![None; objects.len()];
+        for (slot, &index) in emitters.iter().enumerate() {
+            slots[index] = Some(slot as u32);
+        }
+        PathLights { light_cdf: PowerCdf::from_lights(lights), emitters, emitter_cdf, slots }
+    }
+}
+
+/// Unidirectional path tracer with next-event estimation. Light sampling (sky and emissive
+/// blocks) and BSDF sampling are combined with the power heuristic, so both big dim sources
+/// and small bright ones converge without fireflies. Point lights can't be hit by BSDF rays
+/// and are always sampled directly. Lights and emitters are picked by power from
+/// `path_lights`, one of each per bounce, so the cost per hit doesn't grow with the number
+/// of lights.
+pub fn trace_path(
+    origin: Vector3,
+    direction: Vector3,
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    lights: &[Light],
+    path_lights: &PathLights,
+    sky: &Sky,
+    options: &RenderOptions,
+    sampler: &mut Sampler,
+) -> Vector3 {
+    let mut radiance = Vector3::zero();
+    let mut throughput = Vector3::one();
+    let mut ray_origin = origin;
+    let mut ray_direction = direction;
+    // Solid-angle pdf of the last bounce when it was diffuse, None after the camera or a mirror
+    let mut bsdf_pdf: Option<f32> = None;
+
+    for bounce in 0..=PATH_MAX_BOUNCES {
+        let Some((hit, material)) = closest_hit(objects, materials, accel, &ray_origin, &ray_direction) else {
+            let weight = bsdf_pdf.map_or(1.0, |pdf| power_heuristic(pdf, sky_pdf(ray_direction)));
+            radiance = radiance + mul(throughput, sky.sample(ray_direction)) * weight;
+            break;
+        };
+
+        if material.emission != Vector3::zero() {
+            let weight = match (bsdf_pdf, path_lights.slots.get(hit.object).copied().flatten()) {
+                (Some(pdf), Some(slot)) => {
+                    let cos_light = hit.normal.dot(ray_direction).abs().max(1e-4);
+                    let size = objects[hit.object].as_cube().map_or(1.0, |object| object.size);
+                    power_heuristic(pdf, emitter_pdf(size, path_lights.emitter_cdf.probability(slot as usize), hit.distance, cos_light))
+                }
+                _ => 1.0,
+            };
+            radiance = radiance + mul(throughput, material.emission) * weight;
+        }
+
+        // Pick a lobe in proportion to the material's weights
+        let metallic = material.metallic;
+        let diffuse_weight = material.albedo[0] * (1.0 - metallic);
+        let mirror_weight = material.albedo[2] * (1.0 - metallic) + metallic;
+        let transmit_weight = material.albedo[3] * (1.0 - metallic);
+        let total = diffuse_weight + mirror_weight + transmit_weight;
+        if total <= 0.0 {
+            break;
+        }
+        throughput = throughput * total.min(1.0);
+        let choice = sampler.next_f32() * total;
+
+        if choice < diffuse_weight {
+            let base = material.diffuse;
+            let direct = sample_point_light(&hit, lights, &path_lights.light_cdf, objects, materials, accel, options, sampler)
+                + sample_sky(&hit, sky, objects, materials, accel, options, sampler)
+                + sample_emitter(&hit, &path_lights.emitters, &path_lights.emitter_cdf, objects, materials, accel, options, sampler);
+            radiance = radiance + mul(throughput, mul(base, direct));
+
+            let new_direction = cosine_direction(hit.normal, sampler);
+            bsdf_pdf = Some(hit.normal.dot(new_direction).max(1e-4) / PI);
+            throughput = mul(throughput, base);
+            ray_origin = offset_origin(&hit, &new_direction, &options.bias);
+            ray_direction = new_direction;
+        } else if choice < diffuse_weight + mirror_weight {
+            // Metals tint their reflection with the base color
+            let tint = Vector3::one() * (1.0 - metallic) + material.diffuse * metallic;
+            let new_direction = reflect(&ray_direction, &hit.normal).normalized();
+            bsdf_pdf = None;
+            throughput = mul(throughput, tint);
+            ray_origin = offset_origin(&hit, &new_direction, &options.bias);
+            ray_direction = new_direction;
+        } else {
+            // Thin transparent blocks (leaves) let the path continue unbent
+            bsdf_pdf = None;
+            ray_origin = offset_origin(&hit, &ray_direction, &options.bias);
+        }
+
+        if bounce >= RUSSIAN_ROULETTE_START {
+            let survival = throughput.x.max(throughput.y).max(throughput.z).clamp(0.05, 0.95);
+            if sampler.next_f32() > survival {
+                break;
+            }
+            throughput = throughput / survival;
+        }
+    }
+
+    radiance
+}
+
+// Nearest hit along a ray and the shaded material there
+pub(crate) fn closest_hit(objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, origin: &Vector3, direction: &Vector3) -> Option<(Intersect, Material)> {
+    let mut closest: Option<Intersect> = None;
+    candidates(accel, objects.len(), *origin, *direction, f32::INFINITY, |index| {
+        let mut intersect = objects[index].ray_intersect(origin, direction);
+        let closer = closest.as_ref().is_none_or(|best| intersect.distance < best.distance);
+        if intersect.is_intersecting && closer && !objects[index].is_cut_out(&intersect, materials) {
+            intersect.object = index;
+            closest = Some(intersect);
+        }
+        Some(closest.as_ref().map_or(f32::INFINITY, |best| best.distance))
+    });
+    let mut hit = closest?;
+    let material = objects[hit.object].surface(&hit, direction, materials);
+    hit.shadow_bias = material.shadow_bias;
+    Some((hit, material))
+}
+
+fn occluded(objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, origin: &Vector3, direction: &Vector3, max_distance: f32) -> bool {
+    let mut blocked = false;
+    candidates(accel, objects.len(), *origin, *direction, max_distance, |index| {
+        blocked = objects[index].solid_hit_distance(origin, direction, materials).is_some_and(|distance| distance < max_distance);
+        (!blocked).then_some(max_distance)
+    });
+    blocked
+}
+
+// One point light picked by power, same convention as the Whitted renderer:
+// intensity · falloff · cos, tinted by the light color, divided by the pick probability
+fn sample_point_light(
+    hit: &Intersect,
+    lights: &[Light],
+    light_cdf: &PowerCdf,
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    options: &RenderOptions,
+    sampler: &mut Sampler,
+) -> Vector3 {
+    let Some((index, probability)) = light_cdf.sample(sampler.next_f32()) else {
+        return Vector3::zero();
+    };
+    let light = &lights[index];
+    let (s, t) = sampler.next_2d();
+    let (direction, distance) = light.towards_sample(hit.point, s, t);
+    let cos = hit.normal.dot(direction);
+    if cos <= 0.0 {
+        return Vector3::zero();
+    }
+    let spot = light.spot.map_or(1.0, |spot| spot.factor(-direction));
+    let strength = light.intensity * light.attenuation(distance) * spot * cos;
+    if strength <= 0.0 {
+        return Vector3::zero();
+    }
+    let origin = offset_origin(hit, &direction, &options.bias);
+    if options.shadows && occluded(objects, materials, accel, &origin, &direction, distance - options.bias.occluder_epsilon) {
+        return Vector3::zero();
+    }
+    color_vector(light.color) * (strength / probability)
+}
+
+// Uniform over the upper hemisphere, where the bright part of the sky is
+fn sky_pdf(direction: Vector3) -> f32 {
+    if direction.y > 0.0 { 1.0 / (2.0 * PI) } else { 0.0 }
+}
+
+// One MIS-weighted sky sample; the returned value still needs the surface's base color
+fn sample_sky(hit: &Intersect, sky: &Sky, objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, options: &RenderOptions, sampler: &mut Sampler) -> Vector3 {
+    let (y, u) = sampler.next_2d();
+    let r = (1.0 - y * y).max(0.0).sqrt();
+    let phi = 2.0 * PI * u;
+    let direction = Vector3::new(r * phi.cos(), y, r * phi.sin());
+
+    let cos = hit.normal.dot(direction);
+    if cos <= 0.0 {
+        return Vector3::zero();
+    }
+    let origin = offset_origin(hit, &direction, &options.bias);
+    if occluded(objects, materials, accel, &origin, &direction, f32::INFINITY) {
+        return Vector3::zero();
+    }
+    let light_pdf = sky_pdf(direction);
+    let weight = power_heuristic(light_pdf, cos / PI);
+    // f = 1/π (base applied by the caller), estimate = f · L · cos / pdf
+    sky.sample(direction) * (cos / PI / light_pdf * weight)
+}
+
+// Solid-angle pdf of picking a point on an emissive cube: emitter by power, then uniform face and point
+fn emitter_pdf(size: f32, probability: f32, distance: f32, cos_light: f32) -> f32 {
+    let area_pdf = probability / (6.0 * size * size);
+    area_pdf * distance * distance / cos_light
+}
+
+// One MIS-weighted sample of a random point on a random emissive block
+fn sample_emitter(
+    hit: &Intersect,
+    emitters: &[usize],
+    emitter_cdf: &PowerCdf,
+    objects: &[SceneObject],
+    materials: &MaterialTable,
+    accel: Option<&Acceleration>,
+    options: &RenderOptions,
+    sampler: &mut Sampler,
+) -> Vector3 {
+    let (pick_emitter, pick_face) = sampler.next_2d();
+    let (u, v) = sampler.next_2d();
+    let Some((slot, probability)) = emitter_cdf.sample(pick_emitter) else {
+        return Vector3::zero();
+    };
+    let emitter = emitters[slot];
+    let face = ((pick_face * 6.0) as usize).min(5);
+    let face_normal = match face {
+        0 => Vector3::new(1.0, 0.0, 0.0),
+        1 => Vector3::new(-1.0, 0.0, 0.0),
+        2 => Vector3::new(0.0, 1.0, 0.0),
+        3 => Vector3::new(0.0, -1.0, 0.0),
+        4 => Vector3::new(0.0, 0.0, 1.0),
+        _ => Vector3::new(0.0, 0.0, -1.0),
+    };
+    let (tangent, bitangent) = Cube::face_axes(face_normal);
+    let Some(object) = objects[emitter].as_cube() else {
+        return Vector3::zero();
+    };
+    let size = object.size;
+    let point = object.center
+        + face_normal * (size * 0.5)
+        + tangent * ((u - 0.5) * size)
+        + bitangent * ((v - 0.5) * size);
+
+    let to_point = point - hit.point;
+    let distance = to_point.length();
+    let direction = to_point / distance;
+    let cos_surface = hit.normal.dot(direction);
+    let cos_light = -face_normal.dot(direction);
+    if cos_surface <= 0.0 || cos_light <= 1e-4 {
+        return Vector3::zero();
+    }
+
+    // The visibility ray also fetches the emission at that texel (emission maps)
+    let origin = offset_origin(hit, &direction, &options.bias);
+    let Some((light_hit, light_material)) = closest_hit(objects, materials, accel, &origin, &direction) else {
+        return Vector3::zero();
+    };
+    if light_hit.object != emitter || (light_hit.distance - distance).abs() > 1e-2 {
+        return Vector3::zero();
+    }
+
+    let light_pdf = emitter_pdf(size, probability, distance, cos_light);
+    let weight = power_heuristic(light_pdf, cos_surface / PI);
+    light_material.emission * (cos_surface / PI / light_pdf * weight)
+}
+
+fn cosine_direction(normal: Vector3, sampler: &mut Sampler) -> Vector3 {
+    let (tangent, bitangent) = Cube::face_axes(normal);
+    let (u, v) = sampler.next_2d();
+    let r = u.sqrt();
+    let phi = 2.0 * PI * v;
+    (tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * (1.0 - r * r).max(0.0).sqrt()).normalized()
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
+}
+
+fn mul(a: Vector3, b: Vector3) -> Vector3 {
+    Vector3::new(a.x * b.x, a.y * b.y, a.z * b.z)
+}
+
+fn color_vector(color: Color) -> Vector3 {
+    Vector3::new(color.r as f32 / 255.0, color.g as f32 / 255.0, color.b as f32 / 255.0)
+}
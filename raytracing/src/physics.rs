@@ -0,0 +1,202 @@
+use crate::cube::Cube;
+use crate::ray_intersect::SceneObject;
+use std::collections::HashMap;
+
+// Units per second², and the speed falling blocks top out at
+const GRAVITY: f32 = 20.0;
+const TERMINAL_VELOCITY: f32 = 30.0;
+
+// Fixed physics tick, and how much time one update may catch up on after a slow frame
+const TICK: f32 = 1.0 / 60.0;
+const MAX_CATCH_UP: f32 = 0.25;
+
+// How close a block has to sit on another to count as supported
+const CONTACT_EPSILON: f32 = 1e-3;
+
+/// Minecraft-style falling blocks: blocks made of a gravity material (sand, gravel) with
+/// nothing under them fall, speeding up, until they land on the block below or the bottom
+/// of the scene. Support is checked per column of blocks stacked directly on each other;
+/// shapes other than blocks neither fall nor hold anything up.
+#[derive(Default)]
+pub struct FallingBlocks {
+    velocities: HashMap<usize, f32>, // Blocks in the air, by object index
+    last_time: Option<f32>,
+    pending: f32,
+}
+
+impl FallingBlocks {
+    /// Advances the simulation to `time` (simulation seconds) in fixed ticks, returns the
+    /// indices of the blocks that moved
+    pub fn update(&mut self, objects: &mut [SceneObject], gravity_materials: &[u32], time: f32) -> Vec<usize> {
+        let elapsed = self.last_time.map_or(0.0, |last| (time - last).max(0.0));
+        self.last_time = Some(time);
+        self.pending = (self.pending + elapsed).min(MAX_CATCH_UP);
+        let mut moved = Vec::new();
+        while self.pending >= TICK {
+            self.pending -= TICK;
+            self.tick(objects, gravity_materials, &mut moved);
+        }
+        moved.sort_unstable();
+        moved.dedup();
+        moved
+    }
+
+    /// Runs the simulation from `from` to `to` at once, without the per-frame catch-up limit,
+    /// so a replay starts with the blocks where its recording found them
+    pub fn fast_forward(&mut self, objects: &mut [SceneObject], gravity_materials: &[u32], from: f32, to: f32) -> Vec<usize> {
+        let mut moved = Vec::new();
+        for _ in 0..((to - from).max(0.0) / TICK) as u32 {
+            self.tick(objects, gravity_materials, &mut moved);
+        }
+        self.last_time = Some(to);
+        self.pending = 0.0;
+        moved.sort_unstable();
+        moved.dedup();
+        moved
+    }
+
+    /// Whether any block is still falling
+    pub fn is_active(&self) -> bool {
+        !self.velocities.is_empty()
+    }
+
+    /// Follows `objects.swap_remove(index)` of a removed block: the last block, `last`,
+    /// keeps falling at the index it took
+    pub fn removed(&mut self, index: usize, last: usize) {
+        self.velocities.remove(&index);
+        if let Some(velocity) = self.velocities.remove(&last) {
+            self.velocities.insert(index, velocity);
+        }
+    }
+
+    fn tick(&mut self, objects: &mut [SceneObject], gravity_materials: &[u32], moved: &mut Vec<usize>) {
+        if gravity_materials.is_empty() && self.velocities.is_empty() {
+            return;
+        }
+
+        // Tops of the blocks that aren't falling, per column, and the bottom of the scene
+        let mut tops: HashMap<(i64, i64), Vec<f32>> = HashMap::new();
+        let mut floor = f32::INFINITY;
+        let blocks = || objects.iter().enumerate().filter_map(|(index, object)| Some((index, object.as_cube()?)));
+        for (index, object) in blocks() {
+            floor = floor.min(bottom(object));
+            if !self.velocities.contains_key(&index) {
+                tops.entry(column(object)).or_default().push(bottom(object) + object.size);
+            }
+        }
+        // Highest resting surface at or below a height in a column
+        let surface = |object: &Cube, height: f32| {
+            tops.get(&column(object))
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|&top| top <= height + CONTACT_EPSILON)
+                .fold(floor, f32::max)
+        };
+
+        for (index, object) in blocks() {
+            let unsupported = surface(object, bottom(object)) < bottom(object) - CONTACT_EPSILON;
+            if unsupported && gravity_materials.contains(&object.material_id) {
+                self.velocities.entry(index).or_insert(0.0);
+            }
+        }
+
+        self.velocities.retain(|&index, velocity| {
+            let Some(object) = objects.get_mut(index).and_then(|object| object.as_cube_mut()) else {
+                return false;
+            };
+            *velocity = (*velocity + GRAVITY * TICK).min(TERMINAL_VELOCITY);
+            let ground = surface(object, bottom(object));
+            let next_bottom = (bottom(object) - *velocity * TICK).max(ground);
+            if next_bottom != bottom(object) {
+                object.center.y = next_bottom + object.size * 0.5;
+                moved.push(index);
+            }
+            next_bottom > ground
+        });
+    }
+}
+
+fn bottom(object: &Cube) -> f32 {
+    object.center.y - object.size * 0.5
+}
+
+// Blocks sit on a grid of whole or half units, doubling makes either exact
+fn column(object: &Cube) -> (i64, i64) {
+    ((object.center.x * 2.0).round() as i64, (object.center.z * 2.0).round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_intersect::scene_objects;
+    use raylib::prelude::Vector3;
+
+    const SAND: u32 = 7;
+
+    // A unit block centered at (x, y, 0), of sand or of something that doesn't fall
+    fn block(x: f32, y: f32, sand: bool) -> Cube {
+        let mut block = Cube::new(Vector3::new(x, y, 0.0), 1.0, 0);
+        block.material_id = if sand { SAND } else { 1 };
+        block
+    }
+
+    fn height(objects: &[SceneObject], index: usize) -> f32 {
+        objects[index].center().y
+    }
+
+    #[test]
+    fn blocks_resting_on_others_stay_put() {
+        let mut objects = scene_objects(vec![block(0.0, 0.0, false), block(0.0, 1.0, true), block(0.0, 2.0, true)]);
+        let mut falling = FallingBlocks::default();
+        assert!(falling.fast_forward(&mut objects, &[SAND], 0.0, 1.0).is_empty());
+        assert!(!falling.is_active());
+    }
+
+    #[test]
+    fn unsupported_blocks_fall_and_land_on_the_block_below() {
+        // Stone at the bottom of the column, two sand blocks stacked well above it
+        let mut objects = scene_objects(vec![block(0.0, 0.0, false), block(0.0, 4.0, true), block(0.0, 5.0, true)]);
+        let mut falling = FallingBlocks::default();
+        assert_eq!(falling.fast_forward(&mut objects, &[SAND], 0.0, 0.1), [1, 2]);
+        assert!(falling.is_active());
+
+        falling.fast_forward(&mut objects, &[SAND], 0.1, 2.0);
+        assert!(!falling.is_active());
+        assert_eq!((height(&objects, 1), height(&objects, 2)), (1.0, 2.0));
+        // Only gravity materials fall: the stone held still
+        assert_eq!(height(&objects, 0), 0.0);
+    }
+
+    #[test]
+    fn a_block_falls_once_the_one_under_it_is_removed() {
+        let mut objects = scene_objects(vec![block(0.0, 0.0, false), block(0.0, 1.0, false), block(0.0, 2.0, true)]);
+        let mut falling = FallingBlocks::default();
+        objects.swap_remove(1);
+        falling.removed(1, 2);
+        assert_eq!(falling.fast_forward(&mut objects, &[SAND], 0.0, 2.0), [1]);
+        assert_eq!(height(&objects, 1), 1.0);
+    }
+
+    #[test]
+    fn falling_blocks_follow_swap_remove() {
+        // Two columns, sand high above stone in both
+        let mut objects = scene_objects(vec![
+            block(0.0, 0.0, false),
+            block(0.0, 4.0, true),
+            block(3.0, 0.0, false),
+            block(3.0, 6.0, true),
+        ]);
+        let mut falling = FallingBlocks::default();
+        falling.fast_forward(&mut objects, &[SAND], 0.0, 0.1);
+
+        // The first sand block taken out mid-fall, the other one taking its index
+        objects.swap_remove(1);
+        falling.removed(1, 3);
+        assert!(falling.is_active());
+        let moved = falling.fast_forward(&mut objects, &[SAND], 0.1, 2.0);
+        assert_eq!(moved, [1]);
+        assert!(!falling.is_active());
+        assert_eq!(objects[1].center(), Vector3::new(3.0, 1.0, 0.0));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::ray_intersect::SceneObject;
+use raylib::prelude::Vector3;
+
+/// Which side of an enclosure's shell an object or a ray origin is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cell {
+    Interior,
+    Exterior,
+    Shell, // The walls themselves, or too close to them to say; never culled
+}
+
+/// Axis-aligned rectangular opening in the shell, flat along one axis (`min` equals `max` there)
+#[derive(Debug, Clone, Copy)]
+pub struct Portal {
+    pub min: Vector3,
+    pub max: Vector3,
+}
+
+impl Portal {
+    pub fn new(min: Vector3, max: Vector3) -> Self {
+        Portal { min, max }
+    }
+
+    /// The rectangle between two corners that share one coordinate, None if they share none
+    pub fn between(corner: Vector3, opposite: Vector3) -> Option<Self> {
+        let flat = corner.x == opposite.x || corner.y == opposite.y || corner.z == opposite.z;
+        flat.then(|| Portal::new(min_corner(corner, opposite), max_corner(corner, opposite)))
+    }
+
+    // Whether the ray crosses the opening ahead of its origin
+    fn crossed_by(&self, origin: Vector3, direction: Vector3) -> bool {
+        let axis = |v: Vector3| [v.x, v.y, v.z];
+        let (min, max, origin, direction) = (axis(self.min), axis(self.max), axis(origin), axis(direction));
+        let Some(flat) = (0..3).find(|&i| min[i] == max[i]) else {
+            return true;
+        };
+        if direction[flat].abs() < 1e-6 {
+            return false;
+        }
+        let t = (min[flat] - origin[flat]) / direction[flat];
+        t > 0.0
+            && (0..3)
+                .filter(|&i| i != flat)
+                .all(|i| (min[i]..=max[i]).contains(&(origin[i] + direction[i] * t)))
+    }
+}
+
+/// Portal culling for a closed room open only through a few rectangular portals (the cave's
+/// front and the hole in its roof). A ray starting outside can only reach objects inside
+/// through a portal and the other way round, so rays that cross no portal skip the other
+/// side's objects entirely.
+#[derive(Debug, Clone)]
+pub struct Portals {
+    interior: (Vector3, Vector3), // Inside faces of the shell
+    outer: (Vector3, Vector3),    // Outside faces of the shell
+    openings: Vec<Portal>,
+    cells: Vec<Cell>, // Per object, see `classify`
+}
+
+impl Portals {
+    pub fn new(interior: (Vector3, Vector3), outer: (Vector3, Vector3), openings: Vec<Portal>) -> Self {
+        Portals { interior, outer, openings, cells: Vec::new() }
+    }
+
+    /// A closed room: the box between two corners of its inside, walled all round `wall`
+    /// thick. Openings are added with `open`.
+    pub fn room(corner: Vector3, opposite: Vector3, wall: f32) -> Self {
+        let (min, max) = (min_corner(corner, opposite), max_corner(corner, opposite));
+        let wall = Vector3::new(wall, wall, wall);
+        Portals::new((min, max), (min - wall, max + wall), Vec::new())
+    }
+
+    pub fn open(&mut self, portal: Portal) {
+        self.openings.push(portal);
+    }
+
+    /// Sorts objects into cells by where they were built; objects that move have to stay
+    /// on their side (the cave elevator does)
+    pub fn classify(&mut self, objects: &[SceneObject]) {
+        self.cells = objects.iter().map(|object| self.cell_of(object.center())).collect();
+    }
+
+    /// Classifies a block placed at the end of the objects, where it was placed
+    pub fn placed(&mut self, object: &SceneObject) {
+        self.cells.push(self.cell_of(object.center()));
+    }
+
+    /// Follows `objects.swap_remove(index)`
+    pub fn removed(&mut self, index: usize) {
+        if index < self.cells.len() {
+            self.cells.swap_remove(index);
+        }
+    }
+
+    fn cell_of(&self, point: Vector3) -> Cell {
+        if strictly_inside(point, self.interior) {
+            Cell::Interior
+        } else if strictly_inside(point, self.outer) {
+            Cell::Shell
+        } else {
+            Cell::Exterior
+        }
+    }
+
+    /// The cell whose objects a ray can't reach: the other side of the shell, unless the
+    /// ray passes through a portal
+    pub fn culled_cell(&self, origin: Vector3, direction: Vector3) -> Option<Cell> {
+        let culled = match self.cell_of(origin) {
+            Cell::Interior => Cell::Exterior,
+            Cell::Exterior => Cell::Interior,
+            Cell::Shell => return None,
+        };
+        let through_portal = self.openings.iter().any(|portal| portal.crossed_by(origin, direction));
+        (!through_portal).then_some(culled)
+    }
+
+    /// Whether object `index` is in the cell `culled_cell` returned for a ray
+    pub fn is_culled(&self, culled: Option<Cell>, index: usize) -> bool {
+        culled.is_some_and(|cell| self.cells.get(index) == Some(&cell))
+    }
+}
+
+fn strictly_inside(point: Vector3, (min, max): (Vector3, Vector3)) -> bool {
+    point.x > min.x && point.x < max.x && point.y > min.y && point.y < max.y && point.z > min.z && point.z < max.z
+}
+
+fn min_corner(a: Vector3, b: Vector3) -> Vector3 {
+    Vector3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn max_corner(a: Vector3, b: Vector3) -> Vector3 {
+    Vector3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
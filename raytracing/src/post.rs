@@ -0,0 +1,226 @@
+use crate::color_ops::ColorOps;
+use crate::framebuffer::Framebuffer;
+use crate::lut::Lut3d;
+use crate::offline::value_after;
+use crate::rng::mix64;
+use raylib::prelude::{Color, Vector3};
+use std::sync::Arc;
+
+/// Strength steps the post-effect hotkeys (vignette, grain, sharpening, bloom and chromatic
+/// aberration) cycle through, wrapping from the last back to off
+pub const EFFECT_STRENGTHS: [f32; 4] = [0.0, 0.25, 0.5, 1.0];
+
+// Red/blue separation in the corners at strength 1, as a fraction of the half diagonal
+const ABERRATION_SHIFT: f32 = 0.012;
+
+// Bloom blur radius as a fraction of the longer image side
+const BLOOM_RADIUS_FRACTION: usize = 80;
+
+// Grain amplitude at strength 1, in 8-bit levels either side of the pixel value
+const GRAIN_LEVELS: f32 = 40.0;
+
+/// Image-space effects run on a finished frame, after tone mapping and before overlays
+#[derive(Clone, Default)]
+pub struct PostProcess {
+    pub bloom: f32,              // Glow spreading from emissive surfaces, 0 = off; reads `Framebuffer::emission_buffer`
+    pub sharpen: f32,            // Contrast-adaptive sharpening, 0 = off; undoes the blur of upscaled frames
+    pub aberration: f32,         // Chromatic aberration, 0 = off; grows towards the edges like a real lens
+    pub vignette: f32,           // Corner darkening, 0 = off, 1 = black corners
+    pub grain: f32,              // Film grain, 0 = off; re-rolled every frame
+    pub lut: Option<Arc<Lut3d>>, // Color grade, always the last step
+}
+
+impl PostProcess {
+    /// Reads `--bloom <0-1>`, `--sharpen <0-1>`, `--aberration <0-1>`, `--vignette <0-1>`, `--grain <0-1>` and
+    /// `--lut <file.cube>`; a LUT that fails to load is reported and skipped
+    pub fn from_args(args: &[String]) -> Self {
+        let strength = |flag: &str| {
+            value_after(args, flag)
+                .and_then(|value| value.parse::<f32>().ok())
+                .map_or(0.0, |value| value.clamp(0.0, 1.0))
+        };
+        let lut = value_after(args, "--lut").and_then(|path| match Lut3d::load(path) {
+            Ok(lut) => {
+                println!("Loaded LUT {}", lut.title.as_deref().unwrap_or(path));
+                Some(Arc::new(lut))
+            }
+            Err(err) => {
+                println!("ERROR: {} - rendering without a LUT", err);
+                None
+            }
+        });
+        PostProcess {
+            bloom: strength("--bloom"),
+            sharpen: strength("--sharpen"),
+            aberration: strength("--aberration"),
+            vignette: strength("--vignette"),
+            grain: strength("--grain"),
+            lut,
+        }
+    }
+
+    /// Runs every enabled effect; `frame_index` animates the grain
+    pub fn apply(&self, framebuffer: &mut Framebuffer, frame_index: u32) {
+        if self.bloom > 0.0 && framebuffer.emission_buffer.len() == framebuffer.color_buffer.len() {
+            bloom(framebuffer, self.bloom);
+        }
+        if self.sharpen > 0.0 {
+            sharpen(framebuffer, self.sharpen);
+        }
+        if self.aberration > 0.0 {
+            chromatic_aberration(framebuffer, self.aberration);
+        }
+        if self.vignette > 0.0 {
+            vignette(framebuffer, self.vignette);
+        }
+        if self.grain > 0.0 {
+            grain(framebuffer, self.grain, frame_index);
+        }
+        if let Some(lut) = &self.lut {
+            for pixel in framebuffer.color_buffer.iter_mut() {
+                *pixel = lut.apply(*pixel);
+            }
+        }
+    }
+}
+
+// Blurs the emission seen through each pixel and adds it on top, so lava and glowstone bleed
+// light over their edges; two box blurs in a row come close to a gaussian
+fn bloom(framebuffer: &mut Framebuffer, strength: f32) {
+    let (width, height) = (framebuffer.width as usize, framebuffer.height as usize);
+    let radius = (width.max(height) / BLOOM_RADIUS_FRACTION).max(1);
+    let mut glow = framebuffer.emission_buffer.clone();
+    for _ in 0..2 {
+        glow = box_blur(&glow, width, height, radius, 1, width);
+        glow = box_blur(&glow, width, height, radius, width, 1);
+    }
+    for (pixel, glow) in framebuffer.color_buffer.iter_mut().zip(glow) {
+        let add = |channel: u8, value: f32| (channel as f32 + value * strength * 255.0).min(255.0) as u8;
+        pixel.r = add(pixel.r, glow.x);
+        pixel.g = add(pixel.g, glow.y);
+        pixel.b = add(pixel.b, glow.z);
+    }
+}
+
+// Running-sum box blur along one axis: `step` between neighbours, `line_step` between lines
+fn box_blur(source: &[Vector3], width: usize, height: usize, radius: usize, step: usize, line_step: usize) -> Vec<Vector3> {
+    let (length, lines) = if step == 1 { (width, height) } else { (height, width) };
+    let mut blurred = vec![Vector3::zero(); source.len()];
+    let window = (2 * radius + 1) as f32;
+    for line in 0..lines {
+        let at = |i: isize| source[line * line_step + i.clamp(0, length as isize - 1) as usize * step];
+        let mut sum = (-(radius as isize)..=radius as isize).fold(Vector3::zero(), |sum, i| sum + at(i));
+        for i in 0..length {
+            blurred[line * line_step + i * step] = sum / window;
+            sum = sum + at(i as isize + radius as isize + 1) - at(i as isize - radius as isize);
+        }
+    }
+    blurred
+}
+
+// Contrast-adaptive sharpening (after AMD's CAS): each pixel is pushed away from its four
+// neighbours, with less push where the neighbourhood already has strong contrast so edges don't ring
+fn sharpen(framebuffer: &mut Framebuffer, strength: f32) {
+    let (width, height) = (framebuffer.width as i32, framebuffer.height as i32);
+    let source = framebuffer.color_buffer.clone();
+    let at = |x: i32, y: i32| {
+        let color = source[(y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as usize];
+        [color.r as f32 / 255.0, color.g as f32 / 255.0, color.b as f32 / 255.0]
+    };
+    // Strongest negative lobe is -1/5, weakest -1/8
+    let peak = -1.0 / (8.0 - 3.0 * strength);
+    for y in 0..height {
+        for x in 0..width {
+            let (up, left, center, right, down) = (at(x, y - 1), at(x - 1, y), at(x, y), at(x + 1, y), at(x, y + 1));
+            let sharpened: [f32; 3] = std::array::from_fn(|c| {
+                let min = up[c].min(left[c]).min(center[c]).min(right[c]).min(down[c]);
+                let max = up[c].max(left[c]).max(center[c]).max(right[c]).max(down[c]);
+                if max <= 0.0 {
+                    return center[c];
+                }
+                let amount = (min.min(1.0 - max) / max).clamp(0.0, 1.0).sqrt();
+                let weight = amount * peak;
+                ((center[c] + (up[c] + left[c] + right[c] + down[c]) * weight) / (1.0 + 4.0 * weight)).clamp(0.0, 1.0)
+            });
+            let pixel = &mut framebuffer.color_buffer[(y * width + x) as usize];
+            pixel.r = (sharpened[0] * 255.0).round() as u8;
+            pixel.g = (sharpened[1] * 255.0).round() as u8;
+            pixel.b = (sharpened[2] * 255.0).round() as u8;
+        }
+    }
+}
+
+// Samples red slightly outward and blue slightly inward from the center, green stays put
+fn chromatic_aberration(framebuffer: &mut Framebuffer, strength: f32) {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let source = framebuffer.color_buffer.clone();
+    let (center_x, center_y) = (width as f32 * 0.5, height as f32 * 0.5);
+    let shift = strength * ABERRATION_SHIFT;
+    for y in 0..height {
+        for x in 0..width {
+            let dx = x as f32 + 0.5 - center_x;
+            let dy = y as f32 + 0.5 - center_y;
+            let red = sample_bilinear(&source, width, height, center_x + dx * (1.0 + shift), center_y + dy * (1.0 + shift));
+            let blue = sample_bilinear(&source, width, height, center_x + dx * (1.0 - shift), center_y + dy * (1.0 - shift));
+            let pixel = &mut framebuffer.color_buffer[(y * width + x) as usize];
+            pixel.r = red[0].round() as u8;
+            pixel.b = blue[2].round() as u8;
+        }
+    }
+}
+
+// Bilinear RGB lookup at a pixel-space position (pixel centers at +0.5), clamped to the edges
+fn sample_bilinear(pixels: &[Color], width: u32, height: u32, x: f32, y: f32) -> [f32; 3] {
+    let x = (x - 0.5).clamp(0.0, (width - 1) as f32);
+    let y = (y - 0.5).clamp(0.0, (height - 1) as f32);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let at = |x: u32, y: u32| {
+        let color = pixels[(y * width + x) as usize];
+        [color.r as f32, color.g as f32, color.b as f32]
+    };
+    let (c00, c10, c01, c11) = (at(x0, y0), at(x1, y0), at(x0, y1), at(x1, y1));
+    std::array::from_fn(|channel| {
+        let top = c00[channel] + (c10[channel] - c00[channel]) * fx;
+        let bottom = c01[channel] + (c11[channel] - c01[channel]) * fx;
+        top + (bottom - top) * fy
+    })
+}
+
+// Darkens towards the corners with a smooth falloff, round regardless of aspect ratio
+fn vignette(framebuffer: &mut Framebuffer, strength: f32) {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let half_diagonal = (width as f32).hypot(height as f32) * 0.5;
+    for y in 0..height {
+        for x in 0..width {
+            let dx = x as f32 + 0.5 - width as f32 * 0.5;
+            let dy = y as f32 + 0.5 - height as f32 * 0.5;
+            let r = (dx * dx + dy * dy).sqrt() / half_diagonal;
+            let falloff = r * r * (3.0 - 2.0 * r); // Smoothstep, 0 at the center, 1 in the corners
+            let factor = 1.0 - strength * falloff;
+            let pixel = &mut framebuffer.color_buffer[(y * width + x) as usize];
+            *pixel = scale(*pixel, factor);
+        }
+    }
+}
+
+// Monochrome noise, strongest in the midtones like film, so blacks and highlights stay clean
+fn grain(framebuffer: &mut Framebuffer, strength: f32, frame_index: u32) {
+    let width = framebuffer.width;
+    for (index, pixel) in framebuffer.color_buffer.iter_mut().enumerate() {
+        let (x, y) = (index as u32 % width, index as u32 / width);
+        let hash = mix64(((frame_index as u64) << 40) ^ ((y as u64) << 20) ^ x as u64);
+        let noise = (hash >> 40) as f32 / (1u64 << 24) as f32 - 0.5;
+        let luma = pixel.luminance();
+        let midtones = 4.0 * luma * (1.0 - luma);
+        let offset = noise * 2.0 * strength * GRAIN_LEVELS * midtones;
+        let channel = |value: u8| (value as f32 + offset).clamp(0.0, 255.0).round() as u8;
+        *pixel = Color::new(channel(pixel.r), channel(pixel.g), channel(pixel.b), pixel.a);
+    }
+}
+
+fn scale(color: Color, factor: f32) -> Color {
+    let channel = |value: u8| (value as f32 * factor).clamp(0.0, 255.0) as u8;
+    Color::new(channel(color.r), channel(color.g), channel(color.b), color.a)
+}
@@ -0,0 +1,227 @@
+use crate::acceleration::Acceleration;
+use crate::light::Light;
+use crate::material::MaterialTable;
+use crate::pathtrace::closest_hit;
+use crate::ray_intersect::{SceneObject, scene_bounds};
+use crate::reflection_probe::ReflectionProbe;
+use crate::rng::Rng;
+use crate::settings::RenderOptions;
+use crate::sky::Sky;
+use crate::{ENVIRONMENT_LIGHT, shade_light};
+use raylib::prelude::Vector3;
+use std::f32::consts::PI;
+use std::time::Instant;
+
+// Distance between probes, widened until the grid fits in MAX_PROBES
+const PROBE_SPACING: f32 = 1.5;
+const MAX_PROBES: usize = 768;
+// Rays per probe, spread evenly over the sphere
+const PROBE_RAYS: u32 = 48;
+// Strength of the bounce light next to the direct light and the sky ambient
+const PROBE_STRENGTH: f32 = 0.8;
+// Changed lights rebake the grid at most this often, so animated lights don't rebake every frame
+const REBAKE_SECONDS: f32 = 1.0;
+// The reflection capture follows the camera once it is this far from where it was taken
+const RECAPTURE_DISTANCE: f32 = 3.0;
+
+// Light arriving at a probe from +X, -X, +Y, -Y, +Z and -Z (an "ambient cube")
+type AmbientCube = [Vector3; 6];
+
+/// Irradiance probes on a regular grid over the scene, each holding the light that bounced
+/// once off the blocks around it. Probes inside solid blocks are left out.
+pub struct ProbeGrid {
+    origin: Vector3,
+    spacing: f32,
+    counts: [usize; 3],
+    probes: Vec<Option<AmbientCube>>,
+}
+
+impl ProbeGrid {
+    /// Traces `PROBE_RAYS` rays from every probe and keeps what the surfaces they hit
+    /// reflect: their diffuse color lit by the lights (with shadows) and the sky, plus glow
+    pub fn bake(objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, lights: &[Light], sky: &Sky, options: &RenderOptions) -> Option<Self> {
+        let (min, max) = scene_bounds(objects)?;
+        let extent = max - min;
+
+        let mut spacing = PROBE_SPACING;
+        let counts = loop {
+            let counts = [extent.x, extent.y, extent.z].map(|side| (side / spacing).ceil() as usize + 1);
+            if counts.iter().product::<usize>() <= MAX_PROBES {
+                break counts;
+            }
+            spacing *= 1.25;
+        };
+
+        let directions: Vec<Vector3> = (0..PROBE_RAYS).map(fibonacci_direction).collect();
+        let mut probes = Vec::with_capacity(counts.iter().product());
+        for z in 0..counts[2] {
+            for y in 0..counts[1] {
+                for x in 0..counts[0] {
+                    let position = min + Vector3::new(x as f32, y as f32, z as f32) * spacing;
+                    if inside_solid(objects, materials, position) {
+                        probes.push(None);
+                        continue;
+                    }
+                    let mut cube = [Vector3::zero(); 6];
+                    let mut weights = [0.0f32; 6];
+                    for &direction in &directions {
+                        let radiance = bounced_light(objects, materials, accel, lights, sky, options, position, direction);
+                        for (axis, component) in [direction.x, direction.y, direction.z].into_iter().enumerate() {
+                            let face = axis * 2 + usize::from(component < 0.0);
+                            cube[face] = cube[face] + radiance * (component * component);
+                            weights[face] += component * component;
+                        }
+                    }
+                    for (side, weight) in cube.iter_mut().zip(weights) {
+                        *side = *side / weight.max(1e-6);
+                    }
+                    probes.push(Some(cube));
+                }
+            }
+        }
+        Some(ProbeGrid { origin: min, spacing, counts, probes })
+    }
+
+    /// Bounce light reaching a surface at `point` facing `normal`, blended from the eight
+    /// probes around it. Probes behind the surface count less, so light doesn't leak
+    /// through walls as much.
+    pub fn bounce_light(&self, point: Vector3, normal: Vector3) -> Vector3 {
+        let local = (point - self.origin) / self.spacing;
+        let local = [local.x, local.y, local.z];
+        let mut base = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let last = self.counts[axis].saturating_sub(2);
+            base[axis] = (local[axis].floor().max(0.0) as usize).min(last);
+            fraction[axis] = if self.counts[axis] > 1 { (local[axis] - base[axis] as f32).clamp(0.0, 1.0) } else { 0.0 };
+        }
+
+        let mut sum = Vector3::zero();
+        let mut total = 0.0;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let cell: [usize; 3] = std::array::from_fn(|axis| (base[axis] + offset[axis]).min(self.counts[axis] - 1));
+            let index = (cell[2] * self.counts[1] + cell[1]) * self.counts[0] + cell[0];
+            let Some(cube) = &self.probes[index] else {
+                continue;
+            };
+            let trilinear: f32 = (0..3).map(|axis| if offset[axis] == 1 { fraction[axis] } else { 1.0 - fraction[axis] }).product();
+            let position = self.origin + Vector3::new(cell[0] as f32, cell[1] as f32, cell[2] as f32) * self.spacing;
+            let facing = ((position - point).normalized().dot(normal) + 1.0) * 0.5;
+            let weight = trilinear * (facing * facing + 0.2);
+            sum = sum + evaluate(cube, normal) * weight;
+            total += weight;
+        }
+        if total < 1e-4 { Vector3::zero() } else { sum / total * PROBE_STRENGTH }
+    }
+}
+
+/// Baked lighting handed to the tracer, each part only while it is switched on
+#[derive(Clone, Copy, Default)]
+pub struct BakedLighting<'a> {
+    pub irradiance: Option<&'a ProbeGrid>,
+    pub reflections: Option<&'a ReflectionProbe>,
+}
+
+/// The probe grid and reflection capture for the current scene, rebaked when the lights or
+/// sky they were baked for change (at most once a second) or when they are invalidated
+/// after scene edits. The reflection capture is also retaken as the camera moves away.
+#[derive(Default)]
+pub struct ProbeCache {
+    grid: Option<ProbeGrid>,
+    key: Vec<f32>,
+    baked: Option<Instant>,
+    reflections: Option<ReflectionProbe>,
+    reflection_key: Vec<f32>,
+    captured: Option<Instant>,
+}
+
+impl ProbeCache {
+    /// Throws the grid and capture away, the next `update` bakes new ones
+    pub fn invalidate(&mut self) {
+        self.grid = None;
+        self.baked = None;
+        self.reflections = None;
+        self.captured = None;
+    }
+
+    pub fn update(&mut self, objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, lights: &[Light], sky: &Sky, options: &RenderOptions, eye: Vector3) -> BakedLighting<'_> {
+        let key = bake_key(lights, sky);
+        let due = |baked: Option<Instant>| baked.is_none_or(|baked| baked.elapsed().as_secs_f32() >= REBAKE_SECONDS);
+        if options.light_probes && (self.grid.is_none() || key != self.key) && due(self.baked) {
+            let started = Instant::now();
+            self.grid = ProbeGrid::bake(objects, materials, accel, lights, sky, options);
+            self.key.clone_from(&key);
+            self.baked = Some(Instant::now());
+            if let Some(grid) = &self.grid {
+                println!("Baked {} light probes in {:.2}s", grid.probes.len(), started.elapsed().as_secs_f32());
+            }
+        }
+        let moved = self.reflections.as_ref().is_none_or(|probe| (probe.center - eye).length() > RECAPTURE_DISTANCE);
+        if options.glossy_reflections && (moved || key != self.reflection_key) && due(self.captured) {
+            let started = Instant::now();
+            self.reflections = ReflectionProbe::capture(objects, materials, accel, lights, sky, options, eye);
+            self.reflection_key = key;
+            self.captured = Some(Instant::now());
+            if self.reflections.is_some() {
+                println!("Captured glossy reflections in {:.2}s", started.elapsed().as_secs_f32());
+            }
+        }
+        BakedLighting {
+            irradiance: self.grid.as_ref().filter(|_| options.light_probes),
+            reflections: self.reflections.as_ref().filter(|_| options.glossy_reflections),
+        }
+    }
+}
+
+// Everything about the lights and sky that changes what the probes see. Animated lights and
+// the headlamp change every frame, so the probes keep what they saw at the last bake
+// instead of rebaking every `REBAKE_SECONDS`.
+fn bake_key(lights: &[Light], sky: &Sky) -> Vec<f32> {
+    let mut key = vec![sky.tint.x, sky.tint.y, sky.tint.z];
+    for light in lights.iter().filter(|light| !light.animated) {
+        let (position, color) = (light.position, light.color);
+        key.extend([position.x, position.y, position.z, light.intensity, color.r as f32, color.g as f32, color.b as f32]);
+    }
+    key
+}
+
+// Light leaving the first surface along a probe ray back towards the probe, black for sky
+// (the sky's own light is already the ambient term)
+fn bounced_light(objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, lights: &[Light], sky: &Sky, options: &RenderOptions, origin: Vector3, direction: Vector3) -> Vector3 {
+    let Some((hit, material)) = closest_hit(objects, materials, accel, &origin, &direction) else {
+        return Vector3::zero();
+    };
+    let mut radiance = material.emission;
+    let mut rng = Rng::new(0, 0); // Unused: probe rays shadow area lights from their centers
+    for light in lights {
+        radiance = radiance + shade_light(&hit, &material, light, &origin, objects, materials, accel, None, options, 1, &mut rng).0;
+    }
+    let irradiance = sky.irradiance(hit.normal);
+    let base = material.diffuse;
+    radiance + Vector3::new(irradiance.x * base.x, irradiance.y * base.y, irradiance.z * base.z) * (ENVIRONMENT_LIGHT * hit.sky_visibility)
+}
+
+// Ambient cube lookup: each axis weighted by the squared normal component
+fn evaluate(cube: &AmbientCube, normal: Vector3) -> Vector3 {
+    let side = |component: f32, axis: usize| cube[axis * 2 + usize::from(component < 0.0)] * (component * component);
+    side(normal.x, 0) + side(normal.y, 1) + side(normal.z, 2)
+}
+
+// Point `index` of a Fibonacci spiral over the unit sphere
+fn fibonacci_direction(index: u32) -> Vector3 {
+    let golden_angle = PI * (3.0 - 5.0f32.sqrt());
+    let y = 1.0 - 2.0 * (index as f32 + 0.5) / PROBE_RAYS as f32;
+    let radius = (1.0 - y * y).max(0.0).sqrt();
+    let phi = golden_angle * index as f32;
+    Vector3::new(radius * phi.cos(), y, radius * phi.sin())
+}
+
+// Probes inside opaque blocks would only see the block's inside
+fn inside_solid(objects: &[SceneObject], materials: &MaterialTable, point: Vector3) -> bool {
+    objects.iter().filter_map(|object| object.as_cube()).any(|object| {
+        let offset = point - object.center;
+        let half = object.size * 0.5;
+        offset.x.abs() < half && offset.y.abs() < half && offset.z.abs() < half && materials[object.material].albedo[3] <= 0.0
+    })
+}
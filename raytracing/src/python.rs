@@ -0,0 +1,112 @@
+// Python bindings, built with `maturin develop --features python`:
+//
+//     import numpy as np, raytracing
+//     r = raytracing.Renderer()
+//     r.set_camera((0, 4, -12), (0, 3, 0))
+//     w, h, buf = r.render(320, 240, samples=4)
+//     img = np.frombuffer(buf, dtype=np.float32).reshape(h, w, 3)
+
+use crate::acceleration::Acceleration;
+use crate::camera::Camera;
+use crate::daycycle::DayCycle;
+use crate::light::Light;
+use crate::material::{MaterialTable, TextureOptions};
+use crate::offline::trace_sample;
+use crate::pathtrace::PathLights;
+use crate::ray_intersect::SceneObject;
+use crate::settings::RenderOptions;
+use crate::sky::Sky;
+use crate::{block_lights, create_camera, create_light, load_scene};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use raylib::prelude::{Color, Vector3};
+
+/// Scene, camera and light that Python scripts drive between renders
+#[pyclass]
+pub struct Renderer {
+    objects: Vec<SceneObject>,
+    materials: MaterialTable,
+    camera: Camera,
+    light: Light,
+    sky: Sky,
+}
+
+#[pymethods]
+impl Renderer {
+    /// Builds the default diorama; run from the crate directory so the textures are found
+    #[new]
+    fn new() -> Self {
+        let (objects, materials) = load_scene(TextureOptions::default());
+        Renderer {
+            objects,
+            materials,
+            camera: create_camera(),
+            light: create_light(),
+            sky: Sky::default(),
+        }
+    }
+
+    fn set_camera(&mut self, eye: (f32, f32, f32), center: (f32, f32, f32)) {
+        self.camera = Camera::new(
+            Vector3::new(eye.0, eye.1, eye.2),
+            Vector3::new(center.0, center.1, center.2),
+            Vector3::new(0.0, 1.0, 0.0),
+        );
+    }
+
+    fn set_light(&mut self, position: (f32, f32, f32), color: (u8, u8, u8), intensity: f32) {
+        self.light = Light::new(
+            Vector3::new(position.0, position.1, position.2),
+            Color::new(color.0, color.1, color.2, 255),
+            intensity,
+        );
+    }
+
+    /// Replaces the light and sky with the sun/moon for an hour in [0, 24)
+    fn set_time_of_day(&mut self, hour: f32) {
+        let cycle = DayCycle::new(hour);
+        self.light = cycle.light();
+        self.sky = cycle.sky();
+    }
+
+    fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Renders and returns `(width, height, bytes)` with row-major float32 RGB in [0, 1];
+    /// `path_trace=True` uses the path tracer, which needs more samples to converge
+    #[pyo3(signature = (width, height, samples = 1, path_trace = false))]
+    fn render<'py>(&mut self, py: Python<'py>, width: u32, height: u32, samples: u32, path_trace: bool) -> (u32, u32, Bound<'py, PyBytes>) {
+        let samples = samples.max(1);
+        let options = RenderOptions {
+            path_traced: path_trace,
+            ..RenderOptions::default()
+        };
+        let accel = Acceleration::build(&self.objects);
+        let mut lights = vec![self.light.clone()];
+        if !path_trace {
+            lights.extend(block_lights(&self.objects, &self.materials));
+        }
+        let path_lights = PathLights::new(&self.objects, &self.materials, &lights);
+        let mut bytes = Vec::with_capacity((width * height) as usize * 12);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Vector3::zero();
+                for sample in 0..samples {
+                    sum = sum + trace_sample(&self.objects, &self.materials, Some(&accel), &self.camera, &lights, &path_lights, &self.sky, &options, x, y, width, height, sample);
+                }
+                let color = sum / samples as f32;
+                bytes.extend_from_slice(&color.x.to_le_bytes());
+                bytes.extend_from_slice(&color.y.to_le_bytes());
+                bytes.extend_from_slice(&color.z.to_le_bytes());
+            }
+        }
+        (width, height, PyBytes::new(py, &bytes))
+    }
+}
+
+#[pymodule]
+fn raytracing(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Renderer>()?;
+    Ok(())
+}
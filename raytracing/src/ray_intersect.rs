@@ -1,5 +1,7 @@
+use crate::cube::{ALPHA_CUTOFF, Cube};
+use crate::material::{Material, MaterialTable};
+use crate::sphere::Sphere;
 use raylib::prelude::Vector3;
-use crate::material::Material;
 
 #[derive(Debug, Clone, Copy)]
 #[allow(dead_code)]
@@ -8,17 +10,25 @@ pub struct Intersect {
     pub normal: Vector3,
     pub distance: f32,
     pub is_intersecting: bool,
-    pub material: Material,
+    pub shadow_bias: f32, // From the face's material once the hit is shaded, for offsetting secondary rays
+    pub sky_visibility: f32, // Baked fraction of the sky the hit face can see
+    pub uv: (f32, f32), // Position on the face, before parallax and texture rotation
+    pub face: usize, // See `Cube::face_index`
+    pub object: usize, // Index in the scene's objects, set by whoever walks the list
 }
 
 impl Intersect {
-    pub fn new(point: Vector3, normal: Vector3, distance: f32, material: Material) -> Self {
+    pub fn new(point: Vector3, normal: Vector3, distance: f32) -> Self {
         Intersect {
             point,
             normal,
             distance,
             is_intersecting: true,
-            material,
+            shadow_bias: 0.0,
+            sky_visibility: 1.0,
+            uv: (0.0, 0.0),
+            face: 0,
+            object: usize::MAX,
         }
     }
 
@@ -28,11 +38,82 @@ impl Intersect {
             normal: Vector3::zero(),
             distance: 0.0,
             is_intersecting: false,
-            material: Material::black(),
+            shadow_bias: 0.0,
+            sky_visibility: 1.0,
+            uv: (0.0, 0.0),
+            face: 0,
+            object: usize::MAX,
         }
     }
 }
 
+/// A shape the tracer can hit and shade. The render loop only goes through this, so a new
+/// primitive needs an implementation and a place in the scene's object list, nothing more.
 pub trait RayIntersect {
-    fn ray_intersect(&mut self, ray_origin: &Vector3, ray_direction: &Vector3) -> Intersect;
+    fn ray_intersect(&self, ray_origin: &Vector3, ray_direction: &Vector3) -> Intersect;
+
+    /// Distance to the shape along a ray, without any texturing work
+    fn hit_distance(&self, ray_origin: &Vector3, ray_direction: &Vector3) -> Option<f32>;
+
+    /// Shading step for a hit from `ray_intersect`: the material at the hit texel, looked up
+    /// in the scene's material table
+    fn surface(&self, intersect: &Intersect, ray_direction: &Vector3, materials: &MaterialTable) -> Material;
+
+    /// Untextured material where the surface faces `normal`, enough for shadow rays
+    fn material_for(&self, normal: Vector3, materials: &MaterialTable) -> Material;
+
+    /// Texture alpha at a hit, for cutouts like leaves; 1 when the shape has none
+    fn alpha_at(&self, _intersect: &Intersect) -> f32 {
+        1.0
+    }
+
+    /// True where a transparent shape's texture has a hole, rays pass through it as if
+    /// nothing was there (judged on the face the ray enters by)
+    fn is_cut_out(&self, intersect: &Intersect, materials: &MaterialTable) -> bool {
+        self.material_for(intersect.normal, materials).albedo[3] > 0.0 && self.alpha_at(intersect) < ALPHA_CUTOFF
+    }
+
+    /// `hit_distance` for the rays that only ask whether and where something is in the way
+    /// (occlusion, depth, picking); None where the ray goes through a cut-out texel, so they
+    /// agree with what gets shaded. Shapes without texture alpha have no cut-outs.
+    fn solid_hit_distance(&self, ray_origin: &Vector3, ray_direction: &Vector3, _materials: &MaterialTable) -> Option<f32> {
+        self.hit_distance(ray_origin, ray_direction)
+    }
+
+    fn center(&self) -> Vector3;
+
+    /// Axis-aligned box around the shape, as (min, max)
+    fn bounds(&self) -> (Vector3, Vector3);
+
+    /// The block behind this object, for the systems that only work on blocks (keyframes,
+    /// falling sand, material edits, ID passes)
+    fn as_cube(&self) -> Option<&Cube> {
+        None
+    }
+
+    fn as_cube_mut(&mut self) -> Option<&mut Cube> {
+        None
+    }
+
+    /// The ball behind this object, for the OBJ export
+    fn as_sphere(&self) -> Option<&Sphere> {
+        None
+    }
+}
+
+/// One entry in a scene's object list; blocks and any other primitive side by side. Shared
+/// read-only between render threads.
+pub type SceneObject = Box<dyn RayIntersect + Send + Sync>;
+
+/// Boxes a list of blocks into scene objects, keeping their order
+pub fn scene_objects(cubes: Vec<Cube>) -> Vec<SceneObject> {
+    cubes.into_iter().map(|cube| Box::new(cube) as SceneObject).collect()
+}
+
+/// Box around every object, None for an empty scene
+pub fn scene_bounds(objects: &[SceneObject]) -> Option<(Vector3, Vector3)> {
+    let first = objects.first()?.bounds();
+    Some(objects.iter().map(|object| object.bounds()).fold(first, |(min, max), (low, high)| {
+        (Vector3::new(min.x.min(low.x), min.y.min(low.y), min.z.min(low.z)), Vector3::new(max.x.max(high.x), max.y.max(high.y), max.z.max(high.z)))
+    }))
 }
\ No newline at end of file
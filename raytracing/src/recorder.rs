@@ -0,0 +1,88 @@
+use crate::framebuffer::rgb_bytes;
+use raylib::prelude::*;
+use std::io::{self, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::Instant;
+
+/// Video rate of recordings; frames are repeated or dropped to keep it in step with the clock
+pub const RECORDING_FPS: u32 = 30;
+
+/// Streams presented frames as raw RGB into an ffmpeg subprocess that encodes an MP4 at
+/// `RECORDING_FPS`, whatever rate the window presents at
+pub struct Recorder {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub frames: u64,
+    started: Instant,
+    shown: Vec<u8>, // Frame on screen as packed RGB at the video size, empty until one is uploaded
+}
+
+impl Recorder {
+    /// Starts ffmpeg; the video size is fixed here and later frames are resampled to it
+    pub fn start(path: &str, width: u32, height: u32) -> io::Result<Self> {
+        // libx264 with yuv420p needs even dimensions
+        let width = (width.max(2)) & !1;
+        let height = (height.max(2)) & !1;
+
+        let mut child = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-r", &RECORDING_FPS.to_string()])
+            .args(["-i", "-"])
+            .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take();
+
+        Ok(Recorder {
+            child,
+            stdin,
+            path: path.to_string(),
+            width,
+            height,
+            frames: 0,
+            started: Instant::now(),
+            shown: Vec::new(),
+        })
+    }
+
+    /// Keeps the pixels just uploaded for display, resampled to the video size, so the video
+    /// shows exactly what the window does
+    pub fn frame_uploaded(&mut self, width: u32, height: u32, pixels: &[Color]) {
+        self.shown = rgb_bytes(width, height, pixels, self.width, self.height);
+    }
+
+    /// Appends the frame on screen as however many video frames are due since the last call:
+    /// none when presenting faster than the video rate, several when slower, so the video
+    /// plays back in real time
+    pub fn write_frame(&mut self) -> io::Result<()> {
+        let due = (self.started.elapsed().as_secs_f64() * RECORDING_FPS as f64) as u64 + 1;
+        if self.frames >= due || self.shown.is_empty() {
+            return Ok(());
+        }
+        let Some(stdin) = self.stdin.as_mut() else {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "ffmpeg stdin closed"));
+        };
+        while self.frames < due {
+            stdin.write_all(&self.shown)?;
+            self.frames += 1;
+        }
+        Ok(())
+    }
+
+    /// Closes the pipe and waits for ffmpeg to finish writing the file
+    pub fn finish(mut self) -> io::Result<()> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("ffmpeg exited with {}", status)))
+        }
+    }
+}
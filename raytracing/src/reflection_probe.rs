@@ -0,0 +1,156 @@
+use crate::acceleration::Acceleration;
+use crate::camera::Camera;
+use crate::cast_ray;
+use crate::cube::Cube;
+use crate::light::Light;
+use crate::material::MaterialTable;
+use crate::probes::BakedLighting;
+use crate::ray_intersect::{SceneObject, scene_bounds};
+use crate::rng::Rng;
+use crate::settings::RenderOptions;
+use crate::sky::Sky;
+use raylib::prelude::Vector3;
+use std::f32::consts::FRAC_PI_2;
+
+// Texels along each face of the captured cubemap, the sharpest mip
+const CAPTURE_SIZE: usize = 32;
+/// Materials with a duller highlight (a lower specular exponent) than this reflect through
+/// the prefiltered capture; sharper ones keep their traced mirror reflections
+pub const GLOSSY_EXPONENT: f32 = 48.0;
+
+// Outward face directions in `Cube::face_index` order: +X, -X, +Y, -Y, +Z, -Z
+const FACES: [Vector3; 6] = [
+    Vector3 { x: 1.0, y: 0.0, z: 0.0 },
+    Vector3 { x: -1.0, y: 0.0, z: 0.0 },
+    Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+    Vector3 { x: 0.0, y: -1.0, z: 0.0 },
+    Vector3 { x: 0.0, y: 0.0, z: 1.0 },
+    Vector3 { x: 0.0, y: 0.0, z: -1.0 },
+];
+
+// One level of the mip chain, `size` x `size` texels per face
+struct CubeMip {
+    size: usize,
+    faces: [Vec<Vector3>; 6],
+}
+
+/// The scene as seen from one point, stored as a cubemap whose mips are box-filtered down
+/// to one texel per face. A reflection cone reads the mip whose texels are as wide as the
+/// cone, so a rough reflection costs one lookup instead of many stochastic rays.
+pub struct ReflectionProbe {
+    pub center: Vector3,
+    bounds: (Vector3, Vector3),
+    mips: Vec<CubeMip>,
+}
+
+impl ReflectionProbe {
+    /// Traces the six faces from `center` (direct light only, no reflections inside the
+    /// capture) and builds the mips. None for an empty scene.
+    pub fn capture(objects: &[SceneObject], materials: &MaterialTable, accel: Option<&Acceleration>, lights: &[Light], sky: &Sky, options: &RenderOptions, center: Vector3) -> Option<Self> {
+        let (min, max) = scene_bounds(objects)?;
+
+        let capture_options = options.without_secondary();
+        let mut rng = Rng::new(0, 0);
+        let faces = std::array::from_fn(|face| {
+            let normal = FACES[face];
+            let (tangent, bitangent) = Cube::face_axes(normal);
+            // Frustum culling looks along the face being traced
+            let up = if normal.y.abs() > 0.9 { Vector3::new(0.0, 0.0, 1.0) } else { Vector3::new(0.0, 1.0, 0.0) };
+            let camera = Camera::new(center, center + normal, up);
+            let mut texels = Vec::with_capacity(CAPTURE_SIZE * CAPTURE_SIZE);
+            for j in 0..CAPTURE_SIZE {
+                for i in 0..CAPTURE_SIZE {
+                    let (u, v) = (texel_coordinate(i, CAPTURE_SIZE), texel_coordinate(j, CAPTURE_SIZE));
+                    let direction = (normal + tangent * u + bitangent * v).normalized();
+                    let color = cast_ray(&center, &direction, objects, materials, accel, None, BakedLighting::default(), lights, sky, &capture_options, 0, &camera, FRAC_PI_2, 1.0, &mut rng);
+                    texels.push(color);
+                }
+            }
+            texels
+        });
+
+        let mut mips = vec![CubeMip { size: CAPTURE_SIZE, faces }];
+        while let Some(last) = mips.last().filter(|mip| mip.size > 1) {
+            let size = last.size / 2;
+            let faces = std::array::from_fn(|face| {
+                let parent = &last.faces[face];
+                let mut texels = Vec::with_capacity(size * size);
+                for j in 0..size {
+                    for i in 0..size {
+                        let at = |x: usize, y: usize| parent[y * last.size + x];
+                        let (x, y) = (i * 2, j * 2);
+                        texels.push((at(x, y) + at(x + 1, y) + at(x, y + 1) + at(x + 1, y + 1)) * 0.25);
+                    }
+                }
+                texels
+            });
+            mips.push(CubeMip { size, faces });
+        }
+        Some(ReflectionProbe { center, bounds: (min, max), mips })
+    }
+
+    /// Light arriving at `origin` from a cone around `direction` of the given half-angle.
+    /// The direction is first projected onto the scene's bounding box, so reflections of
+    /// nearby blocks line up even away from the capture point.
+    pub fn sample(&self, origin: Vector3, direction: Vector3, cone_angle: f32) -> Vector3 {
+        let lookup = self.box_projected(origin, direction);
+        let footprint = CAPTURE_SIZE as f32 * cone_angle * 2.0 / FRAC_PI_2;
+        let level = footprint.max(1.0).log2().min((self.mips.len() - 1) as f32);
+        let (lower, fraction) = (level.floor() as usize, level.fract());
+        let upper = (lower + 1).min(self.mips.len() - 1);
+        let (sharp, blurred) = (self.mips[lower].sample(lookup), self.mips[upper].sample(lookup));
+        sharp + (blurred - sharp) * fraction
+    }
+
+    // Where a ray from `origin` leaves the scene's box, seen from the capture point
+    fn box_projected(&self, origin: Vector3, direction: Vector3) -> Vector3 {
+        let (min, max) = self.bounds;
+        let inside = origin.x > min.x && origin.y > min.y && origin.z > min.z && origin.x < max.x && origin.y < max.y && origin.z < max.z;
+        if !inside {
+            return direction;
+        }
+        let exit = |origin: f32, direction: f32, low: f32, high: f32| {
+            if direction.abs() < 1e-6 { f32::INFINITY } else { ((if direction > 0.0 { high } else { low }) - origin) / direction }
+        };
+        let distance = exit(origin.x, direction.x, min.x, max.x).min(exit(origin.y, direction.y, min.y, max.y)).min(exit(origin.z, direction.z, min.z, max.z));
+        let projected = origin + direction * distance - self.center;
+        if projected.length() < 1e-4 { direction } else { projected }
+    }
+}
+
+/// Half-angle of a Phong lobe with this exponent, where it falls to half its peak
+pub fn cone_angle(specular: f32) -> f32 {
+    0.5f32.powf(1.0 / specular.max(1.0)).acos()
+}
+
+impl CubeMip {
+    // Bilinear lookup on the face `direction` points at, clamped at the face edges
+    fn sample(&self, direction: Vector3) -> Vector3 {
+        let (x, y, z) = (direction.x.abs(), direction.y.abs(), direction.z.abs());
+        let face = if x >= y && x >= z {
+            usize::from(direction.x < 0.0)
+        } else if y >= z {
+            2 + usize::from(direction.y < 0.0)
+        } else {
+            4 + usize::from(direction.z < 0.0)
+        };
+        let normal = FACES[face];
+        let (tangent, bitangent) = Cube::face_axes(normal);
+        let on_face = direction / direction.dot(normal);
+        let texel = |coordinate: f32| ((coordinate + 1.0) * 0.5 * self.size as f32 - 0.5).clamp(0.0, (self.size - 1) as f32);
+        let (px, py) = (texel(on_face.dot(tangent)), texel(on_face.dot(bitangent)));
+
+        let (x0, y0) = (px as usize, py as usize);
+        let (x1, y1) = ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+        let (tx, ty) = (px - x0 as f32, py - y0 as f32);
+        let at = |x: usize, y: usize| self.faces[face][y * self.size + x];
+        let top = at(x0, y0) * (1.0 - tx) + at(x1, y0) * tx;
+        let bottom = at(x0, y1) * (1.0 - tx) + at(x1, y1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+// Center of texel `index` on a face `size` texels wide, in [-1, 1]
+fn texel_coordinate(index: usize, size: usize) -> f32 {
+    (index as f32 + 0.5) / size as f32 * 2.0 - 1.0
+}
@@ -0,0 +1,622 @@
+use crate::acceleration::Acceleration;
+use crate::animation::{Animator, Timeline};
+use crate::camera::Camera;
+use crate::depth::{depth_path, write_depth_png};
+use crate::framebuffer::{Framebuffer, FrontBuffer};
+use crate::id_pass::{highlight_selection, write_id_passes};
+use crate::light::Light;
+use crate::material::{Material, MaterialTable, TextureOptions};
+use crate::material_library::{MaterialEdit, MaterialLibrary, material_id};
+use crate::physics::FallingBlocks;
+use crate::portals::Portals;
+use crate::post::PostProcess;
+use crate::probes::ProbeCache;
+use crate::ray_intersect::SceneObject;
+use crate::scene_file;
+use crate::scenes::{GALLERY, gallery_builder};
+use crate::settings::{RenderOptions, SplitView};
+use crate::sky::Sky;
+use crate::metadata::ShotMetadata;
+use crate::{RENDER_SEED, bake_sky_visibility, block_lights, closest_object, draw_light_gizmos, render_adaptive, trace_emission};
+use raylib::prelude::Vector3;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+// Simulation seconds between sky-visibility re-bakes while objects keep moving; one more
+// runs when they come to rest
+const SKY_REBAKE_INTERVAL: f32 = 1.0;
+
+/// Everything the worker needs to trace one frame
+pub struct RenderJob {
+    pub camera: Camera,
+    pub lights: Vec<Light>,
+    pub sky: Sky,
+    pub options: RenderOptions,
+    pub split: Option<SplitView>,
+    pub post: PostProcess,
+    pub width: u32,
+    pub height: u32,
+    pub render_scale: f32,
+    pub frame_index: u32,
+    pub time: f32, // Simulation time, drives the scene's keyframed objects
+    pub light_gizmos: bool,
+    pub view_epoch: u64, // Changes whenever the image would, restarting the path tracer's average
+}
+
+/// What a scene builder hands the worker
+pub struct BuiltScene {
+    pub objects: Vec<SceneObject>,
+    pub materials: MaterialTable, // What the objects' material indices point into
+    pub timeline: Timeline,       // Object tracks refer to `objects` by index
+    pub portals: Option<Portals>, // Classified against `objects`
+}
+
+/// Builds a scene on the worker, which owns the material library and the objects
+pub type SceneBuilder = Box<dyn FnOnce(&MaterialLibrary) -> BuiltScene + Send>;
+
+/// Scene changes applied by the worker between frames
+pub enum SceneCommand {
+    /// Re-reads the material library file and restyles every object built from it
+    ReloadMaterials,
+    /// Selects the material of the object under the center of the view
+    Select(Camera),
+    /// Puts a copy of the block under the center of the view against the face it is seen on
+    PlaceBlock(Camera),
+    /// Takes the block under the center of the view out of the scene
+    RemoveBlock(Camera),
+    /// Tweaks the selected material on every object that uses it
+    Edit(MaterialEdit),
+    /// Saves the next frame at full scale to a PNG, with `<name>_depth.png`, `<name>_ids.png`,
+    /// `<name>_materials.png` and the render metadata in `<name>.json` next to it
+    Screenshot(ScreenshotRequest),
+    /// Replaces the scene with this entry of the scene gallery
+    LoadScene(usize),
+    /// Replaces the scene with whatever the builder makes (scene scripts)
+    Replace(SceneBuilder),
+    /// Lets the loose blocks fall from the first to the second simulation time at once, for a
+    /// replay that starts later than its scene was loaded
+    FastForward(f32, f32),
+    /// Writes the blocks as they are now, with these lights and camera, to a `.ron` scene file
+    SaveScene(SaveSceneRequest),
+    /// Draws the targeted objects with a library preset or a debug look (`Material::look`)
+    /// until reset; they keep their IDs and still count as their own block type
+    OverrideMaterial(OverrideTarget, String),
+    /// Gives the targeted objects back the material they were built with
+    ResetMaterial(OverrideTarget),
+}
+
+/// Where a screenshot goes and which scene it shows, for the metadata saved with it
+pub struct ScreenshotRequest {
+    pub path: PathBuf,
+    pub scene: String,
+    pub script: Option<PathBuf>, // The scene script, when the scene came from one
+}
+
+/// Where a saved scene goes and the view and lights saved with its blocks
+pub struct SaveSceneRequest {
+    pub path: PathBuf,
+    pub camera: Camera,
+    pub lights: Vec<Light>,
+}
+
+/// Which objects a material override applies to
+#[derive(Clone)]
+pub enum OverrideTarget {
+    /// The object under the center of this camera's view
+    Aimed(Camera),
+    /// Every object built from this material preset
+    Block(String),
+    All,
+}
+
+/// How long the worker took to trace a finished frame, and the render scale it used
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTiming {
+    pub seconds: f32,
+    pub scale: f32,
+}
+
+/// The block under the crosshair as of the last finished frame
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub center: Vector3,
+    pub block: String, // Material preset name, "unknown" for blocks not from the library
+    pub distance: f32,
+}
+
+// The worker's scene: the objects, the material table they index into and their voxel
+// grid or BVH, what moves them between frames, its portals, the built materials of objects
+// under a material override, its light probes and when its sky visibility was last baked
+struct Scene {
+    objects: Vec<SceneObject>,
+    materials: MaterialTable,
+    accel: Acceleration,
+    animator: Option<Animator>,
+    falling: FallingBlocks,
+    portals: Option<Portals>,
+    originals: HashMap<usize, (u32, u32)>, // Material entry and preset ID of overridden blocks
+    probes: ProbeCache,
+    sky_stale: bool, // Objects moved since the last sky-visibility bake
+    sky_baked_at: f32,
+}
+
+impl Scene {
+    fn new(BuiltScene { mut objects, mut materials, timeline, portals }: BuiltScene) -> Self {
+        let animator = (!timeline.objects.is_empty()).then(|| Animator::new(timeline, &mut objects, &mut materials));
+        let accel = Acceleration::build(&objects);
+        Scene { objects, materials, accel, animator, falling: FallingBlocks::default(), portals, originals: HashMap::new(), probes: ProbeCache::default(), sky_stale: false, sky_baked_at: 0.0 }
+    }
+
+    // Keyframed objects first, then loose blocks fall around them; the acceleration
+    // structure follows the objects that moved (including blocks landing this frame). Sky
+    // visibility costs more than a frame to bake, so it catches up every
+    // `SKY_REBAKE_INTERVAL` during a move and once the objects are still again. Returns
+    // whether any object moved
+    fn advance(&mut self, library: &MaterialLibrary, time: f32) -> bool {
+        let mut moved = match &self.animator {
+            Some(animator) => animator.apply(&mut self.objects, &mut self.materials, time),
+            None => Vec::new(),
+        };
+        moved.extend(self.falling.update(&mut self.objects, &library.gravity_ids(), time));
+        if !moved.is_empty() {
+            self.accel.update(&self.objects, &moved);
+            self.sky_stale = true;
+        }
+        if self.sky_stale && (moved.is_empty() || (time - self.sky_baked_at).abs() >= SKY_REBAKE_INTERVAL) {
+            bake_sky_visibility(&mut self.objects, &self.accel);
+            self.sky_stale = false;
+            self.sky_baked_at = time;
+        }
+        !moved.is_empty()
+    }
+
+    // Moving objects, falling blocks and rippling water all change the image without input
+    fn is_animated(&self) -> bool {
+        self.animator.is_some() || self.falling.is_active() || self.objects.iter().filter_map(|object| object.as_cube()).any(|object| self.materials[object.material].ripple > 0.0)
+    }
+
+    // A copy of the aimed block (as built, if overridden) against the face the crosshair is
+    // on, with the next free ID. Only the grid chunk it lands in is rebuilt. Returns where
+    // it went
+    fn place_block(&mut self, camera: &Camera) -> Result<Vector3, &'static str> {
+        let direction = (camera.center - camera.eye).normalized();
+        let index = closest_object(&self.objects, &self.materials, Some(&self.accel), &camera.eye, &direction).ok_or("Nothing under the crosshair")?;
+        let point = self.objects[index].ray_intersect(&camera.eye, &direction).point;
+        let mut block = self.objects[index].as_cube().ok_or("Blocks can only be placed against blocks")?.clone();
+        // Out through the face the point is on, the axis it is furthest along
+        let offset = point - block.center;
+        let normal = if offset.x.abs() >= offset.y.abs() && offset.x.abs() >= offset.z.abs() {
+            Vector3::new(offset.x.signum(), 0.0, 0.0)
+        } else if offset.y.abs() >= offset.z.abs() {
+            Vector3::new(0.0, offset.y.signum(), 0.0)
+        } else {
+            Vector3::new(0.0, 0.0, offset.z.signum())
+        };
+        block.center = block.center + normal * block.size;
+        if let Some(&(entry, id)) = self.originals.get(&index) {
+            block.material = entry;
+            block.material_id = id;
+        }
+        block.id = self.objects.iter().filter_map(|object| object.as_cube()).map(|object| object.id + 1).max().unwrap_or(0);
+
+        let center = block.center;
+        self.objects.push(Box::new(block));
+        if let Some(portals) = &mut self.portals {
+            portals.placed(&self.objects[self.objects.len() - 1]);
+        }
+        self.accel.insert(&self.objects);
+        self.edited();
+        Ok(center)
+    }
+
+    // Takes out the aimed block; the last object takes its index (`swap_remove`), and
+    // everything keyed by index follows it. Only the grid chunks the two are in are rebuilt.
+    // Returns where the block was
+    fn remove_block(&mut self, camera: &Camera) -> Result<Vector3, &'static str> {
+        let direction = (camera.center - camera.eye).normalized();
+        let index = closest_object(&self.objects, &self.materials, Some(&self.accel), &camera.eye, &direction).ok_or("Nothing under the crosshair")?;
+        if self.animator.as_ref().is_some_and(|animator| animator.animates(index)) {
+            return Err("Animated blocks can't be removed");
+        }
+
+        let last = self.objects.len() - 1;
+        let center = self.objects.swap_remove(index).center();
+        if let Some(animator) = &mut self.animator {
+            animator.renumber(last, index);
+        }
+        self.falling.removed(index, last);
+        if let Some(portals) = &mut self.portals {
+            portals.removed(index);
+        }
+        self.originals.remove(&index);
+        if let Some(original) = self.originals.remove(&last) {
+            self.originals.insert(index, original);
+        }
+        self.accel.remove(&self.objects, index);
+        self.edited();
+        Ok(center)
+    }
+
+    // After a block was placed or removed: light bounces and sky visibility change around it
+    fn edited(&mut self) {
+        self.probes.invalidate();
+        self.sky_stale = true;
+    }
+}
+
+struct Shared {
+    job: Mutex<Option<RenderJob>>,
+    commands: Mutex<Vec<SceneCommand>>,
+    target: Mutex<Option<Target>>,
+    timing: Mutex<Option<FrameTiming>>,
+    object_count: AtomicUsize,
+    accumulated_frames: AtomicU32, // Frames averaged into the last one presented, 0 when not accumulating
+    animated: AtomicBool, // The scene changes over time on its own, as of the last frame
+    job_ready: Condvar,
+    running: AtomicBool,
+}
+
+/// Traces frames on a worker thread so input and presentation stay responsive
+/// even when a full-quality frame takes hundreds of milliseconds
+pub struct RenderThread {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+    front: Arc<FrontBuffer>,
+}
+
+impl RenderThread {
+    /// Spawns the worker; the material library and the scene are built on the worker
+    /// since it owns them from then on. Frames are traced on `threads` threads, one per
+    /// core when 0
+    pub fn spawn(build_scene: SceneBuilder, threads: usize, textures: TextureOptions) -> Self {
+        let shared = Arc::new(Shared {
+            job: Mutex::new(None),
+            commands: Mutex::new(Vec::new()),
+            target: Mutex::new(None),
+            timing: Mutex::new(None),
+            object_count: AtomicUsize::new(0),
+            accumulated_frames: AtomicU32::new(0),
+            animated: AtomicBool::new(false),
+            job_ready: Condvar::new(),
+            running: AtomicBool::new(true),
+        });
+
+        let mut framebuffer = Framebuffer::new(1, 1);
+        let front = framebuffer.front();
+
+        let (ready_sender, ready_receiver) = mpsc::channel();
+        let worker_shared = Arc::clone(&shared);
+        let handle = thread::spawn(move || {
+            let pool = match ThreadPoolBuilder::new().num_threads(threads).build() {
+                Ok(pool) => pool,
+                Err(err) => {
+                    println!("ERROR: could not start {} render threads: {} - using one per core", threads, err);
+                    ThreadPoolBuilder::new().build().expect("render thread pool")
+                }
+            };
+            let mut library = MaterialLibrary::load(textures);
+            let mut scene = Scene::new(build_scene(&library));
+            worker_shared.object_count.store(scene.objects.len(), Ordering::Relaxed);
+            let _ = ready_sender.send(());
+            worker_loop(&worker_shared, &pool, &mut framebuffer, &mut library, &mut scene);
+        });
+        let _ = ready_receiver.recv();
+
+        RenderThread {
+            shared,
+            handle: Some(handle),
+            front,
+        }
+    }
+
+    /// Queues a frame, replacing any job the worker hasn't started yet
+    pub fn submit(&self, job: RenderJob) {
+        *self.shared.job.lock().unwrap() = Some(job);
+        self.shared.job_ready.notify_one();
+    }
+
+    /// Queues a scene change, applied before the next frame is traced
+    pub fn command(&self, command: SceneCommand) {
+        self.shared.commands.lock().unwrap().push(command);
+    }
+
+    /// What the camera of the last finished frame was pointing at
+    pub fn target(&self) -> Option<Target> {
+        self.shared.target.lock().unwrap().clone()
+    }
+
+    /// Number of objects in the current scene
+    pub fn object_count(&self) -> usize {
+        self.shared.object_count.load(Ordering::Relaxed)
+    }
+
+    /// How many frames the path tracer or a still view's refinement has averaged so far
+    pub fn accumulated_frames(&self) -> u32 {
+        self.shared.accumulated_frames.load(Ordering::Relaxed)
+    }
+
+    /// Trace time of the most recently finished frame
+    pub fn last_frame_timing(&self) -> Option<FrameTiming> {
+        *self.shared.timing.lock().unwrap()
+    }
+
+    /// Whether the scene moves on its own, so a still camera doesn't mean a still image
+    pub fn is_animated(&self) -> bool {
+        self.shared.animated.load(Ordering::Relaxed)
+    }
+
+    /// Front buffer holding the most recently finished frame
+    pub fn front(&self) -> &FrontBuffer {
+        &self.front
+    }
+}
+
+fn worker_loop(shared: &Shared, pool: &ThreadPool, framebuffer: &mut Framebuffer, library: &mut MaterialLibrary, scene: &mut Scene) {
+    let mut selected: Option<String> = None;
+    let mut screenshot: Option<ScreenshotRequest> = None;
+    loop {
+        let mut job = {
+            let mut pending = shared.job.lock().unwrap();
+            while pending.is_none() && shared.running.load(Ordering::Acquire) {
+                pending = shared.job_ready.wait(pending).unwrap();
+            }
+            if !shared.running.load(Ordering::Acquire) {
+                return;
+            }
+            let Some(job) = pending.take() else {
+                continue;
+            };
+            job
+        };
+
+        let commands = std::mem::take(&mut *shared.commands.lock().unwrap());
+        framebuffer.track_view(job.view_epoch);
+        if !commands.is_empty() {
+            framebuffer.reset_accumulation();
+        }
+        for command in commands {
+            apply_command(command, library, &mut selected, &mut screenshot, scene);
+        }
+        // Moved objects make the frames averaged so far stale
+        if scene.advance(library, job.time) {
+            framebuffer.reset_accumulation();
+        }
+        // Rippling water runs on the same clock as the scene's moving objects
+        job.options.time = job.time;
+        if let Some(split) = job.split.as_mut() {
+            split.left.time = job.time;
+            split.right.time = job.time;
+        }
+        shared.animated.store(scene.is_animated(), Ordering::Relaxed);
+        // The path tracer finds glowing blocks itself
+        if !job.options.path_traced {
+            job.lights.extend(block_lights(&scene.objects, &scene.materials));
+        }
+        // Probes and the reflection capture see the scene as it was when they were baked;
+        // moving blocks don't rebake them
+        let baked = scene.probes.update(&scene.objects, &scene.materials, Some(&scene.accel), &job.lights, &job.sky, &job.options, job.camera.eye);
+        let objects = &mut scene.objects;
+        let materials = &scene.materials;
+        shared.object_count.store(objects.len(), Ordering::Relaxed);
+
+        if job.width != framebuffer.width || job.height != framebuffer.height {
+            framebuffer.resize(job.width, job.height);
+        }
+        // Screenshots skip the low-resolution motion preview; they, the selection outline
+        // and the transparent background need depth and object IDs
+        framebuffer.record_aovs = screenshot.is_some() || selected.is_some() || job.options.transparent_background;
+        let render_scale = if screenshot.is_some() { 1.0 } else { job.render_scale };
+        job.options.interlaced &= screenshot.is_none();
+        job.options.half_res_secondary &= screenshot.is_none();
+        job.options.taa &= screenshot.is_none();
+        let started = Instant::now();
+        pool.install(|| render_adaptive(framebuffer, objects, materials, Some(&scene.accel), scene.portals.as_ref(), baked, &job.camera, &job.lights, &job.sky, &job.options, job.split.as_ref(), render_scale, job.frame_index));
+        let accumulating = job.options.path_traced || job.options.progressive;
+        shared.accumulated_frames.store(if accumulating { framebuffer.accumulated_frames() } else { 0 }, Ordering::Relaxed);
+        if job.post.bloom > 0.0 {
+            framebuffer.emission_buffer = pool.install(|| trace_emission(objects, materials, Some(&scene.accel), &job.camera, framebuffer.width, framebuffer.height));
+        }
+        job.post.apply(framebuffer, job.frame_index);
+        if job.options.transparent_background {
+            framebuffer.cut_out_sky();
+        }
+        if let Some(request) = screenshot.take() {
+            save_screenshot(framebuffer, objects, &request.path);
+            let metadata = ShotMetadata {
+                scene: &request.scene,
+                scene_files: request.script.iter().cloned().chain(library.source.iter().map(PathBuf::from)).collect(),
+                camera: &job.camera,
+                lights: &job.lights,
+                options: &job.options,
+                width: job.width,
+                height: job.height,
+                seed: RENDER_SEED,
+                frame_index: job.frame_index,
+            };
+            match metadata.write(&request.path) {
+                Ok(path) => println!("Saved render metadata to {}", path.display()),
+                Err(err) => println!("Could not save render metadata: {}", err),
+            }
+        }
+        if let Some(name) = selected.as_deref() {
+            highlight_selection(framebuffer, objects, material_id(name));
+        }
+        if job.light_gizmos {
+            draw_light_gizmos(framebuffer, objects, materials, Some(&scene.accel), &job.camera, &job.lights);
+        }
+        *shared.timing.lock().unwrap() = Some(FrameTiming { seconds: started.elapsed().as_secs_f32(), scale: render_scale });
+        framebuffer.swap_buffers();
+        *shared.target.lock().unwrap() = aim(objects, materials, &scene.accel, library, &job.camera);
+    }
+}
+
+// Casts the crosshair ray and describes the block it lands on
+fn aim(objects: &mut [SceneObject], materials: &MaterialTable, accel: &Acceleration, library: &MaterialLibrary, camera: &Camera) -> Option<Target> {
+    let direction = (camera.center - camera.eye).normalized();
+    let index = closest_object(objects, materials, Some(accel), &camera.eye, &direction)?;
+    let object = &objects[index];
+    let intersect = object.ray_intersect(&camera.eye, &direction);
+    Some(Target {
+        center: object.center(),
+        block: object.as_cube().and_then(|cube| library.name_of(cube.material_id)).unwrap_or("unknown").to_string(),
+        distance: intersect.distance,
+    })
+}
+
+fn apply_command(
+    command: SceneCommand,
+    library: &mut MaterialLibrary,
+    selected: &mut Option<String>,
+    screenshot: &mut Option<ScreenshotRequest>,
+    scene: &mut Scene,
+) {
+    let objects = &mut scene.objects;
+    match command {
+        SceneCommand::ReloadMaterials => {
+            let reloaded = MaterialLibrary::load(library.textures);
+            let changed = reloaded.restyle(library, objects, &mut scene.materials);
+            *library = reloaded;
+            scene.probes.invalidate();
+            println!("Reloaded materials, {} objects updated", changed);
+        }
+        SceneCommand::Select(camera) => {
+            let direction = (camera.center - camera.eye).normalized();
+            *selected = closest_object(objects, &scene.materials, Some(&scene.accel), &camera.eye, &direction)
+                .and_then(|index| objects[index].as_cube())
+                .and_then(|object| library.name_of(object.material_id))
+                .map(str::to_string);
+            match selected {
+                Some(name) => println!("Selected material: {}", name),
+                None => println!("Nothing selected"),
+            }
+        }
+        SceneCommand::PlaceBlock(camera) => match scene.place_block(&camera) {
+            Ok(center) => println!("Placed a block at {:.1} {:.1} {:.1}", center.x, center.y, center.z),
+            Err(reason) => println!("{}", reason),
+        },
+        SceneCommand::RemoveBlock(camera) => match scene.remove_block(&camera) {
+            Ok(center) => println!("Removed the block at {:.1} {:.1} {:.1}", center.x, center.y, center.z),
+            Err(reason) => println!("{}", reason),
+        },
+        SceneCommand::Edit(edit) => {
+            let Some(name) = selected.as_deref() else {
+                println!("Select a block first (F8)");
+                return;
+            };
+            if let Some(material) = library.edit(name, edit, &mut scene.materials) {
+                scene.probes.invalidate();
+                println!(
+                    "{}: albedo = {:.2} {:.2} {:.2} {:.2}, specular = {:.0}",
+                    name, material.albedo[0], material.albedo[1], material.albedo[2], material.albedo[3], material.specular
+                );
+            }
+        }
+        SceneCommand::Screenshot(request) => *screenshot = Some(request),
+        SceneCommand::LoadScene(index) => {
+            let Some(entry) = GALLERY.get(index) else {
+                return;
+            };
+            *scene = Scene::new(gallery_builder(index)(library));
+            // Selections are by material name and may not exist in the new scene
+            *selected = None;
+            println!("Loaded scene {}: {} objects", entry.name, scene.objects.len());
+        }
+        SceneCommand::Replace(build) => {
+            *scene = Scene::new(build(library));
+            *selected = None;
+            println!("Replaced scene: {} objects", scene.objects.len());
+        }
+        SceneCommand::FastForward(from, to) => {
+            let moved = scene.falling.fast_forward(&mut scene.objects, &library.gravity_ids(), from, to);
+            if !moved.is_empty() {
+                scene.accel.update(&scene.objects, &moved);
+                scene.sky_stale = true;
+            }
+        }
+        SceneCommand::SaveScene(request) => {
+            match scene_file::write(&request.path, objects, &scene.materials, library, &request.lights, &request.camera) {
+                Ok(count) => println!("Saved scene: {} ({} objects)", request.path.display(), count),
+                Err(err) => println!("ERROR: could not save {}: {}", request.path.display(), err),
+            }
+        }
+        SceneCommand::OverrideMaterial(target, name) => {
+            let preset = library.get(&name).and_then(|_| scene.materials.preset(material_id(&name)));
+            let Some(entry) = preset.or_else(|| Material::look(&name).map(|material| scene.materials.share(material))) else {
+                println!("Unknown material {}: use a preset from materials.txt or mirror, chrome, glass, matte", name);
+                return;
+            };
+            let indices = override_targets(&target, objects, &scene.materials, &scene.accel);
+            for &index in &indices {
+                let Some(object) = objects[index].as_cube_mut() else {
+                    continue;
+                };
+                scene.originals.entry(index).or_insert((object.material, object.material_id));
+                object.material = entry;
+                object.material_id = if preset.is_some() { material_id(&name) } else { 0 };
+            }
+            scene.probes.invalidate();
+            println!("{} objects drawn as {}", indices.len(), name);
+        }
+        SceneCommand::ResetMaterial(target) => {
+            let mut restored = 0;
+            for index in override_targets(&target, objects, &scene.materials, &scene.accel) {
+                let Some(object) = objects[index].as_cube_mut() else {
+                    continue;
+                };
+                if let Some((entry, id)) = scene.originals.remove(&index) {
+                    object.material = entry;
+                    object.material_id = id;
+                    restored += 1;
+                }
+            }
+            scene.probes.invalidate();
+            println!("{} objects back to their own material", restored);
+        }
+    }
+}
+
+// Indices of the blocks an override target covers
+fn override_targets(target: &OverrideTarget, objects: &mut [SceneObject], materials: &MaterialTable, accel: &Acceleration) -> Vec<usize> {
+    let candidates: Vec<usize> = match target {
+        OverrideTarget::Aimed(camera) => {
+            let direction = (camera.center - camera.eye).normalized();
+            closest_object(objects, materials, Some(accel), &camera.eye, &direction).into_iter().collect()
+        }
+        OverrideTarget::Block(name) => {
+            let id = material_id(name);
+            (0..objects.len()).filter(|&index| objects[index].as_cube().is_some_and(|object| object.material_id == id)).collect()
+        }
+        OverrideTarget::All => (0..objects.len()).collect(),
+    };
+    candidates.into_iter().filter(|&index| objects[index].as_cube().is_some()).collect()
+}
+
+// Color as seen on screen minus overlays, depth as a 16-bit PNG and the ID passes for compositing
+fn save_screenshot(framebuffer: &Framebuffer, objects: &[SceneObject], path: &Path) {
+    framebuffer.render_to_file(&path.to_string_lossy());
+    let depth_path = depth_path(path);
+    match write_depth_png(&depth_path, framebuffer.width, framebuffer.height, &framebuffer.depth_buffer) {
+        Ok(far) => println!("Saved {} and {} (depth 0-{} units)", path.display(), depth_path.display(), far),
+        Err(err) => println!("Saved {}, depth failed: {}", path.display(), err),
+    }
+    write_id_passes(path, framebuffer.width, framebuffer.height, &framebuffer.id_buffer, objects);
+}
+
+impl Drop for RenderThread {
+    fn drop(&mut self) {
+        {
+            // Hold the job lock so the worker can't miss the wakeup between its check and wait
+            let _pending = self.shared.job.lock().unwrap();
+            self.shared.running.store(false, Ordering::Release);
+            self.shared.job_ready.notify_all();
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::rng::mix64;
+
+/// Low-discrepancy samples for a single pixel: a shuffled, Owen-scrambled 2D Sobol sequence
+/// per pair of dimensions (Burley 2020, "Practical Hash-based Owen Scrambling").
+/// Sample `n` of a pixel fills the gaps left by samples `0..n`, so a handful of samples
+/// already covers the pixel, lens or hemisphere evenly instead of clumping like white noise.
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    seed: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl Sampler {
+    /// Sampler for sample `index` of pixel (x, y); every pixel gets its own scramble
+    pub fn for_pixel(seed: u64, x: u32, y: u32, index: u32) -> Self {
+        let pixel = ((y as u64) << 32) | x as u64;
+        Sampler {
+            seed: mix64(seed ^ mix64(pixel)),
+            index,
+            dimension: 0,
+        }
+    }
+
+    /// Next pair of dimensions, both in [0, 1)
+    pub fn next_2d(&mut self) -> (f32, f32) {
+        let seed = mix64(self.seed ^ self.dimension as u64);
+        self.dimension += 1;
+
+        let index = nested_uniform_scramble(self.index, seed as u32);
+        let x = nested_uniform_scramble(sobol(index, 0), (seed >> 32) as u32);
+        let y = nested_uniform_scramble(sobol(index, 1), mix64(seed) as u32);
+        (to_unit_float(x), to_unit_float(y))
+    }
+
+    /// Next single dimension; uses up a whole pair so later dimensions stay aligned
+    pub fn next_f32(&mut self) -> f32 {
+        self.next_2d().0
+    }
+
+    /// Uniform float in [min, max)
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+}
+
+// First two Sobol dimensions: van der Corput, then the (x + 1) polynomial
+fn sobol(index: u32, dimension: u32) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+    let mut result = 0;
+    let mut direction = 1u32 << 31;
+    let mut bits = index;
+    while bits != 0 {
+        if bits & 1 != 0 {
+            result ^= direction;
+        }
+        bits >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn to_unit_float(x: u32) -> f32 {
+    (x >> 8) as f32 * (1.0 / 16_777_216.0)
+}
+
+/// Jittered point in cell `index` of a `columns × rows` grid over [0, 1)², for loops whose
+/// sample count is fixed up front; `jitter` places the point inside its cell
+pub fn stratified_2d(index: u32, columns: u32, rows: u32, jitter: (f32, f32)) -> (f32, f32) {
+    let column = index % columns;
+    let row = (index / columns) % rows;
+    (
+        (column as f32 + jitter.0) / columns as f32,
+        (row as f32 + jitter.1) / rows as f32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sixteen_samples_fill_every_cell_of_a_four_by_four_grid() {
+        for dimension in 0..3 {
+            let mut cells = [[0; 4]; 4];
+            for index in 0..16 {
+                let mut sampler = Sampler::for_pixel(7, 3, 5, index);
+                let (x, y) = (0..=dimension).map(|_| sampler.next_2d()).last().unwrap();
+                assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+                cells[(y * 4.0) as usize][(x * 4.0) as usize] += 1;
+            }
+            assert_eq!(cells, [[1; 4]; 4], "dimension pair {}", dimension);
+        }
+    }
+
+    #[test]
+    fn pixels_are_scrambled_apart_but_repeat_exactly() {
+        let first = |x, y| Sampler::for_pixel(7, x, y, 0).next_2d();
+        assert_eq!(first(3, 5), first(3, 5));
+        assert_ne!(first(3, 5), first(4, 5));
+        assert_ne!(first(3, 5), first(5, 3));
+    }
+
+    #[test]
+    fn stratified_points_stay_in_their_cell() {
+        assert_eq!(stratified_2d(0, 4, 2, (0.5, 0.5)), (0.125, 0.25));
+        assert_eq!(stratified_2d(5, 4, 2, (0.0, 0.0)), (0.25, 0.5));
+        // Indices past the grid wrap around to its start
+        assert_eq!(stratified_2d(8, 4, 2, (0.0, 0.0)), (0.0, 0.0));
+    }
+}
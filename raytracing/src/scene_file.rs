@@ -0,0 +1,656 @@
+// Scene files, loaded with `cargo run -- --scene-file my.ron` (F12 reloads them): a way to
+// build dioramas without recompiling or the scripting feature. RON, or the same structure in
+// JSON for a `.json` file:
+//
+//     (
+//         camera: (eye: (0, 4, -10), look_at: (0, 1, 0)),
+//         lights: [
+//             (position: (0, 8, -2), color: (255, 240, 200), intensity: 3.0),
+//             (position: (-4, 10, -2), color: (255, 250, 235), intensity: 1.2, kind: Sun(target: (0, 0, 0))),
+//             (position: (0, 5, 0), color: (255, 240, 200), intensity: 2, kind: Area(u: (1, 0, 0), v: (0, 0, 1))),
+//         ],
+//         materials: {
+//             "ruby": (diffuse: (0.9, 0.1, 0.2), specular: 96, albedo: (0.3, 0.3, 0.3, 0.1), ior: 1.7),
+//             "marble": (texture: "Piedra.png"),
+//         },
+//         room: (corner: (-4, 0, -5), opposite: (4, 5, 4), wall: 1),   // for portal culling
+//         portals: [(corner: (-4, 0, -5), opposite: (4, 5, -5))],     // openings in the room
+//         objects: [
+//             Block(at: (0, 0, 0), material: "piedra"),       // a material above or from materials.txt
+//             Fill(from: (-4, 0, -4), to: (4, 0, 4), material: "tierra"),  // every block of the box
+//             Shell(from: (-4, 1, -4), to: (4, 4, 4), material: "piedra"), // only its outer layer
+//             Block(at: (2, 0, 0), material: "ruby", faces: ("ruby", "ruby", "grass", "dirt", "ruby", "ruby")),
+//             Sphere(center: (0, 2, 0), radius: 0.5, material: "marble"),
+//             Color(at: (0, 1, 0), color: (0.8, 0.2, 0.2)),                 // plain block, color in [0, 1]
+//             Glow(at: (2, 1, 0), color: (1.0, 0.7, 0.3), strength: 4.0),   // glowing block
+//         ],
+//     )
+//
+// Blocks take an optional `size` (the edge, 1 by default; fills step by it). Lights take an
+// optional `falloff` (`InverseSquare(k: K)`, `Linear(range: R)`, `Limited(k: K, range: R)`)
+// and `spot: (direction: (X, Y, Z), inner: A, outer: B)` with angles in radians; the first
+// light is the one the hotkeys edit. Materials take `diffuse`, `specular`, `albedo`, `ior`,
+// `emission`, `metallic`, `translucency` and `texture`; `preset: "NAME"` makes their blocks
+// preset blocks (textures and ID) drawn with these values instead. `faces` (+x -x +y -y +z -z)
+// only takes materials declared in the file. Shift+T saves the scene on screen in this
+// format (see `write`).
+
+use crate::camera::Camera;
+use crate::light::{Falloff, Light, LightKind, Spot};
+use crate::material::{Material, MaterialTable};
+use crate::material_library::{MaterialLibrary, material_id};
+use crate::ray_intersect::SceneObject;
+use crate::script::{ScriptScene, Spawn};
+use raylib::prelude::{Color, Vector3};
+use ron::extensions::Extensions;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Fills can't spawn without bound
+const MAX_BLOCKS: usize = 500_000;
+
+/// Which syntax a scene file is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ron,
+    Json,
+}
+
+impl Format {
+    /// `.ron` or `.json`, None for any other file
+    pub fn of(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => Some(Format::Ron),
+            Some("json") => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A scene file as written, before its numbers are checked and its fills counted out
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<CameraFile>,
+    #[serde(default)]
+    pub lights: Vec<LightFile>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<RoomFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub portals: Vec<PortalFile>,
+    #[serde(default)]
+    pub objects: Vec<ObjectFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraFile {
+    pub eye: [f32; 3],
+    pub look_at: [f32; 3],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightFile {
+    pub position: [f32; 3],
+    pub color: [f32; 3], // 0-255 like everywhere else lights are configured
+    pub intensity: f32,
+    #[serde(default, skip_serializing_if = "is_point")]
+    pub kind: LightKindFile,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub falloff: Option<FalloffFile>, // `Falloff::default()` when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spot: Option<SpotFile>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum LightKindFile {
+    #[default]
+    Point,
+    Sun {
+        target: [f32; 3],
+    },
+    Area {
+        u: [f32; 3],
+        v: [f32; 3],
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum FalloffFile {
+    InverseSquare { k: f32 },
+    Linear { range: f32 },
+    Limited { k: f32, range: f32 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpotFile {
+    pub direction: [f32; 3],
+    pub inner: f32, // Radians
+    pub outer: f32,
+}
+
+/// A declared material; left-out values are a plain white block's
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaterialFile {
+    pub diffuse: [f32; 3],
+    pub specular: f32,
+    pub albedo: [f32; 4],
+    pub ior: f32,
+    pub emission: [f32; 3],
+    pub metallic: f32,
+    pub translucency: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub texture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>, // Blocks are this preset's, drawn with these values
+}
+
+impl Default for MaterialFile {
+    fn default() -> Self {
+        MaterialFile {
+            diffuse: [1.0, 1.0, 1.0],
+            specular: 8.0,
+            albedo: [0.95, 0.05, 0.0, 0.0],
+            ior: 1.0,
+            emission: [0.0; 3],
+            metallic: 0.0,
+            translucency: 0.0,
+            texture: None,
+            preset: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoomFile {
+    pub corner: [f32; 3],
+    pub opposite: [f32; 3],
+    pub wall: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortalFile {
+    pub corner: [f32; 3],
+    pub opposite: [f32; 3],
+}
+
+/// One entry of `objects`, in scene order
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum ObjectFile {
+    Block {
+        at: [f32; 3],
+        material: String,
+        #[serde(default = "unit", skip_serializing_if = "is_unit")]
+        size: f32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        faces: Option<[String; 6]>,
+    },
+    Fill {
+        from: [f32; 3],
+        to: [f32; 3],
+        material: String,
+        #[serde(default = "unit", skip_serializing_if = "is_unit")]
+        size: f32,
+    },
+    Shell {
+        from: [f32; 3],
+        to: [f32; 3],
+        material: String,
+        #[serde(default = "unit", skip_serializing_if = "is_unit")]
+        size: f32,
+    },
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        material: String,
+    },
+    Color {
+        at: [f32; 3],
+        color: [f32; 3],
+        #[serde(default = "unit", skip_serializing_if = "is_unit")]
+        size: f32,
+    },
+    Glow {
+        at: [f32; 3],
+        color: [f32; 3],
+        strength: f32,
+        #[serde(default = "unit", skip_serializing_if = "is_unit")]
+        size: f32,
+    },
+}
+
+fn unit() -> f32 {
+    1.0
+}
+
+fn is_unit(size: &f32) -> bool {
+    *size == 1.0
+}
+
+fn is_point(kind: &LightKindFile) -> bool {
+    *kind == LightKindFile::Point
+}
+
+// `Some(..)` may be left out around optional values
+fn ron_options() -> ron::Options {
+    ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME)
+}
+
+// A declared material as the blocks that use it are built
+struct Declared {
+    material: Material,
+    texture: Option<String>,
+    preset: Option<String>, // Blocks are this preset's, drawn with `material`
+}
+
+/// Reads a scene file into the blocks, camera and lights it describes. Syntax errors carry
+/// the line; numbers that aren't finite are refused after reading, naming the entry.
+pub fn parse(text: &str, format: Format) -> Result<ScriptScene, String> {
+    let file: SceneFile = match format {
+        Format::Ron => ron_options().from_str(text).map_err(|err| err.to_string())?,
+        Format::Json => serde_json::from_str(text).map_err(|err| err.to_string())?,
+    };
+    file.into_scene()
+}
+
+impl SceneFile {
+    /// Checks the numbers and counts out the fills
+    pub fn into_scene(self) -> Result<ScriptScene, String> {
+        let mut scene = ScriptScene::default();
+        if let Some(camera) = &self.camera {
+            let (eye, center) = (point("camera", camera.eye)?, point("camera", camera.look_at)?);
+            scene.camera = Some(Camera::new(eye, center, Vector3::new(0.0, 1.0, 0.0)));
+        }
+        for (index, light) in self.lights.iter().enumerate() {
+            scene.lights.push(light.to_light(&format!("light {}", index + 1))?);
+        }
+        let mut materials: BTreeMap<&str, Declared> = BTreeMap::new();
+        for (name, declared) in &self.materials {
+            materials.insert(name, declared.to_declared(&format!("material `{}`", name))?);
+        }
+        if let Some(room) = &self.room {
+            scene.room(point("room", room.corner)?, point("room", room.opposite)?, number("room", room.wall)?.max(0.0));
+        }
+        for (index, portal) in self.portals.iter().enumerate() {
+            let what = format!("portal {}", index + 1);
+            scene.portal(point(&what, portal.corner)?, point(&what, portal.opposite)?).map_err(|err| format!("{}: {}", what, err))?;
+        }
+
+        let mut count = 0;
+        for (index, object) in self.objects.iter().enumerate() {
+            let what = format!("object {}", index + 1);
+            let mut spawn = |center: Vector3, size: f32, name: &str| -> Result<Spawn, String> {
+                count += 1;
+                if count > MAX_BLOCKS {
+                    return Err(format!("{}: more than {} blocks", what, MAX_BLOCKS));
+                }
+                Ok(match materials.get(name) {
+                    Some(Declared { material, preset: Some(preset), .. }) => Spawn::Preset { center, size, name: preset.clone(), look: Some(*material) },
+                    Some(Declared { material, texture, preset: None }) => Spawn::Plain { center, size, material: *material, texture: texture.clone() },
+                    None => Spawn::Preset { center, size, name: name.to_string(), look: None },
+                })
+            };
+
+            match object {
+                ObjectFile::Block { at, material, size, faces } => {
+                    let center = point(&what, *at)?;
+                    scene.push(spawn(center, number(&what, *size)?.max(0.01), material)?);
+                    if let Some(faces) = faces {
+                        let face = |name: &String| {
+                            materials.get(name.as_str()).map(|declared| declared.material).ok_or_else(|| format!("{}: `{}` is not a material of this file", what, name))
+                        };
+                        scene.set_faces([face(&faces[0])?, face(&faces[1])?, face(&faces[2])?, face(&faces[3])?, face(&faces[4])?, face(&faces[5])?]);
+                    }
+                }
+                ObjectFile::Fill { from, to, material, size } | ObjectFile::Shell { from, to, material, size } => {
+                    let shell = matches!(object, ObjectFile::Shell { .. });
+                    let (from, to, size) = (point(&what, *from)?, point(&what, *to)?, number(&what, *size)?.max(0.01));
+                    let min = Vector3::new(from.x.min(to.x), from.y.min(to.y), from.z.min(to.z));
+                    let max = Vector3::new(from.x.max(to.x), from.y.max(to.y), from.z.max(to.z));
+                    let steps = |low: f32, high: f32| ((high - low) / size).round() as usize;
+                    let (nx, ny, nz) = (steps(min.x, max.x), steps(min.y, max.y), steps(min.z, max.z));
+                    // A box too big on its own is refused before spawning any of it, counting it
+                    // out would take long
+                    let total = [nx, ny, nz].iter().fold(1usize, |total, &n| total.saturating_mul(n.saturating_add(1)));
+                    let inner = if shell { [nx, ny, nz].iter().fold(1usize, |inner, &n| inner.saturating_mul(n.saturating_sub(1))) } else { 0 };
+                    if total - inner > MAX_BLOCKS {
+                        return Err(format!("{}: more than {} blocks", what, MAX_BLOCKS));
+                    }
+                    for x in 0..=nx {
+                        for y in 0..=ny {
+                            for z in 0..=nz {
+                                let outside = x == 0 || x == nx || y == 0 || y == ny || z == 0 || z == nz;
+                                if shell && !outside {
+                                    continue;
+                                }
+                                let center = min + Vector3::new(x as f32, y as f32, z as f32) * size;
+                                scene.push(spawn(center, size, material)?);
+                            }
+                        }
+                    }
+                }
+                ObjectFile::Sphere { center, radius, material } => {
+                    let (center, radius) = (point(&what, *center)?, number(&what, *radius)?.max(0.005));
+                    scene.push_sphere(spawn(center, radius * 2.0, material)?);
+                }
+                ObjectFile::Color { at, color, size } | ObjectFile::Glow { at, color, size, .. } => {
+                    let (center, diffuse, size) = (point(&what, *at)?, point(&what, *color)?, number(&what, *size)?.max(0.01));
+                    let mut material = Material::new(diffuse, 8.0, [0.95, 0.05, 0.0, 0.0], 1.0);
+                    if let ObjectFile::Glow { strength, .. } = object {
+                        material.emission = diffuse * number(&what, *strength)?;
+                    }
+                    count += 1;
+                    scene.push(Spawn::Plain { center, size, material, texture: None });
+                }
+            }
+        }
+        Ok(scene)
+    }
+}
+
+impl LightFile {
+    fn to_light(&self, what: &str) -> Result<Light, String> {
+        let channel = |value: f32| value.clamp(0.0, 255.0) as u8;
+        let color = point(what, self.color)?;
+        let mut light = Light::new(point(what, self.position)?, Color::new(channel(color.x), channel(color.y), channel(color.z), 255), number(what, self.intensity)?);
+        light = match &self.kind {
+            LightKindFile::Point => light,
+            LightKindFile::Sun { target } => light.with_kind(LightKind::Directional { target: point(what, *target)? }),
+            LightKindFile::Area { u, v } => light.with_kind(LightKind::Area { u: point(what, *u)?, v: point(what, *v)? }),
+        };
+        if let Some(falloff) = &self.falloff {
+            light.falloff = match *falloff {
+                FalloffFile::InverseSquare { k } => Falloff::InverseSquare { k: number(what, k)? },
+                FalloffFile::Linear { range } => Falloff::Linear { range: number(what, range)? },
+                FalloffFile::Limited { k, range } => Falloff::RangeLimited { k: number(what, k)?, range: number(what, range)? },
+            };
+        }
+        if let Some(spot) = &self.spot {
+            light.spot = Some(Spot::new(point(what, spot.direction)?, number(what, spot.inner)?, number(what, spot.outer)?));
+        }
+        Ok(light)
+    }
+
+    // The light as written back, values only where they differ from `Light::new`
+    fn from_light(light: &Light) -> Self {
+        let kind = match light.kind {
+            LightKind::Point => LightKindFile::Point,
+            LightKind::Directional { target } => LightKindFile::Sun { target: array(target) },
+            LightKind::Area { u, v } => LightKindFile::Area { u: array(u), v: array(v) },
+        };
+        let falloff = (light.falloff != Falloff::default()).then_some(match light.falloff {
+            Falloff::InverseSquare { k } => FalloffFile::InverseSquare { k },
+            Falloff::Linear { range } => FalloffFile::Linear { range },
+            Falloff::RangeLimited { k, range } => FalloffFile::Limited { k, range },
+        });
+        LightFile {
+            position: array(light.position),
+            color: [light.color.r as f32, light.color.g as f32, light.color.b as f32],
+            intensity: light.intensity,
+            kind,
+            falloff,
+            spot: light.spot.map(|spot| SpotFile { direction: array(spot.direction), inner: spot.inner_cos.acos(), outer: spot.outer_cos.acos() }),
+        }
+    }
+}
+
+impl MaterialFile {
+    fn to_declared(&self, what: &str) -> Result<Declared, String> {
+        let mut material = Material::new(point(what, self.diffuse)?, number(what, self.specular)?, self.albedo, number(what, self.ior)?);
+        finite(what, &self.albedo)?;
+        material.emission = point(what, self.emission)?;
+        material.metallic = number(what, self.metallic)?.clamp(0.0, 1.0);
+        material.translucency = number(what, self.translucency)?.clamp(0.0, 1.0);
+        Ok(Declared { material, texture: self.texture.clone(), preset: self.preset.clone() })
+    }
+
+    fn from_material(material: &Material, preset: Option<String>) -> Self {
+        MaterialFile {
+            diffuse: array(material.diffuse),
+            specular: material.specular,
+            albedo: material.albedo,
+            ior: material.refractive_index,
+            emission: array(material.emission),
+            metallic: material.metallic,
+            translucency: material.translucency,
+            texture: None,
+            preset,
+        }
+    }
+}
+
+// Every value of an entry has to be a finite number
+fn finite(what: &str, values: &[f32]) -> Result<(), String> {
+    match values.iter().find(|value| !value.is_finite()) {
+        Some(value) => Err(format!("{}: `{}` is not a finite number", what, value)),
+        None => Ok(()),
+    }
+}
+
+fn number(what: &str, value: f32) -> Result<f32, String> {
+    finite(what, &[value]).map(|_| value)
+}
+
+fn point(what: &str, [x, y, z]: [f32; 3]) -> Result<Vector3, String> {
+    finite(what, &[x, y, z]).map(|_| Vector3::new(x, y, z))
+}
+
+fn array(v: Vector3) -> [f32; 3] {
+    [v.x, v.y, v.z]
+}
+
+/// The scene as a `SceneFile`: camera, lights, then the blocks in order and the spheres.
+/// Objects drawn with a library preset are written by name, or with a declared material
+/// naming the preset when it was tweaked live or they carry a look of their own. Any other
+/// material, per-face ones included, is declared; textures of those have no file name to
+/// write and are left out.
+pub fn to_file(objects: &[SceneObject], materials: &MaterialTable, library: &MaterialLibrary, lights: &[Light], camera: &Camera) -> SceneFile {
+    let mut declared: Vec<(u32, u32)> = Vec::new(); // Entries written as declared materials and their preset's ID, in order
+    let mut file = SceneFile {
+        camera: Some(CameraFile { eye: array(camera.eye), look_at: array(camera.eye + camera.forward) }),
+        lights: lights.iter().map(LightFile::from_light).collect(),
+        ..SceneFile::default()
+    };
+    for block in objects.iter().filter_map(|object| object.as_cube()) {
+        let material = material_name(block.material, block.material_id, materials, library, &mut declared);
+        let faces = block.face_materials.map(|faces| faces.map(|face| material_name(face, 0, materials, library, &mut declared)));
+        file.objects.push(ObjectFile::Block { at: array(block.center), material, size: block.size, faces });
+    }
+    for sphere in objects.iter().filter_map(|object| object.as_sphere()) {
+        // Spheres aren't tagged with a preset, their entry tells
+        let id = library.names().map(|name| material_id(name)).find(|&id| materials.preset(id) == Some(sphere.material)).unwrap_or(0);
+        let material = material_name(sphere.material, id, materials, library, &mut declared);
+        file.objects.push(ObjectFile::Sphere { center: array(sphere.center), radius: sphere.radius, material });
+    }
+    for (index, &(entry, id)) in declared.iter().enumerate() {
+        let preset = library.name_of(id).map(str::to_string);
+        file.materials.insert(format!("material_{}", index), MaterialFile::from_material(&materials[entry], preset));
+    }
+    file
+}
+
+/// The scene as RON text `parse` reads back (see `to_file`), one object per line
+pub fn to_text(objects: &[SceneObject], materials: &MaterialTable, library: &MaterialLibrary, lights: &[Light], camera: &Camera) -> String {
+    let file = to_file(objects, materials, library, lights, camera);
+    let pretty = PrettyConfig::new().depth_limit(2);
+    let text = ron_options().to_string_pretty(&file, pretty).expect("scene files hold only numbers, strings and lists");
+    format!("// Saved scene, load it with --scene-file\n{}\n", text)
+}
+
+/// Saves the scene to a RON file (see `to_text`), returns how many blocks and spheres it holds
+pub fn write(path: &Path, objects: &[SceneObject], materials: &MaterialTable, library: &MaterialLibrary, lights: &[Light], camera: &Camera) -> io::Result<usize> {
+    fs::write(path, to_text(objects, materials, library, lights, camera))?;
+    Ok(objects.iter().filter(|object| object.as_cube().is_some() || object.as_sphere().is_some()).count())
+}
+
+// What an object drawn with table entry `entry` is written with: the name of its preset (ID
+// `id`, 0 for none) when it draws with the preset as loaded, else a `material_N` declared
+// for the entry
+fn material_name(entry: u32, id: u32, materials: &MaterialTable, library: &MaterialLibrary, declared: &mut Vec<(u32, u32)>) -> String {
+    if let Some(name) = library.name_of(id) {
+        let edited = library.get(name).is_some_and(|preset| preset.edited);
+        if materials.preset(id) == Some(entry) && !edited {
+            return name.to_string();
+        }
+    }
+    let index = declared.iter().position(|&written| written == (entry, id)).unwrap_or_else(|| {
+        declared.push((entry, id));
+        declared.len() - 1
+    });
+    format!("material_{}", index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material_library::MaterialEdit;
+    use crate::render_thread::BuiltScene;
+
+    fn error(text: &str) -> String {
+        parse(text, Format::Ron).err().expect("the scene should be refused")
+    }
+
+    #[test]
+    fn reads_ron() {
+        let text = r#"
+            (
+                camera: (eye: (0, 4, -10), look_at: (0, 1, 0)),
+                lights: [(position: (0, 8, -2), color: (255, 240, 200), intensity: 3.0, falloff: Limited(k: 1, range: 12))],
+                materials: {"ruby": (diffuse: (0.9, 0.1, 0.2), ior: 1.7)},
+                objects: [
+                    Block(at: (0, 0, 0), material: "ruby"), // a comment
+                    Fill(from: (-1, 0, -1), to: (1, 0, 1), material: "piedra"),
+                    Shell(from: (0, 0, 0), to: (2, 2, 2), material: "tierra"),
+                    Glow(at: (2, 1, 0), color: (1.0, 0.7, 0.3), strength: 4.0, size: 0.5),
+                ],
+            )"#;
+        let scene = parse(text, Format::Ron).unwrap();
+        assert!(scene.camera.is_some());
+        assert_eq!(scene.lights.len(), 1);
+        assert_eq!(scene.lights[0].falloff, Falloff::RangeLimited { k: 1.0, range: 12.0 });
+        // 1 block, a 3×3 fill, a 3×3×3 shell without its center and a glowing block
+        assert_eq!(scene.block_count(), 1 + 9 + 26 + 1);
+        assert!(scene.portals.is_none());
+    }
+
+    #[test]
+    fn reads_json() {
+        let text = r#"{
+            "lights": [{"position": [0, 8, 0], "color": [255, 255, 255], "intensity": 1, "kind": {"Sun": {"target": [0, 0, 0]}}}],
+            "objects": [{"Fill": {"from": [0, 0, 0], "to": [1, 0, 0], "material": "piedra"}}]
+        }"#;
+        let scene = parse(text, Format::Json).unwrap();
+        assert!(matches!(scene.lights[0].kind, LightKind::Directional { .. }));
+        assert_eq!(scene.block_count(), 2);
+        let short = parse(r#"{"objects": [{"Block": {"at": [0, 0], "material": "piedra"}}]}"#, Format::Json).err();
+        assert!(short.is_some_and(|err| err.contains("line 1")));
+    }
+
+    #[test]
+    fn refuses_unknown_names() {
+        assert!(error("(objects: [Cube(at: (0, 0, 0))])").contains("Cube"));
+        assert!(error(r#"(materials: {"ruby": (shine: 3)})"#).contains("shine"));
+        assert!(error("(lights: [(position: (0, 8, 0), color: (255, 255, 255), intensity: 1, falloff: Cubic(k: 2))])").contains("Cubic"));
+        assert!(error("(scale: 2)").contains("scale"));
+    }
+
+    #[test]
+    fn refuses_missing_values() {
+        assert!(error("(camera: (eye: (0, 4, -10)))").contains("look_at"));
+        assert!(error("(objects: [Block(at: (0, 0, 0))])").contains("material"));
+        assert!(error(r#"(objects: [Block(at: (0, "zero", 0), material: "piedra")])"#).starts_with("1:"));
+    }
+
+    #[test]
+    fn refuses_numbers_that_are_not_finite() {
+        assert_eq!(error(r#"(objects: [Fill(from: (-inf, 0, 0), to: (inf, 0, 0), material: "piedra")])"#), "object 1: `-inf` is not a finite number");
+        assert_eq!(error(r#"(objects: [Block(at: (0, 0, 0), material: "piedra"), Block(at: (0, NaN, 0), material: "piedra")])"#), "object 2: `NaN` is not a finite number");
+        assert_eq!(error("(lights: [(position: (0, 8, 0), color: (255, 255, 255), intensity: inf)])"), "light 1: `inf` is not a finite number");
+        assert_eq!(error(r#"(materials: {"ruby": (albedo: (0.3, NaN, 0.3, 0.1))})"#), "material `ruby`: `NaN` is not a finite number");
+    }
+
+    #[test]
+    fn caps_the_block_count() {
+        let side = (MAX_BLOCKS as f32).cbrt() as usize + 1;
+        let fill = |kind: &str, to: String| format!(r#"{}(from: (0, 0, 0), to: ({}), material: "piedra")"#, kind, to);
+        let scene = |objects: Vec<String>| format!("(objects: [{}])", objects.join(", "));
+        assert_eq!(error(&scene(vec![fill("Fill", format!("{0}, {0}, {0}", side))])), format!("object 1: more than {} blocks", MAX_BLOCKS));
+        // Shells only count their outer layer
+        assert!(parse(&scene(vec![fill("Shell", format!("{0}, {0}, {0}", side))]), Format::Ron).is_ok());
+        // Blocks add up across objects
+        let half = fill("Fill", format!("{}, 0, 0", MAX_BLOCKS / 2 - 1));
+        assert!(parse(&scene(vec![half.clone()]), Format::Ron).is_ok());
+        assert_eq!(error(&scene(vec![half; 3])), format!("object 3: more than {} blocks", MAX_BLOCKS));
+    }
+
+    #[test]
+    fn reads_rooms_and_portals() {
+        assert_eq!(error("(portals: [(corner: (-4, 0, -5), opposite: (4, 5, -5))])"), "portal 1: portal before any room");
+        let scene = parse("(room: (corner: (-4, 0, -5), opposite: (4, 5, 4), wall: 1), portals: [(corner: (-4, 0, -5), opposite: (4, 5, -5))])", Format::Ron).unwrap();
+        assert!(scene.portals.is_some());
+        assert!(error("(room: (corner: (-4, 0, -5), opposite: (4, 5, 4), wall: 1), portals: [(corner: (-4, 0, -5), opposite: (4, 5, -4))])").starts_with("portal 1: a portal is a flat rectangle"));
+    }
+
+    #[test]
+    fn reads_faces_spheres_and_preset_looks() {
+        let text = r#"(
+            materials: {"lit": (preset: "piedra", emission: (2, 2, 2))},
+            objects: [
+                Block(at: (0, 0, 0), material: "lit", faces: ("lit", "lit", "lit", "lit", "lit", "lit")),
+                Sphere(center: (0, 2, 0), radius: 0.5, material: "piedra"),
+            ],
+        )"#;
+        assert_eq!(parse(text, Format::Ron).unwrap().block_count(), 1);
+        assert_eq!(
+            error(r#"(objects: [Block(at: (0, 0, 0), material: "piedra", faces: ("piedra", "piedra", "piedra", "piedra", "piedra", "piedra"))])"#),
+            "object 1: `piedra` is not a material of this file"
+        );
+    }
+
+    #[test]
+    fn saves_what_it_reads() {
+        let build = |text: &str, library: &MaterialLibrary| {
+            let BuiltScene { objects, materials, .. } = parse(text, Format::Ron).unwrap().into_builder()(library);
+            (objects, materials)
+        };
+        let mut library = MaterialLibrary::builtin();
+        let text = r#"(
+            materials: {"ruby": (diffuse: (0.9, 0.1, 0.2), ior: 1.7), "lit": (emission: (2, 2, 2))},
+            objects: [
+                Block(at: (0, 0, 0), material: "piedra"),
+                Block(at: (1, 0, 0), material: "ruby", size: 0.5, faces: ("ruby", "ruby", "lit", "ruby", "ruby", "ruby")),
+                Sphere(center: (0, 2, 0), radius: 0.5, material: "piedra"),
+            ],
+        )"#;
+        let (objects, mut materials) = build(text, &library);
+        // A live edit is kept even though the reloaded library doesn't have it
+        library.edit("piedra", MaterialEdit::Specular(10.0), &mut materials);
+        let camera = Camera::new(Vector3::new(0.0, 4.0, -10.0), Vector3::zero(), Vector3::new(0.0, 1.0, 0.0));
+        let (reloaded, reloaded_materials) = build(&to_text(&objects, &materials, &library, &[], &camera), &MaterialLibrary::builtin());
+
+        assert_eq!(reloaded.len(), objects.len());
+        for (object, again) in objects.iter().zip(&reloaded) {
+            assert_eq!(object.bounds(), again.bounds());
+            for normal in [Vector3::new(0.0, 1.0, 0.0), Vector3::new(1.0, 0.0, 0.0)] {
+                assert_eq!(object.material_for(normal, &materials), again.material_for(normal, &reloaded_materials));
+            }
+        }
+        assert_eq!(reloaded[0].as_cube().unwrap().material_id, material_id("piedra"));
+        assert!(reloaded[2].as_sphere().is_some());
+    }
+}
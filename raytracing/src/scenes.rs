@@ -0,0 +1,339 @@
+use crate::animation::{ObjectTrack, Timeline, Track};
+use crate::camera::Camera;
+use crate::cube::Cube;
+use crate::light::{Light, LightKind};
+use crate::material::{Material, MaterialTable};
+use crate::material_library::MaterialLibrary;
+use crate::portals::Portals;
+use crate::ray_intersect::{SceneObject, scene_objects};
+use crate::render_thread::{BuiltScene, SceneBuilder};
+use crate::rng::mix64;
+use crate::sphere::Sphere;
+use crate::{ELEVATOR_BLOCKS, bake_objects, create_camera, create_cave_lamp, create_diorama, create_light, diorama_portals, finish_scene};
+use raylib::prelude::*;
+
+// Cave elevator: from the cave floor up to just under the skylight pool, speed in units
+// per second and seconds it waits at either end
+const ELEVATOR_RISE: f32 = 4.0;
+const ELEVATOR_SPEED: f32 = 1.5;
+const ELEVATOR_DWELL: f32 = 2.0;
+
+/// A built-in scene: how to build its blocks and the other shapes placed after them (round
+/// props), what moves among the blocks, its portals, where its camera and main light start
+/// and the fixed lamps lit alongside the main light
+pub struct SceneEntry {
+    pub name: &'static str,
+    pub build: fn(&MaterialLibrary, &mut MaterialTable) -> Vec<Cube>,
+    pub props: fn(&MaterialLibrary, &mut MaterialTable) -> Vec<SceneObject>,
+    pub animate: fn(&[Cube]) -> Timeline,
+    pub portals: fn() -> Option<Portals>,
+    pub camera: fn() -> Camera,
+    pub light: fn() -> Light,
+    pub lamps: fn() -> Vec<Light>,
+}
+
+/// Scenes offered by `--scene <name>` and the in-app scene menu; the first is the default
+pub const GALLERY: [SceneEntry; 4] = [
+    SceneEntry {
+        name: "Cave diorama",
+        build: create_diorama,
+        props: cave_boulders,
+        animate: cave_elevator,
+        portals: cave_portals,
+        camera: create_camera,
+        light: create_light,
+        lamps: cave_lamps,
+    },
+    SceneEntry {
+        name: "Cube room",
+        build: cube_room,
+        props: no_props,
+        animate: still,
+        portals: no_portals,
+        camera: cube_room_camera,
+        light: cube_room_light,
+        lamps: no_lamps,
+    },
+    SceneEntry {
+        name: "Reflection test",
+        build: reflection_test,
+        props: reflection_test_balls,
+        animate: still,
+        portals: no_portals,
+        camera: reflection_test_camera,
+        light: reflection_test_light,
+        lamps: no_lamps,
+    },
+    SceneEntry {
+        name: "Terrain",
+        build: terrain,
+        props: no_props,
+        animate: still,
+        portals: no_portals,
+        camera: terrain_camera,
+        light: terrain_light,
+        lamps: no_lamps,
+    },
+];
+
+/// Gallery index for a scene name, ignoring case and spaces ("cube-room", "CubeRoom", ...)
+pub fn find_scene(name: &str) -> Option<usize> {
+    let simplify = |text: &str| {
+        text.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .collect::<String>()
+    };
+    let wanted = simplify(name);
+    GALLERY.iter().position(|entry| simplify(entry.name) == wanted)
+}
+
+/// Builds a gallery scene, its timeline and its portals, for the render thread
+pub fn gallery_builder(index: usize) -> SceneBuilder {
+    let entry = &GALLERY[index];
+    let (build, props, animate, portals) = (entry.build, entry.props, entry.animate, entry.portals);
+    Box::new(move |library| {
+        let mut materials = library.table();
+        let blocks = build(library, &mut materials);
+        let timeline = animate(&blocks);
+        // Props go after the blocks so the timeline's block indices still hold
+        let mut objects = scene_objects(blocks);
+        objects.extend(props(library, &mut materials));
+        bake_objects(&mut objects);
+        let portals = portals().map(|mut portals| {
+            portals.classify(&objects);
+            portals
+        });
+        BuiltScene { objects, materials, timeline, portals }
+    })
+}
+
+fn no_props(_library: &MaterialLibrary, _materials: &mut MaterialTable) -> Vec<SceneObject> {
+    Vec::new()
+}
+
+// Two stone boulders by the cave mouth and a glowing lamp ball on the other side
+fn cave_boulders(library: &MaterialLibrary, materials: &mut MaterialTable) -> Vec<SceneObject> {
+    let mut props: Vec<SceneObject> = Vec::new();
+    if let Some((stone, texture)) = library.block("piedra", materials) {
+        props.push(Box::new(Sphere::with_texture(Vector3::new(-3.0, 0.55, -3.2), 0.55, stone, texture.clone())));
+        props.push(Box::new(Sphere::with_texture(Vector3::new(-2.1, 0.35, -3.7), 0.35, stone, texture.clone())));
+    }
+    let mut lamp = plain(1.0, 0.9, 0.7);
+    lamp.emission = Vector3::new(1.6, 1.2, 0.6);
+    props.push(Box::new(Sphere::new(Vector3::new(3.0, 0.3, -3.3), 0.3, materials.share(lamp))));
+    props
+}
+
+fn no_lamps() -> Vec<Light> {
+    Vec::new()
+}
+
+// The lamp ball by the cave mouth gives off light, not just glow
+fn cave_lamps() -> Vec<Light> {
+    vec![create_cave_lamp()]
+}
+
+fn still(_objects: &[Cube]) -> Timeline {
+    Timeline::default()
+}
+
+// The diorama builds its elevator platform last, it shuttles up the shaft to the hole
+fn cave_elevator(objects: &[Cube]) -> Timeline {
+    let waypoints = [Vector3::zero(), Vector3::new(0.0, ELEVATOR_RISE, 0.0)];
+    let (offset, period) = Track::ping_pong(&waypoints, ELEVATOR_SPEED, ELEVATOR_DWELL);
+    let platform = ObjectTrack {
+        objects: (objects.len().saturating_sub(ELEVATOR_BLOCKS)..objects.len()).collect(),
+        period: Some(period),
+        offset,
+        ..ObjectTrack::default()
+    };
+    Timeline { objects: vec![platform], ..Timeline::default() }
+}
+
+fn no_portals() -> Option<Portals> {
+    None
+}
+
+fn cave_portals() -> Option<Portals> {
+    Some(diorama_portals())
+}
+
+fn plain(r: f32, g: f32, b: f32) -> Material {
+    Material::new(Vector3::new(r, g, b), 8.0, [0.95, 0.05, 0.0, 0.0], 1.0)
+}
+
+// Cornell-box-like room: white floor, ceiling and back wall, red and green side walls,
+// a glowing ceiling panel and two blocks, one metal and one glass
+fn cube_room(library: &MaterialLibrary, materials: &mut MaterialTable) -> Vec<Cube> {
+    const SIZE: i32 = 7;
+    let white = materials.share(plain(0.75, 0.75, 0.75));
+    let red = materials.share(plain(0.7, 0.12, 0.1));
+    let green = materials.share(plain(0.15, 0.6, 0.15));
+    let mut lamp = plain(1.0, 1.0, 1.0);
+    lamp.emission = Vector3::new(4.0, 3.8, 3.4);
+    let lamp = materials.share(lamp);
+
+    let mut cubes = Vec::new();
+    let half = SIZE / 2;
+    for a in -half..=half {
+        for b in -half..=half {
+            let (a, b) = (a as f32, b as f32);
+            let top = SIZE as f32;
+            // Floor, ceiling (with the lamp in the middle) and back wall
+            cubes.push(Cube::new(Vector3::new(a, -0.5, b), 1.0, white));
+            let ceiling = if a.abs() <= 1.0 && b.abs() <= 1.0 { lamp } else { white };
+            cubes.push(Cube::new(Vector3::new(a, top - 0.5, b), 1.0, ceiling));
+            cubes.push(Cube::new(Vector3::new(a, b + half as f32 + 0.5, half as f32 + 1.0), 1.0, white));
+            // Side walls
+            cubes.push(Cube::new(Vector3::new(-(half as f32) - 1.0, b + half as f32 + 0.5, a), 1.0, red));
+            cubes.push(Cube::new(Vector3::new(half as f32 + 1.0, b + half as f32 + 0.5, a), 1.0, green));
+        }
+    }
+
+    // A tall metal block at the back left and a glass block at the front right
+    for y in 0..3 {
+        let center = Vector3::new(-1.5, 0.5 + y as f32, 1.5);
+        cubes.push(library.cube("hierro", center, 1.0, materials).unwrap_or_else(|| Cube::new(center, 1.0, materials.share(plain(0.6, 0.6, 0.6)))));
+    }
+    let glass = materials.share(Material::new(Vector3::new(0.9, 0.95, 1.0), 96.0, [0.05, 0.3, 0.1, 0.8], 1.5));
+    cubes.push(Cube::new(Vector3::new(1.5, 0.5, -1.0), 1.0, glass));
+
+    finish_scene(&mut cubes, library);
+    cubes
+}
+
+fn cube_room_camera() -> Camera {
+    Camera::new(Vector3::new(0.0, 3.5, -9.0), Vector3::new(0.0, 3.0, 0.0), Vector3::new(0.0, 1.0, 0.0))
+}
+
+// The ceiling panel's glowing blocks light the room themselves; this is a faint fill across
+// the whole panel for the light hotkeys to turn up
+fn cube_room_light() -> Light {
+    Light::new(Vector3::new(0.0, 5.9, 0.0), Color::new(255, 240, 220, 255), 0.3)
+        .with_kind(LightKind::Area { u: Vector3::new(2.8, 0.0, 0.0), v: Vector3::new(0.0, 0.0, 2.8) })
+}
+
+// Checkered floor with a row of blocks going from matte to mirror, plus gold and glass
+fn reflection_test(library: &MaterialLibrary, materials: &mut MaterialTable) -> Vec<Cube> {
+    let light_tile = materials.share(plain(0.85, 0.85, 0.85));
+    let dark_tile = materials.share(plain(0.2, 0.2, 0.22));
+    let mut cubes = Vec::new();
+    for x in -8..=8 {
+        for z in -6..=6 {
+            let tile = if (x + z) % 2 == 0 { light_tile } else { dark_tile };
+            cubes.push(Cube::new(Vector3::new(x as f32, -0.5, z as f32), 1.0, tile));
+        }
+    }
+
+    // Reflectivity 0 -> 1 from left to right
+    for step in 0..5 {
+        let reflection = step as f32 / 4.0;
+        let material = Material::new(
+            Vector3::new(0.8, 0.3, 0.25),
+            16.0 + 100.0 * reflection,
+            [1.0 - reflection, 0.2, reflection, 0.0],
+            1.0,
+        );
+        cubes.push(Cube::new(Vector3::new(-6.0 + step as f32 * 3.0, 0.5, 0.0), 1.0, materials.share(material)));
+    }
+
+    // Metals and a refractive block behind the row
+    let glass = Material::new(Vector3::new(0.9, 0.95, 1.0), 96.0, [0.05, 0.3, 0.1, 0.8], 1.5);
+    for (x, preset, fallback) in [(-3.0, Some("oro"), plain(1.0, 0.8, 0.3)), (0.0, None, glass), (3.0, Some("hierro"), plain(0.6, 0.6, 0.6))] {
+        for y in [0.5, 1.5] {
+            let center = Vector3::new(x, y, 3.0);
+            cubes.push(preset.and_then(|name| library.cube(name, center, 1.0, materials)).unwrap_or_else(|| Cube::new(center, 1.0, materials.share(fallback))));
+        }
+    }
+
+    finish_scene(&mut cubes, library);
+    cubes
+}
+
+// A mirror ball and a glass ball in front of the row
+fn reflection_test_balls(_library: &MaterialLibrary, materials: &mut MaterialTable) -> Vec<SceneObject> {
+    let glass = Material::new(Vector3::new(0.9, 0.95, 1.0), 96.0, [0.05, 0.3, 0.1, 0.8], 1.5);
+    let mirror = materials.share(Material::look("mirror").unwrap_or(glass));
+    let glass = materials.share(glass);
+    vec![Box::new(Sphere::new(Vector3::new(-4.5, 0.8, -3.0), 0.8, mirror)), Box::new(Sphere::new(Vector3::new(4.5, 0.8, -3.0), 0.8, glass))]
+}
+
+fn reflection_test_camera() -> Camera {
+    Camera::new(Vector3::new(0.0, 3.0, -9.0), Vector3::new(0.0, 0.5, 1.0), Vector3::new(0.0, 1.0, 0.0))
+}
+
+fn reflection_test_light() -> Light {
+    Light::new(Vector3::new(-4.0, 8.0, -4.0), Color::new(255, 255, 255, 255), 3.0)
+}
+
+// Rolling hills of dirt over stone with a few gold outcrops and water in the lowest valleys,
+// heights from smooth value noise
+fn terrain(library: &MaterialLibrary, materials: &mut MaterialTable) -> Vec<Cube> {
+    const RADIUS: i32 = 12;
+    let mut cubes = Vec::new();
+    if !library.has_block("tierra") {
+        println!("ERROR: Could not load Tierra texture!");
+        return cubes;
+    }
+    let stone = if library.has_block("piedra") { "piedra" } else { "tierra" };
+
+    for x in -RADIUS..=RADIUS {
+        for z in -RADIUS..=RADIUS {
+            let height = terrain_height(x, z);
+            for y in 0..=height {
+                let center = Vector3::new(x as f32, y as f32 + 0.5, z as f32);
+                let name = if y == height {
+                    "tierra"
+                } else if library.get("oro").is_some() && mix64(((x as u64) << 32) ^ (z as u64) ^ ((y as u64) << 48)) % 29 == 0 {
+                    "oro"
+                } else {
+                    stone
+                };
+                cubes.extend(library.cube(name, center, 1.0, materials));
+            }
+            // The lowest valleys fill with water
+            if height == 0 {
+                cubes.extend(library.cube("agua", Vector3::new(x as f32, 1.5, z as f32), 1.0, materials));
+            }
+        }
+    }
+
+    // Loose sand and gravel hanging in the air, they drop onto the hills when the scene starts
+    for (name, x, z) in [("arena", 2, -3), ("arena", 3, -3), ("arena", 2, -2), ("grava", -4, 2), ("grava", -4, 3)] {
+        if library.has_block(name) {
+            for y in 0..3 {
+                let center = Vector3::new(x as f32, (terrain_height(x, z) + 6 + y) as f32 + 0.5, z as f32);
+                cubes.extend(library.cube(name, center, 1.0, materials));
+            }
+        }
+    }
+
+    finish_scene(&mut cubes, library);
+    cubes
+}
+
+// Smoothly interpolated lattice noise at two scales, 0..=4 blocks high
+fn terrain_height(x: i32, z: i32) -> i32 {
+    let lattice = |x: i32, z: i32| (mix64(((x as u32 as u64) << 32) | z as u32 as u64) >> 40) as f32 / (1u64 << 24) as f32;
+    let smooth = |x: f32, z: f32, cell: f32| {
+        let (fx, fz) = (x / cell, z / cell);
+        let (x0, z0) = (fx.floor() as i32, fz.floor() as i32);
+        let (tx, tz) = (fx - x0 as f32, fz - z0 as f32);
+        let (tx, tz) = (tx * tx * (3.0 - 2.0 * tx), tz * tz * (3.0 - 2.0 * tz));
+        let top = lattice(x0, z0) + (lattice(x0 + 1, z0) - lattice(x0, z0)) * tx;
+        let bottom = lattice(x0, z0 + 1) + (lattice(x0 + 1, z0 + 1) - lattice(x0, z0 + 1)) * tx;
+        top + (bottom - top) * tz
+    };
+    let value = smooth(x as f32, z as f32, 8.0) * 0.75 + smooth(x as f32, z as f32, 3.0) * 0.25;
+    (value * 5.0).floor().clamp(0.0, 4.0) as i32
+}
+
+fn terrain_camera() -> Camera {
+    Camera::new(Vector3::new(0.0, 10.0, -20.0), Vector3::new(0.0, 2.0, 0.0), Vector3::new(0.0, 1.0, 0.0))
+}
+
+// Afternoon sun over the hills
+fn terrain_light() -> Light {
+    Light::new(Vector3::new(10.0, 20.0, -10.0), Color::new(255, 245, 220, 255), 1.0).with_kind(LightKind::Directional { target: Vector3::zero() })
+}
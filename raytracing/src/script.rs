@@ -0,0 +1,443 @@
+// Scene scripts in rhai, built with `cargo run --features scripting -- --script scene.rhai`:
+//
+//     for x in -4..=4 {
+//         for z in -4..=4 {
+//             block(x, 0, z, if (x + z) % 2 == 0 { "piedra" } else { "tierra" });
+//         }
+//     }
+//     colored(0, 1, 0, 0.8, 0.2, 0.2);        // plain block, color in [0, 1]
+//     glowing(2, 1, 0, 1.0, 0.7, 0.3, 4.0);   // emissive block
+//     light(0, 8, -2, 255, 240, 200, 3.0);     // first light is the one the hotkeys edit
+//     camera(0, 4, -10, 0, 1, 0);             // eye, then the point to look at
+//
+// Blocks spawned between `begin_group("door")` and `end_group()` can be keyframed:
+//
+//     easing("smooth");                       // "linear" (default), "smooth" or "step"
+//     key_move("door", 0, 0, 0, 0);           // group, seconds, offset from where it was built
+//     key_move("door", 2, 0, 3, 0);
+//     key_color("door", 2, 1.0, 0.2, 0.2);    // also key_glow(group, seconds, r, g, b)
+//     key_light(0, 1, 5.0);                   // light index, seconds, intensity; also
+//                                             // key_light_move and key_light_color
+//     ping_pong("lift", 1.5, 2, [[0, 0, 0], [0, 5, 0]]); // group, speed, wait at the ends,
+//                                             // waypoints; loops on its own
+//     loop_animation(4);                      // repeat every 4 seconds
+//
+// A closed room open only through a few holes renders faster when the script says so:
+//
+//     room(-4, 0, -5, 4, 5, 4, 1);            // inside corners, wall thickness
+//     portal(-4, 0, -5, 4, 5, -5);            // an opening, flat along one axis
+//
+// rhai's loops, math and `random()` (seeded, so a script always builds the same scene)
+// are available for generators.
+
+use crate::animation::Timeline;
+use crate::camera::Camera;
+use crate::cube::Cube;
+use crate::{bake_objects, finish_scene};
+use crate::light::Light;
+use crate::material::{Material, MaterialTable};
+use crate::material_library::{MaterialLibrary, load_texture};
+use crate::portals::{Portal, Portals};
+use crate::ray_intersect::{SceneObject, scene_objects};
+use crate::render_thread::{BuiltScene, SceneBuilder};
+use crate::scene_file;
+use crate::sphere::Sphere;
+use raylib::prelude::Vector3;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// A block the script or scene file asked for, turned into a cube once the material library
+/// is at hand. Plain blocks may name a texture file of their own; a preset block's `look`
+/// replaces the preset's material but keeps its textures and ID (a saved live edit).
+pub(crate) enum Spawn {
+    Preset { center: Vector3, size: f32, name: String, look: Option<Material> },
+    Plain { center: Vector3, size: f32, material: Material, texture: Option<String> },
+}
+
+/// What a script or a scene file (see `scene_file`) produced: blocks to build, optionally a
+/// camera and lights, and keyframes. Object tracks refer to blocks by spawn order, which is
+/// also their order in the scene.
+#[derive(Default)]
+pub struct ScriptScene {
+    spawns: Vec<Spawn>,
+    faces: Vec<(usize, [Material; 6])>, // Per-face materials of the spawns at these indices
+    spheres: Vec<Spawn>,                // Built like blocks, `size` being the diameter
+    pub camera: Option<Camera>,
+    pub lights: Vec<Light>,
+    pub timeline: Timeline,
+    pub portals: Option<Portals>, // The room and its openings, for portal culling
+}
+
+impl ScriptScene {
+    /// Runs a scene script, or reads a `.ron`/`.json` scene file, errors carry the file name
+    /// and the line
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        let scene = match scene_file::Format::of(path) {
+            Some(format) => scene_file::parse(&source, format),
+            None => run(&source),
+        };
+        scene.map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub(crate) fn push(&mut self, spawn: Spawn) {
+        self.spawns.push(spawn);
+    }
+
+    /// Gives the last block a material per face (see `Cube::face_index`)
+    pub(crate) fn set_faces(&mut self, faces: [Material; 6]) {
+        if let Some(index) = self.spawns.len().checked_sub(1) {
+            self.faces.push((index, faces));
+        }
+    }
+
+    pub(crate) fn push_sphere(&mut self, spawn: Spawn) {
+        self.spheres.push(spawn);
+    }
+
+    /// Hands the blocks, the timeline and the portals to the render thread
+    pub fn into_builder(self) -> SceneBuilder {
+        Box::new(move |library| {
+            let mut materials = library.table();
+            let mut objects = scene_objects(self.build(library, &mut materials));
+            // Spheres go after the blocks so the timeline's block indices still hold
+            objects.extend(self.build_props(library, &mut materials));
+            bake_objects(&mut objects);
+            let portals = self.portals.map(|mut portals| {
+                portals.classify(&objects);
+                portals
+            });
+            BuiltScene { objects, materials, timeline: self.timeline, portals }
+        })
+    }
+
+    /// Starts the room portal culling works on: the inside between two corners, walls
+    /// `wall` thick all round
+    pub(crate) fn room(&mut self, corner: Vector3, opposite: Vector3, wall: f32) {
+        self.portals = Some(Portals::room(corner, opposite, wall));
+    }
+
+    /// Opens the room (see `room`) where the rectangle between two corners crosses its walls
+    pub(crate) fn portal(&mut self, corner: Vector3, opposite: Vector3) -> Result<(), String> {
+        let portals = self.portals.as_mut().ok_or("portal before any room")?;
+        let portal = Portal::between(corner, opposite).ok_or("a portal is a flat rectangle, its corners share x, y or z")?;
+        portals.open(portal);
+        Ok(())
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.spawns.len()
+    }
+
+    /// Builds the blocks; presets missing from the library are reported once and built
+    /// magenta, so every spawn keeps its place in the scene
+    pub fn build(&self, library: &MaterialLibrary, materials: &mut MaterialTable) -> Vec<Cube> {
+        let mut cubes = build_spawns(&self.spawns, library, materials);
+        for (index, faces) in &self.faces {
+            for (face, material) in faces.iter().enumerate() {
+                let entry = materials.share(*material);
+                cubes[*index].set_face_material(face, entry);
+            }
+        }
+        finish_scene(&mut cubes, library);
+        cubes
+    }
+
+    /// Builds the spheres, to go after the blocks
+    pub fn build_props(&self, library: &MaterialLibrary, materials: &mut MaterialTable) -> Vec<SceneObject> {
+        build_spawns(&self.spheres, library, materials)
+            .into_iter()
+            .map(|cube| -> SceneObject {
+                Box::new(Sphere { texture: cube.texture, ..Sphere::new(cube.center, cube.size * 0.5, cube.material) })
+            })
+            .collect()
+    }
+}
+
+fn build_spawns(spawns: &[Spawn], library: &MaterialLibrary, materials: &mut MaterialTable) -> Vec<Cube> {
+    let placeholder = Material::new(Vector3::new(1.0, 0.0, 1.0), 8.0, [0.95, 0.05, 0.0, 0.0], 1.0);
+    let mut missing: Vec<&str> = Vec::new();
+    let mut textures = HashMap::new(); // Each file loaded once, its blocks share the texels
+    let mut cubes = Vec::with_capacity(spawns.len());
+    for spawn in spawns {
+        let cube = match spawn {
+            Spawn::Preset { center, size, name, look } => match library.cube(name, *center, *size, materials) {
+                Some(mut cube) => {
+                    if let Some(look) = look {
+                        cube.material = materials.share(*look);
+                    }
+                    cube
+                }
+                None => {
+                    if !missing.contains(&name.as_str()) {
+                        println!("SCRIPT: unknown material {}, its blocks are magenta", name);
+                        missing.push(name);
+                    }
+                    Cube::new(*center, *size, materials.share(placeholder))
+                }
+            },
+            Spawn::Plain { center, size, material, texture } => {
+                let texture = texture.as_ref().and_then(|file| {
+                    textures.entry(file.as_str()).or_insert_with(|| load_texture(file, library.textures)).clone()
+                });
+                let material = materials.share(*material);
+                match texture {
+                    Some(texture) => Cube::with_texture(*center, *size, material, texture),
+                    None => Cube::new(*center, *size, material),
+                }
+            }
+        };
+        cubes.push(cube);
+    }
+    cubes
+}
+
+#[cfg(not(feature = "scripting"))]
+fn run(_source: &str) -> Result<ScriptScene, String> {
+    Err("scene scripts need a build with --features scripting".to_string())
+}
+
+// Scripts can't loop forever or spawn without bound
+#[cfg(feature = "scripting")]
+const MAX_OPERATIONS: u64 = 50_000_000;
+#[cfg(feature = "scripting")]
+const MAX_BLOCKS: usize = 500_000;
+
+#[cfg(feature = "scripting")]
+fn run(source: &str) -> Result<ScriptScene, String> {
+    use crate::animation::{Easing, LightTrack, ObjectTrack, Track};
+    use crate::rng::Rng;
+    use raylib::prelude::Color;
+    use rhai::{Array, Dynamic, Engine, EvalAltResult};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Outcome<T> = Result<T, Box<EvalAltResult>>;
+
+    // Scripts write `1` and `1.0` interchangeably
+    fn number(value: Dynamic) -> Outcome<f32> {
+        match value.as_float() {
+            Ok(float) => Ok(float as f32),
+            Err(_) => value.as_int().map(|int| int as f32).map_err(|kind| format!("expected a number, got {}", kind).into()),
+        }
+    }
+    fn point(x: Dynamic, y: Dynamic, z: Dynamic) -> Outcome<Vector3> {
+        Ok(Vector3::new(number(x)?, number(y)?, number(z)?))
+    }
+
+    // What the registered functions build up while the script runs
+    #[derive(Default)]
+    struct State {
+        scene: ScriptScene,
+        groups: Vec<String>, // Names of `scene.timeline.objects`, same order
+        group: Option<usize>,
+        easing: Easing,
+        rng: Option<Rng>,
+    }
+
+    impl State {
+        fn spawn(&mut self, spawn: Spawn) -> Outcome<()> {
+            if self.scene.spawns.len() >= MAX_BLOCKS {
+                return Err(format!("more than {} blocks", MAX_BLOCKS).into());
+            }
+            if let Some(group) = self.group {
+                self.scene.timeline.objects[group].objects.push(self.scene.spawns.len());
+            }
+            self.scene.push(spawn);
+            Ok(())
+        }
+
+        fn group(&mut self, name: &str) -> Outcome<&mut ObjectTrack> {
+            match self.groups.iter().position(|group| group == name) {
+                Some(index) => Ok(&mut self.scene.timeline.objects[index]),
+                None => Err(format!("no group named {}, spawn it between begin_group and end_group first", name).into()),
+            }
+        }
+
+        fn light(&mut self, index: Dynamic) -> Outcome<&mut LightTrack> {
+            let index = number(index)?.max(0.0) as usize;
+            let tracks = &mut self.scene.timeline.lights;
+            let position = match tracks.iter().position(|track| track.light == index) {
+                Some(position) => position,
+                None => {
+                    tracks.push(LightTrack { light: index, ..LightTrack::default() });
+                    tracks.len() - 1
+                }
+            };
+            Ok(&mut tracks[position])
+        }
+    }
+
+    let state = Rc::new(RefCell::new(State::default()));
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("block", move |x: Dynamic, y: Dynamic, z: Dynamic, name: &str| -> Outcome<()> {
+        let spawn = Spawn::Preset { center: point(x, y, z)?, size: 1.0, name: name.to_string(), look: None };
+        shared.borrow_mut().spawn(spawn)
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("colored", move |x: Dynamic, y: Dynamic, z: Dynamic, r: Dynamic, g: Dynamic, b: Dynamic| -> Outcome<()> {
+        let material = Material::new(point(r, g, b)?, 8.0, [0.95, 0.05, 0.0, 0.0], 1.0);
+        shared.borrow_mut().spawn(Spawn::Plain { center: point(x, y, z)?, size: 1.0, material, texture: None })
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn(
+        "glowing",
+        move |x: Dynamic, y: Dynamic, z: Dynamic, r: Dynamic, g: Dynamic, b: Dynamic, strength: Dynamic| -> Outcome<()> {
+            let diffuse = point(r, g, b)?;
+            let mut material = Material::new(diffuse, 8.0, [0.95, 0.05, 0.0, 0.0], 1.0);
+            material.emission = diffuse * number(strength)?;
+            shared.borrow_mut().spawn(Spawn::Plain { center: point(x, y, z)?, size: 1.0, material, texture: None })
+        },
+    );
+
+    let shared = Rc::clone(&state);
+    engine.register_fn(
+        "light",
+        move |x: Dynamic, y: Dynamic, z: Dynamic, r: Dynamic, g: Dynamic, b: Dynamic, intensity: Dynamic| -> Outcome<()> {
+            let channel = |value: Dynamic| number(value).map(|value| value.clamp(0.0, 255.0) as u8);
+            let color = Color::new(channel(r)?, channel(g)?, channel(b)?, 255);
+            let light = Light::new(point(x, y, z)?, color, number(intensity)?);
+            shared.borrow_mut().scene.lights.push(light);
+            Ok(())
+        },
+    );
+
+    let shared = Rc::clone(&state);
+    engine.register_fn(
+        "camera",
+        move |eye_x: Dynamic, eye_y: Dynamic, eye_z: Dynamic, x: Dynamic, y: Dynamic, z: Dynamic| -> Outcome<()> {
+            let camera = Camera::new(point(eye_x, eye_y, eye_z)?, point(x, y, z)?, Vector3::new(0.0, 1.0, 0.0));
+            shared.borrow_mut().scene.camera = Some(camera);
+            Ok(())
+        },
+    );
+
+    let shared = Rc::clone(&state);
+    engine.register_fn(
+        "room",
+        move |x1: Dynamic, y1: Dynamic, z1: Dynamic, x2: Dynamic, y2: Dynamic, z2: Dynamic, wall: Dynamic| -> Outcome<()> {
+            let (corner, opposite) = (point(x1, y1, z1)?, point(x2, y2, z2)?);
+            shared.borrow_mut().scene.room(corner, opposite, number(wall)?.max(0.0));
+            Ok(())
+        },
+    );
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("portal", move |x1: Dynamic, y1: Dynamic, z1: Dynamic, x2: Dynamic, y2: Dynamic, z2: Dynamic| -> Outcome<()> {
+        let (corner, opposite) = (point(x1, y1, z1)?, point(x2, y2, z2)?);
+        shared.borrow_mut().scene.portal(corner, opposite).map_err(|err| err.into())
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("random", move || {
+        let mut state = shared.borrow_mut();
+        state.rng.get_or_insert_with(|| Rng::new(0x5C21_97ED, 0)).next_f32() as f64
+    });
+
+    // Groups and keyframes
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("begin_group", move |name: &str| {
+        let mut state = shared.borrow_mut();
+        let index = match state.groups.iter().position(|group| group == name) {
+            Some(index) => index,
+            None => {
+                state.groups.push(name.to_string());
+                state.scene.timeline.objects.push(ObjectTrack::default());
+                state.groups.len() - 1
+            }
+        };
+        state.group = Some(index);
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("end_group", move || shared.borrow_mut().group = None);
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("easing", move |name: &str| -> Outcome<()> {
+        let easing = Easing::from_name(name).ok_or_else(|| format!("unknown easing {}, use linear, smooth or step", name))?;
+        shared.borrow_mut().easing = easing;
+        Ok(())
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("loop_animation", move |seconds: Dynamic| -> Outcome<()> {
+        shared.borrow_mut().scene.timeline.length = Some(number(seconds)?);
+        Ok(())
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("key_move", move |name: &str, time: Dynamic, x: Dynamic, y: Dynamic, z: Dynamic| -> Outcome<()> {
+        let mut state = shared.borrow_mut();
+        let easing = state.easing;
+        state.group(name)?.offset.insert(number(time)?, point(x, y, z)?, easing);
+        Ok(())
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("key_color", move |name: &str, time: Dynamic, r: Dynamic, g: Dynamic, b: Dynamic| -> Outcome<()> {
+        let mut state = shared.borrow_mut();
+        let easing = state.easing;
+        state.group(name)?.color.insert(number(time)?, point(r, g, b)?, easing);
+        Ok(())
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("key_glow", move |name: &str, time: Dynamic, r: Dynamic, g: Dynamic, b: Dynamic| -> Outcome<()> {
+        let mut state = shared.borrow_mut();
+        let easing = state.easing;
+        state.group(name)?.emission.insert(number(time)?, point(r, g, b)?, easing);
+        Ok(())
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("ping_pong", move |name: &str, speed: Dynamic, dwell: Dynamic, waypoints: Array| -> Outcome<()> {
+        let waypoint = |value: Dynamic| -> Outcome<Vector3> {
+            match value.into_array().map(<[Dynamic; 3]>::try_from) {
+                Ok(Ok([x, y, z])) => point(x, y, z),
+                _ => Err("waypoints are [x, y, z] arrays".into()),
+            }
+        };
+        let waypoints = waypoints.into_iter().map(waypoint).collect::<Outcome<Vec<_>>>()?;
+        let (offset, period) = Track::ping_pong(&waypoints, number(speed)?, number(dwell)?);
+        let mut state = shared.borrow_mut();
+        let track = state.group(name)?;
+        track.offset = offset;
+        track.period = Some(period);
+        Ok(())
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("key_light", move |index: Dynamic, time: Dynamic, intensity: Dynamic| -> Outcome<()> {
+        let mut state = shared.borrow_mut();
+        let easing = state.easing;
+        state.light(index)?.intensity.insert(number(time)?, number(intensity)?, easing);
+        Ok(())
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("key_light_move", move |index: Dynamic, time: Dynamic, x: Dynamic, y: Dynamic, z: Dynamic| -> Outcome<()> {
+        let mut state = shared.borrow_mut();
+        let easing = state.easing;
+        state.light(index)?.position.insert(number(time)?, point(x, y, z)?, easing);
+        Ok(())
+    });
+
+    let shared = Rc::clone(&state);
+    engine.register_fn("key_light_color", move |index: Dynamic, time: Dynamic, r: Dynamic, g: Dynamic, b: Dynamic| -> Outcome<()> {
+        let mut state = shared.borrow_mut();
+        let easing = state.easing;
+        state.light(index)?.color.insert(number(time)?, point(r, g, b)?, easing);
+        Ok(())
+    });
+
+    engine.run(source).map_err(|err| err.to_string())?;
+    let scene = std::mem::take(&mut state.borrow_mut().scene);
+    Ok(scene)
+}
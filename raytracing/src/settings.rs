@@ -0,0 +1,411 @@
+use crate::tonemap::{MAX_EXPOSURE, MIN_EXPOSURE};
+use crate::camera::Camera;
+use crate::light::Falloff;
+use crate::offline::value_after;
+use crate::{MAX_RAY_DEPTH, ORIGIN_BIAS};
+use raylib::prelude::Vector3;
+use std::fs;
+use std::path::Path;
+
+/// Where the viewer keeps its settings between runs, next to where it is started
+pub const SETTINGS_FILE: &str = "settings.txt";
+
+/// Internal render resolutions, `None` means "match the window"
+pub const RESOLUTION_PRESETS: [Option<(u32, u32)>; 6] = [
+    None,
+    Some((320, 240)),
+    Some((640, 360)),
+    Some((800, 600)),
+    Some((1280, 720)),
+    Some((1920, 1080)),
+];
+
+/// Reflection and refraction bounce limits cycled at runtime
+pub const DEPTH_PRESETS: [u32; 5] = [0, 1, 2, 4, 8];
+// Ambient occlusion rays per pixel the viewer cycles through, 0 for off
+pub const AO_PRESETS: [u32; 4] = [0, 4, 8, 16];
+
+/// Frame rate caps, 0 means uncapped
+pub const FPS_CAP_PRESETS: [u32; 4] = [0, 30, 60, 120];
+
+/// User-adjustable options that live for the whole session
+pub struct Settings {
+    pub resolution_index: usize,
+    pub fps_cap_index: usize,
+    pub vsync: bool,
+    pub options: RenderOptions,
+    pub split_enabled: bool,
+    pub comparison_index: usize,
+    pub divider: f32,
+    pub window_size: (i32, i32),
+    pub view: Option<SavedView>, // Where the camera was when the viewer last closed
+    pub threads: usize,          // Render threads, 0 for one per core
+    pub light_falloff: Option<Falloff>, // Replaces the gallery scenes' own main light falloff
+}
+
+/// Camera position in a gallery scene, restored on the next start
+#[derive(Debug, Clone)]
+pub struct SavedView {
+    pub scene: String,
+    pub eye: Vector3,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl SavedView {
+    pub fn new(scene: &str, camera: &Camera) -> Self {
+        SavedView { scene: scene.to_string(), eye: camera.eye, yaw: camera.yaw, pitch: camera.pitch }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.eye;
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
+        camera.update_basis_vectors();
+    }
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Settings {
+            resolution_index: 0,
+            fps_cap_index: 2,
+            vsync: false,
+            options: RenderOptions::default(),
+            split_enabled: false,
+            comparison_index: 0,
+            divider: 0.5,
+            window_size: (800, 600),
+            view: None,
+            threads: 0,
+            light_falloff: None,
+        }
+    }
+
+    /// Settings saved by the last run, defaults when there are none. A file that doesn't
+    /// parse is reported and ignored, a value out of range falls back to its default.
+    pub fn load(path: &Path) -> Self {
+        let mut settings = Settings::new();
+        let Ok(text) = fs::read_to_string(path) else {
+            return settings;
+        };
+        if let Err(err) = settings.parse(&text) {
+            println!("ERROR: {}: {} - using default settings", path.display(), err);
+            return Settings::new();
+        }
+        println!("Loaded settings from: {}", path.display());
+        settings
+    }
+
+    fn parse(&mut self, text: &str) -> Result<(), String> {
+        let mut view = SavedView { scene: String::new(), eye: Vector3::zero(), yaw: 0.0, pitch: 0.0 };
+        let mut has_view = false;
+        for (index, raw_line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = raw_line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected `key = value`", line_number));
+            };
+            let value = value.trim();
+            let number = || value.parse::<f32>().map_err(|_| format!("line {}: `{}` is not a number", line_number, value));
+            let count = || value.parse::<u32>().map_err(|_| format!("line {}: `{}` is not a whole number", line_number, value));
+            let flag = || match value {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(format!("line {}: `{}` is not true/false", line_number, value)),
+            };
+            let options = &mut self.options;
+            match key.trim() {
+                "resolution" => self.resolution_index = (count()? as usize).min(RESOLUTION_PRESETS.len() - 1),
+                "fps_cap" => self.fps_cap_index = (count()? as usize).min(FPS_CAP_PRESETS.len() - 1),
+                "vsync" => self.vsync = flag()?,
+                "threads" => self.threads = count()? as usize,
+                "window" => {
+                    let (width, height) = value.split_once('x').ok_or_else(|| format!("line {}: expected WxH", line_number))?;
+                    let size = |side: &str| side.trim().parse::<i32>().map_err(|_| format!("line {}: `{}` is not a size", line_number, value));
+                    self.window_size = (size(width)?.max(160), size(height)?.max(120));
+                }
+                "exposure" => options.exposure = number()?.clamp(MIN_EXPOSURE, MAX_EXPOSURE),
+                "shadows" => options.shadows = flag()?,
+                "reflections" => options.reflections = flag()?,
+                "interlaced" => options.interlaced = flag()?,
+                "half_res_secondary" => options.half_res_secondary = flag()?,
+                "taa" => options.taa = flag()?,
+                "light_probes" => options.light_probes = flag()?,
+                "glossy_reflections" => options.glossy_reflections = flag()?,
+                "spectral" => options.spectral = flag()?,
+                "path_traced" => options.path_traced = flag()?,
+                "ao_samples" => options.ao_samples = count()?,
+                "specular_range" => options.specular_range = number()?.max(0.0),
+                "light_falloff" => {
+                    let falloff = Falloff::parse(value).ok_or_else(|| format!("line {}: expected `inverse_square K`, `linear RANGE` or `limited K RANGE`", line_number))?;
+                    self.light_falloff = Some(falloff);
+                }
+                "reflection_depth" => options.max_reflection_depth = count()?,
+                "refraction_depth" => options.max_refraction_depth = count()?,
+                "scene" => {
+                    view.scene = value.to_string();
+                    has_view = true;
+                }
+                "eye" => {
+                    let parts: Vec<f32> = value.split_whitespace().filter_map(|part| part.parse().ok()).collect();
+                    let [x, y, z] = parts[..] else {
+                        return Err(format!("line {}: expected 3 numbers", line_number));
+                    };
+                    view.eye = Vector3::new(x, y, z);
+                }
+                "yaw" => view.yaw = number()?,
+                "pitch" => view.pitch = number()?,
+                other => println!("Ignoring unknown setting `{}` on line {}", other, line_number),
+            }
+        }
+        self.view = has_view.then_some(view);
+        Ok(())
+    }
+
+    /// Writes the settings for the next run to pick up
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let options = &self.options;
+        let mut text = String::from("# Viewer settings, saved on exit and restored on start\n");
+        text += &format!("resolution = {}\n", self.resolution_index);
+        text += &format!("fps_cap = {}\n", self.fps_cap_index);
+        text += &format!("vsync = {}\n", self.vsync);
+        text += &format!("threads = {}\n", self.threads);
+        text += &format!("window = {}x{}\n", self.window_size.0, self.window_size.1);
+        text += &format!("exposure = {}\n", options.exposure);
+        text += &format!("shadows = {}\n", options.shadows);
+        text += &format!("reflections = {}\n", options.reflections);
+        text += &format!("interlaced = {}\n", options.interlaced);
+        text += &format!("half_res_secondary = {}\n", options.half_res_secondary);
+        text += &format!("taa = {}\n", options.taa);
+        text += &format!("light_probes = {}\n", options.light_probes);
+        text += &format!("glossy_reflections = {}\n", options.glossy_reflections);
+        text += &format!("spectral = {}\n", options.spectral);
+        text += &format!("path_traced = {}\n", options.path_traced);
+        text += &format!("ao_samples = {}\n", options.ao_samples);
+        text += &format!("specular_range = {}\n", options.specular_range);
+        if let Some(falloff) = self.light_falloff {
+            text += &format!("light_falloff = {}\n", falloff);
+        }
+        text += &format!("reflection_depth = {}\n", options.max_reflection_depth);
+        text += &format!("refraction_depth = {}\n", options.max_refraction_depth);
+        if let Some(view) = &self.view {
+            text += &format!("scene = {}\n", view.scene);
+            text += &format!("eye = {} {} {}\n", view.eye.x, view.eye.y, view.eye.z);
+            text += &format!("yaw = {}\n", view.yaw);
+            text += &format!("pitch = {}\n", view.pitch);
+        }
+        fs::write(path, text)
+    }
+
+    /// Resolution the tracer should render at for the given window size
+    pub fn render_resolution(&self, window_width: u32, window_height: u32) -> (u32, u32) {
+        match RESOLUTION_PRESETS[self.resolution_index] {
+            Some(resolution) => resolution,
+            None => (window_width.max(1), window_height.max(1)),
+        }
+    }
+
+    pub fn cycle_resolution(&mut self) {
+        self.resolution_index = (self.resolution_index + 1) % RESOLUTION_PRESETS.len();
+    }
+
+    pub fn resolution_label(&self) -> String {
+        match RESOLUTION_PRESETS[self.resolution_index] {
+            Some((w, h)) => format!("{}x{}", w, h),
+            None => "Window".to_string(),
+        }
+    }
+
+    pub fn fps_cap(&self) -> u32 {
+        FPS_CAP_PRESETS[self.fps_cap_index]
+    }
+
+    pub fn cycle_fps_cap(&mut self) {
+        self.fps_cap_index = (self.fps_cap_index + 1) % FPS_CAP_PRESETS.len();
+    }
+
+    /// The active A/B comparison, if split-screen mode is on
+    pub fn split_view(&self) -> Option<SplitView> {
+        if !self.split_enabled {
+            return None;
+        }
+        // Both halves keep the tuned bias, only the compared switches differ
+        let (_, left, right) = COMPARISON_PRESETS[self.comparison_index];
+        Some(SplitView {
+            left: RenderOptions { bias: self.options.bias, ..left },
+            right: RenderOptions { bias: self.options.bias, ..right },
+            divider: self.divider,
+        })
+    }
+
+    /// Scales the surface offset (constant and slope parts) up or down
+    pub fn scale_bias(&mut self, factor: f32) {
+        let bias = &mut self.options.bias;
+        bias.constant = (bias.constant * factor).clamp(1e-6, 0.1);
+        bias.slope = (bias.slope * factor).clamp(1e-6, 0.1);
+    }
+
+    /// Brightens or darkens the image by `stops`, within the supported EV range
+    pub fn adjust_exposure(&mut self, stops: f32) {
+        self.options.exposure = (self.options.exposure + stops).clamp(MIN_EXPOSURE, MAX_EXPOSURE);
+    }
+
+    pub fn cycle_reflection_depth(&mut self) {
+        self.options.max_reflection_depth = next_depth(self.options.max_reflection_depth);
+    }
+
+    pub fn cycle_refraction_depth(&mut self) {
+        self.options.max_refraction_depth = next_depth(self.options.max_refraction_depth);
+    }
+
+    pub fn cycle_ao_samples(&mut self) {
+        self.options.ao_samples = AO_PRESETS.into_iter().find(|&preset| preset > self.options.ao_samples).unwrap_or(AO_PRESETS[0]);
+    }
+
+    pub fn cycle_comparison(&mut self) {
+        self.comparison_index = (self.comparison_index + 1) % COMPARISON_PRESETS.len();
+    }
+
+    pub fn comparison_label(&self) -> &'static str {
+        COMPARISON_PRESETS[self.comparison_index].0
+    }
+}
+
+/// Per-frame shading switches, the same struct drives both halves of the A/B split view
+#[derive(Debug, Clone, Copy)]
+pub struct RenderOptions {
+    pub max_reflection_depth: u32, // Mirror and metal bounces per path
+    pub max_refraction_depth: u32, // Passes through glass, water and leaves per path
+    pub shadows: bool,
+    pub reflections: bool,
+    pub bias: ShadowBias,
+    pub path_traced: bool, // Path tracer with MIS instead of the one-bounce Whitted model
+    pub exposure: f32,     // Exposure compensation in stops, applied when tone mapping
+    pub transparent_background: bool, // Pixels that see only sky get zero alpha, for compositing
+    pub time: f32, // Simulation seconds, animates rippling water
+    pub interlaced: bool, // Traces odd and even rows on alternate frames, the other half reconstructed
+    pub half_res_secondary: bool, // Reflections and refractions at half resolution, upsampled per surface
+    pub taa: bool, // Jittered primary rays blended with the reprojected previous frame
+    pub light_probes: bool, // Bounce light from a baked grid of irradiance probes
+    pub glossy_reflections: bool, // Dull metals reflect a prefiltered capture of the scene
+    pub spectral: bool, // Refraction through diamond traced per wavelength, splitting light into colors
+    pub ao_samples: u32, // Short rays per directly seen hit darkening creases, 0 for off
+    pub progressive: bool, // Still view: full-resolution jittered frames averaged with the ones before
+    pub specular_range: f32, // Point and area lights farther than this add no highlight, 0 for no limit
+}
+
+const DEFAULT_OPTIONS: RenderOptions = RenderOptions {
+    max_reflection_depth: MAX_RAY_DEPTH,
+    max_refraction_depth: MAX_RAY_DEPTH,
+    shadows: true,
+    reflections: true,
+    bias: ShadowBias::DEFAULT,
+    path_traced: false,
+    exposure: 0.0,
+    transparent_background: false,
+    time: 0.0,
+    interlaced: false,
+    half_res_secondary: false,
+    taa: false,
+    light_probes: false,
+    glossy_reflections: false,
+    spectral: false,
+    ao_samples: 0,
+    progressive: false,
+    specular_range: 8.0,
+};
+
+impl RenderOptions {
+    /// Takes `--reflection-depth N`, `--refraction-depth N`, `--ao N` and `--specular-range D`
+    /// from the command line
+    pub fn with_quality_args(self, args: &[String]) -> Self {
+        let count = |flag: &str| value_after(args, flag).and_then(|value| value.parse::<u32>().ok());
+        let distance = |flag: &str| value_after(args, flag).and_then(|value| value.parse::<f32>().ok()).map(|value| value.max(0.0));
+        RenderOptions {
+            max_reflection_depth: count("--reflection-depth").unwrap_or(self.max_reflection_depth),
+            max_refraction_depth: count("--refraction-depth").unwrap_or(self.max_refraction_depth),
+            ao_samples: count("--ao").unwrap_or(self.ao_samples),
+            specular_range: distance("--specular-range").unwrap_or(self.specular_range),
+            ..self
+        }
+    }
+
+    /// The same options with no reflection or refraction rays, direct light only
+    pub fn without_secondary(&self) -> Self {
+        RenderOptions { max_reflection_depth: 0, max_refraction_depth: 0, ..*self }
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        DEFAULT_OPTIONS
+    }
+}
+
+/// How far secondary rays start off the surface. Too little gives shadow acne,
+/// too much detaches shadows from their casters (peter-panning).
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowBias {
+    pub constant: f32,
+    pub slope: f32,            // Extra offset per unit of tan(angle) for grazing rays
+    pub max: f32,              // Cap on the slope-scaled part
+    pub occluder_epsilon: f32, // Occluders this close to the light don't count
+}
+
+impl ShadowBias {
+    pub const DEFAULT: ShadowBias = ShadowBias {
+        constant: ORIGIN_BIAS,
+        slope: ORIGIN_BIAS,
+        max: 0.01,
+        occluder_epsilon: 0.01,
+    };
+
+    /// Offset along the normal for a ray leaving at `cos_angle` to it, plus the material's own bias
+    pub fn offset(&self, cos_angle: f32, material_bias: f32) -> f32 {
+        let cos = cos_angle.abs().clamp(1e-3, 1.0);
+        let tan = (1.0 - cos * cos).sqrt() / cos;
+        self.constant + (self.slope * tan).min(self.max) + material_bias
+    }
+}
+
+/// An A/B comparison: columns left of `divider` (0..1) render with `left`, the rest with
+/// `right`, both instead of the main options
+#[derive(Debug, Clone, Copy)]
+pub struct SplitView {
+    pub left: RenderOptions,
+    pub right: RenderOptions,
+    pub divider: f32,
+}
+
+// Next larger depth preset, wrapping around to the smallest
+fn next_depth(depth: u32) -> u32 {
+    DEPTH_PRESETS.into_iter().find(|&preset| preset > depth).unwrap_or(DEPTH_PRESETS[0])
+}
+
+/// Named A/B pairs cycled in split-screen mode
+pub const COMPARISON_PRESETS: [(&str, RenderOptions, RenderOptions); 4] = [
+    (
+        "Shadows on | off",
+        DEFAULT_OPTIONS,
+        RenderOptions { shadows: false, ..DEFAULT_OPTIONS },
+    ),
+    (
+        "Depth 1 | 3",
+        RenderOptions { max_reflection_depth: 1, max_refraction_depth: 1, ..DEFAULT_OPTIONS },
+        RenderOptions { max_reflection_depth: 3, max_refraction_depth: 3, ..DEFAULT_OPTIONS },
+    ),
+    (
+        "Reflections on | off",
+        DEFAULT_OPTIONS,
+        RenderOptions { reflections: false, ..DEFAULT_OPTIONS },
+    ),
+    (
+        "Ambient occlusion off | 8",
+        DEFAULT_OPTIONS,
+        RenderOptions { ao_samples: 8, ..DEFAULT_OPTIONS },
+    ),
+];
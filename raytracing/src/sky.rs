@@ -0,0 +1,204 @@
+use crate::offline::value_after;
+use raylib::prelude::Vector3;
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// Resolution of the precomputed irradiance table, indexed by the normal's height
+const IRRADIANCE_SIZE: usize = 64;
+const IRRADIANCE_THETA_STEPS: usize = 64;
+const IRRADIANCE_PHI_STEPS: usize = 128;
+
+/// Sky colors picked up from the working directory when `--sky <file>` isn't given
+pub const SKY_FILE: &str = "sky.txt";
+
+/// Procedural gradient sky, scaled by a tint so the time of day can darken or warm it
+#[derive(Debug, Clone, Copy)]
+pub struct Sky {
+    pub tint: Vector3,
+    pub gradient: SkyGradient,
+}
+
+/// Colors and band positions of the sky. Heights go from straight down (0) through
+/// level (0.5) to straight up (1): below the horizon band the ground color fades into
+/// the horizon color, above it the horizon fades into the zenith color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyGradient {
+    pub ground: Vector3,
+    pub horizon: Vector3,
+    pub zenith: Vector3,
+    pub horizon_height: f32, // Top of the solid horizon band
+    pub band: f32,           // Thickness of the solid horizon band
+    pub zenith_height: f32,  // Where the zenith color takes over
+}
+
+// Green ground, white horizon, blue zenith
+const DEFAULT_GRADIENT: SkyGradient = SkyGradient {
+    ground: Vector3 { x: 0.1, y: 0.6, z: 0.2 },
+    horizon: Vector3 { x: 1.0, y: 1.0, z: 1.0 },
+    zenith: Vector3 { x: 0.3, y: 0.5, z: 1.0 },
+    horizon_height: 0.55,
+    band: 0.01,
+    zenith_height: 0.8,
+};
+
+impl Default for SkyGradient {
+    fn default() -> Self {
+        DEFAULT_GRADIENT
+    }
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Sky {
+            tint: Vector3::new(1.0, 1.0, 1.0),
+            gradient: SkyGradient::default(),
+        }
+    }
+}
+
+impl Sky {
+    /// The sky in `--sky <file>`, else in `sky.txt` if there is one, else the built-in
+    /// gradient. A file that doesn't load is reported and the built-in gradient used.
+    pub fn from_args(args: &[String]) -> Self {
+        let path = match value_after(args, "--sky") {
+            Some(path) => Path::new(path),
+            None if Path::new(SKY_FILE).exists() => Path::new(SKY_FILE),
+            None => return Sky::default(),
+        };
+        match SkyGradient::load(path) {
+            Ok(gradient) => {
+                println!("Loaded sky from: {}", path.display());
+                Sky { gradient, ..Sky::default() }
+            }
+            Err(err) => {
+                println!("ERROR: {} - using the default sky", err);
+                Sky::default()
+            }
+        }
+    }
+
+    pub fn sample(&self, dir: Vector3) -> Vector3 {
+        let color = self.gradient.color(dir);
+        Vector3::new(color.x * self.tint.x, color.y * self.tint.y, color.z * self.tint.z)
+    }
+
+    /// Cosine-weighted light arriving from the whole sky at a surface facing `normal`.
+    /// The gradient only varies with height, so the integral is precomputed once per normal.y
+    pub fn irradiance(&self, normal: Vector3) -> Vector3 {
+        let table = irradiance_table(&self.gradient);
+        let position = (normal.y.clamp(-1.0, 1.0) + 1.0) * 0.5 * (IRRADIANCE_SIZE - 1) as f32;
+        let index = (position.floor() as usize).min(IRRADIANCE_SIZE - 2);
+        let t = position - index as f32;
+        let color = table[index] * (1.0 - t) + table[index + 1] * t;
+        Vector3::new(color.x * self.tint.x, color.y * self.tint.y, color.z * self.tint.z)
+    }
+}
+
+impl SkyGradient {
+    /// Reads `key = value` lines: `ground`, `horizon` and `zenith` as three numbers in
+    /// [0, 1], `horizon_height`, `band` and `zenith_height` as one. Missing keys keep
+    /// the built-in values.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        Self::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut gradient = SkyGradient::default();
+        for (index, raw_line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = raw_line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected `key = value`", line_number));
+            };
+            let numbers: Vec<f32> = value.split_whitespace().filter_map(|part| part.parse().ok()).collect();
+            match (key.trim(), numbers.as_slice()) {
+                ("ground", &[r, g, b]) => gradient.ground = Vector3::new(r, g, b),
+                ("horizon", &[r, g, b]) => gradient.horizon = Vector3::new(r, g, b),
+                ("zenith", &[r, g, b]) => gradient.zenith = Vector3::new(r, g, b),
+                ("horizon_height", &[height]) => gradient.horizon_height = height.clamp(0.0, 1.0),
+                ("band", &[band]) => gradient.band = band.max(0.0),
+                ("zenith_height", &[height]) => gradient.zenith_height = height.clamp(0.0, 1.0),
+                (key, _) => return Err(format!("line {}: bad or unknown value for `{}`", line_number, key)),
+            }
+        }
+        if gradient.zenith_height <= gradient.horizon_height {
+            return Err(format!("zenith_height {} must be above horizon_height {}", gradient.zenith_height, gradient.horizon_height));
+        }
+        Ok(gradient)
+    }
+
+    fn color(&self, dir: Vector3) -> Vector3 {
+        let d = dir.normalized();
+        let t = (d.y + 1.0) * 0.5;
+
+        if t < self.horizon_height - self.band {
+            let k = t / self.horizon_height;
+            self.ground * (1.0 - k) + self.horizon * k
+        } else if t < self.horizon_height {
+            self.horizon
+        } else if t < self.zenith_height {
+            let k = (t - self.horizon_height) / (self.zenith_height - self.horizon_height);
+            self.horizon * (1.0 - k) + self.zenith * k
+        } else {
+            self.zenith
+        }
+    }
+}
+
+type IrradianceTable = [Vector3; IRRADIANCE_SIZE];
+
+// One table per gradient in use, most recently used last; usually just the one the session
+// started with. Only the last few are kept, so a long-running process that goes through
+// many skies doesn't keep every table it ever built.
+static IRRADIANCE: Mutex<Vec<(SkyGradient, Arc<IrradianceTable>)>> = Mutex::new(Vec::new());
+const MAX_IRRADIANCE_TABLES: usize = 4;
+
+fn irradiance_table(gradient: &SkyGradient) -> Arc<IrradianceTable> {
+    let mut tables = IRRADIANCE.lock().unwrap();
+    if let Some(position) = tables.iter().position(|(known, _)| known == gradient) {
+        let entry = tables.remove(position);
+        let table = Arc::clone(&entry.1);
+        tables.push(entry);
+        return table;
+    }
+    if tables.len() >= MAX_IRRADIANCE_TABLES {
+        tables.remove(0);
+    }
+    let table = Arc::new(build_irradiance_table(gradient));
+    tables.push((*gradient, Arc::clone(&table)));
+    table
+}
+
+// Integrates the untinted gradient over the hemisphere of each table normal,
+// normalized so a uniform sky of radiance L gives irradiance L
+fn build_irradiance_table(gradient: &SkyGradient) -> IrradianceTable {
+    let mut table = [Vector3::zero(); IRRADIANCE_SIZE];
+    let d_theta = PI / IRRADIANCE_THETA_STEPS as f32;
+    let d_phi = 2.0 * PI / IRRADIANCE_PHI_STEPS as f32;
+
+    for (index, entry) in table.iter_mut().enumerate() {
+        let normal_y = index as f32 / (IRRADIANCE_SIZE - 1) as f32 * 2.0 - 1.0;
+        let normal = Vector3::new((1.0 - normal_y * normal_y).max(0.0).sqrt(), normal_y, 0.0);
+
+        let mut sum = Vector3::zero();
+        for i in 0..IRRADIANCE_THETA_STEPS {
+            let theta = (i as f32 + 0.5) * d_theta;
+            for j in 0..IRRADIANCE_PHI_STEPS {
+                let phi = (j as f32 + 0.5) * d_phi;
+                let dir = Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                let cos = normal.dot(dir);
+                if cos > 0.0 {
+                    sum = sum + gradient.color(dir) * (cos * theta.sin() * d_theta * d_phi);
+                }
+            }
+        }
+        *entry = sum / PI;
+    }
+    table
+}
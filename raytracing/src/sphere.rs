@@ -0,0 +1,134 @@
+use crate::material::{Material, MaterialTable, Texture};
+use crate::ray_intersect::{Intersect, RayIntersect};
+use raylib::prelude::*;
+use std::f32::consts::PI;
+
+/// A ball: boulders, lamps, mirror balls. Textures wrap around it with spherical UVs, u
+/// around the equator and v from the top pole to the bottom one.
+#[derive(Clone)]
+pub struct Sphere {
+    pub center: Vector3,
+    pub radius: f32,
+    pub material: u32, // Entry in the scene's `MaterialTable`
+    pub texture: Option<Texture>,
+}
+
+impl Sphere {
+    pub fn new(center: Vector3, radius: f32, material: u32) -> Self {
+        Sphere { center, radius, material, texture: None }
+    }
+
+    pub fn with_texture(center: Vector3, radius: f32, material: u32, texture: Texture) -> Self {
+        Sphere { center, radius, material, texture: Some(texture) }
+    }
+}
+
+impl RayIntersect for Sphere {
+    fn ray_intersect(&self, ray_origin: &Vector3, ray_direction: &Vector3) -> Intersect {
+        let Some(distance) = self.hit_distance(ray_origin, ray_direction) else {
+            return Intersect::empty();
+        };
+        let point = *ray_origin + *ray_direction * distance;
+        let normal = (point - self.center) / self.radius;
+
+        let mut intersect = Intersect::new(point, normal, distance);
+        intersect.uv = (0.5 + normal.z.atan2(normal.x) / (2.0 * PI), 0.5 - normal.y.clamp(-1.0, 1.0).asin() / PI);
+        intersect
+    }
+
+    fn hit_distance(&self, ray_origin: &Vector3, ray_direction: &Vector3) -> Option<f32> {
+        let offset = *ray_origin - self.center;
+        let a = ray_direction.dot(*ray_direction);
+        let half_b = offset.dot(*ray_direction);
+        let c = offset.dot(offset) - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        // The near side, or the far side for rays starting inside (glass balls)
+        let near = (-half_b - root) / a;
+        let far = (-half_b + root) / a;
+        if near > 1e-4 {
+            Some(near)
+        } else if far > 1e-4 {
+            Some(far)
+        } else {
+            None
+        }
+    }
+
+    /// The material tinted by the texture at the hit
+    fn surface(&self, intersect: &Intersect, _ray_direction: &Vector3, materials: &MaterialTable) -> Material {
+        let mut material = materials[self.material];
+        if let Some(texture) = &self.texture {
+            let color = texture.sample(intersect.uv.0, intersect.uv.1);
+            let base = material.diffuse;
+            material.diffuse = Vector3::new(base.x * color.x, base.y * color.y, base.z * color.z);
+        }
+        material
+    }
+
+    fn material_for(&self, _normal: Vector3, materials: &MaterialTable) -> Material {
+        materials[self.material]
+    }
+
+    fn center(&self) -> Vector3 {
+        self.center
+    }
+
+    fn bounds(&self) -> (Vector3, Vector3) {
+        let half = Vector3::new(self.radius, self.radius, self.radius);
+        (self.center - half, self.center + half)
+    }
+
+    fn as_sphere(&self) -> Option<&Sphere> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ball() -> Sphere {
+        Sphere::new(Vector3::zero(), 1.0, 0)
+    }
+
+    // UV where a ray from `origin` toward the center meets the ball
+    fn uv_seen_from(origin: Vector3) -> (f32, f32) {
+        let intersect = ball().ray_intersect(&origin, &(-origin).normalized());
+        assert!(intersect.is_intersecting);
+        intersect.uv
+    }
+
+    #[test]
+    fn hit_distance_misses_hits_and_leaves_from_inside() {
+        let forward = Vector3::new(0.0, 0.0, 1.0);
+        // Passing beside the ball, and with the ball behind the ray
+        assert_eq!(ball().hit_distance(&Vector3::new(1.5, 0.0, -5.0), &forward), None);
+        assert_eq!(ball().hit_distance(&Vector3::new(0.0, 0.0, 5.0), &forward), None);
+        // From outside, the near side
+        assert_eq!(ball().hit_distance(&Vector3::new(0.0, 0.0, -5.0), &forward), Some(4.0));
+        // From inside, the far side
+        assert_eq!(ball().hit_distance(&Vector3::zero(), &forward), Some(1.0));
+        assert_eq!(ball().hit_distance(&Vector3::new(0.0, 0.0, 0.5), &forward), Some(0.5));
+    }
+
+    #[test]
+    fn v_runs_from_the_top_pole_to_the_bottom_one() {
+        assert!(uv_seen_from(Vector3::new(0.0, 5.0, 0.0)).1.abs() < 1e-4);
+        assert!((uv_seen_from(Vector3::new(0.0, -5.0, 0.0)).1 - 1.0).abs() < 1e-4);
+        let (u, v) = uv_seen_from(Vector3::new(5.0, 0.0, 0.0));
+        assert!((u - 0.5).abs() < 1e-4 && (v - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn u_wraps_around_at_the_seam_behind_the_ball() {
+        // Either side of -x, u is at the opposite ends of its range
+        let just_past = uv_seen_from(Vector3::new(-5.0, 0.0, 0.05)).0;
+        let just_before = uv_seen_from(Vector3::new(-5.0, 0.0, -0.05)).0;
+        assert!(just_past > 0.99 && just_past <= 1.0, "{}", just_past);
+        assert!((0.0..0.01).contains(&just_before), "{}", just_before);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::camera::Camera;
+use crate::{RenderGrid, primary_ray, project_to_screen};
+use raylib::prelude::Color;
+
+// Share of the freshly traced frame in each pixel, the rest comes from the history
+const TAA_BLEND: f32 = 0.1;
+// Frames before the jitter pattern repeats
+const JITTER_PHASES: u32 = 8;
+// Sky pixels have no depth; their history is found through a point this far along the ray
+const SKY_DISTANCE: f32 = 1e4;
+
+/// Sub-pixel offset for the primary rays of frame `frame_index`, in [-0.5, 0.5) on each
+/// axis. Follows the (2, 3) Halton sequence, so every 8 frames cover the pixel evenly.
+pub fn jitter(frame_index: u32) -> (f32, f32) {
+    let index = frame_index % JITTER_PHASES + 1;
+    (halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+}
+
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Temporal anti-aliasing: the last resolved frame and the camera it was seen from
+#[derive(Default)]
+pub struct TemporalHistory {
+    width: u32, // In blocks of the render grid the frame was traced on
+    height: u32,
+    colors: Vec<Color>,
+    camera: Option<Camera>,
+}
+
+impl TemporalHistory {
+    /// Forgets the history, the next resolved frame starts from scratch
+    pub fn clear(&mut self) {
+        self.colors.clear();
+        self.camera = None;
+    }
+
+    /// Blends a frame traced with `jitter` on its primary rays into the history, in place.
+    /// `colors` and `depths` hold one sample per block of `grid` on a `width` × `height`
+    /// screen (one per pixel at full render scale). Each block follows its first hit
+    /// (`depths`) back to where it was on screen last frame, clamps the history color there
+    /// to the range of its 3x3 neighbourhood, so surfaces that moved or just came into view
+    /// don't ghost, and keeps most of it. The result becomes the history for the next frame;
+    /// a frame on another grid starts it over.
+    pub(crate) fn resolve(&mut self, colors: &mut Vec<Color>, depths: &[f32], grid: RenderGrid, width: u32, height: u32, camera: &Camera, fov: f32, jitter: (f32, f32)) {
+        let (columns, rows) = (grid.columns, grid.rows);
+        if let Some(previous) = self.camera.as_ref().filter(|_| self.width == columns && self.height == rows) {
+            let mut resolved = Vec::with_capacity(colors.len());
+            for y in 0..rows {
+                for x in 0..columns {
+                    let index = (y * columns + x) as usize;
+                    let current = colors[index];
+                    let (sample_x, sample_y) = grid.sample_point(x, y, jitter);
+                    let direction = primary_ray(camera, sample_x, sample_y, width, height, fov);
+                    let distance = if depths[index].is_finite() { depths[index] } else { SKY_DISTANCE };
+                    let history = project_to_screen(previous, camera.eye + direction * distance, width, height, fov)
+                        .map(|(hx, hy)| grid.block_at(hx, hy, jitter))
+                        .filter(|&(hx, hy)| hx >= 0.0 && hy >= 0.0 && hx <= (columns - 1) as f32 && hy <= (rows - 1) as f32);
+                    resolved.push(match history {
+                        Some((hx, hy)) => {
+                            let (low, high) = neighbourhood(colors, columns, rows, x, y);
+                            blend(clamp(self.sample(hx, hy), low, high), current)
+                        }
+                        None => current,
+                    });
+                }
+            }
+            *colors = resolved;
+        }
+        self.width = columns;
+        self.height = rows;
+        self.colors.clone_from(colors);
+        self.camera = Some(camera.clone());
+    }
+
+    // Bilinear lookup in the history at a fractional pixel position
+    fn sample(&self, x: f32, y: f32) -> [f32; 3] {
+        let (x0, y0) = (x as u32, y as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+        let texel = |px: u32, py: u32| rgb(self.colors[(py * self.width + px) as usize]);
+        let (a, b, c, d) = (texel(x0, y0), texel(x1, y0), texel(x0, y1), texel(x1, y1));
+        std::array::from_fn(|i| (a[i] * (1.0 - tx) + b[i] * tx) * (1.0 - ty) + (c[i] * (1.0 - tx) + d[i] * tx) * ty)
+    }
+}
+
+fn rgb(color: Color) -> [f32; 3] {
+    [color.r as f32, color.g as f32, color.b as f32]
+}
+
+// Per-channel minimum and maximum of the current frame around (x, y)
+fn neighbourhood(colors: &[Color], width: u32, height: u32, x: u32, y: u32) -> ([f32; 3], [f32; 3]) {
+    let (mut low, mut high) = ([f32::MAX; 3], [f32::MIN; 3]);
+    for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+        for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+            let color = rgb(colors[(ny * width + nx) as usize]);
+            for channel in 0..3 {
+                low[channel] = low[channel].min(color[channel]);
+                high[channel] = high[channel].max(color[channel]);
+            }
+        }
+    }
+    (low, high)
+}
+
+fn clamp(color: [f32; 3], low: [f32; 3], high: [f32; 3]) -> [f32; 3] {
+    std::array::from_fn(|i| color[i].clamp(low[i], high[i]))
+}
+
+fn blend(history: [f32; 3], current: Color) -> Color {
+    let [r, g, b] = std::array::from_fn(|i| (history[i] + (rgb(current)[i] - history[i]) * TAA_BLEND).round() as u8);
+    Color::new(r, g, b, current.a)
+}
@@ -0,0 +1,87 @@
+use std::time::Instant;
+
+// Most simulation steps run in one frame, avoids a spiral of death after a slow frame. Time
+// beyond that is dropped, so a window drag or a slow frame pauses the simulation instead of
+// fast-forwarding it over the frames after
+const MAX_STEPS_PER_FRAME: u32 = 30;
+
+/// Fixed-timestep clock that decouples simulation speed from the render framerate
+pub struct FixedTimestep {
+    pub dt: f32,
+    accumulator: f32,
+    last_time: Instant,
+}
+
+impl FixedTimestep {
+    /// Creates a clock that ticks `hz` times per simulated second
+    pub fn new(hz: f32) -> Self {
+        FixedTimestep {
+            dt: 1.0 / hz,
+            accumulator: 0.0,
+            last_time: Instant::now(),
+        }
+    }
+
+    /// Adds the real time elapsed since the previous call
+    pub fn begin_frame(&mut self) {
+        let now = Instant::now();
+        self.advance((now - self.last_time).as_secs_f32());
+        self.last_time = now;
+    }
+
+    // Adds `elapsed` seconds, keeping at most a frame's worth of steps
+    fn advance(&mut self, elapsed: f32) {
+        self.accumulator = (self.accumulator + elapsed).min(MAX_STEPS_PER_FRAME as f32 * self.dt);
+    }
+
+    /// Consumes one fixed step if enough time has accumulated
+    pub fn step(&mut self) -> bool {
+        if self.accumulator >= self.dt {
+            self.accumulator -= self.dt;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// How far between the last two simulation states the current frame falls, in [0, 1)
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.dt).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(clock: &mut FixedTimestep) -> u32 {
+        let mut count = 0;
+        while clock.step() {
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn steps_once_per_dt_and_keeps_the_rest_for_alpha() {
+        let mut clock = FixedTimestep::new(8.0);
+        clock.advance(0.2);
+        assert_eq!(steps(&mut clock), 1);
+        assert!((clock.alpha() - 0.6).abs() < 1e-4);
+        // The rest carries over into the next frame
+        clock.advance(0.1);
+        assert_eq!(steps(&mut clock), 1);
+        assert!((clock.alpha() - 0.4).abs() < 1e-4);
+    }
+
+    #[test]
+    fn drops_time_beyond_a_frames_worth_of_steps() {
+        let mut clock = FixedTimestep::new(8.0);
+        clock.advance(100.0);
+        assert_eq!(steps(&mut clock), MAX_STEPS_PER_FRAME);
+        assert_eq!(clock.alpha(), 0.0);
+        // Nothing of the slow frame is left for the next one
+        clock.advance(0.0);
+        assert_eq!(steps(&mut clock), 0);
+    }
+}
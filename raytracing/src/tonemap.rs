@@ -0,0 +1,22 @@
+use raylib::prelude::{Color, Vector3};
+
+/// Exposure compensation range and step, in stops
+pub const MIN_EXPOSURE: f32 = -4.0;
+pub const MAX_EXPOSURE: f32 = 4.0;
+pub const EXPOSURE_STEP: f32 = 0.5;
+
+/// Final stage from linear radiance to display color for pixel (x, y): scales by 2^EV,
+/// then quantizes to 8 bits with dithering so smooth sky gradients don't band
+pub fn tonemap(color: Vector3, exposure: f32, x: u32, y: u32) -> Color {
+    let color = color * exposure.exp2();
+    let threshold = dither_threshold(x, y);
+    let channel = |value: f32| (value * 255.0 + threshold).clamp(0.0, 255.0) as u8;
+    Color::new(channel(color.x), channel(color.y), channel(color.z), 255)
+}
+
+/// Interleaved gradient noise in [0, 1): a cheap per-pixel pattern with mostly high
+/// frequencies, so it reads as fine grain instead of the cross-hatch of a Bayer matrix
+pub fn dither_threshold(x: u32, y: u32) -> f32 {
+    let v = (0.067_110_56 * x as f32 + 0.005_837_15 * y as f32).fract();
+    (52.982_918 * v).fract()
+}
@@ -0,0 +1,449 @@
+use crate::ray_intersect::{SceneObject, scene_bounds};
+use raylib::prelude::Vector3;
+use std::collections::BTreeSet;
+
+// Cells per object a grid may spend before a BVH is the better fit (sparse scenes, or a few
+// huge objects among small ones)
+const MAX_CELLS_PER_OBJECT: usize = 32;
+// Hard cap on cells, whatever the object count
+const MAX_CELLS: usize = 1 << 21;
+// Box faces lying exactly on a cell wall don't spill into the neighbouring cell
+const EPSILON: f32 = 1e-4;
+// Cells along each side of a chunk, the unit an edit rebuilds
+const CHUNK: usize = 4;
+
+type Span = ([usize; 3], [usize; 3]); // First and last cell of a box
+
+/// Uniform grid over the scene with cells as big as a typical block, each listing the
+/// objects that overlap it. A ray steps from cell to cell in order (3D-DDA), so it only
+/// tests the objects in the cells it passes through and stops at the first cell with a hit.
+/// The cells are grouped in chunks of CHUNK³ with their own lists, so moving, placing or
+/// removing an object only rebuilds the chunks it touches.
+#[derive(Debug, Clone)]
+pub struct VoxelGrid {
+    min: Vector3,
+    cell: f32, // Side of a cell
+    dims: [usize; 3],
+    chunk_dims: [usize; 3],
+    chunks: Vec<Chunk>,
+    spans: Vec<Span>, // Where each object is, as of the last build or edit
+}
+
+#[derive(Debug, Clone, Default)]
+struct Chunk {
+    dims: [usize; 3],  // Cells along each side, fewer than CHUNK at the grid's far edges
+    members: Vec<u32>, // Objects overlapping any of the chunk's cells
+    starts: Vec<u32>,  // Where each cell's objects begin in `objects`, plus one end entry
+    objects: Vec<u32>,
+}
+
+impl VoxelGrid {
+    /// Sizes cells to the median object. None for an empty scene or one the grid would
+    /// mostly leave empty, where a BVH does better.
+    pub fn build(objects: &[SceneObject]) -> Option<Self> {
+        let (min, max) = scene_bounds(objects)?;
+        let boxes: Vec<(Vector3, Vector3)> = objects.iter().map(|object| object.bounds()).collect();
+        let mut sizes: Vec<f32> = boxes
+            .iter()
+            .map(|&(low, high)| {
+                let extent = high - low;
+                extent.x.max(extent.y).max(extent.z)
+            })
+            .collect();
+        let middle = sizes.len() / 2;
+        let cell = *sizes.select_nth_unstable_by(middle, f32::total_cmp).1;
+        if cell.is_nan() || cell <= EPSILON {
+            return None;
+        }
+
+        let extent = max - min;
+        let dims = [extent.x, extent.y, extent.z].map(|side| ((side / cell).ceil() as usize).max(1));
+        let count = dims.iter().try_fold(1usize, |count, &side| count.checked_mul(side))?;
+        if count > MAX_CELLS || count > objects.len() * MAX_CELLS_PER_OBJECT {
+            return None;
+        }
+
+        let chunk_dims = dims.map(|side| side.div_ceil(CHUNK));
+        let mut grid = VoxelGrid { min, cell, dims, chunk_dims, chunks: Vec::new(), spans: Vec::new() };
+        for z in 0..chunk_dims[2] {
+            for y in 0..chunk_dims[1] {
+                for x in 0..chunk_dims[0] {
+                    let corner = [x, y, z];
+                    let dims = std::array::from_fn(|axis| CHUNK.min(dims[axis] - corner[axis] * CHUNK));
+                    grid.chunks.push(Chunk { dims, ..Chunk::default() });
+                }
+            }
+        }
+        grid.spans = boxes.iter().map(|&bounds| grid.span_of(bounds)).collect();
+        let mut dirty = BTreeSet::new();
+        for object in 0..objects.len() {
+            grid.link(object, &mut dirty);
+        }
+        grid.rebuild(dirty);
+        Some(grid)
+    }
+
+    /// Re-bins the objects at `moved` where they are now, rebuilding only the chunks they
+    /// left or entered. False when an object left the grid and it has to be built again.
+    /// The objects must be the ones the grid was built over, in the same order.
+    pub fn update(&mut self, objects: &[SceneObject], moved: &[usize]) -> bool {
+        if objects.len() != self.spans.len() || !moved.iter().all(|&object| self.contains(objects[object].bounds())) {
+            return false;
+        }
+        let mut dirty = BTreeSet::new();
+        for &object in moved {
+            self.unlink(object, &mut dirty);
+            self.spans[object] = self.span_of(objects[object].bounds());
+            self.link(object, &mut dirty);
+        }
+        self.rebuild(dirty);
+        true
+    }
+
+    /// Bins the objects pushed onto the end of `objects` since the grid was built or last
+    /// edited (placed blocks), rebuilding only the chunks they land in. False when one is
+    /// outside the grid and it has to be built again.
+    pub fn insert(&mut self, objects: &[SceneObject]) -> bool {
+        let Some(added) = objects.get(self.spans.len()..) else {
+            return false;
+        };
+        if !added.iter().all(|object| self.contains(object.bounds())) {
+            return false;
+        }
+        let mut dirty = BTreeSet::new();
+        for object in added {
+            self.spans.push(self.span_of(object.bounds()));
+            self.link(self.spans.len() - 1, &mut dirty);
+        }
+        self.rebuild(dirty);
+        true
+    }
+
+    /// Drops object `index` the way `Vec::swap_remove` does (the last object takes its
+    /// index), rebuilding only the chunks either of them is in
+    pub fn remove(&mut self, index: usize) {
+        let last = self.spans.len() - 1;
+        let mut dirty = BTreeSet::new();
+        self.unlink(index, &mut dirty);
+        if index != last {
+            let (first, high) = self.chunks_of(self.spans[last]);
+            for_each_cell(self.chunk_dims, first, high, |chunk| {
+                for member in &mut self.chunks[chunk].members {
+                    if *member as usize == last {
+                        *member = index as u32;
+                    }
+                }
+                dirty.insert(chunk);
+            });
+        }
+        self.spans.swap_remove(index);
+        self.rebuild(dirty);
+    }
+
+    // Whether a box lies within the grid
+    fn contains(&self, (low, high): (Vector3, Vector3)) -> bool {
+        let top = self.min + Vector3::new(self.dims[0] as f32, self.dims[1] as f32, self.dims[2] as f32) * self.cell;
+        low.x >= self.min.x && low.y >= self.min.y && low.z >= self.min.z && high.x <= top.x && high.y <= top.y && high.z <= top.z
+    }
+
+    // Adds an object to the members of the chunks its span covers
+    fn link(&mut self, object: usize, dirty: &mut BTreeSet<usize>) {
+        let (first, last) = self.chunks_of(self.spans[object]);
+        for_each_cell(self.chunk_dims, first, last, |chunk| {
+            self.chunks[chunk].members.push(object as u32);
+            dirty.insert(chunk);
+        });
+    }
+
+    fn unlink(&mut self, object: usize, dirty: &mut BTreeSet<usize>) {
+        let (first, last) = self.chunks_of(self.spans[object]);
+        for_each_cell(self.chunk_dims, first, last, |chunk| {
+            self.chunks[chunk].members.retain(|&member| member as usize != object);
+            dirty.insert(chunk);
+        });
+    }
+
+    // Lays out the cell lists of each dirty chunk again from its members
+    fn rebuild(&mut self, dirty: BTreeSet<usize>) {
+        for index in dirty {
+            let corner = [
+                index % self.chunk_dims[0] * CHUNK,
+                index / self.chunk_dims[0] % self.chunk_dims[1] * CHUNK,
+                index / (self.chunk_dims[0] * self.chunk_dims[1]) * CHUNK,
+            ];
+            let chunk = &mut self.chunks[index];
+            let dims = chunk.dims;
+            // The part of an object's span inside the chunk, in the chunk's own cells
+            let local = |(first, last): Span| -> Span {
+                (
+                    std::array::from_fn(|axis| first[axis].max(corner[axis]) - corner[axis]),
+                    std::array::from_fn(|axis| last[axis].min(corner[axis] + dims[axis] - 1) - corner[axis]),
+                )
+            };
+
+            // Count each cell's objects, turn the counts into offsets, then fill the cells in
+            let count = dims[0] * dims[1] * dims[2];
+            chunk.starts = vec![0; count + 1];
+            for &object in &chunk.members {
+                let (first, last) = local(self.spans[object as usize]);
+                for_each_cell(dims, first, last, |cell| chunk.starts[cell + 1] += 1);
+            }
+            for cell in 0..count {
+                chunk.starts[cell + 1] += chunk.starts[cell];
+            }
+            let mut cursor = chunk.starts.clone();
+            chunk.objects = vec![0; chunk.starts[count] as usize];
+            for &object in &chunk.members {
+                let (first, last) = local(self.spans[object as usize]);
+                for_each_cell(dims, first, last, |cell| {
+                    chunk.objects[cursor[cell] as usize] = object;
+                    cursor[cell] += 1;
+                });
+            }
+        }
+    }
+
+    // First and last cell a box overlaps
+    fn span_of(&self, (low, high): (Vector3, Vector3)) -> Span {
+        let margin = Vector3::new(EPSILON, EPSILON, EPSILON);
+        let first = self.cell_of(low + margin);
+        let last = self.cell_of(high - margin);
+        (first, std::array::from_fn(|axis| last[axis].max(first[axis])))
+    }
+
+    // First and last chunk a span of cells overlaps
+    fn chunks_of(&self, (first, last): Span) -> Span {
+        (first.map(|cell| cell / CHUNK), last.map(|cell| cell / CHUNK))
+    }
+
+    // Cell holding a point, clamped into the grid
+    fn cell_of(&self, point: Vector3) -> [usize; 3] {
+        let offset = [point.x - self.min.x, point.y - self.min.y, point.z - self.min.z];
+        std::array::from_fn(|axis| ((offset[axis] / self.cell).floor().max(0.0) as usize).min(self.dims[axis] - 1))
+    }
+
+    // The objects listed in a cell
+    fn cell_objects(&self, cell: [usize; 3]) -> &[u32] {
+        let chunk = &self.chunks[cell[0] / CHUNK + self.chunk_dims[0] * (cell[1] / CHUNK + self.chunk_dims[1] * (cell[2] / CHUNK))];
+        let local = cell.map(|side| side % CHUNK);
+        let index = local[0] + chunk.dims[0] * (local[1] + chunk.dims[1] * local[2]);
+        &chunk.objects[chunk.starts[index] as usize..chunk.starts[index + 1] as usize]
+    }
+
+    /// Steps through the cells the ray crosses nearer than `limit`, in order, calling
+    /// `visit` with the index of every object in them (once, in the first of its cells the
+    /// ray reaches). `visit` returns the new limit (the closest hit so far, which ends the
+    /// walk at the cell it lies in) or None to stop the walk.
+    pub fn traverse(&self, origin: Vector3, direction: Vector3, mut limit: f32, mut visit: impl FnMut(usize) -> Option<f32>) {
+        let axis = |v: Vector3| [v.x, v.y, v.z];
+        let (start, direction_axes, low) = (axis(origin), axis(direction), axis(self.min));
+        let inverse = direction_axes.map(|d| 1.0 / d);
+
+        // Where the ray enters and leaves the grid
+        let mut near = 0.0f32;
+        let mut far = f32::INFINITY;
+        for i in 0..3 {
+            let high = low[i] + self.dims[i] as f32 * self.cell;
+            let (a, b) = ((low[i] - start[i]) * inverse[i], (high - start[i]) * inverse[i]);
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+        if near > far || near > limit {
+            return;
+        }
+
+        let mut cell = self.cell_of(origin + direction * near);
+        let mut step = [0isize; 3];
+        let mut next = [f32::INFINITY; 3]; // Distance to the next wall along each axis
+        let mut delta = [f32::INFINITY; 3]; // Distance between walls along each axis
+        for i in 0..3 {
+            if direction_axes[i] > 0.0 {
+                step[i] = 1;
+                next[i] = (low[i] + (cell[i] + 1) as f32 * self.cell - start[i]) * inverse[i];
+                delta[i] = self.cell * inverse[i];
+            } else if direction_axes[i] < 0.0 {
+                step[i] = -1;
+                next[i] = (low[i] + cell[i] as f32 * self.cell - start[i]) * inverse[i];
+                delta[i] = -self.cell * inverse[i];
+            }
+        }
+
+        let mut previous: Option<[usize; 3]> = None;
+        loop {
+            let exit = next[0].min(next[1]).min(next[2]).min(far);
+            for &object in self.cell_objects(cell) {
+                // The ray crosses an object's cells in one unbroken run, so it has seen the
+                // object before exactly when the previous cell is one of them
+                let (first, last) = self.spans[object as usize];
+                let seen = previous.is_some_and(|previous| (0..3).all(|i| (first[i]..=last[i]).contains(&previous[i])));
+                if seen {
+                    continue;
+                }
+                match visit(object as usize) {
+                    Some(closest) => limit = limit.min(closest),
+                    None => return,
+                }
+            }
+            if limit <= exit || exit >= far {
+                return;
+            }
+
+            // Into the neighbour across the nearest wall
+            let i = if next[0] <= next[1] && next[0] <= next[2] { 0 } else if next[1] <= next[2] { 1 } else { 2 };
+            let moved = cell[i] as isize + step[i];
+            if moved < 0 || moved >= self.dims[i] as isize {
+                return;
+            }
+            previous = Some(cell);
+            cell[i] = moved as usize;
+            next[i] += delta[i];
+        }
+    }
+}
+
+// Calls `f` with the index of every cell in the box between two cells
+fn for_each_cell(dims: [usize; 3], first: [usize; 3], last: [usize; 3], mut f: impl FnMut(usize)) {
+    for z in first[2]..=last[2] {
+        for y in first[1]..=last[1] {
+            for x in first[0]..=last[0] {
+                f(x + dims[0] * (y + dims[1] * z));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cube::Cube;
+    use crate::ray_intersect::scene_objects;
+
+    // A 4×4×4 pile of unit blocks (x fastest, its corner at the origin) and one block high
+    // above it, so the grid has room for blocks lifted off the pile
+    fn pile() -> Vec<SceneObject> {
+        let center = |index: usize| Vector3::new((index % 4) as f32, (index / 16) as f32, (index / 4 % 4) as f32);
+        let mut cubes: Vec<Cube> = (0..64).map(|index| Cube::new(center(index), 1.0, 0)).collect();
+        cubes.push(Cube::new(Vector3::new(3.0, 7.0, 3.0), 1.0, 0));
+        scene_objects(cubes)
+    }
+
+    fn move_to(objects: &mut [SceneObject], index: usize, center: Vector3) {
+        objects[index].as_cube_mut().unwrap().center = center;
+    }
+
+    // Index of the closest object along the ray, through the grid
+    fn closest(grid: &VoxelGrid, objects: &[SceneObject], origin: Vector3, direction: Vector3) -> Option<usize> {
+        let mut best = None;
+        grid.traverse(origin, direction, f32::INFINITY, |index| {
+            if let Some(distance) = objects[index].hit_distance(&origin, &direction)
+                && best.is_none_or(|(_, closest)| distance < closest)
+            {
+                best = Some((index, distance));
+            }
+            Some(best.map_or(f32::INFINITY, |(_, closest)| closest))
+        });
+        best.map(|(index, _)| index)
+    }
+
+    // Random rays through and around the pile find the same closest hit through the grid
+    // as by testing every object
+    fn assert_matches_brute_force(grid: &VoxelGrid, objects: &[SceneObject]) {
+        let mut rng = crate::rng::Rng::new(7, 0);
+        for _ in 0..2000 {
+            let origin = Vector3::new(rng.range(-6.0, 9.0), rng.range(-3.0, 11.0), rng.range(-6.0, 9.0));
+            let direction = Vector3::new(rng.range(-1.0, 1.0), rng.range(-1.0, 1.0), rng.range(-1.0, 1.0)).normalized();
+            let through_grid = closest(grid, objects, origin, direction).and_then(|index| objects[index].hit_distance(&origin, &direction));
+            let brute_force = objects.iter().filter_map(|object| object.hit_distance(&origin, &direction)).min_by(f32::total_cmp);
+            assert_eq!(through_grid, brute_force, "ray from {:?} along {:?}", origin, direction);
+        }
+    }
+
+    #[test]
+    fn traversal_finds_the_same_closest_hit_as_brute_force() {
+        let mut objects = pile();
+        let grid = VoxelGrid::build(&objects).unwrap();
+        assert_matches_brute_force(&grid, &objects);
+
+        // And after blocks were re-binned, one lifted and one moved to another column
+        let mut grid = grid;
+        move_to(&mut objects, 53, Vector3::new(1.0, 5.0, 1.0));
+        move_to(&mut objects, 12, Vector3::new(2.5, 6.0, 0.5));
+        assert!(grid.update(&objects, &[12, 53]));
+        assert_matches_brute_force(&grid, &objects);
+    }
+
+    #[test]
+    fn update_rebins_moved_objects() {
+        let mut objects = pile();
+        let mut grid = VoxelGrid::build(&objects).unwrap();
+        let down = Vector3::new(0.0, -1.0, 0.0);
+
+        // Block 53 lifted off the top layer, then carried over and replaced by block 63
+        move_to(&mut objects, 53, Vector3::new(1.0, 5.0, 1.0));
+        assert!(grid.update(&objects, &[53]));
+        assert_eq!(closest(&grid, &objects, Vector3::new(1.0, 10.0, 1.0), down), Some(53));
+        move_to(&mut objects, 53, Vector3::new(2.0, 5.0, 2.0));
+        move_to(&mut objects, 63, Vector3::new(1.0, 5.0, 1.0));
+        assert!(grid.update(&objects, &[53, 63]));
+
+        assert_eq!(closest(&grid, &objects, Vector3::new(2.0, 10.0, 2.0), down), Some(53));
+        assert_eq!(closest(&grid, &objects, Vector3::new(1.0, 10.0, 1.0), down), Some(63));
+        // Under where 63 was, the layer below; blocks that stayed put are still found
+        assert_eq!(closest(&grid, &objects, Vector3::new(3.0, 6.0, 3.0), down), Some(47));
+        assert_eq!(closest(&grid, &objects, Vector3::new(0.0, 10.0, 0.0), down), Some(48));
+        // Each moved block is listed only in the cells it is in now
+        let listed = |object: u32| grid.chunks.iter().flat_map(|chunk| &chunk.objects).filter(|&&listed| listed == object).count();
+        assert_eq!((listed(53), listed(63)), (1, 1));
+    }
+
+    #[test]
+    fn placed_and_removed_blocks_are_hit_and_missed() {
+        let mut objects = pile();
+        let mut grid = VoxelGrid::build(&objects).unwrap();
+        let (down, across) = (Vector3::new(0.0, -1.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let column = Vector3::new(2.0, 10.0, 1.0);
+        let high = Vector3::new(-5.0, 7.0, 3.0);
+        let above_pile = Vector3::new(-5.0, 4.0, 1.0);
+        assert_eq!(closest(&grid, &objects, column, down), Some(54));
+        assert_eq!(closest(&grid, &objects, high, across), Some(64));
+        assert_eq!(closest(&grid, &objects, above_pile, across), None);
+
+        // A block placed on the column, in the upper chunk
+        objects.push(Box::new(Cube::new(Vector3::new(2.0, 4.0, 1.0), 1.0, 0)));
+        assert!(grid.insert(&objects));
+        assert_eq!(closest(&grid, &objects, column, down), Some(65));
+        assert_eq!(closest(&grid, &objects, above_pile, across), Some(65));
+        assert_matches_brute_force(&grid, &objects);
+
+        // The lone high block removed, the placed block taking its index
+        objects.swap_remove(64);
+        grid.remove(64);
+        assert_eq!(closest(&grid, &objects, high, across), None);
+        assert_eq!(closest(&grid, &objects, column, down), Some(64));
+        assert_eq!(closest(&grid, &objects, above_pile, across), Some(64));
+        assert_matches_brute_force(&grid, &objects);
+
+        // And the placed block removed again
+        objects.swap_remove(64);
+        grid.remove(64);
+        assert_eq!(closest(&grid, &objects, column, down), Some(54));
+        assert_eq!(closest(&grid, &objects, above_pile, across), None);
+        assert_matches_brute_force(&grid, &objects);
+    }
+
+    #[test]
+    fn insert_gives_up_when_a_block_is_placed_outside_the_grid() {
+        let mut objects = pile();
+        let mut grid = VoxelGrid::build(&objects).unwrap();
+        objects.push(Box::new(Cube::new(Vector3::new(0.0, 9.0, 0.0), 1.0, 0)));
+        assert!(!grid.insert(&objects));
+    }
+
+    #[test]
+    fn update_gives_up_when_an_object_leaves_the_grid() {
+        let mut objects = pile();
+        let mut grid = VoxelGrid::build(&objects).unwrap();
+        move_to(&mut objects, 0, Vector3::new(0.0, -5.0, 0.0));
+        assert!(!grid.update(&objects, &[0]));
+    }
+}